@@ -0,0 +1,431 @@
+//! A per-user background process that keeps a daemon connection, and the
+//! AQC channels and streams opened over it, alive between CLI invocations.
+//!
+//! The agent listens on a Unix socket derived from the daemon's UDS path.
+//! Each connection carries one newline-delimited JSON [`Request`] and one
+//! [`Response`]. Connections are handled concurrently, but commands run
+//! one at a time in the agent's session. Commands that wait for a peer
+//! only hold the session while checking for it, so they don't hold up
+//! the others.
+
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use aranya_crypto::dangerous::spideroak_crypto::{hash::Hash, rust::Sha256};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinSet;
+
+use crate::commands::{self, Commands};
+use crate::output::{Output, OutputFormat};
+use crate::session::Session;
+
+/// How long `agent start` waits for the agent to accept connections.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Subcommand, Serialize, Deserialize)]
+pub(crate) enum AgentCommand {
+    /// Start the agent in the background
+    Start,
+    /// Show whether the agent is running
+    Status,
+    /// Stop the agent
+    Stop,
+    /// Run the agent in the foreground
+    #[command(hide = true)]
+    Run,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    /// Run a command in the agent's session and render its result in
+    /// `format`.
+    Exec {
+        command: Commands,
+        format: OutputFormat,
+    },
+    Status,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    /// The command succeeded and its result rendered as `stdout`.
+    Output {
+        stdout: String,
+        /// The [exit code][Output::exit_code] for the result.
+        #[serde(default)]
+        code: u8,
+    },
+    /// The command failed.
+    Error {
+        message: String,
+    },
+    Status(AgentStatus),
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AgentStatus {
+    pid: u32,
+    uds_path: PathBuf,
+    channels: usize,
+    streams: usize,
+}
+
+/// Returns the path of the agent socket for the daemon at `uds_path`.
+///
+/// There is one agent per user and daemon, so the socket lives in the
+/// user's runtime directory and its name is derived from `uds_path`.
+pub(crate) fn socket_path(uds_path: &Path) -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("aranya"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "default".to_owned());
+            std::env::temp_dir().join(format!("aranya-{user}"))
+        }
+    };
+    let uds_path = fs::canonicalize(uds_path).unwrap_or_else(|_| uds_path.to_owned());
+    // The name must not change between builds of the CLI, or a newer
+    // CLI would not find an agent started by an older one.
+    let digest = Sha256::hash(uds_path.as_os_str().as_encoded_bytes());
+    let prefix = digest.get(..8).unwrap_or_default();
+    dir.join(format!("agent-{}.sock", hex::encode(prefix)))
+}
+
+/// Handles `aranya agent <command>`.
+///
+/// Returns `None` for `agent run`, which has no result to print.
+pub(crate) async fn run(
+    command: AgentCommand,
+    uds_path: &Path,
+    aqc_addr: &str,
+) -> Result<Option<Output>> {
+    let socket = socket_path(uds_path);
+    let output = match command {
+        AgentCommand::Start => {
+            let already_running = request(&socket, &Request::Status).await?.is_some();
+            if !already_running {
+                start(&socket, uds_path, aqc_addr).await?;
+            }
+            Output::AgentStarted {
+                socket: socket.display().to_string(),
+                already_running,
+            }
+        }
+        AgentCommand::Status => match request(&socket, &Request::Status).await? {
+            Some(Response::Status(status)) => Output::AgentStatus {
+                running: true,
+                socket: socket.display().to_string(),
+                pid: Some(status.pid),
+                uds_path: Some(status.uds_path.display().to_string()),
+                channels: Some(status.channels),
+                streams: Some(status.streams),
+            },
+            Some(_) => anyhow::bail!("Unexpected response from agent"),
+            None => Output::AgentStatus {
+                running: false,
+                socket: socket.display().to_string(),
+                pid: None,
+                uds_path: None,
+                channels: None,
+                streams: None,
+            },
+        },
+        AgentCommand::Stop => Output::AgentStopped {
+            was_running: request(&socket, &Request::Stop).await?.is_some(),
+        },
+        AgentCommand::Run => {
+            let client = crate::connect_to_daemon(uds_path, aqc_addr).await?;
+            let session = Session::new(client, OutputFormat::default());
+            serve(session, &socket, uds_path).await?;
+            return Ok(None);
+        }
+    };
+    Ok(Some(output))
+}
+
+/// Runs `command` in the agent for the daemon at `uds_path` and returns
+/// its result rendered in `format`, along with its exit code.
+///
+/// Returns `None` if no agent is running, in which case the caller
+/// should run the command itself.
+pub(crate) async fn forward(
+    uds_path: &Path,
+    command: Commands,
+    format: OutputFormat,
+) -> Result<Option<(String, u8)>> {
+    let socket = socket_path(uds_path);
+    match request(&socket, &Request::Exec { command, format }).await? {
+        Some(Response::Output { stdout, code }) => Ok(Some((stdout, code))),
+        Some(Response::Error { message }) => Err(anyhow::anyhow!(message)),
+        Some(_) => anyhow::bail!("Unexpected response from agent"),
+        None => Ok(None),
+    }
+}
+
+/// Spawns `aranya agent run` detached from the current terminal and
+/// waits for it to start listening.
+async fn start(socket: &Path, uds_path: &Path, aqc_addr: &str) -> Result<()> {
+    let dir = socket.parent().context("Invalid agent socket path")?;
+    create_private_dir(dir)?;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(socket.with_extension("log"))
+        .context("Failed to open agent log")?;
+
+    let exe = std::env::current_exe().context("Failed to find aranya executable")?;
+    std::process::Command::new(exe)
+        .arg("--uds-path")
+        .arg(uds_path)
+        .arg("--aqc-addr")
+        .arg(aqc_addr)
+        .args(["agent", "run"])
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .process_group(0)
+        .spawn()
+        .context("Failed to spawn agent")?;
+
+    let start = std::time::Instant::now();
+    while start.elapsed() < START_TIMEOUT {
+        if request(socket, &Request::Status).await?.is_some() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!(
+        "Agent did not start within {}s, see {}",
+        START_TIMEOUT.as_secs(),
+        socket.with_extension("log").display()
+    )
+}
+
+/// Sends `req` to the agent listening on `socket`.
+///
+/// Returns `None` if no agent is listening.
+async fn request(socket: &Path, req: &Request) -> Result<Option<Response>> {
+    let dir = socket.parent().context("Invalid agent socket path")?;
+    if !dir.exists() {
+        return Ok(None);
+    }
+    check_private_dir(dir)?;
+    let Ok(stream) = UnixStream::connect(socket).await else {
+        return Ok(None);
+    };
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    writer.shutdown().await?;
+
+    let mut line = String::new();
+    BufReader::new(reader)
+        .read_line(&mut line)
+        .await
+        .context("Failed to read response from agent")?;
+    let resp = serde_json::from_str(&line).context("Invalid response from agent")?;
+    Ok(Some(resp))
+}
+
+/// Creates `dir` with mode 0700 if it does not exist, and checks that
+/// no other user can put an agent socket in it.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .context("Failed to create agent directory")?;
+    check_private_dir(dir)
+}
+
+/// Checks that `dir` is a directory owned by the current user that no
+/// other user can write to.
+///
+/// The directory may be in a shared location such as `/tmp`, where
+/// another user could create it first and listen on our socket.
+fn check_private_dir(dir: &Path) -> Result<()> {
+    let meta =
+        fs::symlink_metadata(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    if !meta.is_dir() {
+        anyhow::bail!("Agent directory {} is not a directory", dir.display());
+    }
+    // SAFETY: `geteuid` has no preconditions and cannot fail.
+    let uid = unsafe { libc::geteuid() };
+    if meta.uid() != uid {
+        anyhow::bail!("Agent directory {} is owned by another user", dir.display());
+    }
+    if meta.mode() & 0o022 != 0 {
+        anyhow::bail!(
+            "Agent directory {} is writable by other users",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// State shared by the agent's connections.
+struct Shared {
+    session: Mutex<Session>,
+    uds_path: PathBuf,
+    /// The number of channels and streams after the last command, so
+    /// that `agent status` doesn't wait for a running command.
+    channels: AtomicUsize,
+    streams: AtomicUsize,
+    /// Notified when the agent is asked to stop.
+    stop: Notify,
+}
+
+/// Serves requests on `socket` until asked to stop.
+async fn serve(session: Session, socket: &Path, uds_path: &Path) -> Result<()> {
+    #![allow(
+        clippy::disallowed_macros,
+        reason = "`tokio::select!` expands to `unreachable!`"
+    )]
+    if let Some(dir) = socket.parent() {
+        create_private_dir(dir)?;
+    }
+    // Any socket left here belongs to an agent that is no longer running,
+    // otherwise `agent start` would not have spawned us.
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket).context("Failed to bind agent socket")?;
+    tracing::info!(socket = %socket.display(), "agent listening");
+
+    let shared = Arc::new(Shared {
+        session: Mutex::new(session),
+        uds_path: uds_path.to_owned(),
+        channels: AtomicUsize::new(0),
+        streams: AtomicUsize::new(0),
+        stop: Notify::new(),
+    });
+    let mut conns = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            res = listener.accept() => res?.0,
+            _ = shared.stop.notified() => break,
+            _ = tokio::signal::ctrl_c() => break,
+            // Reap finished connections.
+            Some(_) = conns.join_next() => continue,
+        };
+        let shared = Arc::clone(&shared);
+        conns.spawn(async move {
+            match handle(&shared, stream).await {
+                // Stop once the response has been written.
+                Ok(true) => shared.stop.notify_one(),
+                Ok(false) => {}
+                Err(err) => tracing::warn!(error = %err, "agent request failed"),
+            }
+        });
+    }
+
+    let _ = fs::remove_file(socket);
+    Ok(())
+}
+
+/// Handles one connection. Returns `true` if the agent should stop.
+async fn handle(shared: &Shared, stream: UnixStream) -> Result<bool> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let (resp, stop) = match serde_json::from_str(&line)? {
+        Request::Exec { command, format } => {
+            shared.session.lock().await.format = format;
+            let resp = match commands::run_shared(&shared.session, command).await {
+                Ok(output) => {
+                    let mut stdout = Vec::new();
+                    output.render(format, &mut stdout)?;
+                    Response::Output {
+                        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+                        code: output.exit_code(),
+                    }
+                }
+                Err(err) => Response::Error {
+                    message: format!("{err:#}"),
+                },
+            };
+            let session = shared.session.lock().await;
+            let registry = &session.registry;
+            shared.channels.store(
+                registry.list_channels().len() + registry.list_received_channels().len(),
+                Ordering::Relaxed,
+            );
+            shared.streams.store(
+                registry.list_streams().len() + registry.list_peer_streams().len(),
+                Ordering::Relaxed,
+            );
+            (resp, false)
+        }
+        Request::Status => {
+            let status = AgentStatus {
+                pid: std::process::id(),
+                uds_path: shared.uds_path.clone(),
+                channels: shared.channels.load(Ordering::Relaxed),
+                streams: shared.streams.load(Ordering::Relaxed),
+            };
+            (Response::Status(status), false)
+        }
+        Request::Stop => (Response::Stopped, true),
+    };
+
+    let mut line = serde_json::to_string(&resp)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_name_is_stable() {
+        // The name is part of how CLIs find a running agent, so it must
+        // not depend on the build.
+        let socket = socket_path(Path::new("/nonexistent/uds.sock"));
+        assert_eq!(socket.file_name().unwrap(), "agent-5f49c313a9b1fd3a.sock");
+    }
+
+    #[test]
+    fn test_private_dir_is_created_0700() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("aranya");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+        // An existing private directory is reused.
+        create_private_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_writable_dir_is_refused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("aranya");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o777)).unwrap();
+        assert!(create_private_dir(&dir).is_err());
+
+        // So is a symlink to a private directory.
+        let link = tmp.path().join("link");
+        fs::set_permissions(&dir, Permissions::from_mode(0o700)).unwrap();
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        assert!(check_private_dir(&link).is_err());
+    }
+}
@@ -0,0 +1,1504 @@
+use anyhow::{Context, Result};
+use aranya_client::{
+    aqc::{self, AqcPeerChannel, AqcPeerStream, FileTransfer, TryReceiveError},
+    QuicSyncConfig, SyncPeerConfig, TeamConfig,
+};
+use aranya_daemon_api::{ChanOp, DeviceId, KeyBundle, LabelId, NetIdentifier, Role, TeamId, Text};
+use aranya_util::Addr;
+use bytes::Bytes;
+use clap::Subcommand;
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::agent::AgentCommand;
+use crate::output::{
+    DeviceRoleOutput, FileOutput, KeyBundleOutput, LabelOutput, NetIdOutput, Output, OutputFormat,
+    SyncPeerOutput, SyncStatusOutput, Waited,
+};
+use crate::pipe::AqcCommand;
+use crate::profile::{self, ProfileCommand};
+use crate::session::Session;
+use crate::team::TeamCommand;
+use crate::watch::EventKind;
+
+#[derive(Clone, Subcommand, Serialize, Deserialize)]
+pub(crate) enum Commands {
+    /// Create a new team
+    CreateTeam {
+        /// Optional seed IKM in hex (32 bytes). If not provided, generates random.
+        #[arg(long)]
+        seed_ikm: Option<String>,
+    },
+    /// Add an existing team to this device
+    AddTeam {
+        /// Team ID to add
+        team_id: String,
+        /// Seed IKM in hex (32 bytes)
+        seed_ikm: String,
+    },
+    /// Remove a team from this device
+    RemoveTeam {
+        /// Team ID to remove
+        team_id: String,
+    },
+    /// Add a device to a team
+    AddDevice {
+        /// Team ID
+        team_id: String,
+        /// Device's identity public key in hex
+        identity_pk: String,
+        /// Device's signing public key in hex
+        signing_pk: String,
+        /// Device's encoding public key in hex
+        encoding_pk: String,
+    },
+    /// Remove a device from a team
+    RemoveDevice {
+        /// Team ID
+        team_id: String,
+        /// Device ID to remove
+        device_id: String,
+    },
+    /// Assign a role to a device
+    AssignRole {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+        /// Role (Owner, Admin, Operator, Member)
+        role: String,
+    },
+    /// List all devices on a team
+    ListDevices {
+        /// Team ID
+        team_id: String,
+    },
+    /// Get device information
+    DeviceInfo {
+        /// Team ID
+        team_id: String,
+        /// Device ID (optional, shows current device if not provided)
+        device_id: Option<String>,
+    },
+    /// Add a sync peer for automatic synchronization
+    AddSyncPeer {
+        /// Team ID
+        team_id: String,
+        /// Peer address (e.g., "192.168.1.100:7812")
+        peer_addr: String,
+        /// Sync interval in seconds (defaults to the profile's, or 1)
+        #[arg(long)]
+        interval_secs: Option<u64>,
+        /// Forget the peer when the daemon restarts
+        #[arg(long)]
+        ephemeral: bool,
+        /// Ask the peer to say as soon as it has new commands, instead of
+        /// waiting for the next interval
+        #[arg(long)]
+        subscribe: bool,
+    },
+    /// List the peers a team is automatically synced with
+    ListSyncPeers {
+        /// Team ID
+        team_id: String,
+    },
+    /// Show when each sync peer of a team was last synced with, and how
+    /// syncing with it is failing
+    SyncStatus {
+        /// Team ID
+        team_id: String,
+    },
+    /// Stop automatically syncing with a peer
+    RemoveSyncPeer {
+        /// Team ID
+        team_id: String,
+        /// Peer address (e.g., "192.168.1.100:7812")
+        peer_addr: String,
+    },
+    /// Sync with a peer immediately
+    SyncNow {
+        /// Team ID
+        team_id: String,
+        /// Peer address (e.g., "192.168.1.100:7812")
+        peer_addr: String,
+    },
+    /// Create a label for data channels
+    CreateLabel {
+        /// Team ID
+        team_id: String,
+        /// Label name
+        label_name: String,
+    },
+    /// Assign a label to a device with channel operations
+    AssignLabel {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+        /// Label ID
+        label_id: String,
+        /// Channel operation (SendOnly, RecvOnly, SendRecv)
+        operation: String,
+    },
+    /// Assign network identifier to device for AQC
+    AssignAqcNetId {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+        /// Network identifier (e.g., "192.168.1.100:5050")
+        net_id: String,
+    },
+    /// List label assignments
+    ListLabelAssignments {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+    },
+    /// List AQC network assignments
+    ListAqcAssignments {
+        /// Team ID
+        team_id: String,
+    },
+    /// Send data with PSK rotation (creates new channel for each send)
+    SendData {
+        /// Team ID
+        team_id: String,
+        /// Target device ID
+        device_id: String,
+        /// Label ID for the channel
+        label_id: String,
+        /// Message to send
+        message: String,
+    },
+    /// Listen for data with PSK rotation (creates new channel for each receive)
+    ListenData {
+        /// Team ID
+        team_id: String,
+        /// Source device ID
+        device_id: String,
+        /// Label ID for the channel
+        label_id: String,
+        /// Timeout in seconds (0 for infinite)
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+    },
+    /// Send a file over a new AQC channel, resuming a partial transfer
+    SendFile {
+        /// Team ID
+        team_id: String,
+        /// Target device ID
+        device_id: String,
+        /// Label ID for the channel
+        label_id: String,
+        /// File to send
+        path: PathBuf,
+    },
+    /// Receive a file sent with send-file
+    RecvFile {
+        /// Directory to write the file to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        /// Timeout in seconds (0 for infinite)
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+    },
+    /// Show active AQC channels
+    ShowChannels {
+        /// Team ID
+        team_id: String,
+    },
+    /// Query devices on a team
+    QueryDevicesOnTeam {
+        /// Team ID
+        team_id: String,
+    },
+    /// Query device role
+    QueryDeviceRole {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+    },
+    /// Query device keybundle
+    QueryDeviceKeybundle {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+    },
+    /// Query AQC network identifier for a device
+    QueryAqcNetIdentifier {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+    },
+    /// Revoke a label assignment from a device
+    RevokeLabel {
+        /// Team ID
+        team_id: String,
+        /// Device ID
+        device_id: String,
+        /// Label ID
+        label_id: String,
+    },
+    /// Delete a label entirely (Admin only)
+    DeleteLabel {
+        /// Team ID
+        team_id: String,
+        /// Label ID
+        label_id: String,
+    },
+    /// Create bidirectional AQC channel
+    CreateBidiChannel {
+        /// Team ID
+        team_id: String,
+        /// Target device network identifier
+        net_id: String,
+        /// Label ID for the channel
+        label_id: String,
+    },
+    /// Receive incoming AQC channel
+    ReceiveChannel {
+        /// Timeout in seconds (0 for infinite)
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+    },
+    /// Create bidirectional stream on channel
+    CreateBidiStream {
+        /// Channel ID (from create-bidi-channel)
+        channel_id: String,
+    },
+    /// Receive stream from channel
+    ReceiveStream {
+        /// Channel ID (from receive-channel)
+        channel_id: String,
+        /// Timeout in seconds (0 for infinite)
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+    },
+    /// Send data on stream
+    SendStreamData {
+        /// Stream ID (from create-bidi-stream)
+        stream_id: String,
+        /// Data to send
+        data: String,
+    },
+    /// Receive data from stream
+    ReceiveStreamData {
+        /// Stream ID (from receive-stream)
+        stream_id: String,
+        /// Timeout in seconds (0 for infinite)
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+    },
+    /// Get device's key bundle
+    GetKeyBundle,
+    /// Get device's ID
+    GetDeviceId,
+    /// Get client/device identity info from daemon
+    CreateClient {
+        /// Output format (deprecated, use the global `--output` flag)
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
+    },
+    /// Create team with custom configuration
+    CreateTeamWithConfig {
+        /// Seed IKM in hex (32 bytes)
+        seed_ikm: String,
+        /// Sync interval in seconds (defaults to the profile's, or 1)
+        #[arg(long)]
+        sync_interval_secs: Option<u64>,
+    },
+    /// Change the sync interval of a team's sync peers
+    SetSyncConfig {
+        /// Team ID
+        team_id: String,
+        /// Sync interval in seconds
+        interval_secs: u64,
+        /// Only change this peer (defaults to every peer of the team)
+        #[arg(long)]
+        peer: Option<String>,
+    },
+    /// Get base58 Label ID
+    GetLabelIdBase58 {
+        /// Label ID in hex format
+        label_id_hex: String,
+    },
+    /// List active channels and streams
+    ListActiveChannels,
+    /// Close a channel
+    CloseChannel {
+        /// Channel ID to close
+        channel_id: String,
+    },
+    /// Close a stream
+    CloseStream {
+        /// Stream ID to close
+        stream_id: String,
+    },
+    /// Start an interactive shell that keeps one daemon connection open
+    Shell {
+        /// History file (defaults to ~/.aranya_history)
+        #[arg(long)]
+        history: Option<PathBuf>,
+    },
+    /// Run a script of commands over one daemon connection
+    Exec {
+        /// Script file (`-` for stdin)
+        script: PathBuf,
+        /// Bind a variable before the script starts, as NAME=VALUE. May be
+        /// repeated.
+        #[arg(long = "var")]
+        vars: Vec<String>,
+        /// Keep running after a command fails
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// Print a team's events as JSON lines as they happen
+    Watch {
+        /// Team ID
+        team_id: String,
+        /// Only print events of this kind. May be repeated.
+        #[arg(long = "event", value_enum)]
+        kinds: Vec<EventKind>,
+        /// Exit after printing this many events
+        #[arg(long)]
+        count: Option<usize>,
+    },
+    /// Manage the background agent that keeps channels and streams open
+    /// between invocations
+    Agent {
+        #[command(subcommand)]
+        command: AgentCommand,
+    },
+    /// Invite devices to a team and join teams with invitation tokens
+    Team {
+        #[command(subcommand)]
+        command: TeamCommand,
+    },
+    /// Pipe data over AQC
+    Aqc {
+        #[command(subcommand)]
+        command: AqcCommand,
+    },
+    /// Show the changes needed to make a team match a manifest
+    Plan {
+        /// Team manifest (.yaml, .yml, or .toml)
+        #[arg(short = 'f', long)]
+        file: PathBuf,
+        /// Include removing devices, labels, label assignments, and AQC
+        /// network identifiers that the manifest does not list
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Make a team match a manifest
+    Apply {
+        /// Team manifest (.yaml, .yml, or .toml)
+        #[arg(short = 'f', long)]
+        file: PathBuf,
+        /// Also remove devices, labels, label assignments, and AQC
+        /// network identifiers that the manifest does not list
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Manage named daemon profiles in ~/.config/aranya/cli.toml
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+}
+
+impl Commands {
+    /// Reports whether the command uses channels or streams from the
+    /// [`ChannelRegistry`][crate::registry::ChannelRegistry], and so must
+    /// run in the agent when one is running.
+    pub(crate) fn uses_channel_registry(&self) -> bool {
+        matches!(
+            self,
+            Self::CreateBidiChannel { .. }
+                | Self::ReceiveChannel { .. }
+                | Self::CreateBidiStream { .. }
+                | Self::ReceiveStream { .. }
+                | Self::SendStreamData { .. }
+                | Self::ReceiveStreamData { .. }
+                | Self::ListActiveChannels
+                | Self::CloseChannel { .. }
+                | Self::CloseStream { .. }
+        )
+    }
+
+    /// Returns how many seconds the command waits for a peer, and what
+    /// it waits for, if it is one of the commands that poll the
+    /// [`ChannelRegistry`][crate::registry::ChannelRegistry].
+    pub(crate) fn waits(&self) -> Option<(u64, Waited)> {
+        match self {
+            Self::ReceiveChannel { timeout } => Some((*timeout, Waited::Channel)),
+            Self::ReceiveStream { timeout, .. } => Some((*timeout, Waited::Stream)),
+            Self::ReceiveStreamData { timeout, .. } => Some((*timeout, Waited::Data)),
+            _ => None,
+        }
+    }
+
+    /// Returns the output format the command itself asks for, if any.
+    pub(crate) fn format_override(&self) -> Option<OutputFormat> {
+        match self {
+            Self::CreateClient { format } => *format,
+            _ => None,
+        }
+    }
+}
+
+impl Session {
+    /// Runs a single command against this session's client.
+    pub(crate) async fn run(&mut self, command: Commands) -> Result<Output> {
+        let output = match command {
+            Commands::CreateTeam { seed_ikm } => {
+                let mut ikm = [0u8; 32];
+                if let Some(hex_ikm) = seed_ikm {
+                    let bytes = hex::decode(&hex_ikm).context("Invalid hex for seed IKM")?;
+                    if bytes.len() != 32 {
+                        anyhow::bail!("Seed IKM must be exactly 32 bytes");
+                    }
+                    ikm.copy_from_slice(&bytes);
+                } else {
+                    // Generate random IKM
+                    let mut rng = rand::thread_rng();
+                    rng.fill(&mut ikm);
+                }
+
+                let team_config = TeamConfig::builder()
+                    .quic_sync(QuicSyncConfig::builder().seed_ikm(ikm).build()?)
+                    .build()?;
+
+                let team = self
+                    .client
+                    .create_team(team_config)
+                    .await
+                    .context("Failed to create team")?;
+                let team_id = team.team_id().to_string();
+                self.known.teams.insert(team_id.clone());
+
+                Output::TeamCreated {
+                    team_id,
+                    seed_ikm: hex::encode(ikm),
+                    sync_interval_secs: None,
+                }
+            }
+            Commands::AddTeam { team_id, seed_ikm } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let ikm = hex::decode(seed_ikm).context("Invalid hex for seed IKM")?;
+                if ikm.len() != 32 {
+                    anyhow::bail!("Seed IKM must be exactly 32 bytes");
+                }
+                let mut ikm_array = [0u8; 32];
+                ikm_array.copy_from_slice(&ikm);
+
+                let sync_cfg = QuicSyncConfig::builder().seed_ikm(ikm_array).build()?;
+                let cfg = TeamConfig::builder().quic_sync(sync_cfg).build()?;
+
+                self.client.add_team(team_id, cfg).await?;
+                self.known.teams.insert(team_id.to_string());
+                Output::TeamAdded {
+                    team_id: team_id.to_string(),
+                }
+            }
+            Commands::RemoveTeam { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                self.client.remove_team(team_id).await?;
+                self.known.teams.remove(&team_id.to_string());
+                Output::TeamRemoved {
+                    team_id: team_id.to_string(),
+                }
+            }
+            Commands::AddDevice {
+                team_id,
+                identity_pk,
+                signing_pk,
+                encoding_pk,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let identity = hex::decode(identity_pk).context("Invalid hex for identity key")?;
+                let signing = hex::decode(signing_pk).context("Invalid hex for signing key")?;
+                let encoding = hex::decode(encoding_pk).context("Invalid hex for encoding key")?;
+
+                let key_bundle = KeyBundle {
+                    identity,
+                    signing,
+                    encoding,
+                };
+
+                let device_id = crate::team::device_id(&key_bundle)?;
+                let mut team = self.client.team(team_id);
+                team.add_device_to_team(key_bundle).await?;
+                Output::DeviceAdded {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                }
+            }
+            Commands::RemoveDevice { team_id, device_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+
+                let mut team = self.client.team(team_id);
+                team.remove_device_from_team(device_id).await?;
+                Output::DeviceRemoved {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                }
+            }
+            Commands::AssignRole {
+                team_id,
+                device_id,
+                role,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let role = parse_role(&role)?;
+
+                let mut team = self.client.team(team_id);
+                team.assign_role(device_id, role).await?;
+                Output::RoleAssigned {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    role: format!("{role:?}"),
+                }
+            }
+            Commands::ListDevices { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let devices = team.queries().devices_on_team().await?;
+
+                let mut roles = Vec::new();
+                for device_id in devices.iter() {
+                    self.known.devices.insert(device_id.to_string());
+                    let role = team.queries().device_role(*device_id).await?;
+                    roles.push(DeviceRoleOutput {
+                        device_id: device_id.to_string(),
+                        role: format!("{role:?}"),
+                    });
+                }
+                Output::DeviceRoles {
+                    team_id: team_id.to_string(),
+                    devices: roles,
+                }
+            }
+            Commands::DeviceInfo { team_id, device_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = if let Some(id) = device_id {
+                    DeviceId::from_str(&id)?
+                } else {
+                    self.client.get_device_id().await?
+                };
+
+                let mut team = self.client.team(team_id);
+                let role = team.queries().device_role(device_id).await?;
+                let key_bundle = team.queries().device_keybundle(device_id).await?;
+                let labels = team.queries().device_label_assignments(device_id).await?;
+                let net_id = team.queries().aqc_net_identifier(device_id).await?;
+
+                for label in labels.iter() {
+                    self.known.labels.insert(label.id.to_string());
+                }
+                Output::DeviceInfo {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    role: format!("{role:?}"),
+                    key_bundle: (&key_bundle).into(),
+                    labels: labels.iter().map(label_output).collect(),
+                    aqc_net_id: net_id.map(|n| n.to_string()),
+                }
+            }
+            Commands::AddSyncPeer {
+                team_id,
+                peer_addr,
+                interval_secs,
+                ephemeral,
+                subscribe,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let interval_secs = interval_secs.unwrap_or(self.profile.sync_interval_secs());
+                let addr = Addr::from_str(&peer_addr)?;
+                let config = SyncPeerConfig::builder()
+                    .interval(Duration::from_secs(interval_secs))
+                    .ephemeral(ephemeral)
+                    .subscribe(subscribe)
+                    .build()?;
+
+                let mut team = self.client.team(team_id);
+                team.add_sync_peer(addr, config).await?;
+                Output::SyncPeerAdded {
+                    team_id: team_id.to_string(),
+                    peer_addr,
+                    interval_secs,
+                }
+            }
+            Commands::ListSyncPeers { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let peers = team.list_sync_peers().await?;
+                Output::SyncPeers {
+                    team_id: team_id.to_string(),
+                    peers: peers
+                        .iter()
+                        .map(|p| SyncPeerOutput {
+                            peer_addr: p.addr.to_string(),
+                            interval_secs: p.config.interval.as_secs(),
+                            ephemeral: p.config.ephemeral,
+                            subscribe: p.config.subscribe,
+                        })
+                        .collect(),
+                }
+            }
+            Commands::SyncStatus { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let status = team.sync_status().await?;
+                let unix_secs = |t: Option<SystemTime>| {
+                    t.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs())
+                };
+                Output::SyncStatus {
+                    team_id: team_id.to_string(),
+                    peers: status
+                        .iter()
+                        .map(|p| SyncStatusOutput {
+                            peer_addr: p.addr.to_string(),
+                            last_attempt: unix_secs(p.last_attempt),
+                            last_success: unix_secs(p.last_success),
+                            consecutive_failures: p.consecutive_failures,
+                            last_commands: p.last_commands,
+                            receiving: p.receiving,
+                            last_error: p.last_error.clone(),
+                        })
+                        .collect(),
+                }
+            }
+            Commands::RemoveSyncPeer { team_id, peer_addr } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let addr = Addr::from_str(&peer_addr)?;
+
+                let mut team = self.client.team(team_id);
+                team.remove_sync_peer(addr).await?;
+                Output::SyncPeerRemoved {
+                    team_id: team_id.to_string(),
+                    peer_addr,
+                }
+            }
+            Commands::SyncNow { team_id, peer_addr } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let addr = Addr::from_str(&peer_addr)?;
+
+                let mut team = self.client.team(team_id);
+                team.sync_now(addr, None).await?;
+                Output::Synced {
+                    team_id: team_id.to_string(),
+                    peer_addr,
+                }
+            }
+            Commands::CreateLabel {
+                team_id,
+                label_name,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let label_text: Text = label_name.clone().try_into()?;
+                let label_id = team.create_label(label_text).await?;
+                self.known.labels.insert(label_id.to_string());
+                Output::LabelCreated {
+                    team_id: team_id.to_string(),
+                    name: label_name,
+                    label_id: label_id.to_string(),
+                    label_id_hex: hex::encode(label_id.as_bytes()),
+                }
+            }
+            Commands::AssignLabel {
+                team_id,
+                device_id,
+                label_id,
+                operation,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let label_id = LabelId::from_str(&label_id)
+                    .map_err(|e| anyhow::anyhow!("Invalid label ID format: {}", e))?;
+
+                let op = parse_chan_op(&operation)?;
+
+                self.note(format_args!(
+                    "Attempting to assign label {} to device {} on team {} with operation {:?}",
+                    label_id, device_id, team_id, op
+                ));
+
+                let mut team = self.client.team(team_id);
+                team.assign_label(device_id, label_id, op).await?;
+                Output::LabelAssigned {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    label_id: label_id.to_string(),
+                    op: format!("{op:?}"),
+                }
+            }
+            Commands::AssignAqcNetId {
+                team_id,
+                device_id,
+                net_id,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let net_text: Text = net_id.clone().try_into()?;
+                let net_identifier = NetIdentifier(net_text);
+
+                let mut team = self.client.team(team_id);
+                team.assign_aqc_net_identifier(device_id, net_identifier)
+                    .await?;
+                Output::NetIdAssigned {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    net_id,
+                }
+            }
+            Commands::ListLabelAssignments { team_id, device_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+
+                let mut team = self.client.team(team_id);
+                let labels = team.queries().device_label_assignments(device_id).await?;
+
+                for label in labels.iter() {
+                    self.known.labels.insert(label.id.to_string());
+                }
+                Output::LabelAssignments {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    labels: labels.iter().map(label_output).collect(),
+                }
+            }
+            Commands::ListAqcAssignments { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let devices = team.queries().devices_on_team().await?;
+
+                let mut assignments = Vec::new();
+                for device_id in devices.iter() {
+                    self.known.devices.insert(device_id.to_string());
+                    if let Ok(Some(net_id)) = team.queries().aqc_net_identifier(*device_id).await {
+                        assignments.push(NetIdOutput {
+                            device_id: device_id.to_string(),
+                            net_id: net_id.to_string(),
+                        });
+                    }
+                }
+                Output::NetIdAssignments {
+                    team_id: team_id.to_string(),
+                    assignments,
+                }
+            }
+            Commands::SendData {
+                team_id,
+                device_id,
+                label_id,
+                message,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let label_id = LabelId::from_str(&label_id)?;
+
+                // Get the target device's network identifier
+                let mut team = self.client.team(team_id);
+                let net_id = team
+                    .queries()
+                    .aqc_net_identifier(device_id)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Device {} has no AQC network identifier assigned",
+                            device_id
+                        )
+                    })?;
+
+                // Create a new bidirectional channel (fresh PSKs)
+                let mut aqc = self.client.aqc();
+                let mut channel = aqc.create_bidi_channel(team_id, net_id, label_id).await?;
+
+                // Send data through the channel
+                let mut stream = channel.create_uni_stream().await?;
+                let message_bytes = Bytes::from(message.into_bytes());
+                stream.send(message_bytes).await?;
+                stream.close().await?;
+
+                // Close the channel to ensure PSKs are destroyed
+                aqc.delete_bidi_channel(channel).await?;
+
+                Output::DataSent {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    label_id: label_id.to_string(),
+                }
+            }
+            Commands::SendFile {
+                team_id,
+                device_id,
+                label_id,
+                path,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let label_id = LabelId::from_str(&label_id)?;
+
+                let mut team = self.client.team(team_id);
+                let net_id = team
+                    .queries()
+                    .aqc_net_identifier(device_id)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Device {} has no AQC network identifier assigned",
+                            device_id
+                        )
+                    })?;
+
+                let mut aqc = self.client.aqc();
+                let mut channel = aqc.create_bidi_channel(team_id, net_id, label_id).await?;
+                let stream = channel.create_bidi_stream().await?;
+                let result = aqc::send_file(stream, &path).await;
+                aqc.delete_bidi_channel(channel).await?;
+                let sent = result.with_context(|| format!("Failed to send {}", path.display()))?;
+
+                Output::FileSent {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    label_id: label_id.to_string(),
+                    file: file_output(sent),
+                }
+            }
+            Commands::RecvFile { dir, timeout } => {
+                self.note(format_args!(
+                    "Waiting for incoming file (timeout: {}s)...",
+                    timeout
+                ));
+
+                let timeout_duration = timeout_duration(timeout);
+                let mut aqc = self.client.aqc();
+                let received = tokio::time::timeout(timeout_duration, async {
+                    let channel = aqc.receive_channel().await?;
+                    let AqcPeerChannel::Bidi(mut channel) = channel else {
+                        anyhow::bail!("Expected a bidirectional channel");
+                    };
+                    let stream = channel
+                        .receive_stream()
+                        .await?
+                        .into_bidi()
+                        .map_err(|_| anyhow::anyhow!("Expected a bidirectional stream"))?;
+                    let result = aqc::receive_file(stream, &dir).await;
+                    aqc.delete_bidi_channel(channel).await?;
+                    Ok(result?)
+                })
+                .await;
+                match received {
+                    Ok(received) => Output::FileReceived {
+                        file: file_output(received?),
+                    },
+                    Err(_) => Output::Timeout {
+                        timeout: Waited::File,
+                    },
+                }
+            }
+            Commands::ListenData {
+                team_id,
+                device_id,
+                label_id,
+                timeout,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let label_id = LabelId::from_str(&label_id)?;
+
+                self.note(format_args!(
+                    "Listening for data from device {} with label {} on team {} (timeout: {}s)...",
+                    device_id, label_id, team_id, timeout
+                ));
+
+                // Create a new channel for receiving (fresh PSKs)
+                let mut aqc = self.client.aqc();
+                let mut channel = aqc.receive_channel().await?;
+
+                // Wait for data with timeout
+                let timeout_duration = timeout_duration(timeout);
+                let start = std::time::Instant::now();
+                let mut received_data = Vec::new();
+
+                while start.elapsed() < timeout_duration {
+                    let result = match channel {
+                        AqcPeerChannel::Bidi(ref mut bidi_channel) => {
+                            bidi_channel.try_receive_stream()
+                        }
+                        AqcPeerChannel::Receive(ref mut recv_channel) => recv_channel
+                            .try_receive_uni_stream()
+                            .map(AqcPeerStream::Receive),
+                    };
+                    match result {
+                        Ok(mut stream) => {
+                            while let Ok(Some(chunk)) = stream.receive().await {
+                                received_data.extend_from_slice(&chunk);
+                            }
+                            return Ok(Output::DataReceived {
+                                device_id: device_id.to_string(),
+                                label_id: label_id.to_string(),
+                                data: String::from_utf8(received_data)?,
+                            });
+                        }
+                        Err(TryReceiveError::Empty) => {
+                            // No data available, continue waiting
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                        Err(TryReceiveError::Closed) => {
+                            return Ok(Output::Closed {
+                                closed: Waited::Data,
+                            });
+                        }
+                        Err(TryReceiveError::Error(e)) => {
+                            return Err(anyhow::anyhow!("Error receiving data: {:?}", e));
+                        }
+                    }
+                }
+
+                Output::Timeout {
+                    timeout: Waited::Data,
+                }
+            }
+            Commands::ShowChannels { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                // AQC channels are ephemeral, so there is never anything to list.
+                Output::Channels {
+                    team_id: team_id.to_string(),
+                    channels: Vec::new(),
+                }
+            }
+            Commands::QueryDevicesOnTeam { team_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let devices = team.queries().devices_on_team().await?;
+
+                let devices: Vec<String> = devices.iter().map(ToString::to_string).collect();
+                self.known.devices.extend(devices.iter().cloned());
+                Output::TeamDevices {
+                    team_id: team_id.to_string(),
+                    total: devices.len(),
+                    devices,
+                }
+            }
+            Commands::QueryDeviceRole { team_id, device_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let mut team = self.client.team(team_id);
+                let role = team.queries().device_role(device_id).await?;
+
+                Output::DeviceRole {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    role: format!("{role:?}"),
+                }
+            }
+            Commands::QueryDeviceKeybundle { team_id, device_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let mut team = self.client.team(team_id);
+                let key_bundle = team.queries().device_keybundle(device_id).await?;
+
+                Output::DeviceKeyBundle {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    key_bundle: (&key_bundle).into(),
+                }
+            }
+            Commands::QueryAqcNetIdentifier { team_id, device_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let mut team = self.client.team(team_id);
+                let net_id = team.queries().aqc_net_identifier(device_id).await?;
+
+                Output::DeviceNetId {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    net_id: net_id.map(|n| n.to_string()),
+                }
+            }
+            Commands::RevokeLabel {
+                team_id,
+                device_id,
+                label_id,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let device_id = DeviceId::from_str(&device_id)?;
+                let label_id = LabelId::from_str(&label_id)?;
+
+                let mut team = self.client.team(team_id);
+                team.revoke_label(device_id, label_id).await?;
+                Output::LabelRevoked {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    label_id: label_id.to_string(),
+                }
+            }
+            Commands::DeleteLabel { team_id, label_id } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let label_id = LabelId::from_str(&label_id)?;
+
+                let mut team = self.client.team(team_id);
+                team.delete_label(label_id).await?;
+                self.known.labels.remove(&label_id.to_string());
+                Output::LabelDeleted {
+                    team_id: team_id.to_string(),
+                    label_id: label_id.to_string(),
+                }
+            }
+            Commands::CreateBidiChannel {
+                team_id,
+                net_id,
+                label_id,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let label_id = LabelId::from_str(&label_id)?;
+                let net_text: Text = net_id.clone().try_into()?;
+                let net_identifier = NetIdentifier(net_text);
+
+                let mut aqc = self.client.aqc();
+                let channel = aqc
+                    .create_bidi_channel(team_id, net_identifier, label_id)
+                    .await?;
+
+                // Store the channel in the registry
+                let channel_id = self.registry.store_channel(channel);
+                Output::ChannelCreated { channel_id }
+            }
+            Commands::ReceiveChannel { .. }
+            | Commands::ReceiveStream { .. }
+            | Commands::ReceiveStreamData { .. } => {
+                self.note_wait(&command)?;
+                let (timeout, waited) = command.waits().context("Command does not wait")?;
+                wait_for(timeout, waited, || std::future::ready(self.poll(&command))).await?
+            }
+            Commands::CreateBidiStream { channel_id } => {
+                let registry = &mut self.registry;
+
+                // Try to get the channel from the registry
+                let channel = registry.get_channel(&channel_id).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Channel with ID '{}' not found. Use create-bidi-channel first.",
+                        channel_id
+                    )
+                })?;
+
+                // Create a bidirectional stream on the channel
+                let stream = channel.create_bidi_stream().await?;
+
+                // Store the stream in the registry
+                let stream_id = registry.store_stream(stream);
+                Output::StreamCreated { stream_id }
+            }
+            Commands::SendStreamData { stream_id, data } => {
+                let registry = &mut self.registry;
+
+                // Try to get the stream from the registry (check both types)
+                if let Some(stream) = registry.get_stream(&stream_id) {
+                    // Send the data on the bidirectional stream
+                    stream.send(Bytes::from(data.clone())).await?;
+                    Output::StreamDataSent {
+                        stream_id,
+                        new_stream_id: None,
+                        data,
+                    }
+                } else if let Some(peer_stream) = registry.remove_peer_stream(&stream_id) {
+                    // Try to convert to bidirectional stream for sending
+                    let Ok(mut bidi_stream) = peer_stream.into_bidi() else {
+                        anyhow::bail!(
+                            "Cannot send data on receive-only stream. Use a bidirectional stream."
+                        );
+                    };
+                    bidi_stream.send(Bytes::from(data.clone())).await?;
+
+                    // Store the updated stream back
+                    let new_stream_id = registry.store_stream(bidi_stream);
+                    Output::StreamDataSent {
+                        stream_id,
+                        new_stream_id: Some(new_stream_id),
+                        data,
+                    }
+                } else {
+                    anyhow::bail!("Stream with ID '{}' not found. Use create-bidi-stream or receive-stream first.", stream_id);
+                }
+            }
+            Commands::GetKeyBundle => {
+                let key_bundle = self.client.get_key_bundle().await?;
+                Output::KeyBundle {
+                    key_bundle: (&key_bundle).into(),
+                }
+            }
+            Commands::GetDeviceId => {
+                let device_id = self.client.get_device_id().await?;
+                self.known.devices.insert(device_id.to_string());
+                Output::DeviceId {
+                    device_id: device_id.to_string(),
+                }
+            }
+            Commands::CreateClient { .. } => {
+                // Get device info from the connected daemon
+                let device_id = self.client.get_device_id().await?;
+                let key_bundle = KeyBundleOutput::from(&self.client.get_key_bundle().await?);
+                Output::ClientInfo {
+                    device_id: device_id.to_string(),
+                    identity_key: key_bundle.identity.clone(),
+                    signing_key: key_bundle.signing.clone(),
+                    encoding_key: key_bundle.encoding.clone(),
+                    key_bundle,
+                }
+            }
+            Commands::CreateTeamWithConfig {
+                seed_ikm,
+                sync_interval_secs,
+            } => {
+                let sync_interval_secs =
+                    sync_interval_secs.unwrap_or(self.profile.sync_interval_secs());
+                let ikm = hex::decode(&seed_ikm).context("Invalid hex for seed IKM")?;
+                if ikm.len() != 32 {
+                    anyhow::bail!("Seed IKM must be exactly 32 bytes");
+                }
+                let mut ikm_array = [0u8; 32];
+                ikm_array.copy_from_slice(&ikm);
+
+                let team_config = TeamConfig::builder()
+                    .quic_sync(QuicSyncConfig::builder().seed_ikm(ikm_array).build()?)
+                    .build()?;
+
+                let team = self
+                    .client
+                    .create_team(team_config)
+                    .await
+                    .context("Failed to create team")?;
+                let team_id = team.team_id().to_string();
+                self.known.teams.insert(team_id.clone());
+
+                Output::TeamCreated {
+                    team_id,
+                    seed_ikm,
+                    sync_interval_secs: Some(sync_interval_secs),
+                }
+            }
+            Commands::SetSyncConfig {
+                team_id,
+                interval_secs,
+                peer,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let mut team = self.client.team(team_id);
+                let peer = peer.as_deref().map(Addr::from_str).transpose()?;
+                let peers = team.list_sync_peers().await?;
+                // Keep the rest of each peer's config, such as whether it
+                // is ephemeral.
+                let peers: Vec<_> = peers
+                    .iter()
+                    .filter(|p| peer.is_none_or(|addr| p.addr == addr))
+                    .collect();
+                if let (Some(addr), []) = (peer, peers.as_slice()) {
+                    anyhow::bail!("{addr} is not a sync peer of team {team_id}");
+                }
+                for p in &peers {
+                    let mut config = SyncPeerConfig::builder()
+                        .interval(Duration::from_secs(interval_secs))
+                        .ephemeral(p.config.ephemeral)
+                        .subscribe(p.config.subscribe);
+                    if let Some(backoff) = &p.config.backoff {
+                        config = config.backoff(backoff.clone());
+                    }
+                    let config = config.build()?;
+                    team.update_sync_peer(p.addr, config).await?;
+                }
+                Output::SyncConfigSet {
+                    team_id: team_id.to_string(),
+                    interval_secs,
+                    peers: peers.iter().map(|p| p.addr.to_string()).collect(),
+                }
+            }
+            Commands::GetLabelIdBase58 { label_id_hex } => {
+                // Convert hex string to bytes
+                let label_bytes =
+                    hex::decode(&label_id_hex).context("Invalid hex string for label ID")?;
+
+                // Create LabelId from bytes
+                let label_id = LabelId::decode(&label_bytes).context("Invalid label ID bytes")?;
+
+                Output::LabelId {
+                    label_id: label_id.to_string(),
+                }
+            }
+            Commands::ListActiveChannels => {
+                let registry = &self.registry;
+                Output::ActiveChannels {
+                    channels: registry.list_channels(),
+                    streams: registry.list_streams(),
+                    peer_streams: registry.list_peer_streams(),
+                    received_channels: registry.list_received_channels(),
+                }
+            }
+            Commands::CloseChannel { channel_id } => {
+                let registry = &mut self.registry;
+                // Dropping the channel closes it.
+                let closed = registry.remove_channel(&channel_id).is_some()
+                    || registry.remove_received_channel(&channel_id).is_some();
+                if !closed {
+                    anyhow::bail!("Channel with ID '{}' not found.", channel_id);
+                }
+                Output::ChannelClosed { channel_id }
+            }
+            Commands::CloseStream { stream_id } => {
+                let registry = &mut self.registry;
+                let closed = registry.remove_stream(&stream_id).is_some()
+                    || registry.remove_peer_stream(&stream_id).is_some();
+                if !closed {
+                    anyhow::bail!("Stream with ID '{}' not found.", stream_id);
+                }
+                Output::StreamClosed { stream_id }
+            }
+            Commands::Shell { .. } => {
+                anyhow::bail!("Already in an interactive shell");
+            }
+            Commands::Exec { .. } => {
+                anyhow::bail!("Scripts cannot be run from a session");
+            }
+            Commands::Watch { .. } => {
+                anyhow::bail!("Watching events needs the process's stdout");
+            }
+            Commands::Agent { .. } => {
+                anyhow::bail!("Agent commands cannot be run in a session");
+            }
+            Commands::Aqc { .. } => {
+                anyhow::bail!("AQC pipes need the process's stdin and stdout");
+            }
+            Commands::Team { command } => self.run_team(command).await?,
+            Commands::Plan { file, prune } => self.run_manifest(&file, prune, false).await?,
+            Commands::Apply { file, prune } => self.run_manifest(&file, prune, true).await?,
+            Commands::Profile { command } => profile::run(command)?,
+        };
+
+        Ok(output)
+    }
+
+    /// Prints what a command that [waits][Commands::waits] is waiting
+    /// for, after checking that what it waits on exists.
+    fn note_wait(&mut self, command: &Commands) -> Result<()> {
+        match command {
+            Commands::ReceiveChannel { timeout } => {
+                self.note(format_args!(
+                    "Waiting for incoming channel (timeout: {}s)...",
+                    timeout
+                ));
+            }
+            Commands::ReceiveStream {
+                channel_id,
+                timeout,
+            } => {
+                self.note(format_args!(
+                    "Waiting for incoming stream on channel {} (timeout: {}s)...",
+                    channel_id, timeout
+                ));
+            }
+            Commands::ReceiveStreamData { stream_id, timeout } => {
+                let peer = if self.registry.get_stream(stream_id).is_some() {
+                    false
+                } else if self.registry.get_peer_stream(stream_id).is_some() {
+                    true
+                } else {
+                    anyhow::bail!("Stream with ID '{}' not found. Use create-bidi-stream or receive-stream first.", stream_id);
+                };
+                self.note(format_args!(
+                    "Waiting for data on {} stream {} (timeout: {}s)...",
+                    if peer { "peer" } else { "bidirectional" },
+                    stream_id,
+                    timeout
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Checks once, without waiting, whether what a command that
+    /// [waits][Commands::waits] is waiting for has arrived.
+    ///
+    /// Returns `None` if it has not arrived yet.
+    fn poll(&mut self, command: &Commands) -> Result<Option<Output>> {
+        let output = match command {
+            Commands::ReceiveChannel { .. } => match self.client.aqc().try_receive_channel() {
+                Ok(channel) => {
+                    // Store the received channel in the registry
+                    let channel_id = self.registry.store_received_channel(channel);
+                    Output::ChannelReceived { channel_id }
+                }
+                Err(TryReceiveError::Empty) => return Ok(None),
+                Err(TryReceiveError::Closed) => Output::Closed {
+                    closed: Waited::Channel,
+                },
+                Err(TryReceiveError::Error(e)) => {
+                    return Err(anyhow::anyhow!("Error receiving channel: {:?}", e));
+                }
+            },
+            Commands::ReceiveStream { channel_id, .. } => {
+                let registry = &mut self.registry;
+
+                // Try to get the received channel from the registry
+                let channel = registry.get_received_channel(channel_id).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Received channel with ID '{}' not found. Use receive-channel first.",
+                        channel_id
+                    )
+                })?;
+                let AqcPeerChannel::Bidi(bidi_channel) = channel else {
+                    anyhow::bail!("Cannot receive streams on a receive-only channel. Use a bidirectional channel.");
+                };
+                match bidi_channel.try_receive_stream() {
+                    Ok(stream) => {
+                        // Store the received stream in the registry
+                        let stream_id = registry.store_peer_stream(stream);
+                        Output::StreamReceived {
+                            channel_id: channel_id.clone(),
+                            stream_id,
+                        }
+                    }
+                    Err(TryReceiveError::Empty) => return Ok(None),
+                    Err(TryReceiveError::Closed) => Output::Closed {
+                        closed: Waited::Stream,
+                    },
+                    Err(TryReceiveError::Error(e)) => {
+                        return Err(anyhow::anyhow!("Error receiving stream: {:?}", e));
+                    }
+                }
+            }
+            Commands::ReceiveStreamData { stream_id, .. } => {
+                // The stream may have been closed since we started waiting.
+                let (peer, result) = if let Some(stream) = self.registry.get_stream(stream_id) {
+                    (false, Some(stream.try_receive()))
+                } else {
+                    let stream = self.registry.get_peer_stream(stream_id);
+                    (true, stream.map(|s| s.try_receive()))
+                };
+                match result {
+                    Some(Ok(data)) => Output::StreamDataReceived {
+                        stream_id: stream_id.clone(),
+                        peer,
+                        data: String::from_utf8_lossy(&data).into_owned(),
+                    },
+                    Some(Err(TryReceiveError::Empty)) => return Ok(None),
+                    Some(Err(TryReceiveError::Closed)) | None => Output::Closed {
+                        closed: Waited::Data,
+                    },
+                    Some(Err(TryReceiveError::Error(e))) => {
+                        return Err(anyhow::anyhow!("Error receiving data: {:?}", e));
+                    }
+                }
+            }
+            _ => anyhow::bail!("Command does not wait"),
+        };
+        Ok(Some(output))
+    }
+
+    /// Prints a progress message to stderr, unless the output is JSON.
+    fn note(&self, msg: std::fmt::Arguments<'_>) {
+        if self.format != OutputFormat::Json {
+            eprintln!("{msg}");
+        }
+    }
+}
+
+/// Runs `command` in a session shared with other tasks.
+///
+/// Commands that [wait][Commands::waits] for a peer only lock the
+/// session while checking whether the peer is there yet, so other
+/// commands can use it in the meantime.
+pub(crate) async fn run_shared(session: &Mutex<Session>, command: Commands) -> Result<Output> {
+    let Some((timeout, waited)) = command.waits() else {
+        return session.lock().await.run(command).await;
+    };
+    session.lock().await.note_wait(&command)?;
+    wait_for(timeout, waited, || async {
+        session.lock().await.poll(&command)
+    })
+    .await
+}
+
+/// Calls `poll` every 100ms until it returns an output or `timeout`
+/// seconds have passed.
+async fn wait_for<F>(timeout: u64, waited: Waited, mut poll: impl FnMut() -> F) -> Result<Output>
+where
+    F: Future<Output = Result<Option<Output>>>,
+{
+    let timeout_duration = timeout_duration(timeout);
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < timeout_duration {
+        if let Some(output) = poll().await? {
+            return Ok(output);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    Ok(Output::Timeout { timeout: waited })
+}
+
+fn timeout_duration(timeout: u64) -> Duration {
+    if timeout == 0 {
+        Duration::from_secs(u64::MAX)
+    } else {
+        Duration::from_secs(timeout)
+    }
+}
+
+pub(crate) fn parse_role(role: &str) -> Result<Role> {
+    Ok(match role {
+        "Owner" => Role::Owner,
+        "Admin" => Role::Admin,
+        "Operator" => Role::Operator,
+        "Member" => Role::Member,
+        _ => anyhow::bail!(
+            "Invalid role: {}. Use Owner, Admin, Operator, or Member",
+            role
+        ),
+    })
+}
+
+pub(crate) fn parse_chan_op(op: &str) -> Result<ChanOp> {
+    Ok(match op {
+        "SendOnly" => ChanOp::SendOnly,
+        "RecvOnly" => ChanOp::RecvOnly,
+        "SendRecv" => ChanOp::SendRecv,
+        _ => anyhow::bail!(
+            "Invalid operation: {}. Use SendOnly, RecvOnly, or SendRecv",
+            op
+        ),
+    })
+}
+
+fn file_output(transfer: FileTransfer) -> FileOutput {
+    FileOutput {
+        name: transfer.header.name,
+        path: transfer.path.display().to_string(),
+        size: transfer.header.size,
+        sha256: hex::encode(transfer.header.sha256),
+        resumed_from: transfer.offset,
+    }
+}
+
+fn label_output(label: &aranya_daemon_api::Label) -> LabelOutput {
+    LabelOutput {
+        label_id: label.id.to_string(),
+        name: label.name.to_string(),
+    }
+}
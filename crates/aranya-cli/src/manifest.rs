@@ -0,0 +1,725 @@
+//! Declarative team manifests.
+//!
+//! A manifest (YAML or TOML) lists a team's labels and devices, with each
+//! device's role, label assignments, and AQC network identifier. `plan`
+//! compares it against the team as the daemon sees it and prints the
+//! changes needed to make them match; `apply` makes those changes.
+//!
+//! ```yaml
+//! team_id: 7bLNDMRvqmKGawYwsYZYJq2EPsoUBZDbBWqy7NDHqiHD
+//! labels: [TELEMETRY]
+//! devices:
+//!   - key_bundle: member-a.json
+//!     role: Member
+//!     net_id: 127.0.0.1:5055
+//!     labels:
+//!       TELEMETRY: SendRecv
+//! ```
+//!
+//! Without `--prune`, nothing is ever removed. A device's role is only
+//! changed if the manifest gives one. A label assigned with a different
+//! op than the manifest's is revoked and assigned again.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use aranya_client::Team;
+use aranya_daemon_api::{ChanOp, DeviceId, KeyBundle, LabelId, NetIdentifier, Role, TeamId, Text};
+use serde::Deserialize;
+
+use crate::commands::{parse_chan_op, parse_role};
+use crate::output::{ChangeOutput, Output};
+use crate::session::Session;
+use crate::team::{device_id, read_key_bundle};
+
+/// A team manifest, as written by the user.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// Defaults to the profile's team.
+    team_id: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    devices: Vec<ManifestDevice>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestDevice {
+    /// File with the device's key bundle, relative to the manifest.
+    /// Needed to add the device to the team.
+    key_bundle: Option<PathBuf>,
+    /// The device's ID, for devices whose key bundle is not at hand
+    /// (such as the team's owner).
+    device_id: Option<String>,
+    role: Option<String>,
+    /// Label names and the channel operations allowed for them.
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    net_id: Option<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, which must end in `.yaml`, `.yml`,
+    /// or `.toml`.
+    fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        let manifest = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => serde_yml::from_str(&data)?,
+            Some("toml") => toml::from_str(&data)?,
+            _ => anyhow::bail!("manifest must be a .yaml, .yml, or .toml file"),
+        };
+        Ok(manifest)
+    }
+
+    /// Checks the manifest and reads the key bundles it refers to, which
+    /// are relative to `dir`.
+    fn resolve(self, dir: &Path) -> Result<Desired> {
+        let labels: BTreeSet<String> = self.labels.into_iter().collect();
+        let mut devices = Vec::new();
+        let mut seen = BTreeSet::new();
+        for (i, device) in self.devices.into_iter().enumerate() {
+            let device = resolve_device(device, dir, &labels)
+                .with_context(|| format!("invalid device #{}", i + 1))?;
+            if !seen.insert(device.device_id) {
+                anyhow::bail!("device {} is listed more than once", device.device_id);
+            }
+            devices.push(device);
+        }
+        Ok(Desired { labels, devices })
+    }
+}
+
+fn resolve_device(
+    device: ManifestDevice,
+    dir: &Path,
+    labels: &BTreeSet<String>,
+) -> Result<DesiredDevice> {
+    let keys = device
+        .key_bundle
+        .map(|path| read_key_bundle(&dir.join(path)))
+        .transpose()?;
+    let device_id = match (&keys, device.device_id) {
+        (Some(keys), Some(id)) => {
+            let computed = device_id(keys)?;
+            if computed.to_string() != id {
+                anyhow::bail!("device_id {id} does not match key bundle ({computed})");
+            }
+            computed
+        }
+        (Some(keys), None) => device_id(keys)?,
+        (None, Some(id)) => DeviceId::from_str(&id)?,
+        (None, None) => anyhow::bail!("either key_bundle or device_id is required"),
+    };
+    let role = device.role.as_deref().map(parse_role).transpose()?;
+    let device_labels = device
+        .labels
+        .into_iter()
+        .map(|(name, op)| {
+            if !labels.contains(&name) {
+                anyhow::bail!("label {name} is not listed under labels");
+            }
+            Ok((name, parse_chan_op(&op)?))
+        })
+        .collect::<Result<_>>()?;
+    // The policy only lets Members have a network identifier.
+    if device.net_id.is_some() && role.is_some_and(|r| r != Role::Member) {
+        anyhow::bail!("net_id requires role Member");
+    }
+    Ok(DesiredDevice {
+        device_id,
+        keys,
+        role,
+        labels: device_labels,
+        net_id: device.net_id,
+    })
+}
+
+/// What the manifest asks for.
+struct Desired {
+    labels: BTreeSet<String>,
+    devices: Vec<DesiredDevice>,
+}
+
+struct DesiredDevice {
+    device_id: DeviceId,
+    keys: Option<KeyBundle>,
+    role: Option<Role>,
+    labels: BTreeMap<String, ChanOp>,
+    net_id: Option<String>,
+}
+
+/// The team as the daemon sees it.
+#[derive(Default)]
+struct Current {
+    labels: BTreeMap<LabelId, String>,
+    devices: BTreeMap<DeviceId, CurrentDevice>,
+}
+
+struct CurrentDevice {
+    role: Role,
+    labels: BTreeMap<LabelId, ChanOp>,
+    net_id: Option<String>,
+}
+
+impl Current {
+    async fn query(team: &mut Team<'_>) -> Result<Self> {
+        let mut queries = team.queries();
+        let labels = queries
+            .labels()
+            .await?
+            .iter()
+            .map(|l| (l.id, l.name.to_string()))
+            .collect();
+        let mut devices = BTreeMap::new();
+        for device_id in queries.devices_on_team().await?.iter().copied() {
+            let role = queries.device_role(device_id).await?;
+            let mut labels = BTreeMap::new();
+            for label in queries.device_label_assignments(device_id).await?.iter() {
+                // The label may have been revoked since it was listed.
+                if let Some(op) = queries.device_label_op(device_id, label.id).await? {
+                    labels.insert(label.id, op);
+                }
+            }
+            let net_id = queries
+                .aqc_net_identifier(device_id)
+                .await?
+                .map(|n| n.0.to_string());
+            devices.insert(
+                device_id,
+                CurrentDevice {
+                    role,
+                    labels,
+                    net_id,
+                },
+            );
+        }
+        Ok(Self { labels, devices })
+    }
+
+    /// Returns the ID of the label called `name`, if there is one.
+    fn label_id(&self, name: &str) -> Option<LabelId> {
+        self.labels
+            .iter()
+            .find_map(|(id, n)| (n == name).then_some(*id))
+    }
+}
+
+/// A single team operation.
+enum Change {
+    CreateLabel {
+        name: String,
+    },
+    AddDevice {
+        device_id: DeviceId,
+        keys: KeyBundle,
+    },
+    RevokeLabel {
+        device_id: DeviceId,
+        label_id: LabelId,
+        name: String,
+    },
+    RemoveNetId {
+        device_id: DeviceId,
+        net_id: String,
+    },
+    RevokeRole {
+        device_id: DeviceId,
+        role: Role,
+    },
+    AssignRole {
+        device_id: DeviceId,
+        role: Role,
+    },
+    AssignLabel {
+        device_id: DeviceId,
+        name: String,
+        op: ChanOp,
+    },
+    SetNetId {
+        device_id: DeviceId,
+        net_id: String,
+    },
+    RemoveDevice {
+        device_id: DeviceId,
+    },
+    DeleteLabel {
+        label_id: LabelId,
+        name: String,
+    },
+}
+
+impl Change {
+    fn output(&self) -> ChangeOutput {
+        let (action, device_id, label, value) = match self {
+            Self::CreateLabel { name } => ("create_label", None, Some(name.clone()), None),
+            Self::AddDevice { device_id, .. } => ("add_device", Some(device_id), None, None),
+            Self::RevokeLabel {
+                device_id, name, ..
+            } => ("revoke_label", Some(device_id), Some(name.clone()), None),
+            Self::RemoveNetId { device_id, net_id } => {
+                ("remove_net_id", Some(device_id), None, Some(net_id.clone()))
+            }
+            Self::RevokeRole { device_id, role } => (
+                "revoke_role",
+                Some(device_id),
+                None,
+                Some(format!("{role:?}")),
+            ),
+            Self::AssignRole { device_id, role } => (
+                "assign_role",
+                Some(device_id),
+                None,
+                Some(format!("{role:?}")),
+            ),
+            Self::AssignLabel {
+                device_id,
+                name,
+                op,
+            } => (
+                "assign_label",
+                Some(device_id),
+                Some(name.clone()),
+                Some(format!("{op:?}")),
+            ),
+            Self::SetNetId { device_id, net_id } => {
+                ("set_net_id", Some(device_id), None, Some(net_id.clone()))
+            }
+            Self::RemoveDevice { device_id } => ("remove_device", Some(device_id), None, None),
+            Self::DeleteLabel { name, .. } => ("delete_label", None, Some(name.clone()), None),
+        };
+        ChangeOutput {
+            action: action.to_owned(),
+            device_id: device_id.map(ToString::to_string),
+            label,
+            value,
+        }
+    }
+
+    /// Makes the change. `labels` maps label names to IDs and is updated
+    /// as labels are created.
+    async fn apply(
+        &self,
+        team: &mut Team<'_>,
+        labels: &mut BTreeMap<String, LabelId>,
+    ) -> Result<()> {
+        match self {
+            Self::CreateLabel { name } => {
+                let id = team.create_label(Text::try_from(name.clone())?).await?;
+                labels.insert(name.clone(), id);
+            }
+            Self::AddDevice { keys, .. } => team.add_device_to_team(keys.clone()).await?,
+            Self::RevokeLabel {
+                device_id,
+                label_id,
+                ..
+            } => team.revoke_label(*device_id, *label_id).await?,
+            Self::RemoveNetId { device_id, net_id } => {
+                let net_id = NetIdentifier(Text::try_from(net_id.clone())?);
+                team.remove_aqc_net_identifier(*device_id, net_id).await?
+            }
+            Self::RevokeRole { device_id, role } => team.revoke_role(*device_id, *role).await?,
+            Self::AssignRole { device_id, role } => team.assign_role(*device_id, *role).await?,
+            Self::AssignLabel {
+                device_id,
+                name,
+                op,
+            } => {
+                let label_id = *labels
+                    .get(name)
+                    .with_context(|| format!("label {name} does not exist"))?;
+                team.assign_label(*device_id, label_id, *op).await?
+            }
+            Self::SetNetId { device_id, net_id } => {
+                let net_id = NetIdentifier(Text::try_from(net_id.clone())?);
+                team.assign_aqc_net_identifier(*device_id, net_id).await?
+            }
+            Self::RemoveDevice { device_id } => team.remove_device_from_team(*device_id).await?,
+            Self::DeleteLabel { label_id, .. } => team.delete_label(*label_id).await?,
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let out = self.output();
+        write!(f, "{}", out.action)?;
+        for field in [&out.device_id, &out.label, &out.value]
+            .into_iter()
+            .flatten()
+        {
+            write!(f, " {field}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the changes that make `current` match `desired`, in the order
+/// they must be made.
+///
+/// `local` is this daemon's device, which is never pruned.
+fn plan(desired: &Desired, current: &Current, prune: bool, local: DeviceId) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+
+    for name in &desired.labels {
+        if current.label_id(name).is_none() {
+            changes.push(Change::CreateLabel { name: name.clone() });
+        }
+    }
+
+    // Removals come before additions so that, e.g., a device loses its
+    // network identifier before it stops being a Member.
+    let mut additions = Vec::new();
+    for device in &desired.devices {
+        let device_id = device.device_id;
+        let Some(cur) = current.devices.get(&device_id) else {
+            let keys = device.keys.clone().with_context(|| {
+                format!("device {device_id} is not on the team and has no key_bundle")
+            })?;
+            changes.push(Change::AddDevice { device_id, keys });
+            if let Some(role) = device.role.filter(|r| *r != Role::Member) {
+                additions.push(Change::AssignRole { device_id, role });
+            }
+            for (name, op) in &device.labels {
+                additions.push(Change::AssignLabel {
+                    device_id,
+                    name: name.clone(),
+                    op: *op,
+                });
+            }
+            if let Some(net_id) = &device.net_id {
+                additions.push(Change::SetNetId {
+                    device_id,
+                    net_id: net_id.clone(),
+                });
+            }
+            continue;
+        };
+
+        for (label_id, op) in &cur.labels {
+            let name = current.labels.get(label_id).cloned().unwrap_or_default();
+            // A label can't be reassigned with a different op without
+            // revoking it first.
+            let stale = match device.labels.get(&name) {
+                Some(want) => want != op,
+                None => prune,
+            };
+            if stale {
+                changes.push(Change::RevokeLabel {
+                    device_id,
+                    label_id: *label_id,
+                    name,
+                });
+            }
+        }
+        if let Some(net_id) = &cur.net_id {
+            if prune && device.net_id.is_none() {
+                changes.push(Change::RemoveNetId {
+                    device_id,
+                    net_id: net_id.clone(),
+                });
+            }
+        }
+        if let Some(role) = device.role.filter(|r| *r != cur.role) {
+            // Devices go back to Member when their role is revoked.
+            if cur.role != Role::Member {
+                changes.push(Change::RevokeRole {
+                    device_id,
+                    role: cur.role,
+                });
+            }
+            if role != Role::Member {
+                additions.push(Change::AssignRole { device_id, role });
+            }
+        }
+        for (name, op) in &device.labels {
+            let assigned = current.label_id(name).and_then(|id| cur.labels.get(&id));
+            if assigned != Some(op) {
+                additions.push(Change::AssignLabel {
+                    device_id,
+                    name: name.clone(),
+                    op: *op,
+                });
+            }
+        }
+        if let Some(net_id) = &device.net_id {
+            if cur.net_id.as_ref() != Some(net_id) {
+                additions.push(Change::SetNetId {
+                    device_id,
+                    net_id: net_id.clone(),
+                });
+            }
+        }
+    }
+    changes.append(&mut additions);
+
+    if prune {
+        let listed: BTreeSet<_> = desired.devices.iter().map(|d| d.device_id).collect();
+        for (device_id, cur) in &current.devices {
+            if *device_id != local && !listed.contains(device_id) {
+                // Only Members can be removed.
+                if cur.role != Role::Member {
+                    changes.push(Change::RevokeRole {
+                        device_id: *device_id,
+                        role: cur.role,
+                    });
+                }
+                changes.push(Change::RemoveDevice {
+                    device_id: *device_id,
+                });
+            }
+        }
+        for (label_id, name) in &current.labels {
+            if !desired.labels.contains(name) {
+                changes.push(Change::DeleteLabel {
+                    label_id: *label_id,
+                    name: name.clone(),
+                });
+            }
+        }
+    }
+    Ok(changes)
+}
+
+impl Session {
+    /// Runs `plan` (`apply == false`) or `apply` for the manifest at
+    /// `file`.
+    pub(crate) async fn run_manifest(
+        &mut self,
+        file: &Path,
+        prune: bool,
+        apply: bool,
+    ) -> Result<Output> {
+        let manifest = Manifest::load(file)?;
+        let team_id = manifest
+            .team_id
+            .clone()
+            .or_else(|| self.profile.team_id.clone())
+            .context("manifest has no team_id and the profile has no team")?;
+        let team_id = TeamId::from_str(&team_id)?;
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let desired = manifest.resolve(dir)?;
+
+        let local = self.client.get_device_id().await?;
+        let mut team = self.client.team(team_id);
+        let current = Current::query(&mut team).await?;
+        let changes = plan(&desired, &current, prune, local)?;
+
+        if apply {
+            let mut labels = current
+                .labels
+                .iter()
+                .map(|(id, name)| (name.clone(), *id))
+                .collect();
+            for (i, change) in changes.iter().enumerate() {
+                change
+                    .apply(&mut team, &mut labels)
+                    .await
+                    .with_context(|| {
+                        format!("{change} failed after {i} of {} changes", changes.len())
+                    })?;
+            }
+        }
+
+        self.known.teams.insert(team_id.to_string());
+        let changes = changes.iter().map(Change::output).collect();
+        Ok(if apply {
+            Output::Applied {
+                team_id: team_id.to_string(),
+                changes,
+            }
+        } else {
+            Output::Plan {
+                team_id: team_id.to_string(),
+                changes,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+team_id: 7bLNDMRvqmKGawYwsYZYJq2EPsoUBZDbBWqy7NDHqiHD
+labels: [TELEMETRY, CONTROL]
+devices:
+  - device_id: "1"
+    role: Owner
+  - device_id: "2"
+    role: Member
+    net_id: 127.0.0.1:5055
+    labels:
+      TELEMETRY: SendRecv
+      CONTROL: RecvOnly
+"#;
+
+    const TOML: &str = r#"
+team_id = "7bLNDMRvqmKGawYwsYZYJq2EPsoUBZDbBWqy7NDHqiHD"
+labels = ["TELEMETRY", "CONTROL"]
+
+[[devices]]
+device_id = "1"
+role = "Owner"
+
+[[devices]]
+device_id = "2"
+role = "Member"
+net_id = "127.0.0.1:5055"
+labels = { TELEMETRY = "SendRecv", CONTROL = "RecvOnly" }
+"#;
+
+    fn id(s: &str) -> DeviceId {
+        DeviceId::from_str(s).unwrap()
+    }
+
+    fn actions(changes: &[Change]) -> Vec<String> {
+        changes.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_yaml_and_toml_agree() {
+        let yaml: Manifest = serde_yml::from_str(YAML).unwrap();
+        let toml: Manifest = toml::from_str(TOML).unwrap();
+        assert_eq!(format!("{yaml:?}"), format!("{toml:?}"));
+
+        let desired = yaml.resolve(Path::new(".")).unwrap();
+        assert_eq!(desired.devices.len(), 2);
+        assert_eq!(desired.devices[1].device_id, id("2"));
+        assert_eq!(desired.devices[1].labels.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_rejects_bad_manifests() {
+        let cases = [
+            // Label not listed under labels.
+            "devices: [{device_id: '2', labels: {OTHER: SendRecv}}]",
+            // Network identifiers are only for Members.
+            "devices: [{device_id: '2', role: Admin, net_id: '127.0.0.1:1'}]",
+            // No way to identify the device.
+            "devices: [{role: Admin}]",
+            "devices: [{device_id: '2'}, {device_id: '2'}]",
+            "devices: [{device_id: '2', rol: Admin}]",
+        ];
+        for case in cases {
+            let manifest: Result<Manifest, _> = serde_yml::from_str(case);
+            let resolved = manifest
+                .map_err(anyhow::Error::from)
+                .and_then(|m| m.resolve(Path::new(".")));
+            assert!(resolved.is_err(), "{case}");
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let desired = serde_yml::from_str::<Manifest>(YAML)
+            .unwrap()
+            .resolve(Path::new("."))
+            .unwrap();
+        let telemetry = LabelId::from_str("1").unwrap();
+        let old = LabelId::from_str("2").unwrap();
+        let current = Current {
+            labels: BTreeMap::from([(telemetry, "TELEMETRY".into()), (old, "OLD".into())]),
+            devices: BTreeMap::from([
+                (
+                    id("1"),
+                    CurrentDevice {
+                        role: Role::Owner,
+                        labels: BTreeMap::new(),
+                        net_id: None,
+                    },
+                ),
+                (
+                    id("2"),
+                    CurrentDevice {
+                        role: Role::Operator,
+                        labels: BTreeMap::from([
+                            (telemetry, ChanOp::SendRecv),
+                            (old, ChanOp::SendRecv),
+                        ]),
+                        net_id: None,
+                    },
+                ),
+                (
+                    id("3"),
+                    CurrentDevice {
+                        role: Role::Member,
+                        labels: BTreeMap::new(),
+                        net_id: Some("127.0.0.1:1".into()),
+                    },
+                ),
+                (
+                    id("4"),
+                    CurrentDevice {
+                        role: Role::Operator,
+                        labels: BTreeMap::new(),
+                        net_id: None,
+                    },
+                ),
+            ]),
+        };
+
+        let changes = plan(&desired, &current, false, id("1")).unwrap();
+        assert_eq!(
+            actions(&changes),
+            [
+                "create_label CONTROL",
+                format!("revoke_role {} Operator", id("2")).as_str(),
+                &format!("assign_label {} CONTROL RecvOnly", id("2")),
+                &format!("set_net_id {} 127.0.0.1:5055", id("2")),
+            ]
+        );
+
+        let changes = plan(&desired, &current, true, id("1")).unwrap();
+        assert_eq!(
+            actions(&changes),
+            [
+                "create_label CONTROL",
+                format!("revoke_label {} OLD", id("2")).as_str(),
+                &format!("revoke_role {} Operator", id("2")),
+                &format!("assign_label {} CONTROL RecvOnly", id("2")),
+                &format!("set_net_id {} 127.0.0.1:5055", id("2")),
+                &format!("remove_device {}", id("3")),
+                &format!("revoke_role {} Operator", id("4")),
+                &format!("remove_device {}", id("4")),
+                "delete_label OLD",
+            ]
+        );
+
+        // A label assigned with the wrong op is revoked and reassigned.
+        let mut current = current;
+        let dev = current.devices.get_mut(&id("2")).unwrap();
+        dev.labels.insert(telemetry, ChanOp::RecvOnly);
+        let changes = plan(&desired, &current, false, id("1")).unwrap();
+        assert_eq!(
+            actions(&changes),
+            [
+                "create_label CONTROL",
+                format!("revoke_label {} TELEMETRY", id("2")).as_str(),
+                &format!("revoke_role {} Operator", id("2")),
+                &format!("assign_label {} CONTROL RecvOnly", id("2")),
+                &format!("assign_label {} TELEMETRY SendRecv", id("2")),
+                &format!("set_net_id {} 127.0.0.1:5055", id("2")),
+            ]
+        );
+
+        // Nothing to do once the team matches.
+        let control = LabelId::from_str("3").unwrap();
+        current.labels.remove(&old);
+        current.labels.insert(control, "CONTROL".into());
+        current.devices.remove(&id("3"));
+        current.devices.remove(&id("4"));
+        let dev = current.devices.get_mut(&id("2")).unwrap();
+        dev.role = Role::Member;
+        dev.labels = BTreeMap::from([(telemetry, ChanOp::SendRecv), (control, ChanOp::RecvOnly)]);
+        dev.net_id = Some("127.0.0.1:5055".into());
+        assert!(plan(&desired, &current, true, id("1")).unwrap().is_empty());
+    }
+}
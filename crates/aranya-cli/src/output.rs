@@ -0,0 +1,1290 @@
+//! Structured command results and how they are rendered.
+//!
+//! Every command returns an [`Output`]. `--output text` (the default)
+//! keeps the historical human-readable format, `--output json` prints the
+//! result as JSON, and `--output table` prints it as aligned columns.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aranya_daemon_api::KeyBundle;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::profile::{CliConfig, Profile};
+
+/// The format that results and errors are printed in.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON
+    Json,
+    /// Aligned columns
+    Table,
+}
+
+/// Hex-encoded device keys.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct KeyBundleOutput {
+    pub identity: String,
+    pub signing: String,
+    pub encoding: String,
+}
+
+impl From<&KeyBundle> for KeyBundleOutput {
+    fn from(kb: &KeyBundle) -> Self {
+        Self {
+            identity: hex::encode(&kb.identity),
+            signing: hex::encode(&kb.signing),
+            encoding: hex::encode(&kb.encoding),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct DeviceRoleOutput {
+    pub device_id: String,
+    pub role: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct LabelOutput {
+    pub label_id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct NetIdOutput {
+    pub device_id: String,
+    pub net_id: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct SyncPeerOutput {
+    pub peer_addr: String,
+    pub interval_secs: u64,
+    pub ephemeral: bool,
+    pub subscribe: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct SyncStatusOutput {
+    pub peer_addr: String,
+    /// Seconds since the Unix epoch.
+    pub last_attempt: Option<u64>,
+    /// Seconds since the Unix epoch.
+    pub last_success: Option<u64>,
+    pub consecutive_failures: u32,
+    pub last_commands: u64,
+    /// Commands received so far by a sync that is running.
+    pub receiving: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct FileOutput {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Bytes the receiver already had from an earlier transfer.
+    pub resumed_from: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ProfileOutput {
+    pub profile: String,
+    pub current: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uds_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aqc_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_interval_secs: Option<u64>,
+}
+
+impl ProfileOutput {
+    pub(crate) fn new(name: String, profile: &Profile, config: &CliConfig) -> Self {
+        Self {
+            current: config.current.as_ref() == Some(&name),
+            profile: name,
+            uds_path: profile.uds_path.as_ref().map(|p| p.display().to_string()),
+            aqc_addr: profile.aqc_addr.clone(),
+            team_id: profile.team_id.clone(),
+            sync_interval_secs: profile.sync_interval_secs,
+        }
+    }
+}
+
+/// A change made (or to be made) by `plan`/`apply`.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ChangeOutput {
+    /// E.g. `add_device` or `assign_label`.
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    /// The label's name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The role, channel operation, or network identifier.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// The result of a command.
+///
+/// In JSON, the `type` field names the variant, e.g. `device_added`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Output {
+    TeamCreated {
+        team_id: String,
+        seed_ikm: String,
+        /// Only set by `create-team-with-config`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sync_interval_secs: Option<u64>,
+    },
+    TeamAdded {
+        team_id: String,
+    },
+    TeamRemoved {
+        team_id: String,
+    },
+    DeviceAdded {
+        team_id: String,
+        device_id: String,
+    },
+    DeviceRemoved {
+        team_id: String,
+        device_id: String,
+    },
+    RoleAssigned {
+        team_id: String,
+        device_id: String,
+        role: String,
+    },
+    /// Devices and their roles.
+    DeviceRoles {
+        team_id: String,
+        devices: Vec<DeviceRoleOutput>,
+    },
+    /// Device IDs only.
+    TeamDevices {
+        team_id: String,
+        total: usize,
+        devices: Vec<String>,
+    },
+    DeviceInfo {
+        team_id: String,
+        device_id: String,
+        role: String,
+        key_bundle: KeyBundleOutput,
+        labels: Vec<LabelOutput>,
+        aqc_net_id: Option<String>,
+    },
+    SyncPeerAdded {
+        team_id: String,
+        peer_addr: String,
+        interval_secs: u64,
+    },
+    SyncPeers {
+        team_id: String,
+        peers: Vec<SyncPeerOutput>,
+    },
+    SyncPeerRemoved {
+        team_id: String,
+        peer_addr: String,
+    },
+    SyncStatus {
+        team_id: String,
+        peers: Vec<SyncStatusOutput>,
+    },
+    Synced {
+        team_id: String,
+        peer_addr: String,
+    },
+    LabelCreated {
+        team_id: String,
+        name: String,
+        label_id: String,
+        label_id_hex: String,
+    },
+    LabelAssigned {
+        team_id: String,
+        device_id: String,
+        label_id: String,
+        op: String,
+    },
+    NetIdAssigned {
+        team_id: String,
+        device_id: String,
+        net_id: String,
+    },
+    LabelAssignments {
+        team_id: String,
+        device_id: String,
+        labels: Vec<LabelOutput>,
+    },
+    NetIdAssignments {
+        team_id: String,
+        assignments: Vec<NetIdOutput>,
+    },
+    DataSent {
+        team_id: String,
+        device_id: String,
+        label_id: String,
+    },
+    FileSent {
+        team_id: String,
+        device_id: String,
+        label_id: String,
+        file: FileOutput,
+    },
+    FileReceived {
+        file: FileOutput,
+    },
+    DataReceived {
+        device_id: String,
+        label_id: String,
+        data: String,
+    },
+    Channels {
+        team_id: String,
+        channels: Vec<String>,
+    },
+    DeviceRole {
+        team_id: String,
+        device_id: String,
+        role: String,
+    },
+    DeviceKeyBundle {
+        team_id: String,
+        device_id: String,
+        key_bundle: KeyBundleOutput,
+    },
+    DeviceNetId {
+        team_id: String,
+        device_id: String,
+        net_id: Option<String>,
+    },
+    LabelRevoked {
+        team_id: String,
+        device_id: String,
+        label_id: String,
+    },
+    LabelDeleted {
+        team_id: String,
+        label_id: String,
+    },
+    ChannelCreated {
+        channel_id: String,
+    },
+    ChannelReceived {
+        channel_id: String,
+    },
+    StreamCreated {
+        stream_id: String,
+    },
+    StreamReceived {
+        channel_id: String,
+        stream_id: String,
+    },
+    StreamDataSent {
+        stream_id: String,
+        /// Set when a peer stream was converted to a bidirectional stream.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        new_stream_id: Option<String>,
+        data: String,
+    },
+    StreamDataReceived {
+        stream_id: String,
+        /// Whether the stream was opened locally or by the peer.
+        #[serde(skip)]
+        peer: bool,
+        data: String,
+    },
+    KeyBundle {
+        key_bundle: KeyBundleOutput,
+    },
+    DeviceId {
+        device_id: String,
+    },
+    ClientInfo {
+        device_id: String,
+        identity_key: String,
+        signing_key: String,
+        encoding_key: String,
+        key_bundle: KeyBundleOutput,
+    },
+    SyncConfigSet {
+        team_id: String,
+        interval_secs: u64,
+        peers: Vec<String>,
+    },
+    LabelId {
+        label_id: String,
+    },
+    ActiveChannels {
+        channels: Vec<String>,
+        streams: Vec<String>,
+        peer_streams: Vec<String>,
+        received_channels: Vec<String>,
+    },
+    ChannelClosed {
+        channel_id: String,
+    },
+    StreamClosed {
+        stream_id: String,
+    },
+    /// Nothing arrived before the timeout.
+    Timeout {
+        timeout: Waited,
+    },
+    /// The channel or stream closed before anything arrived.
+    Closed {
+        closed: Waited,
+    },
+    AgentStarted {
+        socket: String,
+        already_running: bool,
+    },
+    AgentStatus {
+        running: bool,
+        socket: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pid: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        uds_path: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channels: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        streams: Option<usize>,
+    },
+    AgentStopped {
+        was_running: bool,
+    },
+    Invitation {
+        team_id: String,
+        device_id: String,
+        key_bundle: KeyBundleOutput,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        role: Option<String>,
+        labels: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        net_id: Option<String>,
+        sync_addr: String,
+        token: String,
+    },
+    TeamJoined {
+        team_id: String,
+        sync_peer: String,
+        interval_secs: u64,
+    },
+    Plan {
+        team_id: String,
+        changes: Vec<ChangeOutput>,
+    },
+    Applied {
+        team_id: String,
+        changes: Vec<ChangeOutput>,
+    },
+    ProfileSaved(ProfileOutput),
+    Profiles {
+        profiles: Vec<ProfileOutput>,
+    },
+    ProfileSelected {
+        profile: String,
+    },
+}
+
+/// Exit code for a command that gave up waiting because nothing arrived
+/// before its timeout.
+pub(crate) const EXIT_TIMEOUT: u8 = 4;
+
+/// Exit code for a command that gave up waiting because the channel or
+/// stream it waited on was closed.
+pub(crate) const EXIT_CLOSED: u8 = 5;
+
+/// What a command was waiting for when it timed out or was closed.
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Waited {
+    Channel,
+    Stream,
+    Data,
+    File,
+}
+
+impl Output {
+    /// Returns the exit code for the result, which is non-zero if the
+    /// command gave up waiting.
+    pub(crate) fn exit_code(&self) -> u8 {
+        match self {
+            Self::Timeout { .. } => EXIT_TIMEOUT,
+            Self::Closed { .. } => EXIT_CLOSED,
+            _ => 0,
+        }
+    }
+
+    /// Writes the result to `w` in `format`.
+    pub(crate) fn render(&self, format: OutputFormat, w: &mut dyn Write) -> io::Result<()> {
+        match format {
+            OutputFormat::Text => self.write_text(w),
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut *w, self)?;
+                writeln!(w)
+            }
+            OutputFormat::Table => {
+                let value = serde_json::to_value(self)?;
+                write_table(&value, w)
+            }
+        }
+    }
+
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::TeamCreated {
+                team_id,
+                seed_ikm,
+                sync_interval_secs: None,
+            } => {
+                writeln!(w, "Team created: {team_id}")?;
+                writeln!(w, "Seed IKM: {seed_ikm}")
+            }
+            Self::TeamCreated {
+                team_id,
+                seed_ikm,
+                sync_interval_secs: Some(interval),
+            } => {
+                writeln!(w, "Team created with custom configuration: {team_id}")?;
+                writeln!(w, "Seed IKM: {seed_ikm}")?;
+                writeln!(w, "Sync interval: {interval}s")
+            }
+            Self::TeamAdded { team_id } => writeln!(w, "Team added: {team_id}"),
+            Self::TeamRemoved { team_id } => writeln!(w, "Team removed: {team_id}"),
+            Self::DeviceAdded { team_id, device_id } => {
+                writeln!(w, "Device {device_id} added to team {team_id}")
+            }
+            Self::DeviceRemoved { team_id, device_id } => {
+                writeln!(w, "Device {device_id} removed from team {team_id}")
+            }
+            Self::RoleAssigned {
+                team_id,
+                device_id,
+                role,
+            } => writeln!(w, "Role {role} assigned to device {device_id} on team {team_id}"),
+            Self::DeviceRoles { team_id, devices } => {
+                writeln!(w, "Devices on team {team_id}:")?;
+                for d in devices {
+                    writeln!(w, "  {} (Role: {})", d.device_id, d.role)?;
+                }
+                Ok(())
+            }
+            Self::TeamDevices {
+                team_id,
+                total,
+                devices,
+            } => {
+                writeln!(w, "Devices on team {team_id}:")?;
+                writeln!(w, "Total devices: {total}")?;
+                for d in devices {
+                    writeln!(w, "  {d}")?;
+                }
+                Ok(())
+            }
+            Self::DeviceInfo {
+                team_id,
+                device_id,
+                role,
+                key_bundle,
+                labels,
+                aqc_net_id,
+            } => {
+                writeln!(w, "Device Info for {device_id} on team {team_id}:")?;
+                writeln!(w, "  Role: {role}")?;
+                write_key_bundle(w, "  ", key_bundle)?;
+                writeln!(w, "  Labels assigned: {}", labels.len())?;
+                for label in labels {
+                    writeln!(w, "    {} ({})", label.label_id, label.name)?;
+                }
+                match aqc_net_id {
+                    Some(net_id) => writeln!(w, "  AQC Network ID: {net_id}"),
+                    None => writeln!(w, "  AQC Network ID: Not assigned"),
+                }
+            }
+            Self::SyncPeerAdded {
+                team_id,
+                peer_addr,
+                interval_secs,
+            } => writeln!(
+                w,
+                "Sync peer {peer_addr} added to team {team_id} with interval {interval_secs}s"
+            ),
+            Self::SyncPeers { team_id, peers } => {
+                writeln!(w, "Sync peers for team {team_id}:")?;
+                if peers.is_empty() {
+                    writeln!(w, "  No sync peers")?;
+                }
+                for p in peers {
+                    let ephemeral = if p.ephemeral { ", ephemeral" } else { "" };
+                    let subscribe = if p.subscribe { ", subscribed" } else { "" };
+                    writeln!(
+                        w,
+                        "  {} (every {}s{ephemeral}{subscribe})",
+                        p.peer_addr, p.interval_secs
+                    )?;
+                }
+                Ok(())
+            }
+            Self::SyncPeerRemoved { team_id, peer_addr } => {
+                writeln!(w, "Sync peer {peer_addr} removed from team {team_id}")
+            }
+            Self::SyncStatus { team_id, peers } => {
+                writeln!(w, "Sync status for team {team_id}:")?;
+                if peers.is_empty() {
+                    return writeln!(w, "  No sync peers");
+                }
+                let headers = [
+                    "PEER",
+                    "LAST ATTEMPT",
+                    "LAST SUCCESS",
+                    "FAILURES",
+                    "COMMANDS",
+                    "RECEIVING",
+                    "LAST ERROR",
+                ]
+                .map(String::from);
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                let rows: Vec<_> = peers
+                    .iter()
+                    .map(|p| {
+                        vec![
+                            p.peer_addr.clone(),
+                            ago(now, p.last_attempt),
+                            ago(now, p.last_success),
+                            p.consecutive_failures.to_string(),
+                            p.last_commands.to_string(),
+                            p.receiving.map_or_else(|| "-".to_owned(), |n| n.to_string()),
+                            p.last_error.clone().unwrap_or_else(|| "-".to_owned()),
+                        ]
+                    })
+                    .collect();
+                write_rows(w, &headers, &rows)
+            }
+            Self::Synced { team_id, peer_addr } => {
+                writeln!(w, "Sync completed with peer {peer_addr} on team {team_id}")
+            }
+            Self::LabelCreated {
+                name,
+                label_id,
+                label_id_hex,
+                ..
+            } => {
+                writeln!(w, "Label '{name}' created successfully")?;
+                writeln!(w, "Label ID (base58): {label_id}")?;
+                writeln!(w, "Label ID (hex):    {label_id_hex}")
+            }
+            Self::LabelAssigned {
+                device_id,
+                label_id,
+                op,
+                ..
+            } => writeln!(
+                w,
+                "Label {label_id} assigned to device {device_id} with operation {op}"
+            ),
+            Self::NetIdAssigned {
+                device_id, net_id, ..
+            } => writeln!(
+                w,
+                "AQC network identifier {net_id} assigned to device {device_id}"
+            ),
+            Self::LabelAssignments {
+                team_id,
+                device_id,
+                labels,
+            } => {
+                writeln!(
+                    w,
+                    "Label assignments for device {device_id} on team {team_id}:"
+                )?;
+                for label in labels {
+                    writeln!(w, "  {} ({})", label.label_id, label.name)?;
+                }
+                Ok(())
+            }
+            Self::NetIdAssignments {
+                team_id,
+                assignments,
+            } => {
+                writeln!(w, "AQC network assignments for team {team_id}:")?;
+                for a in assignments {
+                    writeln!(w, "  {}: {}", a.device_id, a.net_id)?;
+                }
+                Ok(())
+            }
+            Self::DataSent {
+                device_id,
+                label_id,
+                ..
+            } => writeln!(
+                w,
+                "Data sent to device {device_id} with label {label_id} (Channel closed, PSKs destroyed)"
+            ),
+            Self::FileSent {
+                device_id,
+                file,
+                ..
+            } => {
+                writeln!(w, "File sent to device {device_id}: {}", file.name)?;
+                write_file(w, file)
+            }
+            Self::FileReceived { file } => {
+                writeln!(w, "File received: {}", file.path)?;
+                write_file(w, file)
+            }
+            Self::DataReceived {
+                device_id,
+                label_id,
+                data,
+            } => writeln!(
+                w,
+                "Data received from device {device_id} with label {label_id}: {data}"
+            ),
+            Self::Channels { .. } => {
+                writeln!(w, "Note: AQC channels are ephemeral and automatically closed after use for PSK rotation")?;
+                writeln!(w, "Active channels cannot be listed as they are created fresh for each communication")?;
+                writeln!(w, "This ensures perfect forward secrecy through PSK rotation")
+            }
+            Self::DeviceRole {
+                team_id,
+                device_id,
+                role,
+            } => writeln!(w, "Device {device_id} role on team {team_id}: {role}"),
+            Self::DeviceKeyBundle {
+                team_id,
+                device_id,
+                key_bundle,
+            } => {
+                writeln!(w, "Device {device_id} keybundle on team {team_id}:")?;
+                write_key_bundle(w, "  ", key_bundle)
+            }
+            Self::DeviceNetId {
+                team_id,
+                device_id,
+                net_id: Some(net_id),
+            } => writeln!(
+                w,
+                "Device {device_id} AQC network identifier on team {team_id}: {net_id}"
+            ),
+            Self::DeviceNetId {
+                team_id,
+                device_id,
+                net_id: None,
+            } => writeln!(
+                w,
+                "Device {device_id} has no AQC network identifier assigned on team {team_id}"
+            ),
+            Self::LabelRevoked {
+                team_id,
+                device_id,
+                label_id,
+            } => writeln!(
+                w,
+                "Label {label_id} revoked from device {device_id} on team {team_id}"
+            ),
+            Self::LabelDeleted { team_id, label_id } => {
+                writeln!(w, "Label {label_id} deleted from team {team_id}")
+            }
+            Self::ChannelCreated { channel_id } => {
+                writeln!(w, "Bidirectional channel created successfully")?;
+                writeln!(w, "Channel ID: {channel_id}")?;
+                writeln!(w, "Use this channel ID with create-bidi-stream command")
+            }
+            Self::ChannelReceived { channel_id } => {
+                writeln!(w, "Channel received successfully")?;
+                writeln!(w, "Channel ID: {channel_id}")?;
+                writeln!(w, "Use this channel ID with receive-stream command")
+            }
+            Self::StreamCreated { stream_id } => {
+                writeln!(w, "Bidirectional stream created successfully")?;
+                writeln!(w, "Stream ID: {stream_id}")?;
+                writeln!(
+                    w,
+                    "Use this stream ID with send-stream-data and receive-stream-data commands"
+                )
+            }
+            Self::StreamReceived { stream_id, .. } => {
+                writeln!(w, "Stream received successfully")?;
+                writeln!(w, "Stream ID: {stream_id}")?;
+                writeln!(
+                    w,
+                    "Use this stream ID with send-stream-data and receive-stream-data commands"
+                )
+            }
+            Self::StreamDataSent {
+                stream_id,
+                new_stream_id: None,
+                data,
+            } => {
+                writeln!(
+                    w,
+                    "Data sent successfully on bidirectional stream {stream_id}"
+                )?;
+                writeln!(w, "Sent: {data}")
+            }
+            Self::StreamDataSent {
+                stream_id,
+                new_stream_id: Some(new_stream_id),
+                data,
+            } => {
+                writeln!(
+                    w,
+                    "Data sent successfully on peer stream {stream_id} (converted to bidi)"
+                )?;
+                writeln!(w, "New stream ID: {new_stream_id}")?;
+                writeln!(w, "Sent: {data}")
+            }
+            Self::StreamDataReceived {
+                stream_id,
+                peer,
+                data,
+            } => {
+                let kind = if *peer { "peer" } else { "bidirectional" };
+                writeln!(w, "Data received successfully on {kind} stream {stream_id}")?;
+                writeln!(w, "Received: {data}")
+            }
+            Self::KeyBundle { key_bundle } => {
+                writeln!(w, "Device key bundle:")?;
+                write_key_bundle(w, "  ", key_bundle)
+            }
+            Self::DeviceId { device_id } => writeln!(w, "Device ID: {device_id}"),
+            Self::ClientInfo {
+                device_id,
+                key_bundle,
+                ..
+            } => {
+                writeln!(w, "Client Info from Daemon:")?;
+                writeln!(w, "Device ID: {device_id}")?;
+                write_key_bundle(w, "", key_bundle)?;
+                writeln!(w)?;
+                writeln!(w, "Key Bundle (for adding to teams):")?;
+                writeln!(w, "Identity: {}", key_bundle.identity)?;
+                writeln!(w, "Signing: {}", key_bundle.signing)?;
+                writeln!(w, "Encoding: {}", key_bundle.encoding)
+            }
+            Self::SyncConfigSet {
+                team_id,
+                interval_secs,
+                peers,
+            } => {
+                if peers.is_empty() {
+                    return writeln!(w, "Team {team_id} has no sync peers");
+                }
+                writeln!(
+                    w,
+                    "Sync configuration updated for team {team_id}: {interval_secs}s interval"
+                )?;
+                for peer in peers {
+                    writeln!(w, "  {peer}")?;
+                }
+                Ok(())
+            }
+            Self::LabelId { label_id } => writeln!(w, "{label_id}"),
+            Self::ActiveChannels {
+                channels,
+                streams,
+                peer_streams,
+                received_channels,
+            } => {
+                for (title, ids) in [
+                    ("Active Channels:", channels),
+                    ("Active Bidirectional Streams:", streams),
+                    ("Active Peer Streams:", peer_streams),
+                    ("Received Channels:", received_channels),
+                ] {
+                    writeln!(w, "{title}")?;
+                    for id in ids {
+                        writeln!(w, "  {id}")?;
+                    }
+                }
+                Ok(())
+            }
+            Self::ChannelClosed { channel_id } => writeln!(w, "Channel {channel_id} closed."),
+            Self::StreamClosed { stream_id } => writeln!(w, "Stream {stream_id} closed."),
+            Self::Timeout { timeout } => {
+                let what = match timeout {
+                    Waited::Channel => "channel",
+                    Waited::Stream => "stream",
+                    Waited::Data => "data",
+                    Waited::File => "file",
+                };
+                writeln!(w, "Timeout reached, no {what} received")
+            }
+            Self::Closed { closed } => match closed {
+                Waited::Channel => writeln!(w, "Channel closed while waiting"),
+                Waited::Stream => writeln!(w, "Channel closed while waiting for stream"),
+                Waited::Data | Waited::File => {
+                    writeln!(w, "Stream closed while waiting for data")
+                }
+            },
+            Self::AgentStarted {
+                socket,
+                already_running,
+            } => {
+                if *already_running {
+                    writeln!(w, "Agent already running at {socket}")
+                } else {
+                    writeln!(w, "Agent started at {socket}")
+                }
+            }
+            Self::AgentStatus {
+                running: false, ..
+            } => writeln!(w, "Agent not running"),
+            Self::AgentStatus {
+                socket,
+                pid,
+                uds_path,
+                channels,
+                streams,
+                ..
+            } => {
+                writeln!(w, "Agent running (pid {})", pid.unwrap_or_default())?;
+                writeln!(w, "  Socket: {socket}")?;
+                writeln!(w, "  Daemon UDS path: {}", uds_path.as_deref().unwrap_or_default())?;
+                writeln!(w, "  Channels: {}", channels.unwrap_or_default())?;
+                writeln!(w, "  Streams: {}", streams.unwrap_or_default())
+            }
+            Self::AgentStopped { was_running: true } => writeln!(w, "Agent stopped"),
+            Self::AgentStopped { was_running: false } => writeln!(w, "Agent not running"),
+            Self::Invitation {
+                team_id,
+                device_id,
+                role,
+                labels,
+                net_id,
+                sync_addr,
+                token,
+                ..
+            } => {
+                writeln!(w, "Device {device_id} invited to team {team_id}")?;
+                if let Some(role) = role {
+                    writeln!(w, "  Role: {role}")?;
+                }
+                for label in labels {
+                    writeln!(w, "  Label: {label}")?;
+                }
+                if let Some(net_id) = net_id {
+                    writeln!(w, "  AQC Network ID: {net_id}")?;
+                }
+                writeln!(w, "  Sync address: {sync_addr}")?;
+                writeln!(w, "Invitation: {token}")
+            }
+            Self::TeamJoined {
+                team_id,
+                sync_peer,
+                interval_secs,
+            } => {
+                writeln!(w, "Team joined: {team_id}")?;
+                writeln!(w, "Syncing with {sync_peer} every {interval_secs}s")
+            }
+            Self::Plan { team_id, changes } => {
+                if changes.is_empty() {
+                    return writeln!(w, "Team {team_id} matches the manifest");
+                }
+                writeln!(w, "{} changes to team {team_id}:", changes.len())?;
+                write_changes(w, changes)
+            }
+            Self::Applied { team_id, changes } => {
+                if changes.is_empty() {
+                    return writeln!(w, "Team {team_id} already matches the manifest");
+                }
+                writeln!(w, "Applied {} changes to team {team_id}:", changes.len())?;
+                write_changes(w, changes)
+            }
+            Self::ProfileSaved(profile) => {
+                writeln!(w, "Profile saved: {}", profile.profile)?;
+                write_profile(w, "  ", profile)
+            }
+            Self::Profiles { profiles } => {
+                if profiles.is_empty() {
+                    return writeln!(w, "No profiles");
+                }
+                for profile in profiles {
+                    let marker = if profile.current { "*" } else { " " };
+                    writeln!(w, "{marker} {}", profile.profile)?;
+                    write_profile(w, "    ", profile)?;
+                }
+                Ok(())
+            }
+            Self::ProfileSelected { profile } => writeln!(w, "Using profile: {profile}"),
+        }
+    }
+}
+
+fn write_key_bundle(w: &mut dyn Write, indent: &str, kb: &KeyBundleOutput) -> io::Result<()> {
+    writeln!(w, "{indent}Identity Key: {}", kb.identity)?;
+    writeln!(w, "{indent}Signing Key: {}", kb.signing)?;
+    writeln!(w, "{indent}Encoding Key: {}", kb.encoding)
+}
+
+fn write_file(w: &mut dyn Write, file: &FileOutput) -> io::Result<()> {
+    writeln!(w, "  Size: {} bytes", file.size)?;
+    writeln!(w, "  SHA-256: {}", file.sha256)?;
+    if file.resumed_from > 0 {
+        writeln!(w, "  Resumed from byte {}", file.resumed_from)?;
+    }
+    Ok(())
+}
+
+fn write_changes(w: &mut dyn Write, changes: &[ChangeOutput]) -> io::Result<()> {
+    for c in changes {
+        write!(w, "  {}", c.action)?;
+        for field in [&c.device_id, &c.label, &c.value].into_iter().flatten() {
+            write!(w, " {field}")?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+fn write_profile(w: &mut dyn Write, indent: &str, p: &ProfileOutput) -> io::Result<()> {
+    if let Some(uds_path) = &p.uds_path {
+        writeln!(w, "{indent}UDS path: {uds_path}")?;
+    }
+    if let Some(aqc_addr) = &p.aqc_addr {
+        writeln!(w, "{indent}AQC address: {aqc_addr}")?;
+    }
+    if let Some(team_id) = &p.team_id {
+        writeln!(w, "{indent}Team ID: {team_id}")?;
+    }
+    if let Some(secs) = p.sync_interval_secs {
+        writeln!(w, "{indent}Sync interval: {secs}s")?;
+    }
+    Ok(())
+}
+
+/// Writes `err` to stderr in `format`.
+pub(crate) fn render_error(err: &anyhow::Error, format: OutputFormat) {
+    match format {
+        OutputFormat::Json => {
+            let causes: Vec<String> = err.chain().skip(1).map(ToString::to_string).collect();
+            let value = serde_json::json!({
+                "error": err.to_string(),
+                "causes": causes,
+            });
+            eprintln!("{value}");
+        }
+        OutputFormat::Text | OutputFormat::Table => eprintln!("Error: {err:#}"),
+    }
+}
+
+/// Writes a JSON object as tables.
+///
+/// The `type` tag is left out. Scalar fields are written as a two column `FIELD VALUE` table. Each
+/// field holding a list is then written as its own table, with one column
+/// per field of the list's objects.
+fn write_table(value: &Value, w: &mut dyn Write) -> io::Result<()> {
+    let Value::Object(obj) = value else {
+        return writeln!(w, "{}", scalar(value));
+    };
+
+    let mut fields = Vec::new();
+    let mut lists = Vec::new();
+    for (key, v) in obj.iter().filter(|(key, _)| *key != "type") {
+        match v {
+            Value::Array(items) => lists.push((key, items)),
+            Value::Object(inner) => {
+                for (k, v) in inner {
+                    fields.push(vec![format!("{key}.{k}"), scalar(v)]);
+                }
+            }
+            v => fields.push(vec![key.clone(), scalar(v)]),
+        }
+    }
+    if !fields.is_empty() {
+        write_rows(w, &["FIELD".to_owned(), "VALUE".to_owned()], &fields)?;
+    }
+
+    for (key, items) in lists {
+        if !fields.is_empty() {
+            writeln!(w)?;
+        }
+        // Items may omit optional fields, so take the columns from all of
+        // them.
+        let mut keys: Vec<&String> = Vec::new();
+        for item in items {
+            if let Value::Object(o) = item {
+                for k in o.keys() {
+                    if !keys.contains(&k) {
+                        keys.push(k);
+                    }
+                }
+            }
+        }
+        let headers: Vec<String> = if keys.is_empty() {
+            vec![key.to_uppercase()]
+        } else {
+            keys.iter().map(|k| k.to_uppercase()).collect()
+        };
+        let rows: Vec<Vec<String>> = items
+            .iter()
+            .map(|item| match item {
+                Value::Object(o) => keys
+                    .iter()
+                    .map(|k| o.get(k.as_str()).map(scalar).unwrap_or_default())
+                    .collect(),
+                v => vec![scalar(v)],
+            })
+            .collect();
+        write_rows(w, &headers, &rows)?;
+    }
+    Ok(())
+}
+
+fn write_rows(w: &mut dyn Write, headers: &[String], rows: &[Vec<String>]) -> io::Result<()> {
+    let mut widths: Vec<usize> = headers.iter().map(String::len).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: &[String]| {
+        let mut s = String::new();
+        for (i, (cell, width)) in cells.iter().zip(&widths).enumerate() {
+            if i + 1 == cells.len() {
+                s.push_str(cell);
+            } else {
+                s.push_str(&format!("{cell:<width$}  "));
+            }
+        }
+        s
+    };
+    writeln!(w, "{}", line(headers))?;
+    for row in rows {
+        writeln!(w, "{}", line(row))?;
+    }
+    Ok(())
+}
+
+/// Describes `secs` (since the Unix epoch) relative to `now`.
+fn ago(now: u64, secs: Option<u64>) -> String {
+    match secs {
+        Some(secs) => format!("{}s ago", now.saturating_sub(secs)),
+        None => "never".to_owned(),
+    }
+}
+
+fn scalar(v: &Value) -> String {
+    match v {
+        Value::Null => "-".to_owned(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(output: &Output, format: OutputFormat) -> String {
+        let mut buf = Vec::new();
+        output.render(format, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn device_added() -> Output {
+        Output::DeviceAdded {
+            team_id: "T1".into(),
+            device_id: "D1".into(),
+        }
+    }
+
+    fn sync_peers() -> Output {
+        Output::SyncPeers {
+            team_id: "T1".into(),
+            peers: vec![
+                SyncPeerOutput {
+                    peer_addr: "127.0.0.1:5000".into(),
+                    interval_secs: 1,
+                    ephemeral: false,
+                    subscribe: true,
+                },
+                SyncPeerOutput {
+                    peer_addr: "10.0.0.2:5000".into(),
+                    interval_secs: 30,
+                    ephemeral: true,
+                    subscribe: false,
+                },
+            ],
+        }
+    }
+
+    fn device_info() -> Output {
+        Output::DeviceInfo {
+            team_id: "T1".into(),
+            device_id: "D1".into(),
+            role: "Member".into(),
+            key_bundle: KeyBundleOutput {
+                identity: "aa".into(),
+                signing: "bb".into(),
+                encoding: "cc".into(),
+            },
+            labels: vec![LabelOutput {
+                label_id: "L1".into(),
+                name: "telemetry".into(),
+            }],
+            aqc_net_id: None,
+        }
+    }
+
+    #[test]
+    fn test_device_added() {
+        assert_eq!(
+            render(&device_added(), OutputFormat::Json),
+            r#"{
+  "type": "device_added",
+  "team_id": "T1",
+  "device_id": "D1"
+}
+"#
+        );
+        assert_eq!(
+            render(&device_added(), OutputFormat::Table),
+            "FIELD      VALUE
+team_id    T1
+device_id  D1
+"
+        );
+        assert_eq!(
+            render(&device_added(), OutputFormat::Text),
+            "Device D1 added to team T1\n"
+        );
+    }
+
+    #[test]
+    fn test_sync_peers() {
+        assert_eq!(
+            render(&sync_peers(), OutputFormat::Json),
+            r#"{
+  "type": "sync_peers",
+  "team_id": "T1",
+  "peers": [
+    {
+      "peer_addr": "127.0.0.1:5000",
+      "interval_secs": 1,
+      "ephemeral": false,
+      "subscribe": true
+    },
+    {
+      "peer_addr": "10.0.0.2:5000",
+      "interval_secs": 30,
+      "ephemeral": true,
+      "subscribe": false
+    }
+  ]
+}
+"#
+        );
+        assert_eq!(
+            render(&sync_peers(), OutputFormat::Table),
+            "FIELD    VALUE
+team_id  T1
+
+PEER_ADDR       INTERVAL_SECS  EPHEMERAL  SUBSCRIBE
+127.0.0.1:5000  1              false      true
+10.0.0.2:5000   30             true       false
+"
+        );
+    }
+
+    #[test]
+    fn test_device_info() {
+        assert_eq!(
+            render(&device_info(), OutputFormat::Json),
+            r#"{
+  "type": "device_info",
+  "team_id": "T1",
+  "device_id": "D1",
+  "role": "Member",
+  "key_bundle": {
+    "identity": "aa",
+    "signing": "bb",
+    "encoding": "cc"
+  },
+  "labels": [
+    {
+      "label_id": "L1",
+      "name": "telemetry"
+    }
+  ],
+  "aqc_net_id": null
+}
+"#
+        );
+        assert_eq!(
+            render(&device_info(), OutputFormat::Table),
+            "FIELD                VALUE
+team_id              T1
+device_id            D1
+role                 Member
+key_bundle.identity  aa
+key_bundle.signing   bb
+key_bundle.encoding  cc
+aqc_net_id           -
+
+LABEL_ID  NAME
+L1        telemetry
+"
+        );
+    }
+
+    #[test]
+    fn test_newtype_variant_is_tagged() {
+        let output = Output::ProfileSelected {
+            profile: "owner".into(),
+        };
+        let value = serde_json::to_value(&output).unwrap();
+        assert_eq!(value["type"], "profile_selected");
+
+        let output = Output::ProfileSaved(ProfileOutput {
+            profile: "owner".into(),
+            current: true,
+            uds_path: None,
+            aqc_addr: None,
+            team_id: None,
+            sync_interval_secs: None,
+        });
+        assert_eq!(
+            render(&output, OutputFormat::Json),
+            r#"{
+  "type": "profile_saved",
+  "profile": "owner",
+  "current": true
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(device_added().exit_code(), 0);
+        let timeout = Output::Timeout {
+            timeout: Waited::Channel,
+        };
+        assert_eq!(timeout.exit_code(), EXIT_TIMEOUT);
+        let closed = Output::Closed {
+            closed: Waited::Channel,
+        };
+        assert_eq!(closed.exit_code(), EXIT_CLOSED);
+        assert_ne!(EXIT_TIMEOUT, EXIT_CLOSED);
+    }
+}
@@ -0,0 +1,263 @@
+//! Netcat-style piping of stdin and stdout over an AQC stream.
+//!
+//! `aqc pipe` opens (or, with `--listen`, accepts) a bidirectional
+//! channel, copies stdin to the stream and the stream to stdout. When
+//! stdin ends, the send side is closed and the pipe keeps receiving until
+//! the peer closes its side, so the pipe exits once both sides are closed.
+
+use std::io::{self, IsTerminal, Read};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use aranya_client::aqc::{AqcBidiStream, AqcPeerChannel, AqcReceiveStream, AqcSendStream};
+use aranya_client::error::AqcError;
+use aranya_daemon_api::{LabelId, NetIdentifier, TeamId, Text};
+use bytes::Bytes;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::output::OutputFormat;
+use crate::session::Session;
+
+/// The amount of stdin read at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Exit code for a pipe whose peer reset the stream or whose connection
+/// was lost.
+const EXIT_RESET: u8 = 3;
+
+#[derive(Clone, Subcommand, Serialize, Deserialize)]
+pub(crate) enum AqcCommand {
+    /// Copy stdin to an AQC stream and the stream to stdout, like netcat
+    ///
+    /// Exits with 0 once both sides have closed the stream, 3 if the peer
+    /// reset it or the connection was lost, and 1 on any other error.
+    Pipe {
+        /// Wait for a peer to open a channel instead of opening one
+        #[arg(long, conflicts_with_all = ["team", "peer"])]
+        listen: bool,
+        /// Team ID (defaults to the profile's team)
+        #[arg(long)]
+        team: Option<String>,
+        /// AQC network identifier of the peer (e.g., "192.168.1.100:5050")
+        #[arg(long, required_unless_present = "listen")]
+        peer: Option<String>,
+        /// Label ID for the channel. With --listen, channels with other
+        /// labels are refused.
+        #[arg(long)]
+        label: String,
+    },
+}
+
+/// How a pipe ended.
+#[derive(Debug)]
+pub(crate) enum PipeEnd {
+    /// Both sides closed the stream.
+    Closed,
+    /// The peer reset the stream or the connection was lost.
+    Reset(AqcError),
+}
+
+impl PipeEnd {
+    pub(crate) fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::Closed => ExitCode::SUCCESS,
+            Self::Reset(_) => ExitCode::from(EXIT_RESET),
+        }
+    }
+}
+
+impl Session {
+    /// Runs an `aqc` subcommand.
+    pub(crate) async fn run_aqc(&mut self, command: AqcCommand) -> Result<PipeEnd> {
+        let AqcCommand::Pipe {
+            listen,
+            team,
+            peer,
+            label,
+        } = command;
+        let label_id = LabelId::from_str(&label)
+            .map_err(|e| anyhow::anyhow!("Invalid label ID format: {}", e))?;
+
+        let team = team.or_else(|| self.profile.team_id.clone());
+        let json = self.format == OutputFormat::Json;
+        let note = |msg: std::fmt::Arguments<'_>| {
+            if !json {
+                eprintln!("{msg}");
+            }
+        };
+
+        let mut aqc = self.client.aqc();
+        let (channel, stream) = if listen {
+            note(format_args!(
+                "Waiting for incoming channel with label {label_id}..."
+            ));
+            loop {
+                let mut channel = match aqc.receive_channel().await? {
+                    AqcPeerChannel::Bidi(channel) => channel,
+                    AqcPeerChannel::Receive(channel) => {
+                        note(format_args!(
+                            "Refusing unidirectional channel {}",
+                            channel.aqc_id()
+                        ));
+                        if let Err(err) = aqc.delete_receive_uni_channel(channel).await {
+                            tracing::warn!(error = %err, "failed to delete refused channel");
+                        }
+                        continue;
+                    }
+                };
+                if channel.label_id() != label_id {
+                    note(format_args!(
+                        "Refusing channel with label {}",
+                        channel.label_id()
+                    ));
+                    if let Err(err) = aqc.delete_bidi_channel(channel).await {
+                        tracing::warn!(error = %err, "failed to delete refused channel");
+                    }
+                    continue;
+                }
+                let stream = channel
+                    .receive_stream()
+                    .await?
+                    .into_bidi()
+                    .map_err(|_| anyhow::anyhow!("Expected a bidirectional stream"))?;
+                break (channel, stream);
+            }
+        } else {
+            let team = team.context("--team is required unless the profile has a team")?;
+            let team_id = TeamId::from_str(&team)?;
+            let peer = peer.context("--peer is required unless --listen is given")?;
+            let net_id = NetIdentifier(Text::try_from(peer)?);
+            let mut channel = aqc.create_bidi_channel(team_id, net_id, label_id).await?;
+            let stream = channel.create_bidi_stream().await?;
+            (channel, stream)
+        };
+
+        let end = pipe(stream).await;
+        let deleted = aqc.delete_bidi_channel(channel).await;
+        finish(end, deleted)
+    }
+}
+
+/// Returns how the pipe ended, even if deleting its channel failed
+/// afterwards, since callers exit with [`PipeEnd::exit_code`].
+fn finish(end: Result<PipeEnd>, deleted: aranya_client::Result<()>) -> Result<PipeEnd> {
+    if let Err(err) = deleted {
+        tracing::warn!(error = %err, "failed to delete channel");
+    }
+    end
+}
+
+/// Copies stdin to `stream` and `stream` to stdout until both sides have
+/// closed it.
+async fn pipe(stream: AqcBidiStream) -> Result<PipeEnd> {
+    let (send, recv) = stream.split();
+    let (peer_done, peer_closed) = oneshot::channel();
+    let copied = tokio::try_join!(
+        stdin_to_stream(send, peer_closed),
+        stream_to_stdout(recv, peer_done),
+    );
+    match copied {
+        Ok(_) => Ok(PipeEnd::Closed),
+        // Stream errors come from the peer or the network; anything else
+        // is a local problem, such as stdout being closed.
+        Err(err) => match err.downcast::<AqcError>() {
+            Ok(err) => Ok(PipeEnd::Reset(err)),
+            Err(err) => Err(err),
+        },
+    }
+}
+
+/// Sends stdin until it ends, then closes the send side.
+///
+/// Piped input is always sent in full, but nobody is going to finish
+/// typing into a pipe once the peer has closed its side, so an interactive
+/// stdin also stops when `peer_closed` fires.
+async fn stdin_to_stream(
+    mut send: AqcSendStream,
+    mut peer_closed: oneshot::Receiver<()>,
+) -> Result<()> {
+    #![allow(clippy::disallowed_macros)]
+    let interactive = io::stdin().is_terminal();
+    let mut stdin = read_stdin();
+    loop {
+        let chunk = tokio::select! {
+            chunk = stdin.recv() => chunk,
+            _ = &mut peer_closed, if interactive => None,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        send.send(chunk.context("Failed to read stdin")?).await?;
+    }
+    send.close().await?;
+    Ok(())
+}
+
+/// Writes everything received to stdout, then signals `peer_done`.
+async fn stream_to_stdout(
+    mut recv: AqcReceiveStream,
+    peer_done: oneshot::Sender<()>,
+) -> Result<()> {
+    let mut stdout = tokio::io::stdout();
+    while let Some(data) = recv.receive().await? {
+        stdout
+            .write_all(&data)
+            .await
+            .context("Failed to write stdout")?;
+        stdout.flush().await.context("Failed to write stdout")?;
+    }
+    let _ = peer_done.send(());
+    Ok(())
+}
+
+/// Reads stdin on its own thread.
+///
+/// A read from stdin cannot be cancelled, so a blocking task would keep
+/// the runtime from shutting down after the peer closes the stream. The
+/// thread is detached instead and dies with the process.
+fn read_stdin() -> mpsc::Receiver<io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(4);
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let chunk = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => Ok(Bytes::copy_from_slice(&buf[..n])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if tx.blocking_send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cleanup_error() -> aranya_client::Result<()> {
+        Err(AqcError::ConnectionClosed.into())
+    }
+
+    #[test]
+    fn test_cleanup_error_keeps_exit_status() {
+        let end = finish(Ok(PipeEnd::Closed), cleanup_error()).unwrap();
+        assert_eq!(end.exit_code(), ExitCode::SUCCESS);
+
+        let reset = PipeEnd::Reset(AqcError::ConnectionClosed);
+        let end = finish(Ok(reset), cleanup_error()).unwrap();
+        assert_eq!(end.exit_code(), ExitCode::from(EXIT_RESET));
+
+        let err = finish(Err(anyhow::anyhow!("stdout closed")), cleanup_error()).unwrap_err();
+        assert_eq!(err.to_string(), "stdout closed");
+    }
+}
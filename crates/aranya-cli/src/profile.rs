@@ -0,0 +1,417 @@
+//! Named daemon profiles.
+//!
+//! Profiles live in `~/.config/aranya/cli.toml` and save having to pass
+//! the daemon's UDS path, AQC address, team ID, and sync interval on
+//! every invocation:
+//!
+//! ```toml
+//! current = "owner"
+//!
+//! [profiles.owner]
+//! uds_path = "/tmp/aranya1/run/uds.sock"
+//! aqc_addr = "127.0.0.1:5051"
+//! team_id = "..."
+//! sync_interval_secs = 1
+//! ```
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use aranya_daemon_api::TeamId;
+use clap::{Command, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::output::{Output, ProfileOutput};
+
+/// The daemon UDS path used when neither a flag nor a profile sets one.
+pub(crate) const DEFAULT_UDS_PATH: &str = "/var/run/aranya/uds.sock";
+/// The AQC address used when neither a flag nor a profile sets one.
+pub(crate) const DEFAULT_AQC_ADDR: &str = "127.0.0.1:0";
+/// The sync interval used when neither a flag nor a profile sets one.
+pub(crate) const DEFAULT_SYNC_INTERVAL_SECS: u64 = 1;
+
+/// Environment variable that overrides the config file location.
+const CONFIG_ENV: &str = "ARANYA_CLI_CONFIG";
+
+#[derive(Clone, Subcommand, Serialize, Deserialize)]
+pub(crate) enum ProfileCommand {
+    /// Add a profile, or replace an existing one
+    Add {
+        /// Profile name
+        name: String,
+        /// Path to the daemon's Unix Domain Socket
+        #[arg(long)]
+        uds_path: Option<PathBuf>,
+        /// AQC address for the client
+        #[arg(long)]
+        aqc_addr: Option<String>,
+        /// Default team ID
+        #[arg(long)]
+        team: Option<String>,
+        /// Default sync interval in seconds
+        #[arg(long)]
+        sync_interval_secs: Option<u64>,
+        /// Take the UDS path from a daemon config file
+        #[arg(long, conflicts_with = "uds_path")]
+        from_daemon_config: Option<PathBuf>,
+        /// Also make this the current profile
+        #[arg(long)]
+        r#use: bool,
+    },
+    /// List profiles
+    List,
+    /// Make a profile the current profile
+    Use {
+        /// Profile name
+        name: String,
+    },
+}
+
+/// Connection defaults for one daemon.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) uds_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) aqc_addr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) team_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sync_interval_secs: Option<u64>,
+}
+
+impl Profile {
+    /// The sync interval to use when a command does not specify one.
+    pub(crate) fn sync_interval_secs(&self) -> u64 {
+        self.sync_interval_secs
+            .unwrap_or(DEFAULT_SYNC_INTERVAL_SECS)
+    }
+}
+
+/// The contents of `cli.toml`.
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CliConfig {
+    /// The profile used when `--profile` is not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) current: Option<String>,
+    #[serde(default)]
+    pub(crate) profiles: BTreeMap<String, Profile>,
+}
+
+impl CliConfig {
+    /// Returns the path to `cli.toml`.
+    ///
+    /// This is `$ARANYA_CLI_CONFIG` if set, otherwise
+    /// `$XDG_CONFIG_HOME/aranya/cli.toml` or `~/.config/aranya/cli.toml`.
+    pub(crate) fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV) {
+            return Some(PathBuf::from(path));
+        }
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+        Some(dir.join("aranya").join("cli.toml"))
+    }
+
+    /// Reads the config from `path`. A missing file is an empty config.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("unable to read {}", path.display()))
+            }
+        };
+        toml::from_str(&data).with_context(|| format!("unable to parse {}", path.display()))
+    }
+
+    /// Writes the config to `path`, creating its directory if needed.
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+        }
+        let data = toml::to_string_pretty(self)?;
+        std::fs::write(path, data).with_context(|| format!("unable to write {}", path.display()))
+    }
+
+    /// Returns the profile named `name`, or the current profile if `name`
+    /// is `None`.
+    ///
+    /// Naming a profile that does not exist is an error. Having no
+    /// current profile is not; it yields an empty profile.
+    pub(crate) fn select(&self, name: Option<&str>) -> Result<Profile> {
+        match name {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .with_context(|| format!("no such profile: {name}")),
+            None => Ok(self
+                .current
+                .as_ref()
+                .and_then(|name| self.profiles.get(name))
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+}
+
+/// Loads the profile named `name`, or the current profile.
+pub(crate) fn load(name: Option<&str>) -> Result<Profile> {
+    match CliConfig::path() {
+        Some(path) => CliConfig::load(&path)?.select(name),
+        None if name.is_some() => anyhow::bail!("unable to locate cli.toml"),
+        None => Ok(Profile::default()),
+    }
+}
+
+/// Runs a `profile` subcommand.
+pub(crate) fn run(command: ProfileCommand) -> Result<Output> {
+    let path = CliConfig::path().context("unable to locate cli.toml")?;
+    let mut config = CliConfig::load(&path)?;
+
+    let output = match command {
+        ProfileCommand::Add {
+            name,
+            uds_path,
+            aqc_addr,
+            team,
+            sync_interval_secs,
+            from_daemon_config,
+            r#use,
+        } => {
+            let uds_path = match from_daemon_config {
+                Some(path) => Some(aranya_daemon::config::Config::load(path)?.uds_api_sock()),
+                None => uds_path,
+            };
+            if let Some(team) = &team {
+                TeamId::from_str(team).context("invalid team ID")?;
+            }
+            if let Some(addr) = &aqc_addr {
+                aranya_util::Addr::from_str(addr).context("invalid AQC address")?;
+            }
+            let profile = Profile {
+                uds_path,
+                aqc_addr,
+                team_id: team,
+                sync_interval_secs,
+            };
+            config.profiles.insert(name.clone(), profile.clone());
+            if r#use || config.current.is_none() {
+                config.current = Some(name.clone());
+            }
+            config.save(&path)?;
+            Output::ProfileSaved(ProfileOutput::new(name, &profile, &config))
+        }
+        ProfileCommand::List => Output::Profiles {
+            profiles: config
+                .profiles
+                .iter()
+                .map(|(name, profile)| ProfileOutput::new(name.clone(), profile, &config))
+                .collect(),
+        },
+        ProfileCommand::Use { name } => {
+            if !config.profiles.contains_key(&name) {
+                anyhow::bail!("no such profile: {name}");
+            }
+            config.current = Some(name.clone());
+            config.save(&path)?;
+            Output::ProfileSelected { profile: name }
+        }
+    };
+    Ok(output)
+}
+
+/// Returns the profile name requested in `args` (with `--profile`) or
+/// in `$ARANYA_PROFILE`.
+///
+/// This is needed before `args` can be fully parsed, since the profile's
+/// default team may be needed to parse them.
+pub(crate) fn requested(args: &[OsString]) -> Option<String> {
+    let mut args = args.iter().map(|a| a.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--profile" {
+            return args.next().map(|a| a.into_owned());
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_owned());
+        }
+    }
+    std::env::var("ARANYA_PROFILE").ok()
+}
+
+/// Inserts `team` as the team ID argument of the subcommand in `args`.
+///
+/// `args` must not include the binary name. Returns `None` if the
+/// subcommand does not take a team ID as its first argument, or if
+/// enough arguments were given that the missing one is something else.
+pub(crate) fn with_default_team(
+    cmd: &Command,
+    args: &[OsString],
+    team: &str,
+) -> Option<Vec<OsString>> {
+    let (pos, sub) = find_subcommand(cmd, args)?;
+    if sub.get_positionals().next()?.get_id() != "team_id" {
+        return None;
+    }
+    let required = sub
+        .get_positionals()
+        .filter(|a| a.is_required_set())
+        .count();
+    let mut given = positionals(sub, &args[pos + 1..]).peekable();
+    if given.peek().is_some_and(|a| *a == team) || given.count() >= required {
+        return None;
+    }
+    let mut args = args.to_vec();
+    args.insert(pos + 1, team.into());
+    Some(args)
+}
+
+/// Finds the innermost subcommand in `args`, skipping over options and
+/// their values.
+fn find_subcommand<'a>(cmd: &'a Command, args: &[OsString]) -> Option<(usize, &'a Command)> {
+    let mut skip_value = false;
+    for (i, arg) in args.iter().enumerate() {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+        let arg = arg.to_string_lossy();
+        if arg.starts_with('-') {
+            skip_value = takes_value(cmd, &arg);
+            continue;
+        }
+        let sub = cmd.find_subcommand(arg.as_ref())?;
+        if sub.has_subcommands() {
+            return find_subcommand(sub, &args[i + 1..]).map(|(j, sub)| (i + 1 + j, sub));
+        }
+        return Some((i, sub));
+    }
+    None
+}
+
+/// Returns the positional arguments in `args`.
+fn positionals<'a>(
+    cmd: &'a Command,
+    args: &'a [OsString],
+) -> impl Iterator<Item = &'a OsString> + 'a {
+    let mut skip_value = false;
+    args.iter().filter(move |arg| {
+        if skip_value {
+            skip_value = false;
+            return false;
+        }
+        let arg = arg.to_string_lossy();
+        if arg.starts_with('-') {
+            skip_value = takes_value(cmd, &arg);
+            return false;
+        }
+        true
+    })
+}
+
+/// Reports whether the option `arg` is followed by a separate value.
+pub(crate) fn takes_value(cmd: &Command, arg: &str) -> bool {
+    if arg.contains('=') {
+        return false;
+    }
+    let is_match = |a: &clap::Arg| match arg.strip_prefix("--") {
+        Some(long) => a.get_long() == Some(long),
+        None => arg.len() == 2 && a.get_short().is_some_and(|s| arg.ends_with(s)),
+    };
+    cmd.get_opts()
+        .any(|a| is_match(a) && a.get_action().takes_values())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    const TEAM: &str = "7bLNDMRvqmKGawYwsYZYJq2EPsoUBZDbBWqy7NDHqiHD";
+
+    fn os(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn insert(args: &[&str]) -> Option<Vec<OsString>> {
+        with_default_team(&crate::Cli::command(), &os(args), TEAM)
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = CliConfig {
+            current: Some("owner".into()),
+            profiles: BTreeMap::from([(
+                "owner".into(),
+                Profile {
+                    uds_path: Some("/tmp/aranya1/run/uds.sock".into()),
+                    aqc_addr: Some("127.0.0.1:5051".into()),
+                    team_id: None,
+                    sync_interval_secs: Some(5),
+                },
+            )]),
+        };
+        let data = toml::to_string_pretty(&config).unwrap();
+        assert_eq!(toml::from_str::<CliConfig>(&data).unwrap(), config);
+
+        assert_eq!(config.select(None).unwrap().sync_interval_secs(), 5);
+        assert!(config.select(Some("missing")).is_err());
+        assert_eq!(
+            CliConfig::default().select(None).unwrap(),
+            Profile::default()
+        );
+    }
+
+    #[test]
+    fn test_with_default_team() {
+        assert_eq!(
+            insert(&["-u", "/tmp/uds.sock", "list-devices"]),
+            Some(os(&["-u", "/tmp/uds.sock", "list-devices", TEAM])),
+        );
+        assert_eq!(
+            insert(&["add-sync-peer", "--interval-secs", "5", "127.0.0.1:5050"]),
+            Some(os(&[
+                "add-sync-peer",
+                TEAM,
+                "--interval-secs",
+                "5",
+                "127.0.0.1:5050"
+            ])),
+        );
+        assert_eq!(
+            insert(&[
+                "assign-role",
+                "ZRgHGZBsoX5e5ZYUGEVvCGK7JvXuzqkLxmVmGHzMNmoH",
+                "Admin"
+            ]),
+            Some(os(&[
+                "assign-role",
+                TEAM,
+                "ZRgHGZBsoX5e5ZYUGEVvCGK7JvXuzqkLxmVmGHzMNmoH",
+                "Admin",
+            ])),
+        );
+        // The team ID was given, so something else is missing.
+        assert_eq!(insert(&["add-sync-peer", TEAM]), None);
+        assert_eq!(insert(&["assign-role", TEAM, "Admin"]), None);
+        // No team ID argument.
+        assert_eq!(insert(&["create-bidi-stream"]), None);
+        assert_eq!(
+            insert(&["team", "invite", "kb.json", "--role", "Member"]),
+            Some(os(&["team", "invite", TEAM, "kb.json", "--role", "Member"])),
+        );
+        assert_eq!(insert(&["profile", "use"]), None);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use aranya_client::aqc::{AqcBidiChannel, AqcBidiStream, AqcPeerChannel, AqcPeerStream};
+use uuid::Uuid;
+
+/// Registry for managing AQC channels and streams across CLI commands.
+#[derive(Default)]
+pub(crate) struct ChannelRegistry {
+    channels: HashMap<String, AqcBidiChannel>,
+    streams: HashMap<String, AqcBidiStream>,
+    peer_streams: HashMap<String, AqcPeerStream>,
+    received_channels: HashMap<String, AqcPeerChannel>,
+}
+
+impl ChannelRegistry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn store_channel(&mut self, channel: AqcBidiChannel) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.channels.insert(id.clone(), channel);
+        id
+    }
+
+    pub(crate) fn get_channel(&mut self, id: &str) -> Option<&mut AqcBidiChannel> {
+        self.channels.get_mut(id)
+    }
+
+    pub(crate) fn store_stream(&mut self, stream: AqcBidiStream) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.streams.insert(id.clone(), stream);
+        id
+    }
+
+    pub(crate) fn get_stream(&mut self, id: &str) -> Option<&mut AqcBidiStream> {
+        self.streams.get_mut(id)
+    }
+
+    pub(crate) fn store_peer_stream(&mut self, stream: AqcPeerStream) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.peer_streams.insert(id.clone(), stream);
+        id
+    }
+
+    pub(crate) fn get_peer_stream(&mut self, id: &str) -> Option<&mut AqcPeerStream> {
+        self.peer_streams.get_mut(id)
+    }
+
+    pub(crate) fn store_received_channel(&mut self, channel: AqcPeerChannel) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.received_channels.insert(id.clone(), channel);
+        id
+    }
+
+    pub(crate) fn get_received_channel(&mut self, id: &str) -> Option<&mut AqcPeerChannel> {
+        self.received_channels.get_mut(id)
+    }
+
+    pub(crate) fn list_channels(&self) -> Vec<String> {
+        self.channels.keys().cloned().collect()
+    }
+
+    pub(crate) fn list_streams(&self) -> Vec<String> {
+        self.streams.keys().cloned().collect()
+    }
+
+    pub(crate) fn list_peer_streams(&self) -> Vec<String> {
+        self.peer_streams.keys().cloned().collect()
+    }
+
+    pub(crate) fn list_received_channels(&self) -> Vec<String> {
+        self.received_channels.keys().cloned().collect()
+    }
+
+    pub(crate) fn remove_channel(&mut self, id: &str) -> Option<AqcBidiChannel> {
+        self.channels.remove(id)
+    }
+
+    pub(crate) fn remove_stream(&mut self, id: &str) -> Option<AqcBidiStream> {
+        self.streams.remove(id)
+    }
+
+    pub(crate) fn remove_peer_stream(&mut self, id: &str) -> Option<AqcPeerStream> {
+        self.peer_streams.remove(id)
+    }
+
+    pub(crate) fn remove_received_channel(&mut self, id: &str) -> Option<AqcPeerChannel> {
+        self.received_channels.remove(id)
+    }
+}
@@ -0,0 +1,313 @@
+//! Running scripts of CLI commands over one daemon connection.
+//!
+//! A script has one command per line, in the same grammar as the shell.
+//! Blank lines and lines starting with `#` are skipped, and a trailing `\`
+//! continues a command on the next line. `let NAME = COMMAND` runs the
+//! command and binds its result to `NAME`:
+//!
+//! ```text
+//! let team = create-team
+//! let label = create-label $team TELEMETRY
+//! let me = get-device-id
+//! assign-label $team $me $label SendRecv
+//! ```
+//!
+//! `$NAME` (or `${NAME}`) expands to the most specific ID in the result:
+//! its stream, channel, label, device, or team ID, in that order.
+//! `${NAME.FIELD}` expands to a field of its JSON output, e.g.
+//! `${team.seed_ikm}`, and `$$` is a literal `$`.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::output::render_error;
+use crate::session::Session;
+use crate::shell::parse_line;
+
+/// Result fields that `$NAME` expands to, most specific first.
+const ID_FIELDS: [&str; 5] = [
+    "stream_id",
+    "channel_id",
+    "label_id",
+    "device_id",
+    "team_id",
+];
+
+/// A command from a script.
+#[derive(Debug, PartialEq)]
+struct Line {
+    /// The line the command starts on, counting from 1.
+    number: usize,
+    /// The variable to bind the result to.
+    var: Option<String>,
+    command: String,
+}
+
+/// Runs the script at `path` (`-` for stdin) on `session`.
+///
+/// `vars` are bound before the script starts. Stops at the first failing
+/// command unless `keep_going` is set, in which case every command is run
+/// and an error is returned at the end if any failed.
+pub(crate) async fn run(
+    session: &mut Session,
+    path: &Path,
+    vars: Vec<String>,
+    keep_going: bool,
+) -> Result<()> {
+    let mut source = String::new();
+    if path.as_os_str() == "-" {
+        std::io::stdin().read_to_string(&mut source)?;
+    } else {
+        source = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+    }
+    let lines = parse(&source)?;
+
+    let mut bound = BTreeMap::new();
+    for var in vars {
+        let (name, value) = var
+            .split_once('=')
+            .context("--var must look like NAME=VALUE")?;
+        check_name(name)?;
+        bound.insert(name.to_owned(), Value::String(value.to_owned()));
+    }
+
+    let mut failed = 0;
+    for line in &lines {
+        let result = run_line(session, line, &mut bound)
+            .await
+            .with_context(|| format!("line {}", line.number));
+        if let Err(err) = result {
+            if !keep_going {
+                return Err(err);
+            }
+            render_error(&err, session.format);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} commands failed", lines.len());
+    }
+    Ok(())
+}
+
+async fn run_line(
+    session: &mut Session,
+    line: &Line,
+    vars: &mut BTreeMap<String, Value>,
+) -> Result<()> {
+    let args = shell_words::split(&line.command)?
+        .iter()
+        .map(|word| expand(word, vars))
+        .collect::<Result<Vec<_>>>()?;
+    let parsed = parse_line(args, session.profile.team_id.as_deref())
+        .map_err(|err| anyhow::anyhow!("{}", err.render().to_string().trim_end()))?;
+    let format = parsed
+        .output
+        .or(parsed.command.format_override())
+        .unwrap_or(session.format);
+    let output = session.run(parsed.command).await?;
+    output.render(format, &mut std::io::stdout())?;
+    if let Some(var) = &line.var {
+        vars.insert(var.clone(), serde_json::to_value(&output)?);
+    }
+    Ok(())
+}
+
+/// Splits a script into commands.
+fn parse(source: &str) -> Result<Vec<Line>> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, text) in source.lines().enumerate() {
+        let (number, mut command) = match pending.take() {
+            Some((number, command)) => (number, command + text),
+            None => {
+                let text = text.trim();
+                if text.is_empty() || text.starts_with('#') {
+                    continue;
+                }
+                (i + 1, text.to_owned())
+            }
+        };
+        if let Some(head) = command.strip_suffix('\\') {
+            command.truncate(head.len());
+            pending = Some((number, command));
+            continue;
+        }
+        lines.push(parse_command(number, command.trim())?);
+    }
+    if let Some((number, command)) = pending {
+        lines.push(parse_command(number, command.trim())?);
+    }
+    Ok(lines)
+}
+
+fn parse_command(number: usize, command: &str) -> Result<Line> {
+    let Some(rest) = command.strip_prefix("let ") else {
+        return Ok(Line {
+            number,
+            var: None,
+            command: command.to_owned(),
+        });
+    };
+    let (var, command) = rest
+        .split_once('=')
+        .with_context(|| format!("line {number}: expected `let NAME = COMMAND`"))?;
+    let var = var.trim();
+    check_name(var).with_context(|| format!("line {number}"))?;
+    Ok(Line {
+        number,
+        var: Some(var.to_owned()),
+        command: command.trim().to_owned(),
+    })
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        anyhow::bail!("invalid variable name `{name}`");
+    }
+    Ok(())
+}
+
+/// Expands the variable references in `word`.
+fn expand(word: &str, vars: &BTreeMap<String, Value>) -> Result<String> {
+    let mut out = String::with_capacity(word.len());
+    let mut rest = word;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let (reference, tail) = if let Some(r) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = r;
+            continue;
+        } else if let Some(r) = rest.strip_prefix('{') {
+            let end = r.find('}').context("unterminated `${`")?;
+            (&r[..end], &r[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        out.push_str(&lookup(reference, vars)?);
+        rest = tail;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Resolves `NAME` or `NAME.FIELD...` to a string.
+fn lookup(reference: &str, vars: &BTreeMap<String, Value>) -> Result<String> {
+    let mut path = reference.split('.');
+    let name = path.next().unwrap_or_default();
+    let mut value = vars
+        .get(name)
+        .with_context(|| format!("undefined variable `{name}`"))?;
+    let mut fields = path.peekable();
+    if fields.peek().is_none() {
+        if let Value::Object(obj) = value {
+            value = ID_FIELDS
+                .iter()
+                .find_map(|f| obj.get(*f))
+                .with_context(|| format!("`{name}` has no ID; use ${{{name}.FIELD}}"))?;
+        }
+    }
+    for field in fields {
+        value = match value {
+            Value::Array(items) => field.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => value.get(field),
+        }
+        .with_context(|| format!("`{reference}` does not exist"))?;
+    }
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let script = "\
+# Set up a team
+let team = create-team
+
+add-sync-peer $team 127.0.0.1:5051 \\
+    --interval-secs 5
+let label=create-label $team TELEMETRY
+";
+        let lines = parse(script).unwrap();
+        assert_eq!(
+            lines,
+            [
+                Line {
+                    number: 2,
+                    var: Some("team".into()),
+                    command: "create-team".into(),
+                },
+                Line {
+                    number: 4,
+                    var: None,
+                    command: "add-sync-peer $team 127.0.0.1:5051     --interval-secs 5".into(),
+                },
+                Line {
+                    number: 6,
+                    var: Some("label".into()),
+                    command: "create-label $team TELEMETRY".into(),
+                },
+            ]
+        );
+        assert!(parse("let 1x = create-team").is_err());
+        assert!(parse("let team create-team").is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let vars = BTreeMap::from([
+            (
+                "team".to_owned(),
+                serde_json::json!({"team_id": "T1", "seed_ikm": "abcd"}),
+            ),
+            (
+                "label".to_owned(),
+                serde_json::json!({"team_id": "T1", "label_id": "L1"}),
+            ),
+            (
+                "devs".to_owned(),
+                serde_json::json!({"total": 2, "devices": ["D1", "D2"]}),
+            ),
+            ("peer".to_owned(), Value::String("127.0.0.1:5051".into())),
+        ]);
+        let cases = [
+            ("$team", "T1"),
+            ("${team}", "T1"),
+            ("$label", "L1"),
+            ("${team.seed_ikm}", "abcd"),
+            ("${devs.devices.1}", "D2"),
+            ("${devs.total}", "2"),
+            ("$peer", "127.0.0.1:5051"),
+            ("$label:SendRecv", "L1:SendRecv"),
+            ("cost: $$5", "cost: $5"),
+            ("plain", "plain"),
+        ];
+        for (word, want) in cases {
+            assert_eq!(expand(word, &vars).unwrap(), want, "{word}");
+        }
+        for word in ["$nope", "$devs", "${team.nope}", "${team"] {
+            assert!(expand(word, &vars).is_err(), "{word}");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeSet;
+
+use aranya_client::Client;
+
+use crate::output::OutputFormat;
+use crate::profile::Profile;
+use crate::registry::ChannelRegistry;
+
+/// A connection to the daemon along with the state that must outlive a
+/// single command, such as open AQC channels and streams.
+pub(crate) struct Session {
+    pub(crate) client: Client,
+    pub(crate) registry: ChannelRegistry,
+    /// IDs seen during this session, used for tab completion.
+    pub(crate) known: KnownIds,
+    /// The format results are printed in.
+    pub(crate) format: OutputFormat,
+    /// Defaults for arguments a command was not given.
+    pub(crate) profile: Profile,
+}
+
+impl Session {
+    pub(crate) fn new(client: Client, format: OutputFormat) -> Self {
+        Self {
+            client,
+            registry: ChannelRegistry::new(),
+            known: KnownIds::default(),
+            format,
+            profile: Profile::default(),
+        }
+    }
+}
+
+/// IDs that have been created or returned by the daemon during a session.
+#[derive(Default)]
+pub(crate) struct KnownIds {
+    pub(crate) teams: BTreeSet<String>,
+    pub(crate) devices: BTreeSet<String>,
+    pub(crate) labels: BTreeSet<String>,
+}
@@ -0,0 +1,330 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use clap::error::ErrorKind;
+use clap::{Command, CommandFactory, Parser};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Helper, Highlighter, Hinter, Validator};
+
+use crate::commands::Commands;
+use crate::output::{render_error, OutputFormat};
+use crate::profile;
+use crate::session::Session;
+
+const PROMPT: &str = "aranya> ";
+
+/// A single line of shell input, parsed with the same grammar as the CLI.
+#[derive(Parser)]
+#[command(name = "aranya", no_binary_name = true, disable_version_flag = true)]
+pub(crate) struct ShellLine {
+    /// Output format for this command only
+    #[arg(short = 'o', long, value_enum, global = true)]
+    pub(crate) output: Option<OutputFormat>,
+
+    #[command(subcommand)]
+    pub(crate) command: Commands,
+}
+
+/// Runs an interactive shell on top of `session`.
+///
+/// Every line is parsed as a CLI subcommand and executed against the same
+/// client, so channels and streams stay open between commands.
+pub(crate) async fn run(session: &mut Session, history: Option<PathBuf>) -> Result<()> {
+    let history = history.or_else(default_history_path);
+    let candidates = Arc::new(Mutex::new(Candidates::default()));
+
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper::new(Arc::clone(&candidates))));
+    if let Some(path) = &history {
+        // A missing history file is not an error.
+        let _ = editor.load_history(path);
+    }
+
+    println!("Aranya interactive shell. Type `help` for commands, `exit` to quit.");
+
+    loop {
+        if let Ok(mut c) = candidates.lock() {
+            *c = Candidates::from_session(session);
+        }
+
+        let line = match tokio::task::block_in_place(|| editor.readline(PROMPT)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+        if matches!(line, "exit" | "quit") {
+            break;
+        }
+
+        let args = match shell_words::split(line) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("Error: {err}");
+                continue;
+            }
+        };
+        let (command, output) = match parse_line(args, session.profile.team_id.as_deref()) {
+            Ok(line) => (line.command, line.output),
+            Err(err) => {
+                // Also covers `help` and `--help`.
+                let _ = err.print();
+                continue;
+            }
+        };
+        let format = output
+            .or(command.format_override())
+            .unwrap_or(session.format);
+        match session.run(command).await {
+            Ok(output) => output.render(format, &mut std::io::stdout())?,
+            Err(err) => render_error(&err, format),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+/// Parses `args`, filling in `team` if the team ID is the only thing
+/// missing.
+pub(crate) fn parse_line(args: Vec<String>, team: Option<&str>) -> Result<ShellLine, clap::Error> {
+    let args: Vec<OsString> = args.into_iter().map(OsString::from).collect();
+    let err = match ShellLine::try_parse_from(&args) {
+        Ok(line) => return Ok(line),
+        Err(err) => err,
+    };
+    if err.kind() == ErrorKind::MissingRequiredArgument {
+        let retry =
+            team.and_then(|team| profile::with_default_team(&ShellLine::command(), &args, team));
+        if let Some(line) = retry.and_then(|args| ShellLine::try_parse_from(args).ok()) {
+            return Ok(line);
+        }
+    }
+    Err(err)
+}
+
+fn default_history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".aranya_history"))
+}
+
+/// Completion candidates for each kind of ID argument.
+#[derive(Default)]
+struct Candidates {
+    teams: Vec<String>,
+    devices: Vec<String>,
+    labels: Vec<String>,
+    channels: Vec<String>,
+    streams: Vec<String>,
+}
+
+impl Candidates {
+    fn from_session(session: &Session) -> Self {
+        let registry = &session.registry;
+        Self {
+            teams: session.known.teams.iter().cloned().collect(),
+            devices: session.known.devices.iter().cloned().collect(),
+            labels: session.known.labels.iter().cloned().collect(),
+            channels: registry
+                .list_channels()
+                .into_iter()
+                .chain(registry.list_received_channels())
+                .collect(),
+            streams: registry
+                .list_streams()
+                .into_iter()
+                .chain(registry.list_peer_streams())
+                .collect(),
+        }
+    }
+
+    /// Returns the candidates for the argument with clap ID `arg`.
+    fn for_arg(&self, arg: &str) -> Vec<String> {
+        match arg {
+            "team_id" => self.teams.clone(),
+            "device_id" => self.devices.clone(),
+            "label_id" => self.labels.clone(),
+            "channel_id" => self.channels.clone(),
+            "stream_id" => self.streams.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Helper, Highlighter, Hinter, Validator)]
+struct ShellHelper {
+    command: Command,
+    candidates: Arc<Mutex<Candidates>>,
+}
+
+impl ShellHelper {
+    fn new(candidates: Arc<Mutex<Candidates>>) -> Self {
+        let mut command = ShellLine::command();
+        // Propagates global options such as `-o` to the subcommands.
+        command.build();
+        Self {
+            command,
+            candidates,
+        }
+    }
+
+    /// Returns everything that could be typed for `word`, given the
+    /// preceding `words` on the line.
+    fn options(&self, words: &[&str], word: &str) -> Vec<String> {
+        let Some((name, rest)) = words.split_first() else {
+            return self
+                .command
+                .get_subcommands()
+                .map(|c| c.get_name().to_owned())
+                .chain(["exit".to_owned(), "quit".to_owned()])
+                .collect();
+        };
+        let Some(sub) = self.command.find_subcommand(name) else {
+            return Vec::new();
+        };
+        if word.starts_with('-') {
+            return sub
+                .get_opts()
+                .filter_map(|a| a.get_long())
+                .map(|l| format!("--{l}"))
+                .collect();
+        }
+
+        // Figure out which positional argument `word` fills,
+        // skipping over flags and their values.
+        let mut index = 0;
+        let mut skip_value = false;
+        for w in rest {
+            if skip_value {
+                skip_value = false;
+            } else if w.starts_with('-') {
+                skip_value = profile::takes_value(sub, w);
+            } else {
+                index += 1;
+            }
+        }
+        if skip_value {
+            return Vec::new();
+        }
+        let Some(arg) = sub.get_positionals().nth(index) else {
+            return Vec::new();
+        };
+        self.candidates
+            .lock()
+            .map(|c| c.for_arg(arg.get_id().as_str()))
+            .unwrap_or_default()
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, words, word) = split_line(line, pos);
+        let pairs = self
+            .options(&words, word)
+            .into_iter()
+            .filter(|o| o.starts_with(word))
+            .map(|o| Pair {
+                display: o.clone(),
+                replacement: o,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+/// Splits `line` up to the cursor at `pos` into the complete words before
+/// the cursor and the word being typed, which starts at the returned
+/// offset.
+fn split_line(line: &str, pos: usize) -> (usize, Vec<&str>, &str) {
+    let head = line.get(..pos).unwrap_or(line);
+    let start = head.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let words = head[..start].split_whitespace().collect();
+    (start, words, &head[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper() -> ShellHelper {
+        let candidates = Candidates {
+            teams: vec!["T1".into()],
+            devices: vec!["D1".into()],
+            labels: vec!["L1".into()],
+            channels: vec!["C1".into()],
+            streams: vec!["S1".into()],
+        };
+        ShellHelper::new(Arc::new(Mutex::new(candidates)))
+    }
+
+    /// Returns the candidates for the end of `line`.
+    fn options(line: &str) -> Vec<String> {
+        let (_, words, word) = split_line(line, line.len());
+        helper().options(&words, word)
+    }
+
+    #[test]
+    fn test_split_line() {
+        assert_eq!(split_line("", 0), (0, vec![], ""));
+        assert_eq!(split_line("add-t", 5), (0, vec![], "add-t"));
+        assert_eq!(
+            split_line("query-device-role  T1 D", 23),
+            (22, vec!["query-device-role", "T1"], "D")
+        );
+        assert_eq!(
+            split_line("query-device-role T1 ", 21),
+            (21, vec!["query-device-role", "T1"], "")
+        );
+        // Only what is before the cursor counts.
+        assert_eq!(
+            split_line("query-device-role T1 D1", 18),
+            (18, vec!["query-device-role"], "")
+        );
+    }
+
+    #[test]
+    fn test_completes_each_id() {
+        assert_eq!(options("query-devices-on-team "), ["T1"]);
+        assert_eq!(options("query-device-role T1 "), ["D1"]);
+        assert_eq!(options("delete-label T1 "), ["L1"]);
+        assert_eq!(options("close-channel "), ["C1"]);
+        assert_eq!(options("close-stream "), ["S1"]);
+        assert!(options("close-stream S1 ").is_empty());
+    }
+
+    #[test]
+    fn test_skips_flag_values() {
+        assert_eq!(options("query-device-role --output json T1 "), ["D1"]);
+        assert_eq!(options("query-device-role --output=json T1 "), ["D1"]);
+        assert_eq!(options("query-device-role -o json T1 "), ["D1"]);
+        assert_eq!(options("query-device-role -ojson T1 "), ["D1"]);
+        // The word after a flag that takes a value is its value.
+        assert!(options("query-device-role -o ").is_empty());
+        assert!(options("query-device-role --output ").is_empty());
+    }
+
+    #[test]
+    fn test_completes_commands_and_flags() {
+        let commands = options("");
+        assert!(commands.iter().any(|c| c == "query-device-role"));
+        assert!(commands.iter().any(|c| c == "exit"));
+        assert!(options("receive-stream-data --").contains(&"--timeout".to_owned()));
+    }
+}
@@ -0,0 +1,262 @@
+//! Onboarding devices with invitation tokens.
+//!
+//! `team invite` adds a device from its exported key bundle and prints a
+//! token holding everything the device needs to join: the team ID, the
+//! team's PSK seed wrapped for the device's encryption key, and the
+//! inviter's sync address. `team join` consumes the token.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use aranya_client::{QuicSyncConfig, SyncPeerConfig, TeamConfig};
+use aranya_crypto::IdentityVerifyingKey;
+use aranya_daemon_api::{DeviceId, KeyBundle, LabelId, NetIdentifier, Role, TeamId, Text, CS};
+use aranya_util::Addr;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::commands::{parse_chan_op, parse_role};
+use crate::output::{KeyBundleOutput, Output};
+use crate::session::Session;
+
+/// Prefix that identifies (and versions) invitation tokens.
+const TOKEN_PREFIX: &str = "aranya-invite-v1:";
+
+#[derive(Clone, Subcommand, Serialize, Deserialize)]
+pub(crate) enum TeamCommand {
+    /// Add a device to a team and print an invitation token for it
+    Invite {
+        /// Team ID
+        team_id: String,
+        /// File with the device's key bundle, as printed by
+        /// `get-key-bundle -o json` (`-` for stdin)
+        key_bundle: PathBuf,
+        /// Role to assign (Owner, Admin, Operator, Member)
+        #[arg(long)]
+        role: Option<String>,
+        /// Label to assign, as LABEL_ID[:OP] where OP is SendOnly,
+        /// RecvOnly, or SendRecv (the default). May be repeated.
+        #[arg(long = "label")]
+        labels: Vec<String>,
+        /// AQC network identifier to assign (e.g., "192.168.1.100:5050")
+        #[arg(long)]
+        net_id: Option<String>,
+        /// Sync address the device should sync with (defaults to this
+        /// daemon's sync address)
+        #[arg(long)]
+        sync_addr: Option<String>,
+    },
+    /// Join a team using an invitation token
+    Join {
+        /// Invitation token from `team invite`
+        token: String,
+        /// Sync interval in seconds (defaults to the profile's, or 1)
+        #[arg(long)]
+        interval_secs: Option<u64>,
+    },
+}
+
+/// The contents of an invitation token.
+#[derive(Debug, Serialize, Deserialize)]
+struct Invitation {
+    team_id: TeamId,
+    /// The team's PSK seed, wrapped for the invited device.
+    wrapped_seed: Vec<u8>,
+    /// Where the invited device should sync from.
+    sync_addr: Addr,
+}
+
+impl Invitation {
+    fn encode(&self) -> Result<String> {
+        let bytes = postcard::to_allocvec(self).context("unable to encode invitation")?;
+        Ok(format!("{TOKEN_PREFIX}{}", hex::encode(bytes)))
+    }
+
+    fn decode(token: &str) -> Result<Self> {
+        let hex = token
+            .trim()
+            .strip_prefix(TOKEN_PREFIX)
+            .context("not an invitation token")?;
+        let bytes = hex::decode(hex).context("invalid invitation token")?;
+        postcard::from_bytes(&bytes).context("invalid invitation token")
+    }
+}
+
+/// A key bundle as printed by `get-key-bundle -o json`, or just the
+/// bundle itself.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExportedKeyBundle {
+    Wrapped { key_bundle: KeyBundleOutput },
+    Bare(KeyBundleOutput),
+}
+
+/// Reads a key bundle from `path` (`-` for stdin).
+pub(crate) fn read_key_bundle(path: &Path) -> Result<KeyBundle> {
+    let mut data = String::new();
+    if path.as_os_str() == "-" {
+        std::io::stdin().read_to_string(&mut data)?;
+    } else {
+        data = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+    }
+    let (ExportedKeyBundle::Wrapped { key_bundle: kb } | ExportedKeyBundle::Bare(kb)) =
+        serde_json::from_str(&data).context("invalid key bundle")?;
+    Ok(KeyBundle {
+        identity: hex::decode(kb.identity).context("Invalid hex for identity key")?,
+        signing: hex::decode(kb.signing).context("Invalid hex for signing key")?,
+        encoding: hex::decode(kb.encoding).context("Invalid hex for encoding key")?,
+    })
+}
+
+/// Computes the ID of the device that owns `keys`.
+pub(crate) fn device_id(keys: &KeyBundle) -> Result<DeviceId> {
+    let pk: IdentityVerifyingKey<CS> =
+        postcard::from_bytes(&keys.identity).context("invalid identity key")?;
+    Ok(pk.id()?.into_id().into())
+}
+
+impl Session {
+    /// Runs a `team` subcommand.
+    pub(crate) async fn run_team(&mut self, command: TeamCommand) -> Result<Output> {
+        let output = match command {
+            TeamCommand::Invite {
+                team_id,
+                key_bundle,
+                role,
+                labels,
+                net_id,
+                sync_addr,
+            } => {
+                let team_id = TeamId::from_str(&team_id)?;
+                let keys = read_key_bundle(&key_bundle)?;
+                let device_id = device_id(&keys)?;
+
+                // Check everything up front so that a typo does not leave
+                // a half-configured device behind.
+                let role = role.as_deref().map(parse_role).transpose()?;
+                let labels = labels
+                    .iter()
+                    .map(|l| {
+                        let (id, op) = l.split_once(':').unwrap_or((l, "SendRecv"));
+                        let id = LabelId::from_str(id)
+                            .map_err(|e| anyhow::anyhow!("Invalid label ID format: {}", e))?;
+                        Ok((id, parse_chan_op(op)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let net_id = net_id
+                    .map(|n| Text::try_from(n).map(NetIdentifier))
+                    .transpose()?;
+                // The policy only lets Members have a network identifier.
+                if net_id.is_some() && role != Some(Role::Member) {
+                    anyhow::bail!("--net-id requires --role Member");
+                }
+                let sync_addr = match sync_addr {
+                    Some(addr) => Addr::from_str(&addr)?,
+                    None => {
+                        let addr = self.client.local_addr().await?;
+                        if addr.ip().is_unspecified() {
+                            anyhow::bail!(
+                                "daemon's sync address {addr} is not reachable by peers; use --sync-addr"
+                            );
+                        }
+                        addr.into()
+                    }
+                };
+
+                let mut team = self.client.team(team_id);
+                let wrapped_seed = team.encrypt_psk_seed_for_peer(&keys.encoding).await?;
+                let key_bundle = KeyBundleOutput::from(&keys);
+                team.add_device_to_team(keys).await?;
+                // Devices are added as Members, and the policy refuses to
+                // assign a role the device already has.
+                if let Some(role) = role.filter(|r| *r != Role::Member) {
+                    team.assign_role(device_id, role).await?;
+                }
+                for (label_id, op) in &labels {
+                    team.assign_label(device_id, *label_id, *op).await?;
+                }
+                if let Some(net_id) = &net_id {
+                    team.assign_aqc_net_identifier(device_id, net_id.clone())
+                        .await?;
+                }
+                self.known.devices.insert(device_id.to_string());
+
+                let token = Invitation {
+                    team_id,
+                    wrapped_seed,
+                    sync_addr,
+                }
+                .encode()?;
+                Output::Invitation {
+                    team_id: team_id.to_string(),
+                    device_id: device_id.to_string(),
+                    key_bundle,
+                    role: role.map(|r| format!("{r:?}")),
+                    labels: labels.iter().map(|(id, _)| id.to_string()).collect(),
+                    net_id: net_id.map(|n| n.0.to_string()),
+                    sync_addr: sync_addr.to_string(),
+                    token,
+                }
+            }
+            TeamCommand::Join {
+                token,
+                interval_secs,
+            } => {
+                let invitation = Invitation::decode(&token)?;
+                let interval_secs = interval_secs.unwrap_or(self.profile.sync_interval_secs());
+
+                let cfg = TeamConfig::builder()
+                    .quic_sync(
+                        QuicSyncConfig::builder()
+                            .wrapped_seed(&invitation.wrapped_seed)?
+                            .build()?,
+                    )
+                    .build()?;
+                let mut team = self
+                    .client
+                    .add_team(invitation.team_id, cfg)
+                    .await
+                    .context("Failed to add team")?;
+                let config = SyncPeerConfig::builder()
+                    .interval(Duration::from_secs(interval_secs))
+                    .build()?;
+                team.add_sync_peer(invitation.sync_addr, config).await?;
+                self.known.teams.insert(invitation.team_id.to_string());
+
+                Output::TeamJoined {
+                    team_id: invitation.team_id.to_string(),
+                    sync_peer: invitation.sync_addr.to_string(),
+                    interval_secs,
+                }
+            }
+        };
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invitation_round_trip() {
+        let invitation = Invitation {
+            team_id: TeamId::from_str("7bLNDMRvqmKGawYwsYZYJq2EPsoUBZDbBWqy7NDHqiHD").unwrap(),
+            wrapped_seed: vec![1, 2, 3],
+            sync_addr: Addr::from_str("127.0.0.1:5050").unwrap(),
+        };
+        let token = invitation.encode().unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+
+        let got = Invitation::decode(&format!(" {token}\n")).unwrap();
+        assert_eq!(got.team_id, invitation.team_id);
+        assert_eq!(got.wrapped_seed, invitation.wrapped_seed);
+        assert_eq!(got.sync_addr, invitation.sync_addr);
+
+        assert!(Invitation::decode(&token[TOKEN_PREFIX.len()..]).is_err());
+    }
+}
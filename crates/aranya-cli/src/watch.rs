@@ -0,0 +1,147 @@
+//! Printing a team's events as they happen.
+//!
+//! Each event is printed as one line of JSON, with the event's name in
+//! `event` and its fields alongside:
+//!
+//! ```text
+//! {"team_id":"...","event":"label-assigned","label_id":"...","name":"TELEMETRY",...}
+//! ```
+//!
+//! A `lagged` event means `count` events were dropped because they were
+//! not read quickly enough.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use aranya_daemon_api::{TeamEvent, TeamEventKind, TeamId};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::session::Session;
+
+/// The kinds of events that `watch` can be limited to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub(crate) enum EventKind {
+    TeamCreated,
+    TeamTerminated,
+    DeviceAdded,
+    DeviceRemoved,
+    RoleAssigned,
+    RoleRevoked,
+    LabelCreated,
+    LabelDeleted,
+    LabelAssigned,
+    LabelRevoked,
+    AqcNetIdentifierSet,
+    AqcNetIdentifierUnset,
+    AqcChannelClosed,
+}
+
+impl From<EventKind> for TeamEventKind {
+    fn from(kind: EventKind) -> Self {
+        match kind {
+            EventKind::TeamCreated => Self::TeamCreated,
+            EventKind::TeamTerminated => Self::TeamTerminated,
+            EventKind::DeviceAdded => Self::DeviceAdded,
+            EventKind::DeviceRemoved => Self::DeviceRemoved,
+            EventKind::RoleAssigned => Self::RoleAssigned,
+            EventKind::RoleRevoked => Self::RoleRevoked,
+            EventKind::LabelCreated => Self::LabelCreated,
+            EventKind::LabelDeleted => Self::LabelDeleted,
+            EventKind::LabelAssigned => Self::LabelAssigned,
+            EventKind::LabelRevoked => Self::LabelRevoked,
+            EventKind::AqcNetIdentifierSet => Self::AqcNetIdentifierSet,
+            EventKind::AqcNetIdentifierUnset => Self::AqcNetIdentifierUnset,
+            EventKind::AqcChannelClosed => Self::AqcChannelClosed,
+        }
+    }
+}
+
+/// Prints the events of `team_id` until `count` have been printed, or
+/// until interrupted.
+pub(crate) async fn run(
+    session: &mut Session,
+    team_id: &str,
+    kinds: Vec<EventKind>,
+    count: Option<usize>,
+) -> Result<()> {
+    #![allow(clippy::disallowed_macros)]
+    let team = TeamId::from_str(team_id)?;
+    let kinds: Vec<TeamEventKind> = kinds.into_iter().map(Into::into).collect();
+    let mut sub = session.client.team(team).subscribe(&kinds).await?;
+
+    let mut printed = 0;
+    let result = loop {
+        if count.is_some_and(|count| printed >= count) {
+            break Ok(());
+        }
+        let event = tokio::select! {
+            event = sub.next() => event,
+            _ = tokio::signal::ctrl_c() => break Ok(()),
+        };
+        match event {
+            Ok(event) => {
+                println!("{}", event_json(team_id, &event)?);
+                printed += 1;
+            }
+            Err(err) => break Err(err.into()),
+        }
+    };
+    sub.unsubscribe().await?;
+    result
+}
+
+/// Flattens `event` into one JSON object tagged with its team and name.
+fn event_json(team_id: &str, event: &TeamEvent) -> Result<Value> {
+    let Value::Object(tagged) = serde_json::to_value(event)? else {
+        anyhow::bail!("unexpected event encoding");
+    };
+    let (name, fields) = tagged
+        .into_iter()
+        .next()
+        .context("unexpected event encoding")?;
+
+    let mut object = Map::new();
+    object.insert("team_id".into(), team_id.into());
+    object.insert("event".into(), kebab_case(&name).into());
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+    Ok(Value::Object(object))
+}
+
+/// Converts a variant name like `LabelAssigned` to `label-assigned`.
+fn kebab_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('-');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let value = event_json("TEAM", &TeamEvent::Lagged { count: 3 }).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"{"team_id":"TEAM","event":"lagged","count":3}"#
+        );
+
+        // Event names match the values of `--event`.
+        let name = kebab_case("AqcNetIdentifierSet");
+        let kind = EventKind::from_str(&name, false).unwrap();
+        assert_eq!(kind, EventKind::AqcNetIdentifierSet);
+    }
+}
@@ -163,6 +163,12 @@ sleep $SLEEP_INTERVAL
 # STEP 12: AQC Communication Test (MemberA to MemberB using new CLI API)
 echo "=== STEP 12: AQC Communication Test (MemberA to MemberB, new CLI API) ==="
 
+# Channel and stream IDs only live as long as the process that opened them,
+# so run an agent per daemon to keep them open between commands.
+for i in 1 4 5; do
+    aranya --uds-path /tmp/aranya$i/run/uds.sock agent start
+done
+
 # MemberA creates a bidirectional channel to MemberB
 echo "=== MemberA creating bidirectional channel to MemberB ==="
 CHANNEL_OUTPUT=$(aranya --uds-path /tmp/aranya4/run/uds.sock create-bidi-channel $MAIN_TEAM_ID "127.0.0.1:6005" $LABEL_ID)
@@ -251,6 +257,10 @@ aranya --uds-path /tmp/aranya1/run/uds.sock close-channel $CHANNEL_ID
 
 echo "✅ Owner self-send AQC test (new CLI API) completed!"
 
+for i in 1 4 5; do
+    aranya --uds-path /tmp/aranya$i/run/uds.sock agent stop
+done
+
 echo "=== END ARANYA AQC CHANNELS MULTI-DAEMON FLOW ==="
 
 
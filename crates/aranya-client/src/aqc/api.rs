@@ -6,7 +6,10 @@ use aranya_daemon_api::{LabelId, NetIdentifier, TeamId};
 use tarpc::context;
 use tracing::{debug, instrument};
 
-use super::{net::TryReceiveError, AqcBidiChannel, AqcPeerChannel, AqcSendChannel};
+use super::{
+    net::{AqcChannelId, TryReceiveError},
+    AqcBidiChannel, AqcPeerChannel, AqcReceiveChannel, AqcSendChannel,
+};
 use crate::{
     error::{aranya_error, no_addr, AqcError, IpcError},
     Client,
@@ -128,29 +131,35 @@ impl<'a> AqcChannels<'a> {
     /// Deletes an AQC bidi channel.
     #[instrument(skip_all, fields(?chan))]
     pub async fn delete_bidi_channel(&mut self, mut chan: AqcBidiChannel) -> crate::Result<()> {
-        // let _ctrl = self
-        //     .client
-        //     .daemon
-        //     .delete_aqc_bidi_channel(context::current(), chan.aqc_id().into_id().into())
-        //     .await
-        //     .map_err(IpcError::new)?
-        //     .map_err(aranya_error)?;
         chan.close();
-        Ok(())
+        self.client
+            .aqc
+            .delete_channel(AqcChannelId::Bidi(chan.aqc_id()))
+            .await
     }
 
     /// Deletes an AQC uni channel.
     #[instrument(skip_all, fields(?chan))]
     pub async fn delete_uni_channel(&mut self, mut chan: AqcSendChannel) -> crate::Result<()> {
-        // let _ctrl = self
-        //     .client
-        //     .daemon
-        //     .delete_aqc_uni_channel(context::current(), chan.aqc_id().into_id().into())
-        //     .await
-        //     .map_err(IpcError::new)?
-        //     .map_err(aranya_error)?;
         chan.close();
-        Ok(())
+        self.client
+            .aqc
+            .delete_channel(AqcChannelId::Uni(chan.aqc_id()))
+            .await
+    }
+
+    /// Deletes the receive side of an AQC uni channel that a peer
+    /// created.
+    #[instrument(skip_all, fields(?chan))]
+    pub async fn delete_receive_uni_channel(
+        &mut self,
+        mut chan: AqcReceiveChannel,
+    ) -> crate::Result<()> {
+        chan.close();
+        self.client
+            .aqc
+            .delete_channel(AqcChannelId::Uni(chan.aqc_id()))
+            .await
     }
 
     /// Waits for a peer to create an AQC channel with this client.
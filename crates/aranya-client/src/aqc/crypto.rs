@@ -1,9 +1,9 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    sync::{Arc, LazyLock, Mutex},
+    collections::HashMap,
+    sync::{Arc, Mutex},
 };
 
-use aranya_daemon_api::{AqcPsk, AqcPsks, CipherSuiteId};
+use aranya_daemon_api::{AqcCtrlPsk, AqcPsk, AqcPsks, CipherSuiteId, TeamId};
 #[allow(deprecated)]
 use s2n_quic::provider::tls::rustls::rustls::{
     self,
@@ -11,25 +11,18 @@ use s2n_quic::provider::tls::rustls::rustls::{
     crypto::{hash::HashAlgorithm, PresharedKey},
     server::SelectsPresharedKeys,
 };
-use tokio::sync::mpsc;
-use tracing::error;
-
-// Define constant PSK identity and bytes
-pub(super) const PSK_IDENTITY_CTRL: &[u8; 16] = b"aranya-ctrl-psk!"; // 16 bytes
-const PSK_BYTES_CTRL: &[u8; 32] = b"this-is-a-32-byte-secret-psk!!!!"; // 32 bytes
-
-pub(super) static CTRL_PSK: LazyLock<Arc<PresharedKey>> = LazyLock::new(|| {
-    let psk = PresharedKey::external(PSK_IDENTITY_CTRL, PSK_BYTES_CTRL)
-        .expect("identity and bytes are small and nonzero");
-    let psk = psk
-        .with_hash_alg(HashAlgorithm::SHA384)
-        .expect("valid hash alg");
-    Arc::new(psk)
-});
+use tokio::sync::{mpsc, Notify};
+
+/// A control PSK and the team it belongs to.
+type CtrlKey = (TeamId, Arc<PresharedKey>);
 
 #[derive(Debug)]
 pub(crate) struct ServerPresharedKeys {
     keys: Mutex<HashMap<Vec<u8>, Arc<PresharedKey>>>,
+    /// Control PSKs and the team each one belongs to.
+    ctrl_keys: Mutex<HashMap<Vec<u8>, CtrlKey>>,
+    /// Notified when a handshake uses an unknown identity.
+    missed: Notify,
     // Optional sender to report the selected identity
     identity_sender: mpsc::Sender<Vec<u8>>,
 }
@@ -42,24 +35,14 @@ impl ServerPresharedKeys {
         (
             Self {
                 keys: Mutex::default(),
+                ctrl_keys: Mutex::default(),
+                missed: Notify::new(),
                 identity_sender: identity_tx,
             },
             identity_rx,
         )
     }
 
-    pub fn insert(&self, psk: Arc<PresharedKey>) {
-        let identity = psk.identity().to_vec();
-        match self.keys.lock().expect("poisoned").entry(identity.clone()) {
-            Entry::Vacant(v) => {
-                v.insert(psk);
-            }
-            Entry::Occupied(_) => {
-                error!("Duplicate PSK identity inserted: {:?}", identity);
-            }
-        }
-    }
-
     pub fn load_psks(&self, psks: AqcPsks) {
         let mut keys = self.keys.lock().expect("poisoned");
         for (suite, psk) in psks {
@@ -68,11 +51,68 @@ impl ServerPresharedKeys {
             keys.insert(identity, key);
         }
     }
+
+    /// Removes the PSKs with `identities`, so that connections
+    /// using them are refused.
+    pub fn remove_psks<'a, I>(&self, identities: I)
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut keys = self.keys.lock().expect("poisoned");
+        for identity in identities {
+            keys.remove(identity);
+        }
+    }
+
+    /// Replaces the control PSKs with `psks`.
+    ///
+    /// Control connections that use any other identity are
+    /// refused.
+    pub fn set_ctrl_psks(&self, psks: &[AqcCtrlPsk]) -> Option<()> {
+        let keys = psks
+            .iter()
+            .map(|psk| {
+                let key = make_ctrl_preshared_key(psk)?;
+                Some((psk.identity.to_vec(), (psk.team, key)))
+            })
+            .collect::<Option<_>>()?;
+        *self.ctrl_keys.lock().expect("poisoned") = keys;
+        Some(())
+    }
+
+    /// Returns the team of the control PSK with `identity`, if
+    /// there is one.
+    pub fn ctrl_team(&self, identity: &[u8]) -> Option<TeamId> {
+        self.ctrl_keys
+            .lock()
+            .expect("poisoned")
+            .get(identity)
+            .map(|(team, _)| *team)
+    }
+
+    /// Waits until a handshake uses an identity that is not one
+    /// of our PSKs, e.g., a control PSK that has not been fetched
+    /// yet.
+    pub async fn missed(&self) {
+        self.missed.notified().await
+    }
 }
 
 impl SelectsPresharedKeys for ServerPresharedKeys {
     fn load_psk(&self, identity: &[u8]) -> Option<Arc<PresharedKey>> {
-        self.keys.lock().expect("poisoned").get(identity).cloned()
+        if let Some(key) = self.keys.lock().expect("poisoned").get(identity) {
+            return Some(Arc::clone(key));
+        }
+        let key = self
+            .ctrl_keys
+            .lock()
+            .expect("poisoned")
+            .get(identity)
+            .map(|(_, key)| Arc::clone(key));
+        if key.is_none() {
+            self.missed.notify_one();
+        }
+        key
     }
 
     fn chosen(&self, identity: &[u8]) {
@@ -83,22 +123,26 @@ impl SelectsPresharedKeys for ServerPresharedKeys {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ClientPresharedKeys {
     keys: Mutex<Vec<Arc<PresharedKey>>>,
 }
 
 impl ClientPresharedKeys {
-    pub fn new(key: Arc<PresharedKey>) -> Self {
-        Self {
-            keys: Mutex::new(vec![key]),
+    /// Uses the current control PSKs for `team` from `psks`.
+    ///
+    /// Returns `None` if there are none.
+    pub fn set_ctrl_psks(&self, team: TeamId, psks: &[AqcCtrlPsk]) -> Option<()> {
+        let keys = psks
+            .iter()
+            .filter(|psk| psk.team == team && psk.current)
+            .map(make_ctrl_preshared_key)
+            .collect::<Option<Vec<_>>>()?;
+        if keys.is_empty() {
+            return None;
         }
-    }
-
-    pub fn set_key(&self, key: Arc<PresharedKey>) {
-        let mut keys_guard = self.keys.lock().expect("Client PSK mutex poisoned");
-        keys_guard.clear();
-        keys_guard.push(key);
+        *self.keys.lock().expect("Client PSK mutex poisoned") = keys;
+        Some(())
     }
 
     pub fn load_psks(&self, psks: AqcPsks) {
@@ -123,6 +167,12 @@ fn make_preshared_key(suite: CipherSuiteId, psk: AqcPsk) -> Option<Arc<Preshared
     Some(Arc::new(key))
 }
 
+fn make_ctrl_preshared_key(psk: &AqcCtrlPsk) -> Option<Arc<PresharedKey>> {
+    let key = PresharedKey::external(&psk.identity, psk.secret.raw_secret_bytes())?
+        .with_hash_alg(suite_hash(psk.suite)?)?;
+    Some(Arc::new(key))
+}
+
 fn suite_hash(suite: CipherSuiteId) -> Option<HashAlgorithm> {
     Some(match suite {
         CipherSuiteId::TlsAes128GcmSha256 => HashAlgorithm::SHA256,
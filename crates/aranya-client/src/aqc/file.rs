@@ -0,0 +1,318 @@
+//! File transfer over AQC streams.
+//!
+//! A file is sent over a single bidirectional stream:
+//!
+//! 1. The sender sends a [`FileHeader`]: its postcard encoding prefixed
+//!    with its length as a big-endian `u32`.
+//! 2. The receiver replies with the number of bytes of the file it already
+//!    has from an earlier, interrupted transfer, as a big-endian `u64`.
+//! 3. The sender sends the rest of the file from that offset and then
+//!    closes its side of the stream.
+//! 4. The receiver checks the SHA-256 of the whole file and replies with a
+//!    single status byte.
+//!
+//! The receiver keeps partial files next to the destination, so an
+//! interrupted transfer is resumed by sending the same file again, e.g.
+//! on a new channel.
+
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+};
+use tracing::debug;
+
+use super::{AqcBidiStream, AqcReceiveStream};
+use crate::error::AqcError;
+
+/// The amount of file data sent at a time.
+pub const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Upper bound on the encoded size of a [`FileHeader`].
+const MAX_HEADER_SIZE: usize = 4096;
+
+/// The receiver stored the file and its hash matched.
+const STATUS_OK: u8 = 0;
+/// The receiver discarded the file because its hash did not match.
+const STATUS_HASH_MISMATCH: u8 = 1;
+
+/// Describes the file being sent.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FileHeader {
+    /// The file's name, without any directories.
+    pub name: String,
+    /// The file's size in bytes.
+    pub size: u64,
+    /// The SHA-256 of the file's contents.
+    pub sha256: [u8; 32],
+}
+
+/// The result of a file transfer.
+#[derive(Clone, Debug)]
+pub struct FileTransfer {
+    /// The file that was transferred.
+    pub header: FileHeader,
+    /// The number of bytes the receiver already had, so were not sent
+    /// again.
+    pub offset: u64,
+    /// Where the file was read from (sender) or written to (receiver).
+    pub path: PathBuf,
+}
+
+/// Sends the file at `path` over `stream`.
+///
+/// If the receiver has part of the file from an earlier transfer, only the
+/// remainder is sent. Returns once the receiver has verified the file.
+pub async fn send_file(stream: AqcBidiStream, path: &Path) -> Result<FileTransfer, AqcError> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or(AqcError::InvalidFileTransfer("path has no file name"))?
+        .to_owned();
+    let mut file = File::open(path).await.map_err(AqcError::FileIo)?;
+    let size = file.metadata().await.map_err(AqcError::FileIo)?.len();
+    let sha256 = hash_prefix(&mut file, size).await?;
+    let header = FileHeader { name, size, sha256 };
+
+    let (mut send, recv) = stream.split();
+    let mut recv = StreamReader::new(recv);
+
+    let encoded = postcard::to_allocvec(&header).map_err(AqcError::Serde)?;
+    let mut frame = BytesMut::with_capacity(4 + encoded.len());
+    frame.extend_from_slice(
+        &u32::try_from(encoded.len())
+            .unwrap_or(u32::MAX)
+            .to_be_bytes(),
+    );
+    frame.extend_from_slice(&encoded);
+    send.send(frame.freeze()).await?;
+
+    let offset = recv.read_u64().await?;
+    if offset > size {
+        return Err(AqcError::InvalidFileTransfer(
+            "receiver offset is past end of file",
+        ));
+    }
+    debug!(name = header.name, size, offset, "sending file");
+
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(AqcError::FileIo)?;
+    let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await.map_err(AqcError::FileIo)?;
+        if n == 0 {
+            break;
+        }
+        send.send(Bytes::copy_from_slice(&buf[..n])).await?;
+    }
+    send.close().await?;
+
+    match recv.read_exact(1).await?[0] {
+        STATUS_OK => Ok(FileTransfer {
+            header,
+            offset,
+            path: path.to_owned(),
+        }),
+        STATUS_HASH_MISMATCH => Err(AqcError::FileHashMismatch),
+        _ => Err(AqcError::InvalidFileTransfer(
+            "unknown status from receiver",
+        )),
+    }
+}
+
+/// Receives a file sent with [`send_file`] into `dir`.
+///
+/// The file is written to `dir/<name>.<hash>.part`, where `<hash>` is the
+/// first 16 hex digits of its SHA-256, and renamed to `dir/<name>` once
+/// its hash has been verified. If the transfer is
+/// interrupted, the partial file is kept so that sending the same file
+/// again resumes where it left off.
+pub async fn receive_file(stream: AqcBidiStream, dir: &Path) -> Result<FileTransfer, AqcError> {
+    let (mut send, recv) = stream.split();
+    let mut recv = StreamReader::new(recv);
+
+    let len = usize::try_from(recv.read_u32().await?).unwrap_or(usize::MAX);
+    if len > MAX_HEADER_SIZE {
+        return Err(AqcError::InvalidFileTransfer("file header too large"));
+    }
+    let header: FileHeader =
+        postcard::from_bytes(&recv.read_exact(len).await?).map_err(AqcError::Serde)?;
+    let name = checked_name(&header.name)?;
+    let path = dir.join(name);
+    let part = dir.join(format!("{name}.{}.part", hex_prefix(&header.sha256)));
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&part)
+        .await
+        .map_err(AqcError::FileIo)?;
+    let mut offset = file.metadata().await.map_err(AqcError::FileIo)?.len();
+    if offset > header.size {
+        offset = 0;
+        file.set_len(0).await.map_err(AqcError::FileIo)?;
+    }
+    let mut hasher = Sha256::new();
+    hash_into(&mut file, offset, &mut hasher).await?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(AqcError::FileIo)?;
+    debug!(name, size = header.size, offset, "receiving file");
+
+    send.send(Bytes::copy_from_slice(&offset.to_be_bytes()))
+        .await?;
+
+    // Keep whatever arrived, even if the transfer fails part way.
+    let copied = copy_to_file(&mut recv, &mut file, &mut hasher, header.size - offset).await;
+    file.flush().await.map_err(AqcError::FileIo)?;
+    file.sync_all().await.map_err(AqcError::FileIo)?;
+    drop(file);
+    if copied? < header.size - offset {
+        return Err(AqcError::InvalidFileTransfer("transfer interrupted"));
+    }
+
+    if <[u8; 32]>::from(hasher.finalize()) != header.sha256 {
+        // Resuming would not help, so start over next time.
+        let _ = fs::remove_file(&part).await;
+        send.send(Bytes::from_static(&[STATUS_HASH_MISMATCH]))
+            .await?;
+        let _ = send.close().await;
+        return Err(AqcError::FileHashMismatch);
+    }
+    fs::rename(&part, &path).await.map_err(AqcError::FileIo)?;
+    send.send(Bytes::from_static(&[STATUS_OK])).await?;
+    send.close().await?;
+
+    Ok(FileTransfer {
+        header,
+        offset,
+        path,
+    })
+}
+
+/// Copies the rest of the stream into `file`, returning how many bytes
+/// were copied. Fails if the stream holds more than `max` bytes.
+async fn copy_to_file(
+    recv: &mut StreamReader,
+    file: &mut File,
+    hasher: &mut Sha256,
+    max: u64,
+) -> Result<u64, AqcError> {
+    let mut copied = 0u64;
+    while let Some(chunk) = recv.next_chunk().await? {
+        copied += chunk.len() as u64;
+        if copied > max {
+            return Err(AqcError::InvalidFileTransfer(
+                "more data than the header announced",
+            ));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(AqcError::FileIo)?;
+    }
+    Ok(copied)
+}
+
+/// Returns the SHA-256 of the first `len` bytes of `file`.
+async fn hash_prefix(file: &mut File, len: u64) -> Result<[u8; 32], AqcError> {
+    let mut hasher = Sha256::new();
+    hash_into(file, len, &mut hasher).await?;
+    Ok(hasher.finalize().into())
+}
+
+/// Feeds the first `len` bytes of `file` into `hasher`.
+async fn hash_into(file: &mut File, len: u64, hasher: &mut Sha256) -> Result<(), AqcError> {
+    file.seek(SeekFrom::Start(0))
+        .await
+        .map_err(AqcError::FileIo)?;
+    let mut reader = file.take(len);
+    let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await.map_err(AqcError::FileIo)?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// Makes sure a peer-supplied file name cannot escape the destination
+/// directory.
+fn checked_name(name: &str) -> Result<&str, AqcError> {
+    let valid =
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0']);
+    if valid {
+        Ok(name)
+    } else {
+        Err(AqcError::InvalidFileTransfer("invalid file name"))
+    }
+}
+
+fn hex_prefix(hash: &[u8; 32]) -> String {
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&hash[..8]);
+    format!("{:016x}", u64::from_be_bytes(prefix))
+}
+
+/// Reads framed values from a receive stream, whose chunks do not line
+/// up with what the peer sent.
+struct StreamReader {
+    stream: AqcReceiveStream,
+    buf: BytesMut,
+}
+
+impl StreamReader {
+    fn new(stream: AqcReceiveStream) -> Self {
+        Self {
+            stream,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Returns the next data from the stream, or `None` once the peer has
+    /// closed it.
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, AqcError> {
+        if !self.buf.is_empty() {
+            return Ok(Some(self.buf.split().freeze()));
+        }
+        self.stream.receive().await
+    }
+
+    async fn read_exact(&mut self, n: usize) -> Result<Bytes, AqcError> {
+        while self.buf.len() < n {
+            match self.stream.receive().await? {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None => return Err(AqcError::InvalidFileTransfer("stream closed early")),
+            }
+        }
+        Ok(self.buf.split_to(n).freeze())
+    }
+
+    async fn read_u32(&mut self) -> Result<u32, AqcError> {
+        Ok(self.read_exact(4).await?.get_u32())
+    }
+
+    async fn read_u64(&mut self) -> Result<u64, AqcError> {
+        Ok(self.read_exact(8).await?.get_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_name() {
+        assert!(checked_name("report.pdf").is_ok());
+        assert!(checked_name(".hidden").is_ok());
+        for name in ["", ".", "..", "../etc/passwd", "a/b", "a\\b", "a\0b"] {
+            assert!(checked_name(name).is_err(), "{name:?}");
+        }
+    }
+}
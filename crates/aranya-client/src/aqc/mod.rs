@@ -1,8 +1,10 @@
 mod api;
 mod crypto;
+mod file;
 mod net;
 
 pub use api::AqcChannels;
+pub use file::{receive_file, send_file, FileHeader, FileTransfer, FILE_CHUNK_SIZE};
 pub(super) use net::AqcClient;
 pub use net::{
     channels::{
@@ -5,15 +5,17 @@
 use std::{
     collections::HashMap,
     net::{Ipv4Addr, SocketAddr},
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
 use aranya_crypto::aqc::{BidiChannelId, UniChannelId};
 use aranya_daemon_api::{
-    AqcBidiPsks, AqcCtrl, AqcPsks, AqcUniPsks, DaemonApiClient, LabelId, TeamId,
+    self as api, AqcBidiPsks, AqcCtrl, AqcCtrlPsk, AqcPsks, AqcUniPsks, DaemonApiClient, LabelId,
+    TeamEvent, TeamEventKind, TeamId,
 };
-use aranya_util::rustls::{NoCertResolver, SkipServerVerification};
+use aranya_util::rustls::{NoCertResolver, NoCertVerifier};
 use buggy::{Bug, BugExt as _};
 use bytes::{Bytes, BytesMut};
 use channels::AqcPeerChannel;
@@ -24,17 +26,22 @@ use s2n_quic::{
         congestion_controller::Bbr,
         tls::rustls::{
             self as rustls_provider,
-            rustls::{server::PresharedKeySelection, ClientConfig, ServerConfig},
+            rustls::{
+                client::PskKexMode, server::PresharedKeySelection, ClientConfig, ServerConfig,
+            },
         },
     },
     stream::BidirectionalStream,
     Client, Connection, Server,
 };
 use tarpc::context;
-use tokio::sync::mpsc;
+use tokio::{
+    sync::mpsc,
+    task::{AbortHandle, JoinSet},
+};
 use tracing::{debug, error, warn};
 
-use super::crypto::{ClientPresharedKeys, ServerPresharedKeys, CTRL_PSK, PSK_IDENTITY_CTRL};
+use super::crypto::{ClientPresharedKeys, ServerPresharedKeys};
 use crate::error::{aranya_error, AqcError, IpcError};
 
 pub mod channels;
@@ -42,6 +49,24 @@ pub mod channels;
 /// ALPN protocol identifier for Aranya QUIC Channels
 const ALPN_AQC: &[u8] = b"aqc-v1";
 
+/// How often the control PSKs are fetched from the daemon, so that
+/// channels are still received after they're rotated.
+///
+/// The daemon rotates them far less often than this.
+const CTRL_PSK_REFRESH: Duration = Duration::from_secs(10 * 60);
+
+/// The least time between fetches of the control PSKs that were
+/// prompted by failed handshakes.
+const CTRL_PSK_MIN_REFRESH: Duration = Duration::from_secs(1);
+
+/// How long the daemon waits for channel closures before
+/// answering an empty batch.
+const CLOSE_POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The application error code used to close a connection
+/// because its channel was closed by the daemon.
+const CHANNEL_CLOSED_ERROR_CODE: u32 = 1;
+
 /// An AQC client. Used to create and receive channels.
 #[derive(Debug)]
 pub(crate) struct AqcClient {
@@ -61,8 +86,18 @@ pub(crate) struct AqcClient {
     /// Receives latest selected PSK for accepted channel from server PSK provider.
     identity_rx: mpsc::Receiver<PskIdentity>,
 
-    /// Map of PSK identity to channel type
-    channels: HashMap<PskIdentity, AqcChannelInfo>,
+    /// Our channels, shared with `watchers`.
+    channels: Arc<OpenChannels>,
+    /// For each team, a task that closes channels when the
+    /// daemon says to, along with the task that refreshes the
+    /// server's control PSKs.
+    watchers: JoinSet<()>,
+    watched: HashMap<TeamId, AbortHandle>,
+
+    /// The control PSKs of every team, from the daemon.
+    ctrl_psks: Vec<AqcCtrlPsk>,
+    /// When `ctrl_psks` was last fetched.
+    ctrl_psks_refreshed: Instant,
 
     daemon: DaemonApiClient,
 }
@@ -71,22 +106,31 @@ pub(crate) struct AqcClient {
 type PskIdentity = Vec<u8>;
 
 impl AqcClient {
-    pub async fn new(server_addr: SocketAddr, daemon: DaemonApiClient) -> Result<Self, AqcError> {
-        let client_keys = Arc::new(ClientPresharedKeys::new(CTRL_PSK.clone()));
+    pub async fn new(server_addr: SocketAddr, daemon: DaemonApiClient) -> crate::Result<Self> {
+        let mut client = Self::start(server_addr, daemon)?;
+        client.refresh_ctrl_psks().await?;
+        client.watchers.spawn(refresh_ctrl_psks(
+            client.daemon.clone(),
+            Arc::clone(&client.server_keys),
+        ));
+        Ok(client)
+    }
 
-        // Create Client Config (INSECURE: Skips server cert verification)
+    fn start(server_addr: SocketAddr, daemon: DaemonApiClient) -> Result<Self, AqcError> {
+        let client_keys = Arc::new(ClientPresharedKeys::default());
+
+        // Create Client Config (PSKs only: every server certificate is rejected)
         let mut client_config = ClientConfig::builder()
             .dangerous()
-            .with_custom_certificate_verifier(SkipServerVerification::new())
+            .with_custom_certificate_verifier(NoCertVerifier::new())
             .with_no_client_auth();
         client_config.alpn_protocols = vec![ALPN_AQC.to_vec()]; // Set field directly
         client_config.preshared_keys = client_keys.clone(); // Pass the Arc<ClientPresharedKeys>
 
-        // TODO(jdygert): enable after rustls upstream fix.
-        // client_config.psk_kex_modes = vec![PskKexMode::PskOnly];
+        // Use (EC)DHE with the PSK for forward secrecy.
+        client_config.psk_kex_modes = vec![PskKexMode::PskWithDhe];
 
         let (server_keys, identity_rx) = ServerPresharedKeys::new();
-        server_keys.insert(CTRL_PSK.clone());
         let server_keys = Arc::new(server_keys);
 
         // Create Server Config
@@ -119,14 +163,74 @@ impl AqcClient {
         Ok(AqcClient {
             quic_client,
             client_keys,
+            channels: Arc::new(OpenChannels::new(Arc::clone(&server_keys))),
+            watchers: JoinSet::new(),
+            watched: HashMap::new(),
             server_keys,
-            channels: HashMap::new(),
+            ctrl_psks: Vec::new(),
+            ctrl_psks_refreshed: Instant::now(),
             quic_server: server,
             daemon,
             identity_rx,
         })
     }
 
+    /// Fetches the control PSKs of every team from the daemon,
+    /// and starts watching new teams for closed channels.
+    ///
+    /// The server only accepts control messages sent with these
+    /// PSKs.
+    pub async fn refresh_ctrl_psks(&mut self) -> crate::Result<()> {
+        let psks = self
+            .daemon
+            .aqc_ctrl_psks(context::current())
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)?;
+        self.server_keys
+            .set_ctrl_psks(&psks)
+            .assume("can make ctrl psks")?;
+        self.ctrl_psks = psks;
+        self.ctrl_psks_refreshed = Instant::now();
+        self.watch_teams();
+        Ok(())
+    }
+
+    /// Watches every team in `ctrl_psks`, and stops watching
+    /// the rest.
+    fn watch_teams(&mut self) {
+        while self.watchers.try_join_next().is_some() {}
+
+        let teams = self
+            .ctrl_psks
+            .iter()
+            .map(|psk| psk.team)
+            .collect::<Vec<_>>();
+        self.watched.retain(|team, watcher| {
+            let keep = teams.contains(team) && !watcher.is_finished();
+            if !keep {
+                watcher.abort();
+            }
+            keep
+        });
+        for team in teams {
+            if self.watched.contains_key(&team) {
+                continue;
+            }
+            let watcher = self.watchers.spawn(watch_team(
+                self.daemon.clone(),
+                team,
+                Arc::clone(&self.channels),
+            ));
+            self.watched.insert(team, watcher);
+        }
+    }
+
+    /// Reports whether the control PSKs should be fetched again.
+    fn ctrl_psks_stale(&self) -> bool {
+        self.ctrl_psks_refreshed.elapsed() >= CTRL_PSK_REFRESH
+    }
+
     /// Get the client address.
     pub fn client_addr(&self) -> Result<SocketAddr, Bug> {
         self.quic_client.local_addr().assume("can get local addr")
@@ -144,6 +248,7 @@ impl AqcClient {
         label_id: LabelId,
         psks: AqcUniPsks,
     ) -> Result<channels::AqcSendChannel, AqcError> {
+        self.forget_closed_channels().await;
         let channel_id = UniChannelId::from(*psks.channel_id());
         self.client_keys.load_psks(AqcPsks::Uni(psks));
         let mut conn = self
@@ -151,6 +256,8 @@ impl AqcClient {
             .connect(Connect::new(addr).with_server_name(addr.ip().to_string()))
             .await?;
         conn.keep_alive(true)?;
+        self.channels
+            .add_conn(AqcChannelId::Uni(channel_id), conn.handle());
         Ok(channels::AqcSendChannel::new(
             label_id,
             channel_id,
@@ -165,6 +272,7 @@ impl AqcClient {
         label_id: LabelId,
         psks: AqcBidiPsks,
     ) -> Result<channels::AqcBidiChannel, AqcError> {
+        self.forget_closed_channels().await;
         let channel_id = BidiChannelId::from(*psks.channel_id());
         self.client_keys.load_psks(AqcPsks::Bidi(psks));
         let mut conn = self
@@ -172,12 +280,55 @@ impl AqcClient {
             .connect(Connect::new(addr).with_server_name(addr.ip().to_string()))
             .await?;
         conn.keep_alive(true)?;
+        self.channels
+            .add_conn(AqcChannelId::Bidi(channel_id), conn.handle());
         Ok(channels::AqcBidiChannel::new(label_id, channel_id, conn))
     }
 
+    /// Deletes the channel `id`: its connections are closed and the
+    /// daemon forgets it.
+    pub async fn delete_channel(&mut self, id: AqcChannelId) -> crate::Result<()> {
+        self.channels.close(id);
+        self.forget_channel(id).await
+    }
+
+    /// Tells the daemon to forget the channel `id`.
+    async fn forget_channel(&self, id: AqcChannelId) -> crate::Result<()> {
+        let ctx = context::current();
+        match id {
+            AqcChannelId::Bidi(id) => {
+                self.daemon
+                    .delete_aqc_bidi_channel(ctx, id.into_id().into())
+                    .await
+            }
+            AqcChannelId::Uni(id) => {
+                self.daemon
+                    .delete_aqc_uni_channel(ctx, id.into_id().into())
+                    .await
+            }
+        }
+        .map_err(IpcError::new)?
+        .map_err(aranya_error)?;
+        Ok(())
+    }
+
+    /// Tells the daemon to forget the channels whose connections
+    /// closed.
+    async fn forget_closed_channels(&self) {
+        for id in self.channels.take_closed() {
+            if let Err(err) = self.forget_channel(id).await {
+                warn!(?id, %err, "unable to forget closed channel");
+            }
+        }
+    }
+
     /// Receive the next available channel.
     pub async fn receive_channel(&mut self) -> crate::Result<AqcPeerChannel> {
+        self.forget_closed_channels().await;
         loop {
+            if self.ctrl_psks_stale() {
+                self.refresh_ctrl_psks().await?;
+            }
             // Accept a new connection
             let mut conn = self
                 .quic_server
@@ -195,17 +346,17 @@ impl AqcClient {
                 "Processing connection accepted after seeing PSK identity hint: {:02x?}",
                 identity
             );
-            // If the PSK identity hint is the control PSK, receive a control message.
+            // If the PSK identity hint is a control PSK, receive a control message.
             // This will update the channel map with the PSK and associate it with an
             // AqcChannel.
-            if identity == PSK_IDENTITY_CTRL {
-                self.receive_ctrl_message(&mut conn).await?;
+            if let Some(team) = self.server_keys.ctrl_team(&identity) {
+                self.receive_ctrl_message(&mut conn, team).await?;
                 continue;
             }
             // If the PSK identity hint is not the control PSK, check if it's in the channel map.
             // If it is, create a channel of the appropriate type. We should have already received
             // the control message for this PSK, if we don't we can't create a channel.
-            let channel_info = self.channels.get(&identity).ok_or_else(|| {
+            let channel_info = self.channels.accept(&identity, &conn).ok_or_else(|| {
                 warn!(
                     "No channel info found in map for identity hint {:02x?}",
                     identity
@@ -224,6 +375,10 @@ impl AqcClient {
     ///
     /// If there is no channel available, return Empty.
     /// If the channel is closed, return Closed.
+    ///
+    /// This uses the control PSKs that were last fetched, since it
+    /// can't wait for the daemon. They are fetched again in the
+    /// background every [`CTRL_PSK_REFRESH`].
     pub fn try_receive_channel(&mut self) -> Result<AqcPeerChannel, TryReceiveError<crate::Error>> {
         let mut cx = Context::from_waker(Waker::noop());
         loop {
@@ -251,12 +406,13 @@ impl AqcClient {
                 "Processing connection accepted after seeing PSK identity hint: {:02x?}",
                 identity
             );
-            // If the PSK identity hint is the control PSK, receive a control message.
+            // If the PSK identity hint is a control PSK, receive a control message.
             // This will update the channel map with the PSK and associate it with an
             // AqcChannel.
-            if identity == PSK_IDENTITY_CTRL {
+            if let Some(team) = self.server_keys.ctrl_team(&identity) {
                 // Block on the async function
-                let result = futures_lite::future::block_on(self.receive_ctrl_message(&mut conn));
+                let result =
+                    futures_lite::future::block_on(self.receive_ctrl_message(&mut conn, team));
 
                 if let Err(e) = result {
                     // The original function logged an error and returned ControlFlow::Break
@@ -276,7 +432,7 @@ impl AqcClient {
             // If the PSK identity hint is not the control PSK, check if it's in the channel map.
             // If it is, create a channel of the appropriate type. We should have already received
             // the control message for this PSK, if we don't we can't create a channel.
-            let channel_info = self.channels.get(&identity).ok_or_else(|| {
+            let channel_info = self.channels.accept(&identity, &conn).ok_or_else(|| {
                 debug!(
                     "No channel info found in map for identity hint {:02x?}",
                     identity
@@ -297,15 +453,35 @@ impl AqcClient {
         addr: SocketAddr,
         ctrl: AqcCtrl,
         team_id: TeamId,
+    ) -> crate::Result<()> {
+        // Use the team's current control PSK.
+        self.refresh_ctrl_psks().await?;
+        self.client_keys
+            .set_ctrl_psks(team_id, &self.ctrl_psks)
+            .ok_or(AqcError::NoCtrlPsk)?;
+        let msg = AqcCtrlMessage { team_id, ctrl };
+        Ok(self.send_ctrl_message(addr, msg).await?)
+    }
+
+    async fn send_ctrl_message(
+        &mut self,
+        addr: SocketAddr,
+        msg: AqcCtrlMessage,
     ) -> Result<(), AqcError> {
-        self.client_keys.set_key(CTRL_PSK.clone());
-        let mut conn = self
-            .quic_client
-            .connect(Connect::new(addr).with_server_name(addr.ip().to_string()))
-            .await?;
+        let connect = || Connect::new(addr).with_server_name(addr.ip().to_string());
+        let mut conn = match self.quic_client.connect(connect()).await {
+            Ok(conn) => conn,
+            // The peer fetches its control PSKs again after a
+            // failed handshake, e.g., if ours are from a seed it
+            // only just received, so try once more.
+            Err(err) => {
+                debug!(%err, "retrying control connection");
+                tokio::time::sleep(CTRL_PSK_MIN_REFRESH).await;
+                self.quic_client.connect(connect()).await?
+            }
+        };
         let mut stream = conn.open_bidirectional_stream().await?;
 
-        let msg = AqcCtrlMessage { team_id, ctrl };
         let msg_bytes = postcard::to_stdvec(&msg).assume("can serialize")?;
         stream.send(Bytes::from(msg_bytes)).await?;
         stream.finish()?;
@@ -320,7 +496,13 @@ impl AqcClient {
         Ok(())
     }
 
-    async fn receive_ctrl_message(&mut self, conn: &mut Connection) -> crate::Result<()> {
+    /// Receives a control message over a connection that used
+    /// one of `team`'s control PSKs.
+    async fn receive_ctrl_message(
+        &mut self,
+        conn: &mut Connection,
+        team: TeamId,
+    ) -> crate::Result<()> {
         let mut stream = conn
             .accept_bidirectional_stream()
             .await
@@ -328,6 +510,21 @@ impl AqcClient {
             .ok_or(AqcError::ConnectionClosed)?;
         let ctrl_bytes = read_to_end(&mut stream).await.map_err(AqcError::from)?;
         match postcard::from_bytes::<AqcCtrlMessage>(&ctrl_bytes) {
+            Ok(ctrl) if ctrl.team_id != team => {
+                warn!(%team, team_id = %ctrl.team_id, "AqcCtrlMessage is for another team");
+                let ack_msg = AqcAckMessage::Failure(format!(
+                    "AqcCtrlMessage for team {} was sent with a PSK for team {team}",
+                    ctrl.team_id
+                ));
+                let ack_bytes = postcard::to_stdvec(&ack_msg).assume("can serialize")?;
+                stream.send(Bytes::from(ack_bytes)).await.ok();
+                if let Err(err) = stream.close().await {
+                    if !is_close_error(err) {
+                        error!(%err, "error closing stream after ctrl failure");
+                    }
+                }
+                return Err(AqcError::CtrlTeamMismatch.into());
+            }
             Ok(ctrl) => {
                 self.process_ctrl_message(ctrl.team_id, ctrl.ctrl).await?;
                 // Send an ACK back
@@ -370,31 +567,7 @@ impl AqcClient {
             .map_err(aranya_error)?;
 
         self.server_keys.load_psks(psks.clone());
-
-        match psks {
-            AqcPsks::Bidi(psks) => {
-                for (_suite, psk) in psks {
-                    self.channels.insert(
-                        psk.identity.as_bytes().to_vec(),
-                        AqcChannelInfo {
-                            label_id,
-                            channel_id: AqcChannelId::Bidi(*psk.identity.channel_id()),
-                        },
-                    );
-                }
-            }
-            AqcPsks::Uni(psks) => {
-                for (_suite, psk) in psks {
-                    self.channels.insert(
-                        psk.identity.as_bytes().to_vec(),
-                        AqcChannelInfo {
-                            label_id,
-                            channel_id: AqcChannelId::Uni(*psk.identity.channel_id()),
-                        },
-                    );
-                }
-            }
-        }
+        self.channels.add_psks(label_id, psks);
 
         Ok(())
     }
@@ -414,19 +587,223 @@ pub enum TryReceiveError<E = AqcError> {
     Closed,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 struct AqcChannelInfo {
     label_id: LabelId,
     channel_id: AqcChannelId,
 }
 
 /// An AQC Channel ID.
-#[derive(Copy, Clone, Debug)]
-enum AqcChannelId {
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub(crate) enum AqcChannelId {
     Bidi(BidiChannelId),
     Uni(UniChannelId),
 }
 
+impl From<api::AqcChannelId> for AqcChannelId {
+    fn from(id: api::AqcChannelId) -> Self {
+        match id {
+            api::AqcChannelId::Bidi(id) => Self::Bidi(id.into_id().into()),
+            api::AqcChannelId::Uni(id) => Self::Uni(id.into_id().into()),
+        }
+    }
+}
+
+/// The channels we have with peers.
+#[derive(Debug)]
+struct OpenChannels {
+    /// Accepts connections for the channels' PSKs.
+    server_keys: Arc<ServerPresharedKeys>,
+    inner: Mutex<OpenChannelsInner>,
+}
+
+#[derive(Debug, Default)]
+struct OpenChannelsInner {
+    /// Map of PSK identity to channel type
+    by_identity: HashMap<PskIdentity, AqcChannelInfo>,
+    /// The connections of each channel.
+    conns: HashMap<AqcChannelId, Vec<s2n_quic::connection::Handle>>,
+    /// The channels whose connections all closed, which the daemon
+    /// has not been told about yet.
+    closed: Vec<AqcChannelId>,
+}
+
+impl OpenChannelsInner {
+    /// Forgets the connections that closed, and closes the channels
+    /// that have none left.
+    fn prune(&mut self, server_keys: &ServerPresharedKeys) {
+        let mut closed = Vec::new();
+        self.conns.retain(|id, handles| {
+            // Pinging a connection fails once it's closed.
+            handles.retain_mut(|handle| handle.ping().is_ok());
+            if handles.is_empty() {
+                closed.push(*id);
+            }
+            !handles.is_empty()
+        });
+        for id in closed {
+            self.remove(server_keys, id);
+            self.closed.push(id);
+        }
+    }
+
+    /// Removes the channel `id`: its connections are closed and its
+    /// PSKs are deleted.
+    fn remove(&mut self, server_keys: &ServerPresharedKeys, id: AqcChannelId) {
+        let mut identities = Vec::new();
+        self.by_identity.retain(|identity, info| {
+            let keep = info.channel_id != id;
+            if !keep {
+                identities.push(identity.clone());
+            }
+            keep
+        });
+        server_keys.remove_psks(identities.iter().map(Vec::as_slice));
+        for handle in self.conns.remove(&id).unwrap_or_default() {
+            handle.close(CHANNEL_CLOSED_ERROR_CODE.into());
+        }
+    }
+}
+
+impl OpenChannels {
+    fn new(server_keys: Arc<ServerPresharedKeys>) -> Self {
+        Self {
+            server_keys,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Adds the PSKs of a channel that peers can connect with.
+    fn add_psks(&self, label_id: LabelId, psks: AqcPsks) {
+        let mut inner = self.inner.lock().expect("poisoned");
+        for (_suite, psk) in psks {
+            let channel_id = match &psk {
+                api::AqcPsk::Bidi(psk) => AqcChannelId::Bidi(*psk.identity.channel_id()),
+                api::AqcPsk::Uni(psk) => AqcChannelId::Uni(*psk.identity.channel_id()),
+            };
+            inner.by_identity.insert(
+                psk.identity().as_bytes().to_vec(),
+                AqcChannelInfo {
+                    label_id,
+                    channel_id,
+                },
+            );
+        }
+    }
+
+    /// Adds a connection for the channel `id`.
+    fn add_conn(&self, id: AqcChannelId, handle: s2n_quic::connection::Handle) {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.prune(&self.server_keys);
+        inner.conns.entry(id).or_default().push(handle);
+    }
+
+    /// Returns the channel of a connection accepted with the PSK
+    /// `identity`, adding the connection to it.
+    fn accept(&self, identity: &[u8], conn: &Connection) -> Option<AqcChannelInfo> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.prune(&self.server_keys);
+        let info = *inner.by_identity.get(identity)?;
+        inner
+            .conns
+            .entry(info.channel_id)
+            .or_default()
+            .push(conn.handle());
+        Some(info)
+    }
+
+    /// Closes the channel `id`: its connections are closed and
+    /// its PSKs are deleted.
+    fn close(&self, id: AqcChannelId) {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.remove(&self.server_keys, id);
+    }
+
+    /// Returns the channels whose connections closed since this was
+    /// last called.
+    fn take_closed(&self) -> Vec<AqcChannelId> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.prune(&self.server_keys);
+        std::mem::take(&mut inner.closed)
+    }
+}
+
+/// Fetches the control PSKs from the daemon every
+/// [`CTRL_PSK_REFRESH`], so that the server accepts them after a
+/// team's seed is rotated even if channels are only received with
+/// [`AqcClient::try_receive_channel`].
+///
+/// They are fetched sooner when a handshake uses an unknown PSK,
+/// but at most once every [`CTRL_PSK_MIN_REFRESH`].
+async fn refresh_ctrl_psks(daemon: DaemonApiClient, server_keys: Arc<ServerPresharedKeys>) {
+    loop {
+        // Wait until they're due, or a handshake used a PSK we
+        // don't have.
+        let _ = tokio::time::timeout(CTRL_PSK_REFRESH, server_keys.missed()).await;
+        match daemon.aqc_ctrl_psks(context::current()).await {
+            Ok(Ok(psks)) => {
+                if server_keys.set_ctrl_psks(&psks).is_none() {
+                    error!("unable to make control PSKs");
+                }
+            }
+            Ok(Err(err)) => warn!(%err, "unable to refresh control PSKs"),
+            Err(err) => warn!(%err, "unable to refresh control PSKs"),
+        }
+        tokio::time::sleep(CTRL_PSK_MIN_REFRESH).await;
+    }
+}
+
+/// Closes `team`'s channels when the daemon says they were
+/// closed, e.g., because a peer lost access to the label.
+async fn watch_team(daemon: DaemonApiClient, team: TeamId, channels: Arc<OpenChannels>) {
+    let id = match daemon
+        .subscribe(
+            context::current(),
+            team,
+            vec![TeamEventKind::AqcChannelClosed],
+        )
+        .await
+    {
+        Ok(Ok(id)) => id,
+        Ok(Err(err)) => {
+            warn!(%team, %err, "unable to watch for closed channels");
+            return;
+        }
+        Err(err) => {
+            warn!(%team, %err, "unable to watch for closed channels");
+            return;
+        }
+    };
+    loop {
+        let events = match daemon
+            .next_events(context::current(), id, CLOSE_POLL_TIMEOUT)
+            .await
+        {
+            Ok(Ok(events)) => events,
+            Ok(Err(err)) => {
+                warn!(%team, %err, "stopped watching for closed channels");
+                return;
+            }
+            Err(err) => {
+                warn!(%team, %err, "stopped watching for closed channels");
+                return;
+            }
+        };
+        for event in events {
+            match event {
+                TeamEvent::AqcChannelClosed { channel_id, .. } => {
+                    debug!(%team, ?channel_id, "closing channel");
+                    channels.close(channel_id.into());
+                }
+                TeamEvent::Lagged { count } => {
+                    warn!(%team, count, "missed closed channels");
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
 /// An AQC control message.
 #[derive(serde::Serialize, serde::Deserialize)]
 struct AqcCtrlMessage {
@@ -147,6 +147,12 @@ impl AqcReceiveChannel {
             Poll::Pending => Err(TryReceiveError::Empty),
         }
     }
+
+    /// Close the channel if it's open. If the channel is already closed, do nothing.
+    pub fn close(&mut self) {
+        const ERROR_CODE: u32 = 0;
+        self.conn.close(ERROR_CODE.into());
+    }
 }
 
 /// A unique channel between two peers.
@@ -1,6 +1,6 @@
 //! Client-daemon connection.
 
-use std::{io, net::SocketAddr, path::Path};
+use std::{collections::VecDeque, io, net::SocketAddr, path::Path, time::Duration};
 
 use anyhow::Context as _;
 use aranya_crypto::{Csprng, EncryptionPublicKey, Rng};
@@ -9,8 +9,8 @@ use aranya_daemon_api::{
         txp::{self, LengthDelimitedCodec},
         PublicApiKey,
     },
-    ChanOp, DaemonApiClient, DeviceId, KeyBundle, Label, LabelId, NetIdentifier, Role, TeamId,
-    Text, Version, CS,
+    ChanOp, DaemonApiClient, DeviceId, KeyBundle, Label, LabelId, NetIdentifier, Role,
+    SubscriptionId, SyncPeer, SyncPeerStatus, TeamEvent, TeamEventKind, TeamId, Text, Version, CS,
 };
 use aranya_util::Addr;
 use buggy::BugExt as _;
@@ -56,6 +56,84 @@ impl Labels {
     }
 }
 
+/// List of sync peers.
+pub struct SyncPeers {
+    data: Vec<SyncPeer>,
+}
+
+impl SyncPeers {
+    pub fn iter(&self) -> impl Iterator<Item = &SyncPeer> {
+        self.data.iter()
+    }
+
+    #[doc(hidden)]
+    pub fn __data(&self) -> &[SyncPeer] {
+        self.data.as_slice()
+    }
+}
+
+/// How syncing with each sync peer has gone.
+pub struct SyncStatus {
+    data: Vec<SyncPeerStatus>,
+}
+
+impl SyncStatus {
+    pub fn iter(&self) -> impl Iterator<Item = &SyncPeerStatus> {
+        self.data.iter()
+    }
+
+    #[doc(hidden)]
+    pub fn __data(&self) -> &[SyncPeerStatus] {
+        self.data.as_slice()
+    }
+}
+
+/// How long the daemon waits for events before answering
+/// [`Subscription::next`] with nothing.
+///
+/// This must be shorter than the RPC deadline.
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A subscription to a team's events, created with [`Team::subscribe`].
+///
+/// The daemon drops subscriptions that are not polled for a few minutes.
+#[derive(Debug)]
+pub struct Subscription {
+    daemon: DaemonApiClient,
+    id: SubscriptionId,
+    pending: VecDeque<TeamEvent>,
+}
+
+impl Subscription {
+    /// Waits for the next event.
+    ///
+    /// Returns [`TeamEvent::Lagged`] if events were dropped because they
+    /// were not read quickly enough.
+    pub async fn next(&mut self) -> Result<TeamEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            let events = self
+                .daemon
+                .next_events(context::current(), self.id, POLL_TIMEOUT)
+                .await
+                .map_err(IpcError::new)?
+                .map_err(aranya_error)?;
+            self.pending.extend(events);
+        }
+    }
+
+    /// Stops the subscription.
+    pub async fn unsubscribe(self) -> Result<()> {
+        self.daemon
+            .unsubscribe(context::current(), self.id)
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)
+    }
+}
+
 /// Builds a [`Client`].
 pub struct ClientBuilder<'a> {
     /// The UDS that the daemon is listening on.
@@ -242,6 +320,7 @@ impl Client {
             .await
             .map_err(IpcError::new)?
             .map_err(aranya_error)?;
+        self.aqc.refresh_ctrl_psks().await?;
         Ok(Team {
             client: self,
             team_id,
@@ -269,6 +348,7 @@ impl Client {
             .await
             .map_err(IpcError::new)?
             .map_err(aranya_error)?;
+        self.aqc.refresh_ctrl_psks().await?;
         Ok(Team {
             client: self,
             team_id,
@@ -281,7 +361,9 @@ impl Client {
             .remove_team(context::current(), team_id)
             .await
             .map_err(IpcError::new)?
-            .map_err(aranya_error)
+            .map_err(aranya_error)?;
+        self.aqc.refresh_ctrl_psks().await?;
+        Ok(())
     }
 
     /// Get access to Aranya QUIC Channels.
@@ -329,6 +411,22 @@ impl Team<'_> {
         Ok(wrapped)
     }
 
+    /// Replaces the team's PSK seed with a new one.
+    ///
+    /// The new seed is shared with every other device on the team
+    /// through the graph, so removed devices lose the ability to
+    /// sync once the daemons' grace period for the old seed ends.
+    /// Devices that miss the rotation can still fetch it with the
+    /// old seed over QUIC until the seed is rotated again.
+    pub async fn rotate_sync_seed(&mut self) -> Result<()> {
+        self.client
+            .daemon
+            .rotate_sync_seed(context::current(), self.team_id)
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)
+    }
+
     /// Adds a peer for automatic periodic Aranya state syncing.
     pub async fn add_sync_peer(&mut self, addr: Addr, config: SyncPeerConfig) -> Result<()> {
         self.client
@@ -362,6 +460,61 @@ impl Team<'_> {
             .map_err(aranya_error)
     }
 
+    /// Returns the peers the team is automatically synced with.
+    pub async fn list_sync_peers(&mut self) -> Result<SyncPeers> {
+        let data = self
+            .client
+            .daemon
+            .list_sync_peers(context::current(), self.team_id)
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)?;
+        Ok(SyncPeers { data })
+    }
+
+    /// Changes the configuration of a peer added with
+    /// [`add_sync_peer`][Self::add_sync_peer].
+    pub async fn update_sync_peer(&mut self, addr: Addr, config: SyncPeerConfig) -> Result<()> {
+        self.client
+            .daemon
+            .update_sync_peer(context::current(), addr, self.team_id, config.into())
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)
+    }
+
+    /// Returns how syncing with each of the team's sync peers has gone.
+    pub async fn sync_status(&mut self) -> Result<SyncStatus> {
+        let data = self
+            .client
+            .daemon
+            .sync_status(context::current(), self.team_id)
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)?;
+        Ok(SyncStatus { data })
+    }
+
+    /// Subscribes to the team's events.
+    ///
+    /// Only events whose kind is in `kinds` are delivered, or every event
+    /// if `kinds` is empty. Events from syncing with peers are delivered
+    /// as well as events from this device's own actions.
+    pub async fn subscribe(&mut self, kinds: &[TeamEventKind]) -> Result<Subscription> {
+        let id = self
+            .client
+            .daemon
+            .subscribe(context::current(), self.team_id, kinds.to_vec())
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)?;
+        Ok(Subscription {
+            daemon: self.client.daemon.clone(),
+            id,
+            pending: VecDeque::new(),
+        })
+    }
+
     /// Close the team and stop all operations on the graph.
     pub async fn close_team(&mut self) -> Result<()> {
         self.client
@@ -548,6 +701,21 @@ impl Queries<'_> {
         Ok(Labels { data })
     }
 
+    /// Returns the operations the device may perform with the label, or
+    /// `None` if the label is not assigned to the device.
+    pub async fn device_label_op(
+        &mut self,
+        device: DeviceId,
+        label_id: LabelId,
+    ) -> Result<Option<ChanOp>> {
+        self.client
+            .daemon
+            .query_device_label_op(context::current(), self.team_id, device, label_id)
+            .await
+            .map_err(IpcError::new)?
+            .map_err(aranya_error)
+    }
+
     /// Returns the AQC network identifier assigned to the current device.
     pub async fn aqc_net_identifier(&mut self, device: DeviceId) -> Result<Option<NetIdentifier>> {
         self.client
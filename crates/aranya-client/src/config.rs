@@ -1,6 +1,6 @@
 use core::time::Duration;
 
-use aranya_daemon_api::{SeedMode, SEED_IKM_SIZE};
+use aranya_daemon_api::{SeedMode, SyncBackoff, SyncTransport, SEED_IKM_SIZE};
 use tracing::error;
 
 use crate::{error::InvalidArg, ConfigError, Result};
@@ -10,6 +10,10 @@ use crate::{error::InvalidArg, ConfigError, Result};
 pub struct SyncPeerConfig {
     interval: Duration,
     sync_now: bool,
+    ephemeral: bool,
+    backoff: Option<SyncBackoff>,
+    subscribe: bool,
+    transport: Option<SyncTransport>,
 }
 
 impl SyncPeerConfig {
@@ -24,6 +28,10 @@ impl From<SyncPeerConfig> for aranya_daemon_api::SyncPeerConfig {
         Self {
             interval: value.interval,
             sync_now: value.sync_now,
+            ephemeral: value.ephemeral,
+            backoff: value.backoff,
+            subscribe: value.subscribe,
+            transport: value.transport,
         }
     }
 }
@@ -32,6 +40,10 @@ impl From<SyncPeerConfig> for aranya_daemon_api::SyncPeerConfig {
 pub struct SyncPeerConfigBuilder {
     interval: Option<Duration>,
     sync_now: bool,
+    ephemeral: bool,
+    backoff: Option<SyncBackoff>,
+    subscribe: bool,
+    transport: Option<SyncTransport>,
 }
 
 impl SyncPeerConfigBuilder {
@@ -53,6 +65,10 @@ impl SyncPeerConfigBuilder {
         Ok(SyncPeerConfig {
             interval,
             sync_now: self.sync_now,
+            ephemeral: self.ephemeral,
+            backoff: self.backoff,
+            subscribe: self.subscribe,
+            transport: self.transport,
         })
     }
 
@@ -72,6 +88,45 @@ impl SyncPeerConfigBuilder {
         self.sync_now = sync_now;
         self
     }
+
+    /// Configures whether the peer is forgotten when the daemon restarts.
+    ///
+    /// By default, the peer is saved by the daemon and synced with again
+    /// after it restarts.
+    pub fn ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    /// Sets how the daemon backs off while syncs with the peer fail.
+    ///
+    /// By default, the daemon's configured backoff is used.
+    pub fn backoff(mut self, backoff: SyncBackoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Configures whether the peer tells the daemon as soon as it has new
+    /// commands, so they arrive before the next sync interval.
+    ///
+    /// Hints prompt at most one extra sync per interval, and are ignored
+    /// while syncs with the peer are failing. The peer is still synced
+    /// with at the interval, in case it misses one. By default, the peer
+    /// is only synced with at the interval.
+    pub fn subscribe(mut self, subscribe: bool) -> Self {
+        self.subscribe = subscribe;
+        self
+    }
+
+    /// Sets the transport the peer is synced with over.
+    ///
+    /// The daemon must have a sync config for the transport. By default,
+    /// the peer is synced with over QUIC if the daemon is configured for
+    /// it, else over TCP.
+    pub fn transport(mut self, transport: SyncTransport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
 }
 
 impl Default for SyncPeerConfigBuilder {
@@ -79,6 +134,10 @@ impl Default for SyncPeerConfigBuilder {
         Self {
             interval: None,
             sync_now: true,
+            ephemeral: false,
+            backoff: None,
+            subscribe: false,
+            transport: None,
         }
     }
 }
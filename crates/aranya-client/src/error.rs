@@ -143,6 +143,28 @@ pub enum AqcError {
     #[error("serialization/deserialization error: {0}")]
     Serde(postcard::Error),
 
+    /// A local file could not be read or written.
+    #[error("file I/O error: {0}")]
+    FileIo(io::Error),
+
+    /// The peer did not follow the file transfer protocol, or the
+    /// transfer was cut short.
+    #[error("invalid file transfer: {0}")]
+    InvalidFileTransfer(&'static str),
+
+    /// The received file did not match the sender's SHA-256.
+    #[error("file hash mismatch")]
+    FileHashMismatch,
+
+    /// There is no control PSK for the team.
+    #[error("no AQC control PSK for team")]
+    NoCtrlPsk,
+
+    /// A control message was sent with another team's control
+    /// PSK.
+    #[error("control message sent with another team's PSK")]
+    CtrlTeamMismatch,
+
     /// Peer failed to process control message.
     #[error("error from peer processing control message: {0}")]
     CtrlFailure(String),
@@ -20,7 +20,7 @@ pub mod error;
 
 #[doc(inline)]
 pub use crate::{
-    client::{Client, Team},
+    client::{Client, Subscription, Team},
     config::{
         QuicSyncConfig, QuicSyncConfigBuilder, SyncPeerConfig, SyncPeerConfigBuilder, TeamConfig,
         TeamConfigBuilder,
@@ -4,13 +4,14 @@ use std::time::Duration;
 
 mod common;
 use anyhow::Result;
-use aranya_client::aqc::AqcPeerChannel;
+use aranya_client::aqc::{self, AqcBidiChannel, AqcPeerChannel, FILE_CHUNK_SIZE};
 use aranya_crypto::dangerous::spideroak_crypto::csprng::rand;
 use aranya_daemon_api::{text, ChanOp};
 use buggy::BugExt;
 use bytes::{Bytes, BytesMut};
-use common::{sleep, TeamCtx};
+use common::{external_psk, quic_handshake, sleep, TeamCtx};
 use futures_util::future::try_join;
+use sha2::{Digest, Sha256};
 use tempfile::tempdir;
 
 /// Demonstrate nominal usage of AQC channels.
@@ -670,3 +671,503 @@ async fn test_aqc_chans_delete_chan_send_recv() -> Result<()> {
 
     Ok(())
 }
+
+/// Demonstrate that deleting the receive side of a uni channel closes it
+/// for the sender, e.g. when the receiver refuses the channel.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_aqc_chans_delete_receive_uni_channel() -> Result<()> {
+    let interval = Duration::from_millis(100);
+    let sleep_interval = interval * 6;
+
+    let tmp = tempdir()?;
+    let work_dir = tmp.path().to_path_buf();
+
+    let mut team = TeamCtx::new("test_aqc_chans_delete_receive_uni", work_dir).await?;
+
+    // create team.
+    let team_id = team.create_and_add_team().await?;
+
+    // Tell all peers to sync with one another, and assign their roles.
+    team.add_all_sync_peers(team_id).await?;
+    team.add_all_device_roles(team_id).await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    let mut operator_team = team.operator.client.team(team_id);
+    operator_team
+        .assign_aqc_net_identifier(team.membera.id, team.membera.aqc_net_id())
+        .await?;
+    operator_team
+        .assign_aqc_net_identifier(team.memberb.id, team.memberb.aqc_net_id())
+        .await?;
+
+    let label1 = operator_team.create_label(text!("label1")).await?;
+    let op = ChanOp::SendRecv;
+    operator_team
+        .assign_label(team.membera.id, label1, op)
+        .await?;
+    operator_team
+        .assign_label(team.memberb.id, label1, op)
+        .await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    let (mut uni_chan1, peer_channel) = try_join(
+        team.membera
+            .client
+            .aqc()
+            .create_uni_channel(team_id, team.memberb.aqc_net_id(), label1),
+        team.memberb.client.aqc().receive_channel(),
+    )
+    .await
+    .expect("can create uni channel");
+
+    let uni_chan2 = match peer_channel {
+        AqcPeerChannel::Receive(receiver) => receiver,
+        _ => panic!("Expected a unidirectional channel"),
+    };
+
+    // memberb refuses the channel.
+    team.memberb
+        .client
+        .aqc()
+        .delete_receive_uni_channel(uni_chan2)
+        .await?;
+
+    // wait for the close to reach membera.
+    sleep(Duration::from_millis(100)).await;
+
+    let sent = async {
+        let mut send1_1 = uni_chan1.create_uni_stream().await?;
+        send1_1.send(Bytes::from_static(b"hello")).await
+    }
+    .await;
+    assert!(sent.is_err(), "channel should be closed");
+
+    Ok(())
+}
+
+/// Demonstrate sending files over AQC streams, including resuming a
+/// transfer that was interrupted.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_aqc_file_transfer() -> Result<()> {
+    let interval = Duration::from_millis(100);
+    let sleep_interval = interval * 6;
+
+    let tmp = tempdir()?;
+    let work_dir = tmp.path().to_path_buf();
+
+    let mut team = TeamCtx::new("test_aqc_file_transfer", work_dir).await?;
+
+    // create team.
+    let team_id = team.create_and_add_team().await?;
+
+    // Tell all peers to sync with one another, and assign their roles.
+    team.add_all_sync_peers(team_id).await?;
+    team.add_all_device_roles(team_id).await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    let mut operator_team = team.operator.client.team(team_id);
+    operator_team
+        .assign_aqc_net_identifier(team.membera.id, team.membera.aqc_net_id())
+        .await?;
+    operator_team
+        .assign_aqc_net_identifier(team.memberb.id, team.memberb.aqc_net_id())
+        .await?;
+
+    let label1 = operator_team.create_label(text!("label1")).await?;
+    let op = ChanOp::SendRecv;
+    operator_team
+        .assign_label(team.membera.id, label1, op)
+        .await?;
+    operator_team
+        .assign_label(team.memberb.id, label1, op)
+        .await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    let send_dir = tmp.path().join("send");
+    let recv_dir = tmp.path().join("recv");
+    std::fs::create_dir_all(&send_dir)?;
+    std::fs::create_dir_all(&recv_dir)?;
+
+    let data = {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0u8; FILE_CHUNK_SIZE * 5 + 123];
+        rand::Rng::fill(&mut rng, &mut data[..]);
+        data
+    };
+    let src = send_dir.join("data.bin");
+    std::fs::write(&src, &data)?;
+
+    // Pretend an earlier transfer stopped part way, so only the rest of
+    // the file should be sent.
+    let resume_at = FILE_CHUNK_SIZE * 2 + 7;
+    let hash: [u8; 32] = Sha256::digest(&data).into();
+    let part = recv_dir.join(format!(
+        "data.bin.{:016x}.part",
+        u64::from_be_bytes(hash[..8].try_into()?)
+    ));
+
+    for (offset, partial) in [(0, None), (resume_at, Some(&data[..resume_at]))] {
+        let _ = std::fs::remove_file(recv_dir.join("data.bin"));
+        if let Some(partial) = partial {
+            std::fs::write(&part, partial)?;
+        }
+
+        // Each transfer uses a new channel.
+        let (mut bidi_chan1, peer_channel) = try_join(
+            team.membera.client.aqc().create_bidi_channel(
+                team_id,
+                team.memberb.aqc_net_id(),
+                label1,
+            ),
+            team.memberb.client.aqc().receive_channel(),
+        )
+        .await
+        .expect("can create and receive channel");
+        let mut bidi_chan2 = match peer_channel {
+            AqcPeerChannel::Bidi(channel) => channel,
+            _ => buggy::bug!("Expected a bidirectional channel on memberb"),
+        };
+
+        let stream1 = bidi_chan1.create_bidi_stream().await?;
+        let (sent, received) =
+            try_join(async { Ok(aqc::send_file(stream1, &src).await?) }, async {
+                let stream2 = bidi_chan2
+                    .receive_stream()
+                    .await?
+                    .into_bidi()
+                    .ok()
+                    .assume("expected a bidi stream")?;
+                anyhow::Ok(aqc::receive_file(stream2, &recv_dir).await?)
+            })
+            .await?;
+
+        assert_eq!(sent.offset as usize, offset);
+        assert_eq!(received.offset as usize, offset);
+        assert_eq!(sent.header, received.header);
+        assert_eq!(received.header.name, "data.bin");
+        assert_eq!(received.header.size as usize, data.len());
+        assert_eq!(received.header.sha256, hash);
+        assert_eq!(received.path, recv_dir.join("data.bin"));
+        assert_eq!(std::fs::read(&received.path)?, data);
+        assert!(!part.exists());
+
+        team.membera
+            .client
+            .aqc()
+            .delete_bidi_channel(bidi_chan1)
+            .await?;
+        team.memberb
+            .client
+            .aqc()
+            .delete_bidi_channel(bidi_chan2)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Creates a bidi channel from membera to memberb.
+async fn open_bidi_channels(
+    team: &mut TeamCtx,
+    team_id: aranya_daemon_api::TeamId,
+    label: aranya_daemon_api::LabelId,
+) -> Result<(AqcBidiChannel, AqcBidiChannel)> {
+    let (chan1, peer_channel) = try_join(
+        team.membera
+            .client
+            .aqc()
+            .create_bidi_channel(team_id, team.memberb.aqc_net_id(), label),
+        team.memberb.client.aqc().receive_channel(),
+    )
+    .await?;
+    let AqcPeerChannel::Bidi(chan2) = peer_channel else {
+        buggy::bug!("Expected a bidirectional channel on memberb");
+    };
+    Ok((chan1, chan2))
+}
+
+/// Demonstrate that a receiver discards a file whose hash does not
+/// match, and keeps a truncated file so that sending it again resumes.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_aqc_file_transfer_failures() -> Result<()> {
+    use aranya_client::error::AqcError;
+
+    let interval = Duration::from_millis(100);
+    let sleep_interval = interval * 6;
+
+    let tmp = tempdir()?;
+    let work_dir = tmp.path().to_path_buf();
+
+    let mut team = TeamCtx::new("test_aqc_file_transfer_failures", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    team.add_all_sync_peers(team_id).await?;
+    team.add_all_device_roles(team_id).await?;
+    sleep(sleep_interval).await;
+
+    let mut operator_team = team.operator.client.team(team_id);
+    operator_team
+        .assign_aqc_net_identifier(team.membera.id, team.membera.aqc_net_id())
+        .await?;
+    operator_team
+        .assign_aqc_net_identifier(team.memberb.id, team.memberb.aqc_net_id())
+        .await?;
+    let label1 = operator_team.create_label(text!("label1")).await?;
+    for device in [team.membera.id, team.memberb.id] {
+        operator_team
+            .assign_label(device, label1, ChanOp::SendRecv)
+            .await?;
+    }
+    sleep(sleep_interval).await;
+
+    let send_dir = tmp.path().join("send");
+    let recv_dir = tmp.path().join("recv");
+    std::fs::create_dir_all(&send_dir)?;
+    std::fs::create_dir_all(&recv_dir)?;
+
+    let data = {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0u8; FILE_CHUNK_SIZE * 3 + 45];
+        rand::Rng::fill(&mut rng, &mut data[..]);
+        data
+    };
+    let src = send_dir.join("data.bin");
+    std::fs::write(&src, &data)?;
+    let dst = recv_dir.join("data.bin");
+    let hash: [u8; 32] = Sha256::digest(&data).into();
+    let part = recv_dir.join(format!(
+        "data.bin.{:016x}.part",
+        u64::from_be_bytes(hash[..8].try_into()?)
+    ));
+
+    // A corrupted partial file makes the hash mismatch, and is removed.
+    let corrupt_len = FILE_CHUNK_SIZE + 3;
+    std::fs::write(&part, vec![0xff; corrupt_len])?;
+    let (mut chan1, mut chan2) = open_bidi_channels(&mut team, team_id, label1).await?;
+    let stream1 = chan1.create_bidi_stream().await?;
+    let (sent, received) = futures_util::future::join(aqc::send_file(stream1, &src), async {
+        let stream2 = chan2
+            .receive_stream()
+            .await?
+            .into_bidi()
+            .ok()
+            .assume("expected a bidi stream")?;
+        anyhow::Ok(aqc::receive_file(stream2, &recv_dir).await)
+    })
+    .await;
+    assert!(matches!(sent, Err(AqcError::FileHashMismatch)), "{sent:?}");
+    assert!(
+        matches!(received?, Err(AqcError::FileHashMismatch)),
+        "receiver accepted a corrupted file"
+    );
+    assert!(!part.exists());
+    assert!(!dst.exists());
+
+    // A sender that stops part way leaves the bytes it sent.
+    let truncated_at = FILE_CHUNK_SIZE * 2 + 9;
+    let mut stream1 = chan1.create_bidi_stream().await?;
+    let (sender, received) = futures_util::future::join(
+        async {
+            let header = postcard::to_allocvec(&aqc::FileHeader {
+                name: "data.bin".into(),
+                size: data.len() as u64,
+                sha256: hash,
+            })?;
+            let mut frame = BytesMut::new();
+            frame.extend_from_slice(&u32::try_from(header.len())?.to_be_bytes());
+            frame.extend_from_slice(&header);
+            stream1.send(frame.freeze()).await?;
+            // The receiver has nothing yet.
+            let mut offset = BytesMut::new();
+            while offset.len() < 8 {
+                let chunk = stream1.receive().await?.assume("stream closed early")?;
+                offset.extend_from_slice(&chunk);
+            }
+            assert_eq!(&offset[..], &0u64.to_be_bytes());
+            stream1
+                .send(Bytes::copy_from_slice(&data[..truncated_at]))
+                .await?;
+            stream1.close().await?;
+            anyhow::Ok(())
+        },
+        async {
+            let stream2 = chan2
+                .receive_stream()
+                .await?
+                .into_bidi()
+                .ok()
+                .assume("expected a bidi stream")?;
+            anyhow::Ok(aqc::receive_file(stream2, &recv_dir).await)
+        },
+    )
+    .await;
+    sender?;
+    assert!(
+        matches!(received?, Err(AqcError::InvalidFileTransfer(_))),
+        "receiver accepted a truncated file"
+    );
+    assert_eq!(std::fs::read(&part)?, &data[..truncated_at]);
+    assert!(!dst.exists());
+
+    // Sending the file again resumes after the truncated part.
+    let stream1 = chan1.create_bidi_stream().await?;
+    let (sent, received) = try_join(async { Ok(aqc::send_file(stream1, &src).await?) }, async {
+        let stream2 = chan2
+            .receive_stream()
+            .await?
+            .into_bidi()
+            .ok()
+            .assume("expected a bidi stream")?;
+        anyhow::Ok(aqc::receive_file(stream2, &recv_dir).await?)
+    })
+    .await?;
+    assert_eq!(sent.offset as usize, truncated_at);
+    assert_eq!(received.offset as usize, truncated_at);
+    assert_eq!(std::fs::read(&dst)?, data);
+    assert!(!part.exists());
+
+    Ok(())
+}
+
+/// Demonstrate that the AQC server refuses control connections that
+/// do not use one of the team's control PSKs, and connections that
+/// do not use a PSK at all.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_aqc_ctrl_rejects_unknown_psk() -> Result<()> {
+    let tmp = tempdir()?;
+    let work_dir = tmp.path().to_path_buf();
+
+    let mut team = TeamCtx::new("test_aqc_ctrl_rejects_unknown_psk", work_dir).await?;
+    team.create_and_add_team().await?;
+
+    let addr = team.memberb.client.aqc().server_addr()?;
+    // The control PSK that every client used to share.
+    let old_ctrl_psk = external_psk(b"aranya-ctrl-psk!", b"this-is-a-32-byte-secret-psk!!!!")?;
+    assert!(
+        !quic_handshake(addr, b"aqc-v1", vec![old_ctrl_psk]).await?,
+        "connected with an unknown control PSK"
+    );
+    assert!(
+        !quic_handshake(addr, b"aqc-v1", vec![]).await?,
+        "connected without a PSK"
+    );
+
+    Ok(())
+}
+
+/// Demonstrate that revoking a label closes the open channels that use
+/// it, even while a stream is active, and that the channel cannot be
+/// recreated.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_aqc_chans_revoke_label_during_stream() -> Result<()> {
+    let interval = Duration::from_millis(100);
+    let sleep_interval = interval * 6;
+
+    let tmp = tempdir()?;
+    let work_dir = tmp.path().to_path_buf();
+
+    let mut team = TeamCtx::new("test_aqc_chans_revoke_label_during_stream", work_dir).await?;
+
+    // create team.
+    let team_id = team.create_and_add_team().await?;
+
+    sleep(sleep_interval).await;
+
+    // Tell all peers to sync with one another, and assign their roles.
+    team.add_all_sync_peers(team_id).await?;
+    team.add_all_device_roles(team_id).await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    let mut operator_team = team.operator.client.team(team_id);
+    operator_team
+        .assign_aqc_net_identifier(team.membera.id, team.membera.aqc_net_id())
+        .await?;
+    operator_team
+        .assign_aqc_net_identifier(team.memberb.id, team.memberb.aqc_net_id())
+        .await?;
+
+    let label1 = operator_team.create_label(text!("label1")).await?;
+    let op = ChanOp::SendRecv;
+    operator_team
+        .assign_label(team.membera.id, label1, op)
+        .await?;
+    operator_team
+        .assign_label(team.memberb.id, label1, op)
+        .await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    let (mut bidi_chan1, peer_channel) = try_join(
+        team.membera
+            .client
+            .aqc()
+            .create_bidi_channel(team_id, team.memberb.aqc_net_id(), label1),
+        team.memberb.client.aqc().receive_channel(),
+    )
+    .await
+    .expect("can create and receive channel");
+
+    let mut bidi_chan2 = match peer_channel {
+        AqcPeerChannel::Bidi(channel) => channel,
+        _ => buggy::bug!("Expected a bidirectional channel on memberb"),
+    };
+
+    let mut bidi1_2 = bidi_chan1.create_bidi_stream().await?;
+    let msg1 = Bytes::from_static(b"hello");
+    bidi1_2.send(msg1.clone()).await?;
+    let mut bidi2_2 = bidi_chan2
+        .receive_stream()
+        .await
+        .unwrap()
+        .into_bidi()
+        .ok()
+        .unwrap();
+    let bytes = bidi2_2.receive().await?.assume("no data received")?;
+    assert_eq!(bytes, msg1);
+
+    // Revoke the label while the stream is open.
+    team.operator
+        .client
+        .team(team_id)
+        .revoke_label(team.memberb.id, label1)
+        .await?;
+
+    // wait for syncing.
+    sleep(sleep_interval).await;
+
+    // Both ends of the stream are closed.
+    let mut closed = false;
+    for _ in 0..50 {
+        if bidi1_2.send(Bytes::from_static(b"hello2")).await.is_err() {
+            closed = true;
+            break;
+        }
+        sleep(interval).await;
+    }
+    assert!(closed, "membera can still send after the label was revoked");
+    assert!(
+        bidi2_2.receive().await.is_err(),
+        "memberb can still receive after the label was revoked"
+    );
+
+    // The channel cannot be recreated.
+    team.membera
+        .client
+        .aqc()
+        .create_bidi_channel(team_id, team.memberb.aqc_net_id(), label1)
+        .await
+        .expect_err("created channel with revoked label");
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use std::{
     net::{Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
@@ -8,17 +9,36 @@ use anyhow::{Context, Result};
 use aranya_client::{client::Client, QuicSyncConfig, SyncPeerConfig, TeamConfig};
 use aranya_daemon::{
     config::{self as daemon_cfg, Config},
+    sync::task::loopback::Network,
     Daemon, DaemonHandle,
 };
 use aranya_daemon_api::{DeviceId, KeyBundle, NetIdentifier, Role, TeamId, SEED_IKM_SIZE};
-use aranya_util::Addr;
+use aranya_util::{rustls::NoCertVerifier, Addr};
 use backon::{ExponentialBuilder, Retryable as _};
+#[allow(deprecated)]
+use s2n_quic::{
+    client::Connect,
+    provider::tls::rustls::{
+        self as rustls_provider,
+        rustls::{
+            client::PresharedKeyStore,
+            crypto::{hash::HashAlgorithm, PresharedKey},
+            pki_types::ServerName,
+            ClientConfig,
+        },
+    },
+};
 use tokio::{fs, time};
 use tracing::{info, instrument, trace};
 
-const SYNC_INTERVAL: Duration = Duration::from_millis(100);
+#[allow(unused, reason = "module compiled for each test file")]
+pub mod sim;
+
+pub const SYNC_INTERVAL: Duration = Duration::from_millis(100);
 // Allow for one missed sync and a misaligned sync rate, while keeping run times low.
 pub const SLEEP_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the daemons keep the PSKs of a rotated seed.
+pub const SEED_GRACE: Duration = Duration::from_secs(3);
 
 #[instrument(skip_all)]
 pub async fn sleep(duration: Duration) {
@@ -26,6 +46,67 @@ pub async fn sleep(duration: Duration) {
     time::sleep(duration).await;
 }
 
+/// How long a refused QUIC handshake is given before it counts as
+/// refused.
+///
+/// Servers drop handshakes without a valid PSK instead of failing
+/// them.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A [`PresharedKeyStore`] that always offers the same PSKs.
+#[derive(Debug)]
+struct FixedPsks(Vec<Arc<PresharedKey>>);
+
+#[allow(deprecated)]
+impl PresharedKeyStore for FixedPsks {
+    fn psks(&self, _server_name: &ServerName<'_>) -> Vec<Arc<PresharedKey>> {
+        self.0.clone()
+    }
+}
+
+/// Creates an external PSK that uses SHA-384.
+pub fn external_psk(identity: &[u8], secret: &[u8]) -> Result<Arc<PresharedKey>> {
+    let psk = PresharedKey::external(identity, secret)
+        .context("unable to create PSK")?
+        .with_hash_alg(HashAlgorithm::SHA384)
+        .context("unable to set PSK hash")?;
+    Ok(Arc::new(psk))
+}
+
+/// Reports whether a QUIC handshake with the server at `addr` that
+/// offers `psks` for `alpn` completes.
+pub async fn quic_handshake(
+    addr: SocketAddr,
+    alpn: &[u8],
+    psks: Vec<Arc<PresharedKey>>,
+) -> Result<bool> {
+    let mut config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(NoCertVerifier::new())
+        .with_no_client_auth();
+    config.alpn_protocols = vec![alpn.to_vec()];
+    config.preshared_keys = Arc::new(FixedPsks(psks));
+    #[allow(deprecated)]
+    let provider = rustls_provider::Client::new(config);
+    let client = s2n_quic::Client::builder()
+        .with_tls(provider)?
+        .with_io((Ipv4Addr::LOCALHOST, 0))?
+        .start()?;
+
+    let connect = client.connect(Connect::new(addr).with_server_name(addr.ip().to_string()));
+    let res = time::timeout(HANDSHAKE_TIMEOUT, connect).await;
+    Ok(matches!(res, Ok(Ok(_))))
+}
+
+/// The sync transports a device's daemon is configured with.
+#[derive(Copy, Clone, Debug)]
+#[allow(unused, reason = "module compiled for each test file")]
+pub enum Transports {
+    Quic,
+    Tcp,
+    Both,
+}
+
 pub struct TeamCtx {
     pub owner: DeviceCtx,
     pub admin: DeviceCtx,
@@ -36,11 +117,23 @@ pub struct TeamCtx {
 
 impl TeamCtx {
     pub async fn new(name: &str, work_dir: PathBuf) -> Result<Self> {
-        let owner = DeviceCtx::new(name, "owner", work_dir.join("owner")).await?;
-        let admin = DeviceCtx::new(name, "admin", work_dir.join("admin")).await?;
-        let operator = DeviceCtx::new(name, "operator", work_dir.join("operator")).await?;
-        let membera = DeviceCtx::new(name, "membera", work_dir.join("membera")).await?;
-        let memberb = DeviceCtx::new(name, "memberb", work_dir.join("memberb")).await?;
+        Self::with_transports(name, work_dir, [Transports::Quic; 5]).await
+    }
+
+    /// Creates a team whose devices use the given transports, in the
+    /// order owner, admin, operator, membera, memberb.
+    pub async fn with_transports(
+        name: &str,
+        work_dir: PathBuf,
+        transports: [Transports; 5],
+    ) -> Result<Self> {
+        let [t_owner, t_admin, t_operator, t_membera, t_memberb] = transports;
+        let owner = DeviceCtx::new(name, "owner", work_dir.join("owner"), t_owner).await?;
+        let admin = DeviceCtx::new(name, "admin", work_dir.join("admin"), t_admin).await?;
+        let operator =
+            DeviceCtx::new(name, "operator", work_dir.join("operator"), t_operator).await?;
+        let membera = DeviceCtx::new(name, "membera", work_dir.join("membera"), t_membera).await?;
+        let memberb = DeviceCtx::new(name, "memberb", work_dir.join("memberb"), t_memberb).await?;
 
         Ok(Self {
             owner,
@@ -166,29 +259,102 @@ pub struct DeviceCtx {
     pub client: Client,
     pub pk: KeyBundle,
     pub id: DeviceId,
-    #[expect(unused, reason = "manages tasks")]
+    #[allow(unused, reason = "manages tasks")]
     pub daemon: DaemonHandle,
+    /// The daemon's config and network, for restarting it.
+    cfg: Config,
+    network: Option<Network>,
 }
 
 impl DeviceCtx {
-    async fn new(_team_name: &str, name: &str, work_dir: PathBuf) -> Result<Self> {
-        let addr_any = Addr::from((Ipv4Addr::LOCALHOST, 0));
-
+    async fn new(
+        _team_name: &str,
+        name: &str,
+        work_dir: PathBuf,
+        transports: Transports,
+    ) -> Result<Self> {
         // Setup daemon config.
-        let quic_sync = Some(daemon_cfg::QuicSyncConfig {});
+        let seed_grace_ms = Some(SEED_GRACE.as_millis().try_into()?);
+        let quic_sync = matches!(transports, Transports::Quic | Transports::Both).then(|| {
+            daemon_cfg::QuicSyncConfig {
+                seed_grace_ms,
+                ..Default::default()
+            }
+        });
+        let tcp_sync = matches!(transports, Transports::Tcp | Transports::Both).then(|| {
+            daemon_cfg::TcpSyncConfig {
+                seed_grace_ms,
+                ..Default::default()
+            }
+        });
+        let cfg = Self::config(name, &work_dir, quic_sync, tcp_sync);
+
+        Self::start(cfg, None).await
+    }
 
-        let cfg = Config {
+    /// Creates a device whose daemon syncs over `network`.
+    pub async fn with_network(name: &str, work_dir: PathBuf, network: Network) -> Result<Self> {
+        let cfg = Self::config(name, &work_dir, None, None);
+        Self::start(cfg, Some(network)).await
+    }
+
+    /// Creates a device whose daemon syncs over `network` and saves its
+    /// peer caches.
+    #[allow(unused, reason = "module compiled for each test file")]
+    pub async fn with_network_caches(
+        name: &str,
+        work_dir: PathBuf,
+        network: Network,
+    ) -> Result<Self> {
+        // Only the syncer settings are used while there's a network.
+        let quic_sync = daemon_cfg::QuicSyncConfig {
+            persist_peer_caches: true,
+            ..Default::default()
+        };
+        let cfg = Self::config(name, &work_dir, Some(quic_sync), None);
+        Self::start(cfg, Some(network)).await
+    }
+
+    /// Stops the daemon and starts it again from the state it saved.
+    #[allow(unused, reason = "module compiled for each test file")]
+    pub async fn restart(self) -> Result<Self> {
+        let Self {
+            client,
+            daemon,
+            cfg,
+            network,
+            ..
+        } = self;
+        drop(client);
+        drop(daemon);
+        // Give the aborted tasks time to finish.
+        sleep(SLEEP_INTERVAL).await;
+        Self::start(cfg, network).await
+    }
+
+    fn config(
+        name: &str,
+        work_dir: &Path,
+        quic_sync: Option<daemon_cfg::QuicSyncConfig>,
+        tcp_sync: Option<daemon_cfg::TcpSyncConfig>,
+    ) -> Config {
+        Config {
             name: name.into(),
             runtime_dir: work_dir.join("run"),
             state_dir: work_dir.join("state"),
             cache_dir: work_dir.join("cache"),
             logs_dir: work_dir.join("log"),
             config_dir: work_dir.join("config"),
-            sync_addr: addr_any,
+            sync_addr: Addr::from((Ipv4Addr::LOCALHOST, 0)),
             afc: None,
             aqc: None,
             quic_sync,
-        };
+            tcp_sync,
+        }
+    }
+
+    async fn start(cfg: Config, network: Option<Network>) -> Result<Self> {
+        let addr_any = Addr::from((Ipv4Addr::LOCALHOST, 0));
 
         for dir in [
             &cfg.runtime_dir,
@@ -204,10 +370,12 @@ impl DeviceCtx {
         let uds_path = cfg.uds_api_sock();
 
         // Load and start daemon from config.
-        let daemon = Daemon::load(cfg.clone())
-            .await
-            .context("unable to init daemon")?
-            .spawn();
+        let daemon = match &network {
+            Some(network) => Daemon::load_with_network(cfg.clone(), network.clone()).await,
+            None => Daemon::load(cfg.clone()).await,
+        }
+        .context("unable to init daemon")?
+        .spawn();
 
         // give daemon time to setup UDS API and write the public key.
         sleep(SLEEP_INTERVAL).await;
@@ -232,6 +400,8 @@ impl DeviceCtx {
             pk,
             id,
             daemon,
+            cfg,
+            network,
         })
     }
 
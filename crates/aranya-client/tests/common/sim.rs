@@ -0,0 +1,185 @@
+//! Deterministic simulation of several daemons syncing one team.
+//!
+//! The daemons sync over an in-memory [`Network`] instead of UDP, and only when the simulation
+//! tells them to, one sync at a time. Which device syncs with which, and which syncs the network
+//! loses, come from one seed, so a failing run can be repeated by running it with the same seed.
+
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use aranya_client::{QuicSyncConfig, TeamConfig};
+use aranya_daemon::sync::task::loopback::Network;
+use aranya_daemon_api::{DeviceId, KeyBundle, TeamId, SEED_IKM_SIZE};
+use aranya_util::Addr;
+use tokio::time;
+use tracing::{debug, info};
+
+use super::DeviceCtx;
+
+/// How long a single sync may take before the simulation gives up.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Several daemons that sync over a simulated network.
+pub struct Sim {
+    pub network: Network,
+    pub devices: Vec<DeviceCtx>,
+    addrs: Vec<SocketAddr>,
+    team_id: Option<TeamId>,
+}
+
+impl Sim {
+    /// Starts `n` daemons on a network seeded with `seed`.
+    pub async fn new(name: &str, work_dir: PathBuf, n: usize, seed: u64) -> Result<Self> {
+        info!(name, n, seed, "starting simulation");
+        let network = Network::new(seed);
+        let mut devices = Vec::with_capacity(n);
+        let mut addrs = Vec::with_capacity(n);
+        for i in 0..n {
+            let device = DeviceCtx::with_network(
+                &format!("device{i}"),
+                work_dir.join(format!("device{i}")),
+                network.clone(),
+            )
+            .await?;
+            addrs.push(device.aranya_local_addr().await?);
+            devices.push(device);
+        }
+        Ok(Self {
+            network,
+            devices,
+            addrs,
+            team_id: None,
+        })
+    }
+
+    /// Adds a device created on the simulation's network.
+    pub async fn add_device(&mut self, device: DeviceCtx) -> Result<()> {
+        self.addrs.push(device.aranya_local_addr().await?);
+        self.devices.push(device);
+        Ok(())
+    }
+
+    /// Restarts the daemon of device `i`, which rejoins the network at a
+    /// new address.
+    pub async fn restart(&mut self, i: usize) -> Result<()> {
+        let device = self.devices.remove(i).restart().await?;
+        self.addrs[i] = device.aranya_local_addr().await?;
+        self.devices.insert(i, device);
+        Ok(())
+    }
+
+    /// Returns the team created by [`Sim::create_team`].
+    pub fn team_id(&self) -> TeamId {
+        self.team_id.expect("team should be created")
+    }
+
+    /// Returns the address of device `i` on the network.
+    pub fn addr(&self, i: usize) -> Addr {
+        self.addrs[i].into()
+    }
+
+    pub fn id(&self, i: usize) -> DeviceId {
+        self.devices[i].id
+    }
+
+    pub fn pk(&self, i: usize) -> KeyBundle {
+        self.devices[i].pk.clone()
+    }
+
+    /// Returns the team of device `i`.
+    pub fn team(&mut self, i: usize) -> aranya_client::Team<'_> {
+        let team_id = self.team_id();
+        self.devices[i].client.team(team_id)
+    }
+
+    /// Creates a team owned by device 0, which every device adds.
+    ///
+    /// Only device 0 has the team's graph until the others sync.
+    pub async fn create_team(&mut self) -> Result<TeamId> {
+        let seed_ikm = {
+            let mut buf = [0; SEED_IKM_SIZE];
+            self.devices[0].client.rand(&mut buf).await;
+            buf
+        };
+        let cfg = {
+            let qs_cfg = QuicSyncConfig::builder().seed_ikm(seed_ikm).build()?;
+            TeamConfig::builder().quic_sync(qs_cfg).build()?
+        };
+        let team_id = self.devices[0]
+            .client
+            .create_team(cfg.clone())
+            .await?
+            .team_id();
+        for device in &mut self.devices[1..] {
+            device.client.add_team(team_id, cfg.clone()).await?;
+        }
+        self.team_id = Some(team_id);
+        Ok(team_id)
+    }
+
+    /// Has device `i` sync with device `j`, and waits for the sync to
+    /// finish.
+    ///
+    /// The sync fails if the network partitions or loses it.
+    pub async fn sync(&mut self, i: usize, j: usize) -> Result<()> {
+        debug!(i, j, "syncing");
+        let mut finished = self.network.finished();
+        let before = *finished.borrow_and_update();
+        let addr = self.addr(j);
+        self.team(i).sync_now(addr, None).await?;
+        time::timeout(SYNC_TIMEOUT, finished.wait_for(|n| *n > before))
+            .await
+            .context("sync timed out")??;
+        Ok(())
+    }
+
+    /// Has device `i` sync with device `j`, and returns the number of
+    /// bytes the sync transferred.
+    pub async fn sync_bytes(&mut self, i: usize, j: usize) -> Result<u64> {
+        let before = self.network.transferred();
+        self.sync(i, j).await?;
+        Ok(self.network.transferred() - before)
+    }
+
+    /// Has a random device sync with another random device.
+    pub async fn step(&mut self) -> Result<(usize, usize)> {
+        let n = self.devices.len();
+        let i = self.network.random(n);
+        // Any device but `i`.
+        let j = (i + 1 + self.network.random(n - 1)) % n;
+        self.sync(i, j).await?;
+        Ok((i, j))
+    }
+
+    /// Runs `steps` random syncs.
+    pub async fn run(&mut self, steps: usize) -> Result<()> {
+        for _ in 0..steps {
+            self.step().await?;
+        }
+        Ok(())
+    }
+
+    /// Has every device sync with every other device, twice, so that
+    /// each has every command if the network is healed and loses
+    /// nothing.
+    pub async fn converge(&mut self) -> Result<()> {
+        let n = self.devices.len();
+        for _ in 0..2 {
+            for i in 0..n {
+                for j in (0..n).filter(|&j| j != i) {
+                    self.sync(i, j).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Partitions every device in `a` from every device in `b`.
+    pub fn partition(&self, a: &[usize], b: &[usize]) {
+        for &i in a {
+            for &j in b {
+                self.network.partition(self.addr(i), self.addr(j));
+            }
+        }
+    }
+}
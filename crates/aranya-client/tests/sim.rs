@@ -0,0 +1,176 @@
+//! Simulated tests of devices syncing over an in-memory network.
+
+#![allow(
+    clippy::disallowed_macros,
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::panic,
+    clippy::unwrap_used,
+    rust_2018_idioms
+)]
+
+use anyhow::Result;
+use aranya_daemon_api::{text, ChanOp, Role};
+use test_log::test;
+
+#[allow(unused, reason = "only the simulation is used here")]
+mod common;
+use common::{sim::Sim, DeviceCtx};
+
+const OWNER: usize = 0;
+
+/// Returns what each device sees as the role of every device.
+async fn roles(sim: &mut Sim) -> Vec<Vec<Option<Role>>> {
+    let ids: Vec<_> = (0..sim.devices.len()).map(|i| sim.id(i)).collect();
+    let mut roles = Vec::new();
+    for i in 0..sim.devices.len() {
+        let mut team = sim.team(i);
+        let mut queries = team.queries();
+        let mut seen = Vec::new();
+        for id in &ids {
+            seen.push(queries.device_role(*id).await.ok());
+        }
+        roles.push(seen);
+    }
+    roles
+}
+
+/// Adds devices 1 to 3 to the team, and makes device 1 an admin.
+async fn add_devices(sim: &mut Sim) -> Result<()> {
+    for i in 1..sim.devices.len() {
+        let pk = sim.pk(i);
+        sim.team(OWNER).add_device_to_team(pk).await?;
+    }
+    let admin = sim.id(1);
+    sim.team(OWNER).assign_role(admin, Role::Admin).await?;
+    sim.converge().await
+}
+
+/// Tests that devices agree on roles after an admin assigns a role
+/// while the owner, on the other side of a partition, revokes the
+/// admin's role.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sim_concurrent_role_changes() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut sim = Sim::new("test_sim_concurrent_role_changes", work_dir, 4, 7).await?;
+    sim.create_team().await?;
+    add_devices(&mut sim).await?;
+
+    sim.partition(&[OWNER], &[1]);
+    let (admin, operator) = (sim.id(1), sim.id(2));
+    sim.team(1).assign_role(operator, Role::Operator).await?;
+    sim.team(OWNER).revoke_role(admin, Role::Admin).await?;
+    sim.run(30).await?;
+
+    sim.network.heal();
+    sim.converge().await?;
+
+    let roles = roles(&mut sim).await;
+    for seen in &roles[1..] {
+        assert_eq!(seen, &roles[OWNER]);
+    }
+    assert_eq!(roles[OWNER][1], Some(Role::Member));
+
+    Ok(())
+}
+
+/// Tests that a label revocation reaches every device while the network
+/// loses syncs.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sim_label_revocation_with_loss() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut sim = Sim::new("test_sim_label_revocation_with_loss", work_dir, 4, 11).await?;
+    sim.create_team().await?;
+    add_devices(&mut sim).await?;
+
+    let (member1, member2) = (sim.id(2), sim.id(3));
+    let label = sim.team(OWNER).create_label(text!("label")).await?;
+    sim.team(OWNER)
+        .assign_label(member1, label, ChanOp::SendRecv)
+        .await?;
+    sim.team(OWNER)
+        .assign_label(member2, label, ChanOp::SendRecv)
+        .await?;
+
+    sim.network.set_loss(0.3);
+    sim.run(20).await?;
+    sim.team(OWNER).revoke_label(member2, label).await?;
+    sim.run(20).await?;
+
+    sim.network.set_loss(0.0);
+    sim.converge().await?;
+
+    for i in 0..sim.devices.len() {
+        let mut team = sim.team(i);
+        let mut queries = team.queries();
+        let labels = queries.device_label_assignments(member1).await?;
+        assert_eq!(labels.iter().count(), 1, "device {i}");
+        let labels = queries.device_label_assignments(member2).await?;
+        assert_eq!(labels.iter().count(), 0, "device {i}");
+    }
+
+    Ok(())
+}
+
+/// Tests that a device that saves its peer caches sends fewer bytes in
+/// an idle sync after restarting than one that doesn't, and that it
+/// still starts after the team is removed.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sim_peer_caches_survive_restart() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut sim = Sim::new(
+        "test_sim_peer_caches_survive_restart",
+        work_dir.clone(),
+        2,
+        13,
+    )
+    .await?;
+    // Device 2 saves its peer caches, device 1 doesn't.
+    let device =
+        DeviceCtx::with_network_caches("device2", work_dir.join("device2"), sim.network.clone())
+            .await?;
+    sim.add_device(device).await?;
+    let team_id = sim.create_team().await?;
+
+    for i in 1..sim.devices.len() {
+        let pk = sim.pk(i);
+        sim.team(OWNER).add_device_to_team(pk).await?;
+    }
+    let operator = sim.id(1);
+    sim.team(OWNER)
+        .assign_role(operator, Role::Operator)
+        .await?;
+    sim.converge().await?;
+
+    // Concurrent commands give the graph many segments, which an
+    // uncached sync request samples.
+    for round in 0..10 {
+        sim.team(OWNER)
+            .create_label(format!("owner{round}").parse()?)
+            .await?;
+        sim.team(1)
+            .create_label(format!("operator{round}").parse()?)
+            .await?;
+        sim.sync(OWNER, 1).await?;
+        sim.sync(1, OWNER).await?;
+    }
+    sim.converge().await?;
+
+    sim.restart(1).await?;
+    sim.restart(2).await?;
+    let uncached = sim.sync_bytes(1, OWNER).await?;
+    let cached = sim.sync_bytes(2, OWNER).await?;
+    assert!(
+        cached < uncached,
+        "idle sync sent {cached} bytes with a cache, {uncached} without"
+    );
+
+    // The removed team's caches are deleted, and the daemon starts again.
+    sim.devices[2].client.remove_team(team_id).await?;
+    common::sleep(common::SLEEP_INTERVAL).await;
+    sim.restart(2).await?;
+    let caches = work_dir.join("device2").join("cache").join("peer_caches");
+    assert_eq!(std::fs::read_dir(caches)?.count(), 0);
+
+    Ok(())
+}
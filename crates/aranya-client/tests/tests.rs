@@ -12,13 +12,19 @@
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use aranya_client::{QuicSyncConfig, TeamConfig};
-use aranya_daemon_api::Role;
+use aranya_client::{QuicSyncConfig, SyncPeerConfig, TeamConfig};
+use aranya_daemon_api::{
+    text, ChanOp, LabelId, Role, SyncTransport, TeamEvent, TeamEventKind, TeamId,
+};
+use aranya_util::Addr;
 use test_log::test;
 use tracing::{debug, info};
 
 mod common;
-use common::{sleep, TeamCtx, SLEEP_INTERVAL};
+use common::{
+    external_psk, quic_handshake, sleep, DeviceCtx, TeamCtx, Transports, SEED_GRACE,
+    SLEEP_INTERVAL, SYNC_INTERVAL,
+};
 
 /// Tests sync_now() by showing that an admin cannot assign any roles until it syncs with the owner.
 #[test(tokio::test(flavor = "multi_thread"))]
@@ -111,6 +117,275 @@ async fn test_remove_devices() -> Result<()> {
     Ok(())
 }
 
+/// Tests that removing a device revokes its labels and unsets its AQC
+/// network identifier, and that re-adding its keys does not restore them.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_remove_device_cleans_up_facts() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_remove_device_cleans_up_facts", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let net_id = team.membera.aqc_net_id();
+
+    let mut owner = team.owner.client.team(team_id);
+    owner.add_device_to_team(team.membera.pk.clone()).await?;
+    let label1 = owner.create_label(text!("label1")).await?;
+    let label2 = owner.create_label(text!("label2")).await?;
+    owner
+        .assign_label(team.membera.id, label1, ChanOp::SendRecv)
+        .await?;
+    owner
+        .assign_label(team.membera.id, label2, ChanOp::RecvOnly)
+        .await?;
+    owner
+        .assign_aqc_net_identifier(team.membera.id, net_id.clone())
+        .await?;
+    let mut queries = owner.queries();
+    assert_eq!(
+        queries.device_label_op(team.membera.id, label2).await?,
+        Some(ChanOp::RecvOnly)
+    );
+
+    let mut events = owner
+        .subscribe(&[
+            TeamEventKind::DeviceRemoved,
+            TeamEventKind::LabelRevoked,
+            TeamEventKind::AqcNetIdentifierUnset,
+        ])
+        .await?;
+    owner.remove_device_from_team(team.membera.id).await?;
+
+    // Every fact keyed by the device is removed, with an event for each.
+    let timeout = Duration::from_secs(10);
+    let mut revoked = Vec::new();
+    let mut unset = false;
+    loop {
+        match tokio::time::timeout(timeout, events.next()).await?? {
+            TeamEvent::LabelRevoked {
+                device_id,
+                label_id,
+                ..
+            } if device_id == team.membera.id => revoked.push(label_id),
+            TeamEvent::AqcNetIdentifierUnset { device_id } if device_id == team.membera.id => {
+                unset = true
+            }
+            TeamEvent::DeviceRemoved { device_id } if device_id == team.membera.id => break,
+            event => bail!("unexpected event {event:?}"),
+        }
+    }
+    revoked.sort();
+    let mut expected = vec![label1, label2];
+    expected.sort();
+    assert_eq!(revoked, expected);
+    assert!(unset, "net identifier was not unset");
+    events.unsubscribe().await?;
+
+    let mut queries = owner.queries();
+    assert_eq!(
+        queries
+            .device_label_assignments(team.membera.id)
+            .await?
+            .iter()
+            .count(),
+        0
+    );
+
+    // Re-adding the same keys starts from scratch.
+    owner.add_device_to_team(team.membera.pk.clone()).await?;
+    let mut queries = owner.queries();
+    assert_eq!(
+        queries
+            .device_label_assignments(team.membera.id)
+            .await?
+            .iter()
+            .count(),
+        0
+    );
+    assert_eq!(queries.aqc_net_identifier(team.membera.id).await?, None);
+    assert_eq!(
+        queries.device_label_op(team.membera.id, label1).await?,
+        None
+    );
+    assert_eq!(
+        queries.device_label_op(team.membera.id, label2).await?,
+        None
+    );
+
+    Ok(())
+}
+
+/// Tests that a label assigned concurrently with removing the device
+/// does not survive the removal.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_remove_device_concurrent_assign_label() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_remove_device_concurrent_assign_label", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let owner_addr = team.owner.aranya_local_addr().await?;
+    let operator_addr = team.operator.aranya_local_addr().await?;
+
+    let mut owner = team.owner.client.team(team_id);
+    let mut operator = team.operator.client.team(team_id);
+
+    owner.add_device_to_team(team.operator.pk.clone()).await?;
+    owner.assign_role(team.operator.id, Role::Operator).await?;
+    owner.add_device_to_team(team.membera.pk.clone()).await?;
+    let label = owner.create_label(text!("label")).await?;
+    operator.sync_now(owner_addr.into(), None).await?;
+    sleep(SLEEP_INTERVAL).await;
+
+    // Neither device sees the other's command before it is made.
+    operator
+        .assign_label(team.membera.id, label, ChanOp::SendRecv)
+        .await?;
+    owner.remove_device_from_team(team.membera.id).await?;
+
+    owner.sync_now(operator_addr.into(), None).await?;
+    operator.sync_now(owner_addr.into(), None).await?;
+    sleep(SLEEP_INTERVAL).await;
+
+    for mut queries in [owner.queries(), operator.queries()] {
+        assert_eq!(
+            queries
+                .device_label_assignments(team.membera.id)
+                .await?
+                .iter()
+                .count(),
+            0
+        );
+    }
+
+    // Re-adding the device does not restore the label either.
+    owner.add_device_to_team(team.membera.pk.clone()).await?;
+    operator.sync_now(owner_addr.into(), None).await?;
+    sleep(SLEEP_INTERVAL).await;
+
+    for mut queries in [owner.queries(), operator.queries()] {
+        assert_eq!(
+            queries
+                .device_label_assignments(team.membera.id)
+                .await?
+                .iter()
+                .count(),
+            0
+        );
+        assert_eq!(queries.device_label_op(team.membera.id, label).await?, None);
+    }
+
+    Ok(())
+}
+
+/// Reports whether `device` sees `label` within `timeout`.
+async fn sees_label(
+    device: &mut DeviceCtx,
+    team_id: TeamId,
+    label: LabelId,
+    timeout: Duration,
+) -> Result<bool> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let labels = device.client.team(team_id).queries().labels().await?;
+        if labels.iter().any(|l| l.id == label) {
+            return Ok(true);
+        }
+        if tokio::time::Instant::now() > deadline {
+            return Ok(false);
+        }
+        sleep(SLEEP_INTERVAL).await;
+    }
+}
+
+/// Tests that rotating the sync seed stops a removed device from
+/// syncing once the old seed's grace period ends, while the remaining
+/// devices keep syncing.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_rotate_sync_seed() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_rotate_sync_seed", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    team.add_all_sync_peers(team_id).await?;
+    team.add_all_device_roles(team_id).await?;
+
+    // A removed device can't run queries, so watch its events instead.
+    let mut created = team
+        .memberb
+        .client
+        .team(team_id)
+        .subscribe(&[TeamEventKind::LabelCreated])
+        .await?;
+
+    let mut owner = team.owner.client.team(team_id);
+    owner.remove_device_from_team(team.memberb.id).await?;
+
+    // Removing the device alone does not stop it from syncing.
+    let timeout = Duration::from_secs(10);
+    let before = owner.create_label(text!("before")).await?;
+    match tokio::time::timeout(timeout, created.next()).await?? {
+        TeamEvent::LabelCreated { label_id, .. } => assert_eq!(label_id, before),
+        event => bail!("unexpected event {event:?}"),
+    }
+
+    owner.rotate_sync_seed().await?;
+    sleep(SEED_GRACE + SLEEP_INTERVAL * 2).await;
+
+    // Connections made with the old seed are dropped on their next
+    // sync, after which the remaining devices reconnect.
+    let mut owner = team.owner.client.team(team_id);
+    let after = owner.create_label(text!("after")).await?;
+    assert!(sees_label(&mut team.admin, team_id, after, timeout).await?);
+    assert!(sees_label(&mut team.membera, team_id, after, timeout).await?);
+    assert!(
+        tokio::time::timeout(SLEEP_INTERVAL * 4, created.next())
+            .await
+            .is_err(),
+        "removed device synced after the rotation"
+    );
+
+    Ok(())
+}
+
+/// Tests that a device that missed a sync seed rotation can fetch it
+/// with the old seed's PSKs after the grace period ends, and then
+/// sync again.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_rotate_sync_seed_recovery() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_rotate_sync_seed_recovery", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    team.add_all_sync_peers(team_id).await?;
+    team.add_all_device_roles(team_id).await?;
+
+    // Keep membera from syncing the rotation.
+    let mut peers = Vec::new();
+    for device in [&team.owner, &team.admin, &team.operator, &team.memberb] {
+        peers.push(Addr::from(device.aranya_local_addr().await?));
+    }
+    let mut membera = team.membera.client.team(team_id);
+    for peer in &peers {
+        membera.remove_sync_peer(*peer).await?;
+    }
+
+    let mut owner = team.owner.client.team(team_id);
+    owner.rotate_sync_seed().await?;
+    let after = owner.create_label(text!("after")).await?;
+    sleep(SEED_GRACE + SLEEP_INTERVAL * 2).await;
+
+    let config = SyncPeerConfig::builder().interval(SYNC_INTERVAL).build()?;
+    let owner_addr = team.owner.aranya_local_addr().await?;
+    team.membera
+        .client
+        .team(team_id)
+        .add_sync_peer(owner_addr.into(), config)
+        .await?;
+    let timeout = Duration::from_secs(10);
+    assert!(sees_label(&mut team.membera, team_id, after, timeout).await?);
+
+    Ok(())
+}
+
 /// Tests functionality to make sure that we can query the fact database for various things.
 #[test(tokio::test(flavor = "multi_thread"))]
 async fn test_query_functions() -> Result<()> {
@@ -373,3 +648,441 @@ async fn test_multi_team_sync() -> Result<()> {
 
     Ok(())
 }
+
+/// Tests listing, updating, and removing sync peers.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_update_sync_peers() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_update_sync_peers", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    team.add_all_sync_peers(team_id).await?;
+
+    let admin_addr: Addr = team.admin.aranya_local_addr().await?.into();
+    let operator_addr: Addr = team.operator.aranya_local_addr().await?.into();
+    let mut owner = team.owner.client.team(team_id);
+
+    // The owner syncs with the other four devices.
+    let peers = owner.list_sync_peers().await?;
+    assert_eq!(peers.iter().count(), 4);
+    let admin = peers
+        .iter()
+        .find(|p| p.addr == admin_addr)
+        .context("admin should be a sync peer")?;
+    assert_eq!(admin.config.interval, SYNC_INTERVAL);
+
+    // Change the admin's interval.
+    let interval = Duration::from_secs(60);
+    let config = SyncPeerConfig::builder().interval(interval).build()?;
+    owner.update_sync_peer(admin_addr, config.clone()).await?;
+    let peers = owner.list_sync_peers().await?;
+    assert_eq!(peers.iter().count(), 4);
+    for peer in peers.iter() {
+        let want = if peer.addr == admin_addr {
+            interval
+        } else {
+            SYNC_INTERVAL
+        };
+        assert_eq!(peer.config.interval, want, "{}", peer.addr);
+    }
+
+    // Removed peers are no longer listed and cannot be updated.
+    owner.remove_sync_peer(operator_addr).await?;
+    let peers = owner.list_sync_peers().await?;
+    assert_eq!(peers.iter().count(), 3);
+    assert!(peers.iter().all(|p| p.addr != operator_addr));
+    owner
+        .update_sync_peer(operator_addr, config)
+        .await
+        .expect_err("operator should not be a sync peer");
+
+    // Another team's peers are separate.
+    let cfg = TeamConfig::builder()
+        .quic_sync(QuicSyncConfig::builder().build()?)
+        .build()?;
+    let other = team.owner.client.create_team(cfg).await?.team_id();
+    let peers = team.owner.client.team(other).list_sync_peers().await?;
+    assert_eq!(peers.iter().count(), 0);
+
+    Ok(())
+}
+
+/// Tests that subscriptions see the events of local actions and of syncing.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_subscribe() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_subscribe", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let owner_addr = team.owner.aranya_local_addr().await?;
+
+    let mut owner = team.owner.client.team(team_id);
+    let mut owner_events = owner.subscribe(&[]).await?;
+    let mut admin_events = team
+        .admin
+        .client
+        .team(team_id)
+        .subscribe(&[TeamEventKind::DeviceAdded])
+        .await?;
+
+    owner.add_device_to_team(team.admin.pk.clone()).await?;
+    owner.assign_role(team.admin.id, Role::Admin).await?;
+    let label_id = owner.create_label(text!("TELEMETRY")).await?;
+
+    // The owner sees its own actions in order.
+    let timeout = Duration::from_secs(10);
+    let event = tokio::time::timeout(timeout, owner_events.next()).await??;
+    assert_eq!(
+        event,
+        TeamEvent::DeviceAdded {
+            device_id: team.admin.id
+        }
+    );
+    let event = tokio::time::timeout(timeout, owner_events.next()).await??;
+    assert_eq!(
+        event,
+        TeamEvent::RoleAssigned {
+            device_id: team.admin.id,
+            role: Role::Admin,
+        }
+    );
+    let event = tokio::time::timeout(timeout, owner_events.next()).await??;
+    assert!(
+        matches!(event, TeamEvent::LabelCreated { label_id: id, .. } if id == label_id),
+        "{event:?}"
+    );
+
+    // The admin sees the owner's actions once it syncs, but only the kinds
+    // it subscribed to.
+    team.admin
+        .client
+        .team(team_id)
+        .sync_now(owner_addr.into(), None)
+        .await?;
+    loop {
+        let event = tokio::time::timeout(timeout, admin_events.next()).await??;
+        debug!(?event, "admin event");
+        match event {
+            TeamEvent::DeviceAdded { device_id } if device_id == team.admin.id => break,
+            TeamEvent::DeviceAdded { .. } => {}
+            event => bail!("unexpected event {event:?}"),
+        }
+    }
+
+    owner_events.unsubscribe().await?;
+    admin_events.unsubscribe().await?;
+
+    Ok(())
+}
+
+/// Tests that sync status reports successful and failing peers.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sync_status() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_sync_status", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let owner_addr: Addr = team.owner.aranya_local_addr().await?.into();
+    team.owner
+        .client
+        .team(team_id)
+        .add_device_to_team(team.admin.pk.clone())
+        .await?;
+
+    // The admin syncs with the owner and with an address nothing listens on.
+    let mut admin = team.admin.client.team(team_id);
+    let unreachable: Addr = "127.0.0.1:1".parse()?;
+    let config = SyncPeerConfig::builder().interval(SYNC_INTERVAL).build()?;
+    admin.add_sync_peer(owner_addr, config.clone()).await?;
+    admin.add_sync_peer(unreachable, config).await?;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(60);
+    loop {
+        let status = admin.sync_status().await?;
+        assert_eq!(status.iter().count(), 2);
+        let owner = status
+            .iter()
+            .find(|p| p.addr == owner_addr)
+            .context("owner should be a sync peer")?;
+        let bad = status
+            .iter()
+            .find(|p| p.addr == unreachable)
+            .context("unreachable peer should be a sync peer")?;
+        if owner.last_success.is_some() && bad.consecutive_failures > 0 {
+            assert_eq!(owner.consecutive_failures, 0);
+            assert!(owner.last_error.is_none(), "{:?}", owner.last_error);
+            assert!(bad.last_attempt.is_some());
+            assert!(bad.last_success.is_none());
+            assert!(bad.last_error.is_some());
+            assert_eq!(bad.last_commands, 0);
+            break;
+        }
+        if tokio::time::Instant::now() > deadline {
+            bail!("peers were not synced with: {:?}", status.__data());
+        }
+        sleep(SLEEP_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Tests that a subscribed peer is told about new commands before its next
+/// sync interval.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_push_sync() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_push_sync", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let owner_addr: Addr = team.owner.aranya_local_addr().await?.into();
+    let mut owner = team.owner.client.team(team_id);
+    owner.add_device_to_team(team.admin.pk.clone()).await?;
+
+    // The admin would only poll the owner once an hour.
+    let mut admin = team.admin.client.team(team_id);
+    let mut admin_events = admin.subscribe(&[TeamEventKind::RoleAssigned]).await?;
+    let config = SyncPeerConfig::builder()
+        .interval(Duration::from_secs(60 * 60))
+        .subscribe(true)
+        .build()?;
+    admin.add_sync_peer(owner_addr, config).await?;
+
+    // Wait for the first sync, which subscribes to the owner.
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+    while admin
+        .sync_status()
+        .await?
+        .iter()
+        .all(|p| p.last_success.is_none())
+    {
+        if tokio::time::Instant::now() > deadline {
+            bail!("admin did not sync with the owner");
+        }
+        sleep(SLEEP_INTERVAL).await;
+    }
+
+    owner.assign_role(team.admin.id, Role::Admin).await?;
+    let event = tokio::time::timeout(Duration::from_secs(10), admin_events.next()).await??;
+    assert_eq!(
+        event,
+        TeamEvent::RoleAssigned {
+            device_id: team.admin.id,
+            role: Role::Admin,
+        }
+    );
+    admin_events.unsubscribe().await?;
+
+    Ok(())
+}
+
+/// Tests that a single sync catches a device up on more commands than fit
+/// in one sync message.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sync_large_graph() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_sync_large_graph", work_dir).await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let owner_addr = team.owner.aranya_local_addr().await?;
+    let mut owner = team.owner.client.team(team_id);
+    owner.add_device_to_team(team.admin.pk.clone()).await?;
+
+    // More commands than a sync message holds.
+    const LABELS: usize = 250;
+    for i in 0..LABELS {
+        owner.create_label(format!("label{i}").parse()?).await?;
+    }
+
+    let mut admin = team.admin.client.team(team_id);
+    admin.sync_now(owner_addr.into(), None).await?;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+    loop {
+        // The admin has no copy of the graph until the sync commits.
+        let labels = admin
+            .queries()
+            .labels()
+            .await
+            .map_or(0, |labels| labels.iter().count());
+        if labels == LABELS {
+            break;
+        }
+        if tokio::time::Instant::now() > deadline {
+            bail!("admin only has {labels} of {LABELS} labels after one sync");
+        }
+        sleep(SLEEP_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Tests that devices syncing several teams with each other at the same
+/// time have each connection checked against its own team.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_concurrent_multi_team_sync() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_concurrent_multi_team_sync", work_dir).await?;
+
+    // The same devices are on both teams.
+    let team_ids = [
+        team.create_and_add_team().await?,
+        team.create_and_add_team().await?,
+    ];
+    for team_id in team_ids {
+        team.add_all_sync_peers(team_id).await?;
+    }
+
+    // Assigning the roles of each team relies on its commands syncing
+    // while the other team is syncing too.
+    for team_id in team_ids {
+        team.add_all_device_roles(team_id).await?;
+    }
+
+    for device in team.devices() {
+        for team_id in team_ids {
+            let status = device.client.team(team_id).sync_status().await?;
+            for peer in status.iter() {
+                assert!(
+                    peer.last_success.is_some(),
+                    "{} was not synced with",
+                    peer.addr
+                );
+                if let Some(err) = &peer.last_error {
+                    assert!(!err.contains("InvalidPSK"), "{err}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Tests that a daemon can sync over TCP, and that a daemon with both
+/// syncers configured serves syncs over each of them.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sync_over_tcp() -> Result<()> {
+    // Set up our team context so we can run the test.
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::with_transports(
+        "test_sync_over_tcp",
+        work_dir,
+        [
+            Transports::Both,
+            Transports::Tcp,
+            Transports::Quic,
+            Transports::Quic,
+            Transports::Quic,
+        ],
+    )
+    .await?;
+
+    let team_id = team.create_and_add_team().await?;
+    let owner_addr = team.owner.aranya_local_addr().await?;
+    let mut owner = team.owner.client.team(team_id);
+    owner.add_device_to_team(team.admin.pk.clone()).await?;
+    owner.add_device_to_team(team.operator.pk.clone()).await?;
+    owner.assign_role(team.admin.id, Role::Admin).await?;
+
+    // The admin syncs over TCP and the operator over QUIC.
+    let mut admin = team.admin.client.team(team_id);
+    admin.sync_now(owner_addr.into(), None).await?;
+    let mut operator = team.operator.client.team(team_id);
+    operator.sync_now(owner_addr.into(), None).await?;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        // Neither has a copy of the graph until its sync commits.
+        let admin_devices = admin
+            .queries()
+            .devices_on_team()
+            .await
+            .map_or(0, |devices| devices.iter().count());
+        let operator_devices = operator
+            .queries()
+            .devices_on_team()
+            .await
+            .map_or(0, |devices| devices.iter().count());
+        if admin_devices == 3 && operator_devices == 3 {
+            break;
+        }
+        if tokio::time::Instant::now() > deadline {
+            bail!("admin sees {admin_devices} and operator sees {operator_devices} of 3 devices");
+        }
+        sleep(SLEEP_INTERVAL).await;
+    }
+
+    // The admin's role came over TCP.
+    admin.assign_role(team.operator.id, Role::Operator).await?;
+
+    // The owner syncs with the admin, which only serves TCP, over TCP
+    // while it syncs with the operator over QUIC.
+    let admin_addr = team.admin.aranya_local_addr().await?;
+    let operator_addr = team.operator.aranya_local_addr().await?;
+    let tcp_cfg = SyncPeerConfig::builder()
+        .interval(SYNC_INTERVAL)
+        .transport(SyncTransport::Tcp)
+        .build()?;
+    let quic_cfg = SyncPeerConfig::builder().interval(SYNC_INTERVAL).build()?;
+    owner.add_sync_peer(admin_addr.into(), tcp_cfg).await?;
+    owner.add_sync_peer(operator_addr.into(), quic_cfg).await?;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        let status = owner.sync_status().await?;
+        let synced = status
+            .iter()
+            .filter(|peer| peer.last_success.is_some())
+            .count();
+        let role = owner.queries().device_role(team.operator.id).await?;
+        if synced == 2 && role == Role::Operator {
+            break;
+        }
+        if tokio::time::Instant::now() > deadline {
+            let peers: Vec<_> = status.iter().collect();
+            bail!("owner synced with {synced} of 2 peers: {peers:?}");
+        }
+        sleep(SLEEP_INTERVAL).await;
+    }
+
+    // A daemon can't sync over a transport it isn't configured with.
+    let mut admin = team.admin.client.team(team_id);
+    let quic_cfg = SyncPeerConfig::builder()
+        .interval(SYNC_INTERVAL)
+        .transport(SyncTransport::Quic)
+        .build()?;
+    admin
+        .add_sync_peer(owner_addr.into(), quic_cfg)
+        .await
+        .expect_err("added a peer over an unconfigured transport");
+
+    Ok(())
+}
+
+/// Tests that the QUIC sync server refuses connections that do not
+/// use one of its teams' PSKs.
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn test_sync_rejects_unknown_psk() -> Result<()> {
+    let work_dir = tempfile::tempdir()?.path().to_path_buf();
+    let mut team = TeamCtx::new("test_sync_rejects_unknown_psk", work_dir).await?;
+    team.create_and_add_team().await?;
+
+    let addr = team.owner.aranya_local_addr().await?;
+    let alpn = b"quic-sync-unstable-2";
+    let psk = external_psk(&[1; 34], &[1; 48])?;
+    assert!(
+        !quic_handshake(addr, alpn, vec![psk]).await?,
+        "connected with an unknown PSK"
+    );
+    assert!(
+        !quic_handshake(addr, alpn, vec![]).await?,
+        "connected without a PSK"
+    );
+
+    Ok(())
+}
@@ -8,7 +8,10 @@ use core::{
     ops::Deref,
     time::Duration,
 };
-use std::collections::hash_map::{self, HashMap};
+use std::{
+    collections::hash_map::{self, HashMap},
+    time::SystemTime,
+};
 
 use anyhow::bail;
 pub use aranya_crypto::aqc::CipherSuiteId;
@@ -116,6 +119,15 @@ custom_id! {
     pub struct AqcUniChannelId;
 }
 
+/// An AQC channel ID.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum AqcChannelId {
+    /// A bidirectional channel.
+    Bidi(AqcBidiChannelId),
+    /// A unidirectional channel.
+    Uni(AqcUniChannelId),
+}
+
 /// A device's public key bundle.
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct KeyBundle {
@@ -500,6 +512,30 @@ impl ConstantTimeEq for AqcUniPsk {
 
 impl ZeroizeOnDrop for AqcUniPsk {}
 
+/// A PSK that authenticates AQC control messages for a team.
+///
+/// The daemon derives these from the team's PSK seed and rotates
+/// them periodically.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AqcCtrlPsk {
+    /// The team the PSK belongs to.
+    pub team: TeamId,
+    /// The PSK identity.
+    pub identity: Box<[u8]>,
+    /// The PSK's cipher suite.
+    pub suite: CipherSuiteId,
+    /// The PSK's secret.
+    pub secret: Secret,
+    /// Whether the PSK is for the current rotation period.
+    ///
+    /// Control messages are sent with current PSKs. PSKs for
+    /// the previous and next periods are only accepted, so peers
+    /// whose clocks differ a bit still agree on a PSK.
+    pub current: bool,
+}
+
+impl ZeroizeOnDrop for AqcCtrlPsk {}
+
 /// Either send only or receive only.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Directed<T> {
@@ -563,10 +599,209 @@ pub struct SyncPeerConfig {
     pub interval: Duration,
     /// Determines if a peer should be synced with immediately after they're added
     pub sync_now: bool,
+    /// Determines if a peer is forgotten when the daemon restarts instead of
+    /// being saved to disk
+    pub ephemeral: bool,
+    /// How to back off while syncs with the peer fail, or `None` to use
+    /// the daemon's default
+    #[serde(default)]
+    pub backoff: Option<SyncBackoff>,
+    /// Determines if the peer is asked to tell us as soon as it has new
+    /// commands, rather than them waiting for the next sync interval
+    #[serde(default)]
+    pub subscribe: bool,
+    /// The transport the peer is synced with over, or `None` to use the
+    /// daemon's default: QUIC if it's configured, else TCP
+    #[serde(default)]
+    pub transport: Option<SyncTransport>,
+}
+
+/// A transport that peers are synced with over.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SyncTransport {
+    /// QUIC, authenticated with the team's PSKs.
+    Quic,
+    /// TCP, authenticated with the team's PSKs.
+    Tcp,
+}
+
+/// Exponential backoff for a sync peer whose syncs are failing.
+///
+/// After each failed sync the delay before the next attempt grows by
+/// `factor`, from `min_delay` up to `max_delay`, but is never shorter than
+/// the peer's sync interval. A successful sync resets it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyncBackoff {
+    /// The delay after the first failure.
+    pub min_delay: Duration,
+    /// The longest delay between attempts.
+    pub max_delay: Duration,
+    /// How much the delay grows after each failure.
+    pub factor: f32,
+    /// Whether to add a random delay of up to `min_delay`, so that peers
+    /// don't retry in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for SyncBackoff {
+    fn default() -> Self {
+        Self {
+            min_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            factor: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+/// A peer that a team is synced with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncPeer {
+    /// The peer's address.
+    pub addr: Addr,
+    /// How often the peer is synced with.
+    pub config: SyncPeerConfig,
+}
+
+/// How syncing with a peer has gone.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncPeerStatus {
+    /// The peer's address.
+    pub addr: Addr,
+    /// When the last sync started, if the peer has been synced with.
+    pub last_attempt: Option<SystemTime>,
+    /// When the last successful sync started.
+    pub last_success: Option<SystemTime>,
+    /// The number of syncs that have failed since the last success.
+    pub consecutive_failures: u32,
+    /// The error from the last failed sync.
+    pub last_error: Option<String>,
+    /// The number of commands received by the last sync.
+    pub last_commands: u64,
+    /// The number of commands received so far by the sync that is
+    /// running, if one is.
+    pub receiving: Option<u64>,
+}
+
+/// Identifies a subscription created by [`DaemonApi::subscribe`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SubscriptionId(pub u64);
+
+impl fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A change to a team, made by this device or learned by syncing.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TeamEvent {
+    TeamCreated {
+        owner_id: DeviceId,
+    },
+    TeamTerminated {
+        owner_id: DeviceId,
+    },
+    DeviceAdded {
+        device_id: DeviceId,
+    },
+    DeviceRemoved {
+        device_id: DeviceId,
+    },
+    RoleAssigned {
+        device_id: DeviceId,
+        role: Role,
+    },
+    RoleRevoked {
+        device_id: DeviceId,
+        role: Role,
+    },
+    LabelCreated {
+        label_id: LabelId,
+        name: Text,
+        author_id: DeviceId,
+    },
+    LabelDeleted {
+        label_id: LabelId,
+        name: Text,
+        author_id: DeviceId,
+    },
+    LabelAssigned {
+        label_id: LabelId,
+        name: Text,
+        device_id: DeviceId,
+        author_id: DeviceId,
+    },
+    LabelRevoked {
+        label_id: LabelId,
+        name: Text,
+        device_id: DeviceId,
+        author_id: DeviceId,
+    },
+    AqcNetIdentifierSet {
+        device_id: DeviceId,
+        net_id: NetIdentifier,
+    },
+    AqcNetIdentifierUnset {
+        device_id: DeviceId,
+    },
+    /// An AQC channel with `peer_id` was closed because one of
+    /// its members lost access to `label_id`.
+    AqcChannelClosed {
+        channel_id: AqcChannelId,
+        label_id: LabelId,
+        peer_id: DeviceId,
+    },
+    /// The subscriber fell behind and `count` events were dropped.
+    Lagged {
+        count: u64,
+    },
+}
+
+impl TeamEvent {
+    /// Returns the kind of event, or `None` for [`TeamEvent::Lagged`],
+    /// which every subscription receives.
+    pub fn kind(&self) -> Option<TeamEventKind> {
+        let kind = match self {
+            Self::TeamCreated { .. } => TeamEventKind::TeamCreated,
+            Self::TeamTerminated { .. } => TeamEventKind::TeamTerminated,
+            Self::DeviceAdded { .. } => TeamEventKind::DeviceAdded,
+            Self::DeviceRemoved { .. } => TeamEventKind::DeviceRemoved,
+            Self::RoleAssigned { .. } => TeamEventKind::RoleAssigned,
+            Self::RoleRevoked { .. } => TeamEventKind::RoleRevoked,
+            Self::LabelCreated { .. } => TeamEventKind::LabelCreated,
+            Self::LabelDeleted { .. } => TeamEventKind::LabelDeleted,
+            Self::LabelAssigned { .. } => TeamEventKind::LabelAssigned,
+            Self::LabelRevoked { .. } => TeamEventKind::LabelRevoked,
+            Self::AqcNetIdentifierSet { .. } => TeamEventKind::AqcNetIdentifierSet,
+            Self::AqcNetIdentifierUnset { .. } => TeamEventKind::AqcNetIdentifierUnset,
+            Self::AqcChannelClosed { .. } => TeamEventKind::AqcChannelClosed,
+            Self::Lagged { .. } => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// The kinds of [`TeamEvent`], used to filter subscriptions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum TeamEventKind {
+    TeamCreated,
+    TeamTerminated,
+    DeviceAdded,
+    DeviceRemoved,
+    RoleAssigned,
+    RoleRevoked,
+    LabelCreated,
+    LabelDeleted,
+    LabelAssigned,
+    LabelRevoked,
+    AqcNetIdentifierSet,
+    AqcNetIdentifierUnset,
+    AqcChannelClosed,
 }
 
 /// Valid channel operations for a label assignment.
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum ChanOp {
     /// The device can only receive data in channels with this
     /// label.
@@ -609,6 +844,30 @@ pub trait DaemonApi {
     /// Removes the peer from automatic syncing.
     async fn remove_sync_peer(addr: Addr, team: TeamId) -> Result<()>;
 
+    /// Returns the peers the team is automatically synced with.
+    async fn list_sync_peers(team: TeamId) -> Result<Vec<SyncPeer>>;
+
+    /// Changes the configuration of an existing sync peer.
+    async fn update_sync_peer(addr: Addr, team: TeamId, config: SyncPeerConfig) -> Result<()>;
+
+    /// Returns how syncing with each of the team's sync peers has gone.
+    async fn sync_status(team: TeamId) -> Result<Vec<SyncPeerStatus>>;
+
+    /// Subscribes to events on a team.
+    ///
+    /// Only events of the given kinds are delivered, or every event if
+    /// `kinds` is empty.
+    async fn subscribe(team: TeamId, kinds: Vec<TeamEventKind>) -> Result<SubscriptionId>;
+
+    /// Waits up to `timeout` for events on a subscription.
+    ///
+    /// Returns the events that are ready as soon as there is one, or
+    /// nothing if `timeout` elapses first.
+    async fn next_events(sub: SubscriptionId, timeout: Duration) -> Result<Vec<TeamEvent>>;
+
+    /// Ends a subscription.
+    async fn unsubscribe(sub: SubscriptionId) -> Result<()>;
+
     /// add a team to the local device store that was created by someone else. Not an aranya action/command.
     async fn add_team(team: TeamId, cfg: TeamConfig) -> Result<()>;
 
@@ -624,6 +883,9 @@ pub trait DaemonApi {
         team: TeamId,
         peer_enc_pk: EncryptionPublicKey<CS>,
     ) -> Result<WrappedSeed>;
+    /// Replaces the team's QUIC sync PSK seed with a new one,
+    /// which is shared with every other device on the team.
+    async fn rotate_sync_seed(team: TeamId) -> Result<()>;
 
     /// Add device to the team.
     async fn add_device_to_team(team: TeamId, keys: KeyBundle) -> Result<()>;
@@ -675,11 +937,13 @@ pub trait DaemonApi {
         label_id: LabelId,
     ) -> Result<(AqcCtrl, AqcUniPsks)>;
     /// Delete a QUIC bidi channel.
-    async fn delete_aqc_bidi_channel(chan: AqcBidiChannelId) -> Result<AqcCtrl>;
+    async fn delete_aqc_bidi_channel(chan: AqcBidiChannelId) -> Result<()>;
     /// Delete a QUIC uni channel.
-    async fn delete_aqc_uni_channel(chan: AqcUniChannelId) -> Result<AqcCtrl>;
+    async fn delete_aqc_uni_channel(chan: AqcUniChannelId) -> Result<()>;
     /// Receive AQC ctrl message.
     async fn receive_aqc_ctrl(team: TeamId, ctrl: AqcCtrl) -> Result<(LabelId, AqcPsks)>;
+    /// Returns the PSKs for AQC control messages of every team.
+    async fn aqc_ctrl_psks() -> Result<Vec<AqcCtrlPsk>>;
 
     /// Query devices on team.
     async fn query_devices_on_team(team: TeamId) -> Result<Vec<DeviceId>>;
@@ -689,6 +953,12 @@ pub trait DaemonApi {
     async fn query_device_keybundle(team: TeamId, device: DeviceId) -> Result<KeyBundle>;
     /// Query device label assignments.
     async fn query_device_label_assignments(team: TeamId, device: DeviceId) -> Result<Vec<Label>>;
+    /// Query the operations a device may perform with a label.
+    async fn query_device_label_op(
+        team: TeamId,
+        device: DeviceId,
+        label: LabelId,
+    ) -> Result<Option<ChanOp>>;
     /// Query AQC network ID.
     async fn query_aqc_net_identifier(
         team: TeamId,
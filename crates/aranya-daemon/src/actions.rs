@@ -1,11 +1,11 @@
 //! Aranya graph actions/effects API.
 
 use core::{future::Future, marker::PhantomData};
-use std::{borrow::Cow, sync::Arc};
+use std::borrow::Cow;
 
 use anyhow::{Context, Result};
 use aranya_aqc_util::LabelId;
-use aranya_crypto::{Csprng, DeviceId, Rng};
+use aranya_crypto::{Csprng, DeviceId, Id, Rng};
 use aranya_daemon_api::NetIdentifier;
 use aranya_keygen::PublicKeys;
 use aranya_policy_ifgen::{Actor, VmAction, VmEffect};
@@ -15,7 +15,6 @@ use aranya_runtime::{
     VmPolicy,
 };
 use futures_util::TryFutureExt as _;
-use tokio::sync::Mutex;
 use tracing::{debug, info, instrument, warn, Instrument};
 
 use crate::{
@@ -62,7 +61,7 @@ where
     #[instrument(skip_all, fields(id = %id))]
     pub fn actions(&self, id: &GraphId) -> impl Actions<EN, SP, CE> {
         ActionsImpl {
-            aranya: Arc::clone(&self.aranya),
+            client: self.clone(),
             graph_id: *id,
             _eng: PhantomData,
         }
@@ -94,7 +93,7 @@ where
 /// Implements [`Actions`] for a particular storage.
 struct ActionsImpl<EN, SP, CE> {
     /// Aranya client graph state.
-    aranya: Arc<Mutex<ClientState<EN, SP>>>,
+    client: Client<EN, SP>,
     /// Aranya graph ID.
     graph_id: GraphId,
     /// Crypto engine.
@@ -115,10 +114,11 @@ where
         let mut sink = VecSink::new();
         // Make sure we drop the lock as quickly as possible.
         {
-            let mut client = self.aranya.lock().await;
+            let mut client = self.client.lock().await;
             let mut actor = ActorImpl::new(&mut client, &mut sink, &self.graph_id);
             f(&mut actor)?;
         }
+        self.client.committed(self.graph_id);
 
         let total = sink.effects.len();
         for (i, effect) in sink.effects.iter().enumerate() {
@@ -137,7 +137,7 @@ where
     where
         F: FnOnce() -> <<EN as Engine>::Policy as Policy>::Action<'a>,
     {
-        let mut client = self.aranya.lock().await;
+        let mut client = self.client.lock().await;
         let mut session = client.session(self.graph_id)?;
         let mut sink = VecSink::new();
         let mut msg_sink = MsgSink::new();
@@ -200,6 +200,22 @@ where
         .in_current_span()
     }
 
+    /// Shares a new QUIC sync PSK seed with the team.
+    #[instrument(skip(self, wrapped_seeds), fields(seed_id = %seed_id))]
+    fn rotate_sync_seed(
+        &self,
+        seed_id: Id,
+        rotated_at: i64,
+        wrapped_seeds: Vec<u8>,
+    ) -> impl Future<Output = Result<Vec<Effect>>> + Send {
+        info!(%seed_id, rotated_at, "rotating sync seed");
+        self.with_actor(move |actor| {
+            actor.rotate_sync_seed(seed_id, rotated_at, wrapped_seeds)?;
+            Ok(())
+        })
+        .in_current_span()
+    }
+
     /// Assigns role to a team member.
     #[instrument(skip_all)]
     fn assign_role(
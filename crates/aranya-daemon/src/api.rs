@@ -4,7 +4,11 @@
 #![allow(clippy::expect_used, clippy::panic, clippy::indexing_slicing)]
 
 use core::{future, net::SocketAddr, ops::Deref, pin::pin};
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{anyhow, Context as _};
 use aranya_crypto::{
@@ -34,15 +38,16 @@ use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
 use crate::{
     actions::Actions,
-    aqc::Aqc,
+    aqc::{Aqc, OpenChannel},
     daemon::{CE, CS, KS},
     keystore::LocalStore,
     policy::{ChanOp, Effect, KeyBundle, Role},
     sync::task::{quic as qs, SyncPeers},
-    util::SeedDir,
+    util::{SeedDir, TeamSeeds},
     AranyaStore, Client, InvalidGraphs, EF,
 };
 
+mod events;
 mod quic_sync;
 
 /// returns first effect matching a particular type.
@@ -96,6 +101,7 @@ impl DaemonApiServer {
         let aqc = Arc::new(aqc);
         let effect_handler = EffectHandler {
             aqc: Arc::clone(&aqc),
+            subscriptions: Arc::new(events::Subscriptions::new()),
         };
         let api = Api(Arc::new(ApiInner {
             client,
@@ -122,12 +128,15 @@ impl DaemonApiServer {
     pub(crate) async fn serve(mut self) {
         scope(async |s| {
             s.spawn({
-                let effect_handler = self.api.effect_handler.clone();
+                let api = self.api.clone();
                 async move {
                     while let Some((graph, effects)) = self.recv_effects.recv().await {
-                        if let Err(err) = effect_handler.handle_effects(graph, &effects).await {
+                        if let Err(err) = api.effect_handler.handle_effects(graph, &effects).await {
                             error!(?err, "error handling effects");
                         }
+                        if let Err(err) = api.handle_seed_rotations(graph, &effects).await {
+                            error!(?err, "error handling sync seed rotation");
+                        }
                     }
                     info!("effect handler exiting");
                 }
@@ -174,6 +183,7 @@ impl DaemonApiServer {
 #[derive(Clone, Debug)]
 struct EffectHandler {
     aqc: Arc<Aqc<CE, KS>>,
+    subscriptions: Arc<events::Subscriptions>,
 }
 
 impl EffectHandler {
@@ -182,14 +192,25 @@ impl EffectHandler {
     async fn handle_effects(&self, graph: GraphId, effects: &[Effect]) -> anyhow::Result<()> {
         trace!("handling effects");
 
+        self.subscriptions.publish(graph, effects);
+
         use Effect::*;
         for effect in effects {
             trace!(?effect, "handling effect");
             match effect {
                 TeamCreated(_team_created) => {}
-                TeamTerminated(_team_terminated) => {}
+                TeamTerminated(_team_terminated) => {
+                    let closed = self.aqc.close_team(graph).await;
+                    self.channels_closed(graph, closed);
+                }
                 MemberAdded(_member_added) => {}
-                MemberRemoved(_member_removed) => {}
+                MemberRemoved(e) => {
+                    let closed = self.aqc.close_device(graph, e.device_id).await;
+                    self.channels_closed(graph, closed);
+                }
+                // Handled by `Api::handle_seed_rotations`, which has
+                // the keystore.
+                SyncSeedRotated(_) => {}
                 OwnerAssigned(_owner_assigned) => {}
                 AdminAssigned(_admin_assigned) => {}
                 OperatorAssigned(_operator_assigned) => {}
@@ -197,9 +218,18 @@ impl EffectHandler {
                 AdminRevoked(_admin_revoked) => {}
                 OperatorRevoked(_operator_revoked) => {}
                 LabelCreated(_) => {}
-                LabelDeleted(_) => {}
+                LabelDeleted(e) => {
+                    let closed = self.aqc.close_label(graph, e.label_id).await;
+                    self.channels_closed(graph, closed);
+                }
                 LabelAssigned(_) => {}
-                LabelRevoked(_) => {}
+                LabelRevoked(e) => {
+                    let closed = self
+                        .aqc
+                        .close_label_channels(graph, e.label_id, e.device_id)
+                        .await;
+                    self.channels_closed(graph, closed);
+                }
                 AqcNetworkNameSet(e) => {
                     self.aqc
                         .add_peer(
@@ -211,10 +241,26 @@ impl EffectHandler {
                 }
                 AqcNetworkNameUnset(e) => self.aqc.remove_peer(graph, e.device_id.into()).await,
                 QueriedLabel(_) => {}
-                AqcBidiChannelCreated(_) => {}
-                AqcBidiChannelReceived(_) => {}
-                AqcUniChannelCreated(_) => {}
-                AqcUniChannelReceived(_) => {}
+                AqcBidiChannelCreated(e) => {
+                    let id = api::AqcChannelId::Bidi(e.channel_id.into());
+                    let members = [e.author_id, e.peer_id];
+                    self.aqc.add_channel(graph, id, e.label_id, members).await;
+                }
+                AqcBidiChannelReceived(e) => {
+                    let id = api::AqcChannelId::Bidi(e.channel_id.into());
+                    let members = [e.author_id, e.peer_id];
+                    self.aqc.add_channel(graph, id, e.label_id, members).await;
+                }
+                AqcUniChannelCreated(e) => {
+                    let id = api::AqcChannelId::Uni(e.channel_id.into());
+                    let members = [e.sender_id, e.receiver_id];
+                    self.aqc.add_channel(graph, id, e.label_id, members).await;
+                }
+                AqcUniChannelReceived(e) => {
+                    let id = api::AqcChannelId::Uni(e.channel_id.into());
+                    let members = [e.sender_id, e.receiver_id];
+                    self.aqc.add_channel(graph, id, e.label_id, members).await;
+                }
                 QueryDevicesOnTeamResult(_) => {}
                 QueryDeviceRoleResult(_) => {}
                 QueryDeviceKeyBundleResult(_) => {}
@@ -226,6 +272,21 @@ impl EffectHandler {
         }
         Ok(())
     }
+
+    /// Tells clients that `closed` were closed so that they can
+    /// drop the channels' PSKs and connections.
+    fn channels_closed(&self, graph: GraphId, closed: Vec<OpenChannel>) {
+        self.subscriptions.publish_events(
+            graph,
+            closed
+                .into_iter()
+                .map(|c| api::TeamEvent::AqcChannelClosed {
+                    channel_id: c.id,
+                    label_id: c.label_id,
+                    peer_id: c.peer_id,
+                }),
+        );
+    }
 }
 
 /// The guts of [`Api`].
@@ -383,6 +444,96 @@ impl DaemonApi for Api {
         Ok(())
     }
 
+    #[instrument(skip(self))]
+    async fn list_sync_peers(
+        self,
+        _: context::Context,
+        team: api::TeamId,
+    ) -> api::Result<Vec<api::SyncPeer>> {
+        self.check_team_valid(team).await?;
+
+        let peers = self.peers.lock().await.list_peers(team.into_id().into());
+        Ok(peers
+            .into_iter()
+            .map(|(addr, config)| api::SyncPeer { addr, config })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn update_sync_peer(
+        self,
+        _: context::Context,
+        peer: Addr,
+        team: api::TeamId,
+        cfg: api::SyncPeerConfig,
+    ) -> api::Result<()> {
+        self.check_team_valid(team).await?;
+
+        self.peers
+            .lock()
+            .await
+            .update_peer(peer, team.into_id().into(), cfg)
+            .await
+            .context("unable to update sync peer")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn sync_status(
+        self,
+        _: context::Context,
+        team: api::TeamId,
+    ) -> api::Result<Vec<api::SyncPeerStatus>> {
+        self.check_team_valid(team).await?;
+
+        let status = self.peers.lock().await.status(team.into_id().into());
+        Ok(status
+            .into_iter()
+            .map(|(addr, health)| api::SyncPeerStatus {
+                addr,
+                last_attempt: health.last_attempt,
+                last_success: health.last_success,
+                consecutive_failures: health.consecutive_failures,
+                last_error: health.last_error,
+                last_commands: health.last_commands as u64,
+                receiving: health.receiving.map(|n| n as u64),
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn subscribe(
+        self,
+        _: context::Context,
+        team: api::TeamId,
+        kinds: Vec<api::TeamEventKind>,
+    ) -> api::Result<api::SubscriptionId> {
+        self.check_team_valid(team).await?;
+
+        Ok(self
+            .effect_handler
+            .subscriptions
+            .subscribe(team.into_id().into(), kinds))
+    }
+
+    #[instrument(skip(self))]
+    async fn next_events(
+        self,
+        _: context::Context,
+        sub: api::SubscriptionId,
+        timeout: Duration,
+    ) -> api::Result<Vec<api::TeamEvent>> {
+        self.effect_handler
+            .subscriptions
+            .next_events(sub, timeout)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    async fn unsubscribe(self, _: context::Context, sub: api::SubscriptionId) -> api::Result<()> {
+        self.effect_handler.subscriptions.unsubscribe(sub)
+    }
+
     #[instrument(skip(self))]
     async fn add_team(
         mut self,
@@ -402,6 +553,7 @@ impl DaemonApi for Api {
     async fn remove_team(self, _: context::Context, team: api::TeamId) -> api::Result<()> {
         if let Some(data) = &self.quic {
             self.remove_team_quic_sync(team, data)
+                .await
                 .inspect_err(|err| warn!(%err))?;
         }
 
@@ -410,6 +562,14 @@ impl DaemonApi for Api {
             .await
             .inspect_err(|err| warn!(%err))?;
 
+        self.peers
+            .lock()
+            .await
+            .remove_team(team.into_id().into())
+            .await
+            .context("unable to remove sync peers")
+            .inspect_err(|err| warn!(%err))?;
+
         self.client
             .aranya
             .lock()
@@ -432,11 +592,14 @@ impl DaemonApi for Api {
         let nonce = &mut [0u8; 16];
         Rng.fill_bytes(nonce);
         let pk = self.get_pk()?;
-        let (graph_id, _) = self
+        let (graph_id, effects) = self
             .client
             .create_team(pk, Some(nonce))
             .await
             .context("unable to create team")?;
+        self.effect_handler
+            .handle_effects(graph_id, &effects)
+            .await?;
         debug!(?graph_id);
         let team_id: api::TeamId = graph_id.into_id().into();
 
@@ -469,33 +632,21 @@ impl DaemonApi for Api {
         team: api::TeamId,
         peer_enc_pk: EncryptionPublicKey<CS>,
     ) -> aranya_daemon_api::Result<WrappedSeed> {
-        let enc_pk = self.pk.lock().expect("poisoned").enc_pk.clone();
-
-        let (seed, enc_sk) = {
+        let seed = {
             let crypto = &mut *self.crypto.lock().await;
-            let seed = {
-                let seed_id = self.seed_id_dir.get(&team).await?;
-                qs::PskSeed::load(&mut crypto.engine, &crypto.local_store, &seed_id)?
-                    .context("no seed in dir")?
-            };
-            let enc_sk: EncryptionKey<CS> = crypto
-                .aranya_store
-                .get_key(&mut crypto.engine, enc_pk.id()?.into_id())
-                .context("keystore error")?
-                .context("missing enc_sk for encrypt seed")?;
-            (seed, enc_sk)
+            let seed_id = self.seed_id_dir.get(&team).await?;
+            qs::PskSeed::load(&mut crypto.engine, &crypto.local_store, &seed_id)?
+                .context("no seed in dir")?
         };
 
-        let group = GroupId::from(team.into_id());
-        let (encap_key, encrypted_seed) = enc_sk
-            .seal_psk_seed(&mut Rng, &seed.0, &peer_enc_pk, &group)
-            .context("could not seal psk seed")?;
+        Ok(self.wrap_seed(team, seed, peer_enc_pk).await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn rotate_sync_seed(self, _: context::Context, team: api::TeamId) -> api::Result<()> {
+        self.check_team_valid(team).await?;
 
-        Ok(WrappedSeed {
-            sender_pk: enc_pk,
-            encap_key,
-            encrypted_seed,
-        })
+        self.rotate_team_sync_seed(team).await
     }
 
     #[instrument(skip(self))]
@@ -507,11 +658,14 @@ impl DaemonApi for Api {
     ) -> api::Result<()> {
         self.check_team_valid(team).await?;
 
-        self.client
-            .actions(&team.into_id().into())
+        let graph = GraphId::from(team.into_id());
+        let effects = self
+            .client
+            .actions(&graph)
             .add_member(keys.into())
             .await
             .context("unable to add device to team")?;
+        self.effect_handler.handle_effects(graph, &effects).await?;
         Ok(())
     }
 
@@ -524,11 +678,14 @@ impl DaemonApi for Api {
     ) -> api::Result<()> {
         self.check_team_valid(team).await?;
 
-        self.client
-            .actions(&team.into_id().into())
+        let graph = GraphId::from(team.into_id());
+        let effects = self
+            .client
+            .actions(&graph)
             .remove_member(device.into_id().into())
             .await
             .context("unable to remove device from team")?;
+        self.effect_handler.handle_effects(graph, &effects).await?;
         Ok(())
     }
 
@@ -542,11 +699,14 @@ impl DaemonApi for Api {
     ) -> api::Result<()> {
         self.check_team_valid(team).await?;
 
-        self.client
-            .actions(&team.into_id().into())
+        let graph = GraphId::from(team.into_id());
+        let effects = self
+            .client
+            .actions(&graph)
             .assign_role(device.into_id().into(), role.into())
             .await
             .context("unable to assign role")?;
+        self.effect_handler.handle_effects(graph, &effects).await?;
         Ok(())
     }
 
@@ -560,11 +720,14 @@ impl DaemonApi for Api {
     ) -> api::Result<()> {
         self.check_team_valid(team).await?;
 
-        self.client
-            .actions(&team.into_id().into())
+        let graph = GraphId::from(team.into_id());
+        let effects = self
+            .client
+            .actions(&graph)
             .revoke_role(device.into_id().into(), role.into())
             .await
             .context("unable to revoke device role")?;
+        self.effect_handler.handle_effects(graph, &effects).await?;
         Ok(())
     }
 
@@ -600,11 +763,14 @@ impl DaemonApi for Api {
     ) -> api::Result<()> {
         self.check_team_valid(team).await?;
 
-        self.client
-            .actions(&team.into_id().into())
+        let graph = GraphId::from(team.into_id());
+        let effects = self
+            .client
+            .actions(&graph)
             .unset_aqc_network_name(device.into_id().into())
             .await
             .context("unable to remove aqc net identifier")?;
+        self.effect_handler.handle_effects(graph, &effects).await?;
         Ok(())
     }
 
@@ -695,9 +861,9 @@ impl DaemonApi for Api {
         self,
         _: context::Context,
         chan: api::AqcBidiChannelId,
-    ) -> api::Result<api::AqcCtrl> {
-        // TODO: remove AQC bidi channel from Aranya.
-        todo!();
+    ) -> api::Result<()> {
+        self.aqc.remove_channel(api::AqcChannelId::Bidi(chan)).await;
+        Ok(())
     }
 
     #[instrument(skip(self))]
@@ -705,9 +871,9 @@ impl DaemonApi for Api {
         self,
         _: context::Context,
         chan: api::AqcUniChannelId,
-    ) -> api::Result<api::AqcCtrl> {
-        // TODO: remove AQC uni channel from Aranya.
-        todo!();
+    ) -> api::Result<()> {
+        self.aqc.remove_channel(api::AqcChannelId::Uni(chan)).await;
+        Ok(())
     }
 
     #[instrument(skip(self))]
@@ -753,6 +919,38 @@ impl DaemonApi for Api {
         Err(anyhow!("unable to find AQC effect").into())
     }
 
+    #[instrument(skip(self))]
+    async fn aqc_ctrl_psks(self, _: context::Context) -> api::Result<Vec<api::AqcCtrlPsk>> {
+        let now = SystemTime::now();
+        let epoch = qs::aqc_ctrl_epoch(now);
+
+        let mut psks = Vec::new();
+        for (team, seeds) in self.seed_id_dir.list_seeds().await? {
+            // Peers that have not synced a seed rotation yet still
+            // use the retired seed until its grace period ends.
+            let seed_ids = core::iter::once(seeds.seed_id)
+                .chain(seeds.retired_at(now).map(|(seed_id, _)| seed_id));
+            for seed_id in seed_ids {
+                let seed = {
+                    let crypto = &mut *self.crypto.lock().await;
+                    qs::PskSeed::load(&mut crypto.engine, &crypto.local_store, &seed_id)?
+                };
+                let Some(seed) = seed else {
+                    warn!(%team, "missing PSK seed");
+                    continue;
+                };
+                for e in epoch.saturating_sub(1)..=epoch.saturating_add(1) {
+                    for psk in seed.generate_aqc_ctrl_psks(team, e) {
+                        let mut psk = psk.context("unable to generate AQC ctrl PSK")?;
+                        psk.current = e == epoch;
+                        psks.push(psk);
+                    }
+                }
+            }
+        }
+        Ok(psks)
+    }
+
     /// Create a label.
     #[instrument(skip(self))]
     async fn create_label(
@@ -769,6 +967,9 @@ impl DaemonApi for Api {
             .create_label(label_name)
             .await
             .context("unable to create AQC label")?;
+        self.effect_handler
+            .handle_effects(GraphId::from(team.into_id()), &effects)
+            .await?;
         if let Some(Effect::LabelCreated(e)) = find_effect!(&effects, Effect::LabelCreated(_e)) {
             Ok(e.label_id.into())
         } else {
@@ -792,6 +993,9 @@ impl DaemonApi for Api {
             .delete_label(label_id.into_id().into())
             .await
             .context("unable to delete AQC label")?;
+        self.effect_handler
+            .handle_effects(GraphId::from(team.into_id()), &effects)
+            .await?;
         if let Some(Effect::LabelDeleted(_e)) = find_effect!(&effects, Effect::LabelDeleted(_e)) {
             Ok(())
         } else {
@@ -821,6 +1025,9 @@ impl DaemonApi for Api {
             )
             .await
             .context("unable to assign AQC label")?;
+        self.effect_handler
+            .handle_effects(GraphId::from(team.into_id()), &effects)
+            .await?;
         if let Some(Effect::LabelAssigned(_e)) = find_effect!(&effects, Effect::LabelAssigned(_e)) {
             Ok(())
         } else {
@@ -845,6 +1052,9 @@ impl DaemonApi for Api {
             .revoke_label(device.into_id().into(), label_id.into_id().into())
             .await
             .context("unable to revoke AQC label")?;
+        self.effect_handler
+            .handle_effects(GraphId::from(team.into_id()), &effects)
+            .await?;
         if let Some(Effect::LabelRevoked(_e)) = find_effect!(&effects, Effect::LabelRevoked(_e)) {
             Ok(())
         } else {
@@ -953,6 +1163,33 @@ impl DaemonApi for Api {
         return Ok(labels);
     }
 
+    /// Query the operations a device may perform with a label.
+    #[instrument(skip(self))]
+    async fn query_device_label_op(
+        self,
+        _: context::Context,
+        team: api::TeamId,
+        device: api::DeviceId,
+        label: api::LabelId,
+    ) -> api::Result<Option<api::ChanOp>> {
+        self.check_team_valid(team).await?;
+
+        let (_ctrl, effects) = self
+            .client
+            .actions(&team.into_id().into())
+            .query_label_assignments_off_graph(device.into_id().into())
+            .await
+            .context("unable to query device label assignments")?;
+        for e in effects {
+            if let Effect::QueriedLabelAssignment(e) = e {
+                if api::LabelId::from(e.label_id) == label {
+                    return Ok(Some(e.op.into()));
+                }
+            }
+        }
+        Ok(None)
+    }
+
     /// Query AQC network ID.
     #[instrument(skip(self))]
     async fn query_aqc_net_identifier(
@@ -0,0 +1,396 @@
+//! Subscriptions to team events.
+//!
+//! Effects from local actions and from syncing are turned into
+//! [`TeamEvent`]s and broadcast to the team's subscriptions. Clients read
+//! them by long polling [`Subscriptions::next_events`].
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use aranya_daemon_api::{SubscriptionId, TeamEvent, TeamEventKind};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::Instant,
+};
+
+use super::*;
+
+/// The number of events buffered for each team. A subscription that
+/// falls further behind receives [`TeamEvent::Lagged`].
+const CAPACITY: usize = 1024;
+
+/// The most events returned by one call to
+/// [`next_events`][Subscriptions::next_events].
+const MAX_BATCH: usize = 256;
+
+/// Subscriptions that are not polled for this long are dropped the next
+/// time any subscription is created or polled.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub(crate) struct Subscriptions {
+    next_id: AtomicU64,
+    // NB: Since the locking is short and not held over await points,
+    // we use standard mutexes instead of tokio's. `subs` is always
+    // locked before `teams`.
+    subs: std::sync::Mutex<HashMap<SubscriptionId, Arc<Mutex<Subscription>>>>,
+    /// One channel per team with subscriptions, so that a busy team
+    /// cannot make another team's subscriptions lag.
+    teams: std::sync::Mutex<HashMap<GraphId, broadcast::Sender<TeamEvent>>>,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    /// Kinds to deliver, or all of them if empty.
+    kinds: Vec<TeamEventKind>,
+    recv: broadcast::Receiver<TeamEvent>,
+    last_poll: Instant,
+}
+
+impl Subscription {
+    fn wants(&self, event: &TeamEvent) -> bool {
+        event
+            .kind()
+            .is_none_or(|kind| self.kinds.is_empty() || self.kinds.contains(&kind))
+    }
+}
+
+impl Subscriptions {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            subs: std::sync::Mutex::new(HashMap::new()),
+            teams: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Broadcasts the events for `effects`.
+    pub(crate) fn publish(&self, graph: GraphId, effects: &[Effect]) {
+        self.publish_events(graph, effects.iter().filter_map(team_event));
+    }
+
+    /// Broadcasts `events`.
+    pub(crate) fn publish_events<I>(&self, graph: GraphId, events: I)
+    where
+        I: IntoIterator<Item = TeamEvent>,
+    {
+        let Some(send) = self.teams.lock().expect("poisoned").get(&graph).cloned() else {
+            return;
+        };
+        for event in events {
+            // Only fails if every subscription was dropped meanwhile.
+            let _ = send.send(event);
+        }
+    }
+
+    pub(crate) fn subscribe(&self, graph: GraphId, kinds: Vec<TeamEventKind>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+
+        let mut subs = self.subs.lock().expect("poisoned");
+        self.prune_idle(&mut subs);
+        let recv = self
+            .teams
+            .lock()
+            .expect("poisoned")
+            .entry(graph)
+            .or_insert_with(|| broadcast::channel(CAPACITY).0)
+            .subscribe();
+        let sub = Subscription {
+            kinds,
+            recv,
+            last_poll: Instant::now(),
+        };
+        subs.insert(id, Arc::new(Mutex::new(sub)));
+        id
+    }
+
+    /// Drops subscriptions that have not been polled for
+    /// [`IDLE_TIMEOUT`], and the channels of teams left without any.
+    fn prune_idle(&self, subs: &mut HashMap<SubscriptionId, Arc<Mutex<Subscription>>>) {
+        // A subscription that is being polled is locked, so it is not idle.
+        subs.retain(|_, sub| {
+            sub.try_lock()
+                .map_or(true, |sub| sub.last_poll.elapsed() < IDLE_TIMEOUT)
+        });
+        self.prune_teams();
+    }
+
+    /// Drops the channels of teams without subscriptions.
+    fn prune_teams(&self) {
+        self.teams
+            .lock()
+            .expect("poisoned")
+            .retain(|_, send| send.receiver_count() > 0);
+    }
+
+    /// Waits up to `timeout` for at least one event.
+    pub(crate) async fn next_events(
+        &self,
+        id: SubscriptionId,
+        timeout: Duration,
+    ) -> api::Result<Vec<TeamEvent>> {
+        let sub = {
+            let mut subs = self.subs.lock().expect("poisoned");
+            let sub = subs
+                .get(&id)
+                .cloned()
+                .with_context(|| format!("no subscription {id}"))?;
+            // Mark it polled so that it is not pruned along with idle ones.
+            if let Ok(mut sub) = sub.try_lock() {
+                sub.last_poll = Instant::now();
+            }
+            self.prune_idle(&mut subs);
+            sub
+        };
+        let mut sub = sub.lock().await;
+
+        let deadline = Instant::now() + timeout;
+        let mut events = Vec::new();
+        while events.len() < MAX_BATCH {
+            // Wait for the first event, then take whatever else is ready.
+            let received = if events.is_empty() {
+                match tokio::time::timeout_at(deadline, sub.recv.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => break,
+                }
+            } else {
+                match sub.recv.try_recv() {
+                    Ok(received) => Ok(received),
+                    Err(broadcast::error::TryRecvError::Lagged(n)) => Err(RecvError::Lagged(n)),
+                    Err(_) => break,
+                }
+            };
+            match received {
+                Ok(event) => {
+                    if sub.wants(&event) {
+                        events.push(event);
+                    }
+                }
+                Err(RecvError::Lagged(count)) => events.push(TeamEvent::Lagged { count }),
+                Err(RecvError::Closed) => break,
+            }
+        }
+        sub.last_poll = Instant::now();
+        Ok(events)
+    }
+
+    pub(crate) fn unsubscribe(&self, id: SubscriptionId) -> api::Result<()> {
+        let mut subs = self.subs.lock().expect("poisoned");
+        subs.remove(&id)
+            .with_context(|| format!("no subscription {id}"))?;
+        self.prune_teams();
+        Ok(())
+    }
+}
+
+/// Returns the event for `effect`, if it changes the team.
+fn team_event(effect: &Effect) -> Option<TeamEvent> {
+    use Effect::*;
+    let event = match effect {
+        TeamCreated(e) => TeamEvent::TeamCreated {
+            owner_id: e.owner_id.into(),
+        },
+        TeamTerminated(e) => TeamEvent::TeamTerminated {
+            owner_id: e.owner_id.into(),
+        },
+        MemberAdded(e) => TeamEvent::DeviceAdded {
+            device_id: e.device_id.into(),
+        },
+        MemberRemoved(e) => TeamEvent::DeviceRemoved {
+            device_id: e.device_id.into(),
+        },
+        OwnerAssigned(e) => TeamEvent::RoleAssigned {
+            device_id: e.device_id.into(),
+            role: api::Role::Owner,
+        },
+        AdminAssigned(e) => TeamEvent::RoleAssigned {
+            device_id: e.device_id.into(),
+            role: api::Role::Admin,
+        },
+        OperatorAssigned(e) => TeamEvent::RoleAssigned {
+            device_id: e.device_id.into(),
+            role: api::Role::Operator,
+        },
+        OwnerRevoked(e) => TeamEvent::RoleRevoked {
+            device_id: e.device_id.into(),
+            role: api::Role::Owner,
+        },
+        AdminRevoked(e) => TeamEvent::RoleRevoked {
+            device_id: e.device_id.into(),
+            role: api::Role::Admin,
+        },
+        OperatorRevoked(e) => TeamEvent::RoleRevoked {
+            device_id: e.device_id.into(),
+            role: api::Role::Operator,
+        },
+        LabelCreated(e) => TeamEvent::LabelCreated {
+            label_id: e.label_id.into(),
+            name: e.label_name.clone(),
+            author_id: e.label_author_id.into(),
+        },
+        LabelDeleted(e) => TeamEvent::LabelDeleted {
+            label_id: e.label_id.into(),
+            name: e.label_name.clone(),
+            author_id: e.author_id.into(),
+        },
+        LabelAssigned(e) => TeamEvent::LabelAssigned {
+            label_id: e.label_id.into(),
+            name: e.label_name.clone(),
+            device_id: e.device_id.into(),
+            author_id: e.author_id.into(),
+        },
+        LabelRevoked(e) => TeamEvent::LabelRevoked {
+            label_id: e.label_id.into(),
+            name: e.label_name.clone(),
+            device_id: e.device_id.into(),
+            author_id: e.author_id.into(),
+        },
+        AqcNetworkNameSet(e) => TeamEvent::AqcNetIdentifierSet {
+            device_id: e.device_id.into(),
+            net_id: api::NetIdentifier(e.net_identifier.clone()),
+        },
+        AqcNetworkNameUnset(e) => TeamEvent::AqcNetIdentifierUnset {
+            device_id: e.device_id.into(),
+        },
+        QueriedLabel(_)
+        | SyncSeedRotated(_)
+        | AqcBidiChannelCreated(_)
+        | AqcBidiChannelReceived(_)
+        | AqcUniChannelCreated(_)
+        | AqcUniChannelReceived(_)
+        | QueryDevicesOnTeamResult(_)
+        | QueryDeviceRoleResult(_)
+        | QueryDeviceKeyBundleResult(_)
+        | QueryAqcNetIdentifierResult(_)
+        | QueriedLabelAssignment(_)
+        | QueryLabelExistsResult(_)
+        | QueryAqcNetworkNamesOutput(_) => return None,
+    };
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::Rng;
+    use aranya_policy_ifgen::Id;
+    use test_log::test;
+
+    use super::*;
+    use crate::policy;
+
+    fn label_assigned(label_id: Id) -> Effect {
+        Effect::LabelAssigned(policy::LabelAssigned {
+            label_id,
+            label_name: api::text!("TELEMETRY"),
+            label_author_id: Id::default(),
+            author_id: Id::default(),
+            device_id: Id::default(),
+        })
+    }
+
+    #[test(tokio::test)]
+    async fn test_filters_and_batches() {
+        let subs = Subscriptions::new();
+        let graph = GraphId::from(Id::random(&mut Rng));
+        let other = GraphId::from(Id::random(&mut Rng));
+
+        let all = subs.subscribe(graph, Vec::new());
+        let labels = subs.subscribe(graph, vec![TeamEventKind::LabelAssigned]);
+
+        let member_added = Effect::MemberRemoved(policy::MemberRemoved {
+            device_id: Id::default(),
+        });
+        let label_id = Id::random(&mut Rng);
+        subs.publish(graph, &[member_added, label_assigned(label_id)]);
+        // Other teams' events are not delivered.
+        subs.publish(other, &[label_assigned(Id::random(&mut Rng))]);
+
+        let timeout = Duration::from_millis(100);
+        let events = subs.next_events(all, timeout).await.unwrap();
+        assert_eq!(events.len(), 2);
+        let events = subs.next_events(labels, timeout).await.unwrap();
+        assert!(matches!(
+            events.as_slice(),
+            [TeamEvent::LabelAssigned { label_id: id, .. }] if *id == label_id.into()
+        ));
+
+        // Nothing left, so this times out.
+        let events = subs.next_events(all, timeout).await.unwrap();
+        assert!(events.is_empty());
+
+        subs.unsubscribe(all).unwrap();
+        assert!(subs.next_events(all, timeout).await.is_err());
+        assert!(subs.unsubscribe(all).is_err());
+    }
+
+    #[test(tokio::test)]
+    async fn test_lagged() {
+        let subs = Subscriptions::new();
+        let graph = GraphId::from(Id::random(&mut Rng));
+        let sub = subs.subscribe(graph, Vec::new());
+
+        let effects: Vec<_> = (0..CAPACITY + 10)
+            .map(|_| label_assigned(Id::random(&mut Rng)))
+            .collect();
+        subs.publish(graph, &effects);
+
+        let events = subs
+            .next_events(sub, Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(events.first(), Some(&TeamEvent::Lagged { count: 10 }));
+        assert_eq!(events.len(), MAX_BATCH);
+    }
+
+    #[test(tokio::test)]
+    async fn test_teams_do_not_lag_each_other() {
+        let subs = Subscriptions::new();
+        let graph = GraphId::from(Id::random(&mut Rng));
+        let busy = GraphId::from(Id::random(&mut Rng));
+        let sub = subs.subscribe(graph, Vec::new());
+        let _busy = subs.subscribe(busy, Vec::new());
+
+        let effects: Vec<_> = (0..CAPACITY + 10)
+            .map(|_| label_assigned(Id::random(&mut Rng)))
+            .collect();
+        subs.publish(busy, &effects);
+        let label_id = Id::random(&mut Rng);
+        subs.publish(graph, &[label_assigned(label_id)]);
+
+        let events = subs
+            .next_events(sub, Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(matches!(
+            events.as_slice(),
+            [TeamEvent::LabelAssigned { label_id: id, .. }] if *id == label_id.into()
+        ));
+    }
+
+    #[test(tokio::test(start_paused = true))]
+    async fn test_idle_subscriptions_are_pruned() {
+        let subs = Subscriptions::new();
+        let graph = GraphId::from(Id::random(&mut Rng));
+        let other = GraphId::from(Id::random(&mut Rng));
+        let idle = subs.subscribe(other, Vec::new());
+        let polled = subs.subscribe(graph, Vec::new());
+
+        tokio::time::advance(IDLE_TIMEOUT).await;
+
+        // Polling any subscription drops the idle ones, and the channels
+        // of teams left without subscriptions.
+        let events = subs
+            .next_events(polled, Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+        assert!(subs.next_events(idle, Duration::ZERO).await.is_err());
+        assert!(!subs.teams.lock().unwrap().contains_key(&other));
+
+        subs.unsubscribe(polled).unwrap();
+        assert!(subs.teams.lock().unwrap().is_empty());
+    }
+}
@@ -1,14 +1,18 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use aranya_daemon_api::QuicSyncConfig;
 
 use super::*;
-use crate::sync::task::quic::PskStore;
+use crate::{policy::SyncSeedRotated, sync::task::quic::PskStore, util::SeedRotationDir};
 
 /// Held by [`super::DaemonApiServer`] when the QUIC syncer is used
 #[derive(Debug)]
 pub(crate) struct Data {
     pub(crate) psk_store: Arc<PskStore>,
+    /// How long a rotated seed is still used.
+    pub(crate) seed_grace: Duration,
+    /// The rotation that replaced each team's previous seed.
+    pub(crate) seed_rotations: SeedRotationDir,
 }
 
 impl Api {
@@ -65,28 +69,7 @@ impl Api {
                 ));
             }
             SeedMode::IKM(ikm) => qs::PskSeed::import_from_ikm(&ikm, team),
-            SeedMode::Wrapped(wrapped) => {
-                let enc_sk: EncryptionKey<CS> = {
-                    let enc_id = self.pk.lock().expect("poisoned").enc_pk.id()?;
-                    let crypto = &mut *self.crypto.lock().await;
-                    crypto
-                        .aranya_store
-                        .get_key(&mut crypto.engine, enc_id.into_id())
-                        .context("keystore error")?
-                        .context("missing enc_sk in add_team")?
-                };
-
-                let group = GroupId::from(team.into_id());
-                let seed = enc_sk
-                    .open_psk_seed(
-                        &wrapped.encap_key,
-                        wrapped.encrypted_seed,
-                        &wrapped.sender_pk,
-                        &group,
-                    )
-                    .context("could not open psk seed")?;
-                qs::PskSeed(seed)
-            }
+            SeedMode::Wrapped(wrapped) => self.open_seed(team, wrapped).await?,
         };
 
         self.add_seed(team, seed.clone()).await?;
@@ -101,7 +84,292 @@ impl Api {
         Ok(())
     }
 
-    pub(super) fn remove_team_quic_sync(
+    /// Replaces the team's seed with a new one, which is wrapped
+    /// for every other device on the team and shared through the
+    /// graph.
+    pub(super) async fn rotate_team_sync_seed(&self, team: api::TeamId) -> api::Result<()> {
+        let graph = GraphId::from(team.into_id());
+        let device_id = self.device_id()?;
+        let seed = qs::PskSeed::new(&mut Rng, team);
+
+        let (_ctrl, effects) = self
+            .client
+            .actions(&graph)
+            .query_devices_on_team_off_graph()
+            .await
+            .context("unable to query devices on team")?;
+        let mut wrapped_seeds = Vec::new();
+        for e in effects {
+            let Effect::QueryDevicesOnTeamResult(e) = e else {
+                continue;
+            };
+            let peer = DeviceId::from(e.device_id);
+            if peer == device_id {
+                continue;
+            }
+            let (_ctrl, effects) = self
+                .client
+                .actions(&graph)
+                .query_device_keybundle_off_graph(peer)
+                .await
+                .context("unable to query device keybundle")?;
+            let Some(e) = effects.into_iter().find_map(|e| match e {
+                Effect::QueryDeviceKeyBundleResult(e) => Some(e),
+                _ => None,
+            }) else {
+                return Err(anyhow!("unable to query device keybundle").into());
+            };
+            let peer_enc_pk: EncryptionPublicKey<CS> =
+                postcard::from_bytes(&e.device_keys.enc_key).context("bad peer enc key")?;
+            let wrapped = self.wrap_seed(team, seed.clone(), peer_enc_pk).await?;
+            wrapped_seeds.push((api::DeviceId::from(peer.into_id()), wrapped));
+        }
+        let wrapped_seeds = postcard::to_allocvec(&wrapped_seeds).context("can serialize")?;
+
+        // Rotations are ordered by time, so ours must come after
+        // the current seed's.
+        let current_rotated_at = self.seed_id_dir.get_seeds(&team).await?.rotated_at;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let rotated_at = now.max(current_rotated_at.saturating_add(1));
+
+        let effects = self
+            .client
+            .actions(&graph)
+            .rotate_sync_seed(
+                seed.id()?.into_id(),
+                i64::try_from(rotated_at).context("rotation time is out of range")?,
+                wrapped_seeds,
+            )
+            .await
+            .context("unable to rotate sync seed")?;
+        self.effect_handler.handle_effects(graph, &effects).await?;
+
+        let rotation = effects
+            .iter()
+            .find_map(|e| match e {
+                Effect::SyncSeedRotated(e) => Some(e),
+                _ => None,
+            })
+            .context("missing sync seed rotation effect")?;
+        self.install_seed(team, seed, rotated_at, rotation).await?;
+
+        Ok(())
+    }
+
+    /// Installs the seeds in the [`SyncSeedRotated`] effects
+    /// that were shared with us.
+    ///
+    /// [`SyncSeedRotated`]: crate::policy::SyncSeedRotated
+    pub(super) async fn handle_seed_rotations(
+        &self,
+        graph: GraphId,
+        effects: &[Effect],
+    ) -> anyhow::Result<()> {
+        let team = api::TeamId::from(graph.into_id());
+        let device_id = api::DeviceId::from(self.device_id()?.into_id());
+        for effect in effects {
+            let Effect::SyncSeedRotated(e) = effect else {
+                continue;
+            };
+            let wrapped_seeds: Vec<(api::DeviceId, WrappedSeed)> =
+                match postcard::from_bytes(&e.wrapped_seeds) {
+                    Ok(wrapped_seeds) => wrapped_seeds,
+                    Err(err) => {
+                        error!(%team, %err, "invalid wrapped sync seeds");
+                        continue;
+                    }
+                };
+            let Some((_, wrapped)) = wrapped_seeds.into_iter().find(|(id, _)| *id == device_id)
+            else {
+                warn!(%team, "rotated sync seed was not shared with this device");
+                continue;
+            };
+            let seed = match self.open_seed(team, wrapped).await {
+                Ok(seed) => seed,
+                Err(err) => {
+                    error!(%team, ?err, "unable to open rotated sync seed");
+                    continue;
+                }
+            };
+            match seed.id() {
+                Ok(id) if id.into_id() == e.seed_id => {}
+                Ok(_) => {
+                    error!(%team, "rotated sync seed does not match its ID");
+                    continue;
+                }
+                Err(err) => {
+                    error!(%team, %err, "unable to get rotated sync seed ID");
+                    continue;
+                }
+            }
+            let Ok(rotated_at) = u64::try_from(e.rotated_at) else {
+                error!(%team, rotated_at = e.rotated_at, "invalid sync seed rotation time");
+                continue;
+            };
+            if let Err(err) = self.install_seed(team, seed, rotated_at, e).await {
+                error!(%team, ?err, "unable to install rotated sync seed");
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes `seed` the team's seed, unless the current seed was
+    /// rotated later.
+    ///
+    /// The previous seed is kept until the grace period ends, so
+    /// that peers that have not synced the rotation yet can still
+    /// sync and send AQC control messages. After that, it is kept
+    /// until the next rotation so that peers that missed `rotation`
+    /// can fetch it with the previous seed's PSKs. Both are
+    /// persisted, so they outlast a restart.
+    async fn install_seed(
+        &self,
+        team: api::TeamId,
+        seed: qs::PskSeed,
+        rotated_at: u64,
+        rotation: &SyncSeedRotated,
+    ) -> anyhow::Result<()> {
+        let crypto = &mut *self.crypto.lock().await;
+
+        let id = seed.id().context("getting seed id")?;
+        let current = self.seed_id_dir.get_seeds(&team).await?;
+        // Every device picks the same seed out of concurrent
+        // rotations.
+        if (rotated_at, id.as_bytes()) <= (current.rotated_at, current.seed_id.as_bytes()) {
+            debug!(%team, "keeping the current sync seed");
+            return Ok(());
+        }
+
+        let now = SystemTime::now();
+        let grace = self
+            .quic
+            .as_ref()
+            .map_or(qs::DEFAULT_SEED_GRACE, |data| data.seed_grace);
+        let until = now + grace;
+        let (mut retired, expired): (Vec<_>, Vec<_>) = current
+            .retired
+            .into_iter()
+            .partition(|(_, until)| *until > now);
+        retired.push((current.seed_id, until));
+        let seeds = TeamSeeds {
+            seed_id: id,
+            rotated_at,
+            retired,
+        };
+
+        let psks = seed
+            .clone()
+            .generate_psks(team)
+            .map(|psk| psk.map(Arc::new))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("unable to generate psk")?;
+
+        let wrapped_key = crypto
+            .engine
+            .wrap(seed.into_inner())
+            .context("wrapping seed")?;
+        crypto
+            .local_store
+            .try_insert(id.into_id(), wrapped_key)
+            .context("inserting seed")?;
+        if let Err(err) = self.seed_id_dir.replace(&team, &seeds).await {
+            crypto
+                .local_store
+                .remove::<WrappedKey<CS>>(id.into_id())
+                .context("could not remove seed from keystore")?;
+            return Err(err);
+        }
+
+        if let Some(data) = &self.quic {
+            if let Err(err) = data
+                .seed_rotations
+                .save(&team, std::slice::from_ref(rotation))
+                .await
+            {
+                warn!(%team, ?err, "could not save sync seed rotation");
+            }
+            let rotation = postcard::to_allocvec(rotation).context("can serialize")?;
+            data.psk_store
+                .rotate(team, psks, until, rotation.into())
+                .inspect_err(|err| error!(err = ?err, "unable to rotate PSKs"))?;
+        }
+
+        for (seed_id, _) in expired {
+            if let Err(err) = crypto
+                .local_store
+                .remove::<WrappedKey<CS>>(seed_id.into_id())
+            {
+                warn!(%team, ?err, "could not remove retired seed from keystore");
+            }
+        }
+        info!(%team, "rotated sync seed");
+
+        Ok(())
+    }
+
+    /// Wraps `seed` for the device whose encryption key is
+    /// `peer_enc_pk`.
+    pub(super) async fn wrap_seed(
+        &self,
+        team: api::TeamId,
+        seed: qs::PskSeed,
+        peer_enc_pk: EncryptionPublicKey<CS>,
+    ) -> anyhow::Result<WrappedSeed> {
+        let enc_pk = self.pk.lock().expect("poisoned").enc_pk.clone();
+
+        let enc_sk: EncryptionKey<CS> = {
+            let crypto = &mut *self.crypto.lock().await;
+            crypto
+                .aranya_store
+                .get_key(&mut crypto.engine, enc_pk.id()?.into_id())
+                .context("keystore error")?
+                .context("missing enc_sk for encrypt seed")?
+        };
+
+        let group = GroupId::from(team.into_id());
+        let (encap_key, encrypted_seed) = enc_sk
+            .seal_psk_seed(&mut Rng, &seed.0, &peer_enc_pk, &group)
+            .context("could not seal psk seed")?;
+
+        Ok(WrappedSeed {
+            sender_pk: enc_pk,
+            encap_key,
+            encrypted_seed,
+        })
+    }
+
+    /// Opens a seed that was wrapped for this device.
+    async fn open_seed(
+        &self,
+        team: api::TeamId,
+        wrapped: WrappedSeed,
+    ) -> anyhow::Result<qs::PskSeed> {
+        let enc_sk: EncryptionKey<CS> = {
+            let enc_id = self.pk.lock().expect("poisoned").enc_pk.id()?;
+            let crypto = &mut *self.crypto.lock().await;
+            crypto
+                .aranya_store
+                .get_key(&mut crypto.engine, enc_id.into_id())
+                .context("keystore error")?
+                .context("missing enc_sk in add_team")?
+        };
+
+        let group = GroupId::from(team.into_id());
+        let seed = enc_sk
+            .open_psk_seed(
+                &wrapped.encap_key,
+                wrapped.encrypted_seed,
+                &wrapped.sender_pk,
+                &group,
+            )
+            .context("could not open psk seed")?;
+        Ok(qs::PskSeed(seed))
+    }
+
+    pub(super) async fn remove_team_quic_sync(
         &self,
         team: api::TeamId,
         data: &Data,
@@ -109,6 +377,7 @@ impl Api {
         data.psk_store
             .remove(team)
             .inspect_err(|err| error!(err = ?err, "unable to remove PSK"))?;
+        data.seed_rotations.remove(&team).await?;
 
         Ok(())
     }
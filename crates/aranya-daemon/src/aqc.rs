@@ -7,8 +7,10 @@ use aranya_aqc_util::{
 };
 use aranya_crypto::{DeviceId, Engine, KeyStore};
 use aranya_daemon_api::{
-    AqcBidiPsk, AqcBidiPsks, AqcPsks, AqcUniPsk, AqcUniPsks, Directed, NetIdentifier, Secret,
+    self as api, AqcBidiPsk, AqcBidiPsks, AqcChannelId, AqcPsks, AqcUniPsk, AqcUniPsks, Directed,
+    NetIdentifier, Secret,
 };
+use aranya_policy_ifgen::Id;
 use aranya_runtime::GraphId;
 use bimap::BiBTreeMap;
 use buggy::{bug, BugExt};
@@ -24,12 +26,27 @@ use crate::{
 
 type PeerMap = BTreeMap<GraphId, Peers>;
 type Peers = BiBTreeMap<NetIdentifier, DeviceId>;
+type ChannelMap = BTreeMap<GraphId, Vec<OpenChannel>>;
+
+/// A channel between us and a peer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct OpenChannel {
+    pub id: AqcChannelId,
+    pub label_id: api::LabelId,
+    pub peer_id: api::DeviceId,
+}
 
 pub(crate) struct Aqc<E, KS> {
     /// Our device ID.
     device_id: DeviceId,
     /// All the peers that we have channels with.
     peers: Arc<Mutex<PeerMap>>,
+    /// All the channels that we are a member of, so that their
+    /// members are told to close them when they lose access.
+    ///
+    /// This is only kept in memory: after the daemon restarts,
+    /// the channels created before it are not closed.
+    channels: Mutex<ChannelMap>,
     handler: Mutex<Handler<AranyaStore<KS>>>,
     eng: Mutex<E>,
 }
@@ -42,6 +59,7 @@ impl<E, KS> Aqc<E, KS> {
         Self {
             device_id,
             peers: Arc::new(Mutex::new(PeerMap::from_iter(peers))),
+            channels: Mutex::new(ChannelMap::new()),
             handler: Mutex::new(Handler::new(device_id, store)),
             eng: Mutex::new(eng),
         }
@@ -84,6 +102,111 @@ impl<E, KS> Aqc<E, KS> {
         });
     }
 
+    /// Records a channel between `members`, if we are one of
+    /// them.
+    #[instrument(skip(self))]
+    pub(crate) async fn add_channel(
+        &self,
+        graph: GraphId,
+        id: AqcChannelId,
+        label_id: Id,
+        members: [Id; 2],
+    ) {
+        let us = Id::from(self.device_id);
+        let peer_id = match members {
+            [a, b] if a == us => b,
+            [a, b] if b == us => a,
+            _ => return,
+        };
+        debug!("adding channel");
+
+        self.channels
+            .lock()
+            .await
+            .entry(graph)
+            .or_default()
+            .push(OpenChannel {
+                id,
+                label_id: label_id.into(),
+                peer_id: peer_id.into(),
+            });
+    }
+
+    /// Removes the channels with `label_id` that `device_id` is a
+    /// member of, returning them.
+    #[instrument(skip(self))]
+    pub(crate) async fn close_label_channels(
+        &self,
+        graph: GraphId,
+        label_id: Id,
+        device_id: Id,
+    ) -> Vec<OpenChannel> {
+        let label_id = label_id.into();
+        let us = device_id == Id::from(self.device_id);
+        let device_id = device_id.into();
+        self.close_channels(graph, |c| {
+            c.label_id == label_id && (us || c.peer_id == device_id)
+        })
+        .await
+    }
+
+    /// Removes the channels with `label_id`, returning them.
+    #[instrument(skip(self))]
+    pub(crate) async fn close_label(&self, graph: GraphId, label_id: Id) -> Vec<OpenChannel> {
+        let label_id = label_id.into();
+        self.close_channels(graph, |c| c.label_id == label_id).await
+    }
+
+    /// Removes the channels that `device_id` is a member of,
+    /// returning them.
+    #[instrument(skip(self))]
+    pub(crate) async fn close_device(&self, graph: GraphId, device_id: Id) -> Vec<OpenChannel> {
+        let us = device_id == Id::from(self.device_id);
+        let device_id = device_id.into();
+        self.close_channels(graph, |c| us || c.peer_id == device_id)
+            .await
+    }
+
+    /// Forgets the channel `id`, e.g., because it was deleted.
+    #[instrument(skip(self))]
+    pub(crate) async fn remove_channel(&self, id: AqcChannelId) {
+        debug!("removing channel");
+
+        let mut map = self.channels.lock().await;
+        for channels in map.values_mut() {
+            channels.retain(|c| c.id != id);
+        }
+        map.retain(|_, channels| !channels.is_empty());
+    }
+
+    /// Removes every channel in `graph`, returning them.
+    #[instrument(skip(self))]
+    pub(crate) async fn close_team(&self, graph: GraphId) -> Vec<OpenChannel> {
+        self.channels
+            .lock()
+            .await
+            .remove(&graph)
+            .unwrap_or_default()
+    }
+
+    async fn close_channels<F>(&self, graph: GraphId, mut f: F) -> Vec<OpenChannel>
+    where
+        F: FnMut(&OpenChannel) -> bool,
+    {
+        let mut map = self.channels.lock().await;
+        let Some(channels) = map.get_mut(&graph) else {
+            return Vec::new();
+        };
+        let (closed, open): (Vec<_>, _) = channels.drain(..).partition(|c| f(c));
+        if open.is_empty() {
+            map.remove(&graph);
+        } else {
+            *channels = open;
+        }
+        debug!(n = closed.len(), "closing channels");
+        closed
+    }
+
     async fn while_locked<'a, F, R>(&'a self, f: F) -> R
     where
         F: for<'b> FnOnce(&'b mut Handler<AranyaStore<KS>>, &'b mut E) -> R,
@@ -5,19 +5,34 @@
 
 use std::{fmt, ops::Deref, sync::Arc};
 
-use aranya_runtime::ClientState;
-use tokio::sync::Mutex;
+use aranya_runtime::{ClientState, GraphId};
+use tokio::sync::{broadcast, Mutex};
 
 /// Thread-safe wrapper for an Aranya client.
 pub struct Client<EN, SP> {
     /// Thread-safe Aranya client reference.
     pub(crate) aranya: Arc<Mutex<ClientState<EN, SP>>>,
+    /// Announces graphs that new commands were committed to.
+    commits: broadcast::Sender<GraphId>,
 }
 
 impl<EN, SP> Client<EN, SP> {
     /// Creates a new Client
     pub fn new(aranya: Arc<Mutex<ClientState<EN, SP>>>) -> Self {
-        Client { aranya }
+        let (commits, _) = broadcast::channel(64);
+        Client { aranya, commits }
+    }
+
+    /// Announces that new commands were committed to `id`.
+    pub(crate) fn committed(&self, id: GraphId) {
+        // Nobody might be listening, which is fine.
+        let _ = self.commits.send(id);
+    }
+
+    /// Returns a receiver of the graphs that new commands are committed
+    /// to.
+    pub(crate) fn subscribe_commits(&self) -> broadcast::Receiver<GraphId> {
+        self.commits.subscribe()
     }
 }
 
@@ -31,6 +46,7 @@ impl<EN, SP> Clone for Client<EN, SP> {
     fn clone(&self) -> Self {
         Self {
             aranya: Arc::clone(&self.aranya),
+            commits: self.commits.clone(),
         }
     }
 }
@@ -3,9 +3,11 @@
 use std::{
     fs,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
+use aranya_daemon_api::SyncBackoff;
 use aranya_util::Addr;
 use serde::{
     de::{self, DeserializeOwned},
@@ -112,6 +114,14 @@ pub struct Config {
 
     /// QUIC syncer config
     pub quic_sync: Option<QuicSyncConfig>,
+
+    /// TCP syncer config, for networks that don't allow UDP.
+    ///
+    /// At least one syncer must be configured. If both are, the
+    /// daemon accepts syncs over either one, on the same port, and
+    /// syncs with each peer over the transport it was added with,
+    /// QUIC by default.
+    pub tcp_sync: Option<TcpSyncConfig>,
 }
 
 impl Config {
@@ -122,9 +132,26 @@ impl Config {
     {
         let cfg: Self = read_json(path.as_ref())
             .with_context(|| format!("unable to parse config: {}", path.as_ref().display()))?;
+        cfg.seed_grace_ms()?;
         Ok(cfg)
     }
 
+    /// How long a team's previous seed is still used after it's
+    /// rotated, in milliseconds, if it's configured.
+    ///
+    /// Both syncers use the same PSKs, so the QUIC and TCP configs
+    /// must agree on it if both set it.
+    pub(crate) fn seed_grace_ms(&self) -> Result<Option<u64>> {
+        let quic = self.quic_sync.as_ref().and_then(|cfg| cfg.seed_grace_ms);
+        let tcp = self.tcp_sync.as_ref().and_then(|cfg| cfg.seed_grace_ms);
+        match (quic, tcp) {
+            (Some(quic), Some(tcp)) if quic != tcp => anyhow::bail!(
+                "`quic_sync.seed_grace_ms` ({quic}) and `tcp_sync.seed_grace_ms` ({tcp}) must match"
+            ),
+            (quic, tcp) => Ok(quic.or(tcp)),
+        }
+    }
+
     /// Path to the PID file.
     pub fn pid_path(&self) -> PathBuf {
         self.runtime_dir.join("daemon.pid")
@@ -170,6 +197,29 @@ impl Config {
         self.state_dir.join("seeds")
     }
 
+    /// Path to the directory containing the rotation that replaced
+    /// each team's previous seed.
+    pub(crate) fn seed_rotations_path(&self) -> PathBuf {
+        self.state_dir.join("seed_rotations")
+    }
+
+    /// Path to the directory containing the persistent sync peers.
+    pub(crate) fn sync_peers_path(&self) -> PathBuf {
+        self.state_dir.join("sync_peers")
+    }
+
+    /// Path to the directory containing the heads that sync peers are
+    /// known to have.
+    pub(crate) fn peer_caches_path(&self) -> PathBuf {
+        self.cache_dir.join("peer_caches")
+    }
+
+    /// Path to the directory containing the heads that sync peers
+    /// synced with over TCP are known to have.
+    pub(crate) fn tcp_peer_caches_path(&self) -> PathBuf {
+        self.cache_dir.join("tcp_peer_caches")
+    }
+
     /// Path to the daemon's UDS API socket.
     pub fn uds_api_sock(&self) -> PathBuf {
         self.runtime_dir.join("uds.sock")
@@ -218,9 +268,97 @@ pub struct AfcConfig {
 pub struct AqcConfig {}
 
 /// QUIC syncer configuration.
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct QuicSyncConfig {}
+pub struct QuicSyncConfig {
+    /// How to back off from sync peers whose syncs are failing,
+    /// unless the peer was added with its own backoff.
+    #[serde(default)]
+    pub backoff: SyncBackoffConfig,
+    /// Whether to save the heads that each sync peer is known to have in
+    /// `cache_dir`, so that syncs after a restart don't start from
+    /// scratch.
+    #[serde(default)]
+    pub persist_peer_caches: bool,
+    /// How long the sync and AQC control PSKs of a team's
+    /// previous seed are still used after the seed is rotated, in
+    /// milliseconds. Defaults to 15 minutes. The seed is kept
+    /// until then, even across restarts.
+    ///
+    /// Peers that don't sync the rotation within this time can only
+    /// fetch it with the previous seed's PSKs, over QUIC, until the
+    /// seed is rotated again.
+    #[serde(default)]
+    pub seed_grace_ms: Option<u64>,
+}
+
+/// TCP syncer configuration.
+///
+/// The TCP syncer uses the same team PSKs as the QUIC syncer, over
+/// TLS 1.3. Peers can't push hints over TCP, so `subscribe` has no
+/// effect for peers synced with over TCP.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpSyncConfig {
+    /// How to back off from sync peers whose syncs are failing,
+    /// unless the peer was added with its own backoff.
+    #[serde(default)]
+    pub backoff: SyncBackoffConfig,
+    /// Whether to save the heads that each sync peer is known to have in
+    /// `cache_dir`, so that syncs after a restart don't start from
+    /// scratch.
+    #[serde(default)]
+    pub persist_peer_caches: bool,
+    /// How long the sync and AQC control PSKs of a team's
+    /// previous seed are still used after the seed is rotated, in
+    /// milliseconds. Defaults to 15 minutes. The seed is kept
+    /// until then, even across restarts.
+    ///
+    /// Peers that don't sync the rotation within this time can only
+    /// fetch it with the previous seed's PSKs, over QUIC, until the
+    /// seed is rotated again. Both syncers use the same PSKs, so
+    /// this must match the QUIC config's if both are set.
+    #[serde(default)]
+    pub seed_grace_ms: Option<u64>,
+}
+
+/// Exponential backoff for sync peers whose syncs are failing.
+///
+/// See [`SyncBackoff`].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct SyncBackoffConfig {
+    /// The delay after the first failure, in milliseconds.
+    pub min_delay_ms: u64,
+    /// The longest delay between attempts, in milliseconds.
+    pub max_delay_ms: u64,
+    /// How much the delay is multiplied by after each failure.
+    pub factor: u16,
+    /// Whether to add a random delay of up to `min_delay_ms`.
+    pub jitter: bool,
+}
+
+impl Default for SyncBackoffConfig {
+    fn default() -> Self {
+        Self {
+            min_delay_ms: 1000,
+            max_delay_ms: 300_000,
+            factor: 2,
+            jitter: true,
+        }
+    }
+}
+
+impl From<SyncBackoffConfig> for SyncBackoff {
+    fn from(cfg: SyncBackoffConfig) -> Self {
+        Self {
+            min_delay: Duration::from_millis(cfg.min_delay_ms),
+            max_delay: Duration::from_millis(cfg.max_delay_ms),
+            factor: f32::from(cfg.factor),
+            jitter: cfg.jitter,
+        }
+    }
+}
 
 fn non_empty_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
 where
@@ -257,7 +395,11 @@ mod tests {
             logs_dir: "/var/log/aranya".parse()?,
             config_dir: "/etc/aranya".parse()?,
             sync_addr: Addr::new(Ipv4Addr::UNSPECIFIED.to_string(), 4321)?,
-            quic_sync: Some(QuicSyncConfig {}),
+            quic_sync: Some(QuicSyncConfig {
+                seed_grace_ms: Some(900_000),
+                ..Default::default()
+            }),
+            tcp_sync: Some(TcpSyncConfig::default()),
             afc: None,
             aqc: None,
         };
@@ -291,4 +433,33 @@ mod tests {
         });
         serde_json::from_value::<Config>(data).expect_err("empty `cache_dir` should be rejected");
     }
+
+    #[test]
+    fn test_seed_grace_ms() -> Result<()> {
+        let mut cfg = Config::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("example.json"))?;
+        assert_eq!(cfg.seed_grace_ms()?, Some(900_000));
+
+        // The TCP config's is used if the QUIC config doesn't set one.
+        cfg.quic_sync = Some(QuicSyncConfig::default());
+        cfg.tcp_sync = Some(TcpSyncConfig {
+            seed_grace_ms: Some(60_000),
+            ..Default::default()
+        });
+        assert_eq!(cfg.seed_grace_ms()?, Some(60_000));
+
+        cfg.quic_sync = Some(QuicSyncConfig {
+            seed_grace_ms: Some(60_000),
+            ..Default::default()
+        });
+        assert_eq!(cfg.seed_grace_ms()?, Some(60_000));
+
+        cfg.quic_sync = Some(QuicSyncConfig {
+            seed_grace_ms: Some(900_000),
+            ..Default::default()
+        });
+        cfg.seed_grace_ms()
+            .expect_err("disagreeing grace periods should be rejected");
+
+        Ok(())
+    }
 }
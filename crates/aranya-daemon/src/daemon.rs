@@ -1,4 +1,11 @@
-use std::{collections::BTreeMap, io, path::Path, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use aranya_crypto::{
@@ -7,7 +14,7 @@ use aranya_crypto::{
     keystore::{fs_keystore::Store, KeyStore},
     Engine, Rng,
 };
-use aranya_daemon_api::TeamId;
+use aranya_daemon_api::{SyncBackoff, SyncPeerConfig, TeamId};
 use aranya_keygen::{KeyBundle, PublicKeys};
 use aranya_runtime::{
     storage::linear::{libc::FileManager, LinearStorageProvider},
@@ -18,11 +25,7 @@ use bimap::BiBTreeMap;
 use buggy::{bug, Bug, BugExt};
 use ciborium as cbor;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::{
-    fs,
-    sync::{mpsc::Receiver, Mutex},
-    task::JoinSet,
-};
+use tokio::{fs, sync::Mutex, task::JoinSet};
 use tracing::{error, info, info_span, Instrument as _};
 
 use crate::{
@@ -30,14 +33,19 @@ use crate::{
     api::{ApiKey, DaemonApiServer, QSData},
     aqc::Aqc,
     aranya,
-    config::Config,
+    config::{Config, SyncBackoffConfig},
     keystore::{AranyaStore, LocalStore},
     policy,
     sync::task::{
-        quic::{PskStore, State as QuicSyncState},
-        Syncer,
+        loopback::{Network as LoopbackNetwork, State as LoopbackSyncState},
+        quic::{PskStore, State as QuicSyncState, DEFAULT_SEED_GRACE},
+        tcp::State as TcpSyncState,
+        EffectSender, SyncPeers, SyncState, Syncer,
+    },
+    util::{
+        load_recovery_team_psks, load_retired_team_psks, load_team_psk_pairs, PeerCacheDir,
+        SeedDir, SeedRotationDir, SyncPeerDir,
     },
-    util::{load_team_psk_pairs, SeedDir},
     vm_policy::{PolicyEngine, TEST_POLICY_1},
 };
 
@@ -57,6 +65,8 @@ pub(crate) type EF = policy::Effect;
 
 pub(crate) type Client = aranya::Client<EN, SP>;
 pub(crate) type SyncServer = crate::sync::task::quic::Server<EN, SP>;
+pub(crate) type TcpSyncServer = crate::sync::task::tcp::Server<EN, SP>;
+pub(crate) type LoopbackSyncServer = crate::sync::task::loopback::Server<EN, SP>;
 
 mod invalid_graphs {
     use std::{
@@ -92,6 +102,31 @@ mod invalid_graphs {
 }
 pub(crate) use invalid_graphs::InvalidGraphs;
 
+/// Settings for a syncer from its syncer config.
+struct SyncerSettings {
+    backoff: SyncBackoff,
+    /// Where peer caches are saved, if they are.
+    peer_caches_path: Option<PathBuf>,
+}
+
+impl SyncerSettings {
+    fn new(backoff: &SyncBackoffConfig, persist_peer_caches: bool, path: PathBuf) -> Self {
+        Self {
+            backoff: backoff.clone().into(),
+            peer_caches_path: persist_peer_caches.then_some(path),
+        }
+    }
+}
+
+/// Syncs with peers until the task is aborted.
+async fn run_syncer<ST: SyncState>(mut syncer: Syncer<ST>) {
+    loop {
+        if let Err(err) = syncer.next().await {
+            error!(?err, "unable to sync with peer");
+        }
+    }
+}
+
 /// Handle for the spawned daemon.
 ///
 /// Dropping this will abort the daemon's tasks.
@@ -118,10 +153,51 @@ impl DaemonHandle {
     }
 }
 
+/// A syncer that syncs with the daemon's peers.
+enum AnySyncer {
+    Quic(Syncer<QuicSyncState>),
+    Tcp(Syncer<TcpSyncState>),
+    Loopback(Syncer<LoopbackSyncState>),
+}
+
+impl AnySyncer {
+    /// See [`Syncer::restore_peers`].
+    fn restore_peers(
+        &mut self,
+        peers: &mut SyncPeers,
+        saved: &[(TeamId, Vec<(Addr, SyncPeerConfig)>)],
+    ) {
+        match self {
+            Self::Quic(syncer) => syncer.restore_peers(peers, saved),
+            Self::Tcp(syncer) => syncer.restore_peers(peers, saved),
+            Self::Loopback(syncer) => syncer.restore_peers(peers, saved),
+        }
+    }
+}
+
+/// The sync servers the daemon runs.
+struct SyncServers {
+    quic: Option<SyncServer>,
+    tcp: Option<TcpSyncServer>,
+    loopback: Option<LoopbackSyncServer>,
+}
+
+impl SyncServers {
+    /// Returns the address that peers sync with the daemon at.
+    fn local_addr(&self) -> Result<SocketAddr> {
+        match (&self.quic, &self.tcp, &self.loopback) {
+            (Some(server), _, _) => server.local_addr(),
+            (None, Some(server), _) => server.local_addr(),
+            (None, None, Some(server)) => server.local_addr(),
+            (None, None, None) => bug!("a sync server is configured"),
+        }
+    }
+}
+
 /// The daemon itself.
 pub struct Daemon {
-    sync_server: SyncServer,
-    syncer: Syncer<QuicSyncState>,
+    servers: SyncServers,
+    syncers: Vec<AnySyncer>,
     api: DaemonApiServer,
     span: tracing::Span,
 }
@@ -129,15 +205,30 @@ pub struct Daemon {
 impl Daemon {
     /// Loads a `Daemon` using its config.
     pub async fn load(cfg: Config) -> Result<Self> {
+        Self::load_inner(cfg, None).await
+    }
+
+    /// Loads a `Daemon` that syncs over an in-memory `network` instead
+    /// of QUIC or TCP, for tests.
+    ///
+    /// The sync configs only set the backoff and whether peer caches
+    /// are saved, and are not required.
+    pub async fn load_with_network(cfg: Config, network: LoopbackNetwork) -> Result<Self> {
+        Self::load_inner(cfg, Some(network)).await
+    }
+
+    async fn load_inner(cfg: Config, network: Option<LoopbackNetwork>) -> Result<Self> {
         let name = (!cfg.name.is_empty()).then_some(cfg.name.as_str());
         let span = info_span!("daemon", name);
         let span_id = span.id();
 
         async move {
-            // TODO: Fix this when other syncer types are supported
-            let Some(_qs_config) = &cfg.quic_sync else {
-                anyhow::bail!("Supply a valid QUIC sync config")
-            };
+            if cfg.quic_sync.is_none() && cfg.tcp_sync.is_none() && network.is_none() {
+                anyhow::bail!("Supply a valid QUIC or TCP sync config");
+            }
+            let seed_grace = cfg
+                .seed_grace_ms()?
+                .map_or(DEFAULT_SEED_GRACE, Duration::from_millis);
 
             Self::setup_env(&cfg).await?;
             let mut aranya_store = Self::load_aranya_keystore(&cfg).await?;
@@ -157,11 +248,28 @@ impl Daemon {
             let seed_id_dir = SeedDir::new(cfg.seed_id_path().to_path_buf()).await?;
             let initial_keys =
                 load_team_psk_pairs(&mut eng, &mut local_store, &seed_id_dir).await?;
-            let (psk_store, active_team_rx) = PskStore::new(initial_keys);
-            let psk_store = Arc::new(psk_store);
+            let psk_store = Arc::new(PskStore::new(initial_keys));
+            // Rotated seeds are still used until their grace
+            // period ends, even across restarts.
+            for (team_id, psks, until) in
+                load_retired_team_psks(&mut eng, &mut local_store, &seed_id_dir).await?
+            {
+                psk_store.retire(team_id, psks, until)?;
+            }
+            // After that, the newest one can still be used to fetch
+            // the rotation that retired it.
+            let seed_rotations =
+                SeedRotationDir::new(cfg.seed_rotations_path(), "seed rotation").await?;
+            for (team_id, psks, rotation) in
+                load_recovery_team_psks(&mut eng, &mut local_store, &seed_id_dir, &seed_rotations)
+                    .await?
+            {
+                let rotation = postcard::to_allocvec(&rotation).context("can serialize")?;
+                psk_store.recover(team_id, psks, rotation.into())?;
+            }
 
             // Initialize Aranya client.
-            let (client, sync_server) = Self::setup_aranya(
+            let (client, servers) = Self::setup_aranya(
                 &cfg,
                 eng.clone(),
                 aranya_store
@@ -170,18 +278,93 @@ impl Daemon {
                 &pks,
                 cfg.sync_addr,
                 Arc::clone(&psk_store),
-                active_team_rx,
+                network.as_ref(),
             )
             .await?;
-            let local_addr = sync_server.local_addr()?;
+            let local_addr = servers.local_addr()?;
 
             // Sync in the background at some specified interval.
             let (send_effects, recv_effects) = tokio::sync::mpsc::channel(256);
 
             let invalid_graphs = InvalidGraphs::default();
-            let state = QuicSyncState::new(psk_store.clone())?;
-            let (syncer, peers) =
-                Syncer::new(client.clone(), send_effects, invalid_graphs.clone(), state);
+            let sync_peer_dir = SyncPeerDir::new(cfg.sync_peers_path(), "sync peers").await?;
+            let saved_peers = sync_peer_dir.list().await?;
+            let mut peers = SyncPeers::new(sync_peer_dir);
+            // QUIC is the default transport, so its syncer is created
+            // first.
+            let mut syncers = Vec::new();
+            if let Some(network) = network {
+                let settings = match (&cfg.quic_sync, &cfg.tcp_sync) {
+                    (Some(qs_config), _) => SyncerSettings::new(
+                        &qs_config.backoff,
+                        qs_config.persist_peer_caches,
+                        cfg.peer_caches_path(),
+                    ),
+                    (None, Some(ts_config)) => SyncerSettings::new(
+                        &ts_config.backoff,
+                        ts_config.persist_peer_caches,
+                        cfg.peer_caches_path(),
+                    ),
+                    (None, None) => SyncerSettings::new(
+                        &SyncBackoffConfig::default(),
+                        false,
+                        cfg.peer_caches_path(),
+                    ),
+                };
+                let state = LoopbackSyncState::new(network, local_addr.into());
+                let syncer = Self::setup_syncer(
+                    client.clone(),
+                    state,
+                    &mut peers,
+                    send_effects.clone(),
+                    invalid_graphs.clone(),
+                    settings,
+                )
+                .await?;
+                syncers.push(AnySyncer::Loopback(syncer));
+            } else {
+                if let Some(qs_config) = &cfg.quic_sync {
+                    let settings = SyncerSettings::new(
+                        &qs_config.backoff,
+                        qs_config.persist_peer_caches,
+                        cfg.peer_caches_path(),
+                    );
+                    let state = QuicSyncState::new(psk_store.clone())?;
+                    let syncer = Self::setup_syncer(
+                        client.clone(),
+                        state,
+                        &mut peers,
+                        send_effects.clone(),
+                        invalid_graphs.clone(),
+                        settings,
+                    )
+                    .await?;
+                    syncers.push(AnySyncer::Quic(syncer));
+                }
+                if let Some(ts_config) = &cfg.tcp_sync {
+                    let settings = SyncerSettings::new(
+                        &ts_config.backoff,
+                        ts_config.persist_peer_caches,
+                        cfg.tcp_peer_caches_path(),
+                    );
+                    let state = TcpSyncState::new(psk_store.clone());
+                    let syncer = Self::setup_syncer(
+                        client.clone(),
+                        state,
+                        &mut peers,
+                        send_effects.clone(),
+                        invalid_graphs.clone(),
+                        settings,
+                    )
+                    .await?;
+                    syncers.push(AnySyncer::Tcp(syncer));
+                }
+            }
+            // Each peer is routed to the syncer for its transport, so
+            // every syncer must exist first.
+            for syncer in &mut syncers {
+                syncer.restore_peers(&mut peers, &saved_peers);
+            }
 
             let graph_ids = client
                 .aranya
@@ -216,7 +399,11 @@ impl Daemon {
                 )
             };
 
-            let data = QSData { psk_store };
+            let data = QSData {
+                psk_store,
+                seed_grace,
+                seed_rotations,
+            };
 
             let crypto = crate::api::Crypto {
                 engine: eng,
@@ -239,8 +426,8 @@ impl Daemon {
                 Some(data),
             )?;
             Ok(Self {
-                sync_server,
-                syncer,
+                servers,
+                syncers,
                 api,
                 span,
             })
@@ -250,24 +437,35 @@ impl Daemon {
     }
 
     /// The daemon's entrypoint.
-    pub fn spawn(mut self) -> DaemonHandle {
+    pub fn spawn(self) -> DaemonHandle {
         let _guard = self.span.enter();
         let mut set = JoinSet::new();
-        set.spawn(
-            self.sync_server
-                .serve()
-                .instrument(info_span!("sync-server")),
-        );
-        set.spawn(
-            async move {
-                loop {
-                    if let Err(err) = self.syncer.next().await {
-                        error!(?err, "unable to sync with peer");
-                    }
+        if let Some(server) = self.servers.quic {
+            set.spawn(server.serve().instrument(info_span!("sync-server")));
+        }
+        if let Some(server) = self.servers.tcp {
+            set.spawn(server.serve().instrument(info_span!("tcp-sync-server")));
+        }
+        if let Some(server) = self.servers.loopback {
+            set.spawn(
+                server
+                    .serve()
+                    .instrument(info_span!("loopback-sync-server")),
+            );
+        }
+        for syncer in self.syncers {
+            match syncer {
+                AnySyncer::Quic(syncer) => {
+                    set.spawn(run_syncer(syncer).instrument(info_span!("syncer")));
+                }
+                AnySyncer::Tcp(syncer) => {
+                    set.spawn(run_syncer(syncer).instrument(info_span!("tcp-syncer")));
+                }
+                AnySyncer::Loopback(syncer) => {
+                    set.spawn(run_syncer(syncer).instrument(info_span!("syncer")));
                 }
             }
-            .instrument(info_span!("syncer")),
-        );
+        }
         set.spawn(self.api.serve().instrument(info_span!("api-server")));
         DaemonHandle { set }
     }
@@ -314,7 +512,9 @@ impl Daemon {
         Ok(())
     }
 
-    /// Creates the Aranya client and sync server.
+    /// Creates the Aranya client and the configured sync servers.
+    ///
+    /// Only a loopback sync server is created if there's a `network`.
     async fn setup_aranya(
         cfg: &Config,
         eng: CE,
@@ -322,8 +522,8 @@ impl Daemon {
         pk: &PublicKeys<CS>,
         external_sync_addr: Addr,
         psk_store: Arc<PskStore>,
-        active_team_rx: Receiver<TeamId>,
-    ) -> Result<(Client, SyncServer)> {
+        network: Option<&LoopbackNetwork>,
+    ) -> Result<(Client, SyncServers)> {
         let device_id = pk.ident_pk.id()?;
 
         let aranya = Arc::new(Mutex::new(ClientState::new(
@@ -335,19 +535,81 @@ impl Daemon {
 
         let client = Client::new(Arc::clone(&aranya));
 
-        info!(addr = %external_sync_addr, "starting QUIC sync server");
-        let server = SyncServer::new(
-            client.clone(),
-            &external_sync_addr,
-            psk_store,
-            active_team_rx,
-        )
-        .await
-        .context("unable to initialize QUIC sync server")?;
+        if let Some(network) = network {
+            let server = LoopbackSyncServer::new(client.clone(), network);
+            info!(device_id = %device_id, "set up Aranya");
+            let servers = SyncServers {
+                quic: None,
+                tcp: None,
+                loopback: Some(server),
+            };
+            return Ok((client, servers));
+        }
+
+        let mut tcp_sync_addr = external_sync_addr;
+        let server = match &cfg.quic_sync {
+            Some(_) => {
+                info!(addr = %external_sync_addr, "starting QUIC sync server");
+                let server =
+                    SyncServer::new(client.clone(), &external_sync_addr, psk_store.clone())
+                        .await
+                        .context("unable to initialize QUIC sync server")?;
+                // Peers reach both servers at the same address.
+                tcp_sync_addr = server.local_addr()?.into();
+                Some(server)
+            }
+            None => None,
+        };
+        let tcp_server = match &cfg.tcp_sync {
+            Some(_) => {
+                info!(addr = %tcp_sync_addr, "starting TCP sync server");
+                let server = TcpSyncServer::new(client.clone(), &tcp_sync_addr, psk_store)
+                    .await
+                    .context("unable to initialize TCP sync server")?;
+                Some(server)
+            }
+            None => None,
+        };
 
         info!(device_id = %device_id, "set up Aranya");
 
-        Ok((client, server))
+        let servers = SyncServers {
+            quic: server,
+            tcp: tcp_server,
+            loopback: None,
+        };
+        Ok((client, servers))
+    }
+
+    /// Creates a syncer for `peers` and restores the peer caches saved
+    /// by a previous run.
+    async fn setup_syncer<ST: SyncState>(
+        client: Client,
+        state: ST,
+        peers: &mut SyncPeers,
+        send_effects: EffectSender,
+        invalid_graphs: InvalidGraphs,
+        settings: SyncerSettings,
+    ) -> Result<Syncer<ST>> {
+        let peer_cache_dir = match settings.peer_caches_path {
+            Some(path) => Some(PeerCacheDir::new(path, "peer cache").await?),
+            None => None,
+        };
+        let saved_caches = match &peer_cache_dir {
+            Some(dir) => dir.list().await?,
+            None => Vec::new(),
+        };
+        let mut syncer = Syncer::new(
+            client,
+            send_effects,
+            invalid_graphs,
+            state,
+            peers,
+            settings.backoff,
+            peer_cache_dir,
+        );
+        syncer.restore_caches(saved_caches).await?;
+        Ok(syncer)
     }
 
     /// Loads the crypto engine.
@@ -481,7 +743,8 @@ mod tests {
             logs_dir: work_dir.join("logs"),
             config_dir: work_dir.join("config"),
             sync_addr: any,
-            quic_sync: Some(QuicSyncConfig {}),
+            quic_sync: Some(QuicSyncConfig::default()),
+            tcp_sync: None,
             afc: Some(AfcConfig {
                 shm_path: "/test_daemon1".to_owned(),
                 unlink_on_startup: true,
@@ -39,6 +39,7 @@ pub enum Effect {
     TeamTerminated(TeamTerminated),
     MemberAdded(MemberAdded),
     MemberRemoved(MemberRemoved),
+    SyncSeedRotated(SyncSeedRotated),
     OwnerAssigned(OwnerAssigned),
     AdminAssigned(AdminAssigned),
     OperatorAssigned(OperatorAssigned),
@@ -85,6 +86,14 @@ pub struct MemberAdded {
 pub struct MemberRemoved {
     pub device_id: Id,
 }
+/// SyncSeedRotated policy effect.
+#[effect]
+pub struct SyncSeedRotated {
+    pub seed_id: Id,
+    pub rotated_at: i64,
+    pub wrapped_seeds: Vec<u8>,
+    pub author_id: Id,
+}
 /// OwnerAssigned policy effect.
 #[effect]
 pub struct OwnerAssigned {
@@ -202,6 +211,7 @@ pub struct LabelAssigned {
     pub label_name: Text,
     pub label_author_id: Id,
     pub author_id: Id,
+    pub device_id: Id,
 }
 /// LabelRevoked policy effect.
 #[effect]
@@ -210,6 +220,7 @@ pub struct LabelRevoked {
     pub label_name: Text,
     pub label_author_id: Id,
     pub author_id: Id,
+    pub device_id: Id,
 }
 /// QueryLabelExistsResult policy effect.
 #[effect]
@@ -232,6 +243,7 @@ pub struct QueriedLabelAssignment {
     pub label_id: Id,
     pub label_name: Text,
     pub label_author_id: Id,
+    pub op: ChanOp,
 }
 /// QueryDevicesOnTeamResult policy effect.
 #[effect]
@@ -270,6 +282,12 @@ pub trait ActorExt {
     fn terminate_team(&mut self) -> Result<(), ClientError>;
     fn add_member(&mut self, device_keys: KeyBundle) -> Result<(), ClientError>;
     fn remove_member(&mut self, device_id: Id) -> Result<(), ClientError>;
+    fn rotate_sync_seed(
+        &mut self,
+        seed_id: Id,
+        rotated_at: i64,
+        wrapped_seeds: Vec<u8>,
+    ) -> Result<(), ClientError>;
     fn assign_role(&mut self, device_id: Id, role: Role) -> Result<(), ClientError>;
     fn revoke_role(&mut self, device_id: Id, role: Role) -> Result<(), ClientError>;
     fn set_aqc_network_name(
@@ -9,7 +9,7 @@ pub type Result<T> = core::result::Result<T, SyncError>;
 mod error {
     use thiserror::Error;
 
-    use super::task::quic::Error as QSError;
+    use super::task::{loopback::Error as LSError, quic::Error as QSError, tcp::Error as TSError};
 
     #[derive(Error, Debug)]
     #[non_exhaustive]
@@ -17,6 +17,10 @@ mod error {
         #[error(transparent)]
         QuicSync(#[from] QSError),
         #[error(transparent)]
+        TcpSync(#[from] TSError),
+        #[error(transparent)]
+        LoopbackSync(#[from] LSError),
+        #[error(transparent)]
         Runtime(#[from] aranya_runtime::SyncError),
         #[error("Could not send sync request: {0}")]
         SendSyncRequest(Box<SyncError>),
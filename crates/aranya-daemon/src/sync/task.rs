@@ -1,40 +1,71 @@
 //! Aranya sync task.
 //! A task for syncing with Aranya peers at specified intervals.
 //! A [`DelayQueue`] is used to retrieve the next peer to sync with at the specified interval.
-//! [`SyncPeers`] handles adding/removing peers for the [`Syncer`].
+//! [`SyncPeers`] handles adding/removing peers for the [`Syncer`]s.
+//! There is a [`Syncer`] for each transport the daemon is configured with, and each peer is synced with by the one for its transport.
 //! [`Syncer`] syncs with the next available peer from the [`DelayQueue`].
+//! Peers added with `subscribe` set are also synced with early when they push a hint that they have new commands, at most once per interval and never while backing off.
+//! [`Syncer`] remembers the heads each peer is known to have in a [`PeerCache`], so that a sync only exchanges new commands.
 //! [`SyncPeers`] and [`Syncer`] communicate via mpsc channels so they can run independently.
 //! This prevents the need for an `Arc<<Mutex>>` which would lock until the next peer is retrieved from the [`DelayQueue`]
 
-use std::{collections::HashMap, future::Future, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex as SyncMutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{Context, Result};
-use aranya_daemon_api::SyncPeerConfig;
-use aranya_runtime::{storage::GraphId, ClientError, Engine, Sink};
+use aranya_daemon_api::{SyncBackoff, SyncPeerConfig, SyncTransport, TeamId};
+use aranya_runtime::{
+    storage::{GraphId, StorageError},
+    Address, ClientError, Engine, PeerCache, Sink,
+};
 use aranya_util::Addr;
+use backon::{BackoffBuilder, ExponentialBackoff, ExponentialBuilder};
 use buggy::BugExt;
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, time::Instant};
 use tokio_util::time::{delay_queue::Key, DelayQueue};
-use tracing::{error, instrument, trace};
+use tracing::{error, instrument, trace, warn};
 
 use super::Result as SyncResult;
 use crate::{
     daemon::{Client, EF},
     sync::error::SyncError,
+    util::{PeerCacheDir, SyncPeerDir},
     vm_policy::VecSink,
     InvalidGraphs,
 };
 
+mod framed;
+pub mod loopback;
 pub mod quic;
+pub mod tcp;
 
 /// Message sent from [`SyncPeers`] to [`Syncer`] via mpsc.
 #[derive(Clone)]
 enum Msg {
-    SyncNow { peer: SyncPeer },
-    AddPeer { peer: SyncPeer, cfg: SyncPeerConfig },
-    RemovePeer { peer: SyncPeer },
+    SyncNow {
+        peer: SyncPeer,
+    },
+    AddPeer {
+        peer: SyncPeer,
+        cfg: SyncPeerConfig,
+    },
+    RemovePeer {
+        peer: SyncPeer,
+    },
+    /// The team was removed.
+    RemoveTeam {
+        graph_id: GraphId,
+    },
+    /// The peer pushed a hint that it has new commands.
+    Pushed {
+        peer: SyncPeer,
+    },
 }
 
 /// A sync peer.
@@ -50,10 +81,15 @@ struct SyncPeer {
 /// Handles adding and removing sync peers.
 #[derive(Clone, Debug)]
 pub struct SyncPeers {
-    /// Send messages to add/remove peers.
-    send: mpsc::Sender<Msg>,
+    /// Send messages to add/remove peers to each [`Syncer`], along with
+    /// the transport it syncs over. The first one is the default.
+    syncers: Vec<(Option<SyncTransport>, mpsc::Sender<Msg>)>,
     /// Configuration values for syncing
     cfgs: HashMap<(Addr, GraphId), SyncPeerConfig>,
+    /// Where peers that are not ephemeral are saved.
+    dir: Arc<SyncPeerDir>,
+    /// The health of each peer, updated by the [`Syncer`].
+    health: SharedHealth,
 }
 
 /// A response to a sync request.
@@ -67,25 +103,76 @@ pub(crate) enum SyncResponse {
 
 impl SyncPeers {
     /// Create a new peer manager.
-    fn new(send: mpsc::Sender<Msg>) -> Self {
+    ///
+    /// It has no [`Syncer`]s until they are created with
+    /// [`Syncer::new`].
+    pub(crate) fn new(dir: SyncPeerDir) -> Self {
         Self {
-            send,
+            syncers: Vec::new(),
             cfgs: HashMap::new(),
+            dir: Arc::new(dir),
+            health: SharedHealth::default(),
+        }
+    }
+
+    /// Returns the sender of the [`Syncer`] that syncs over `transport`,
+    /// or of the default one if it's `None`.
+    fn sender(&self, transport: Option<SyncTransport>) -> Result<&mpsc::Sender<Msg>> {
+        let syncer = match transport {
+            None => self.syncers.first(),
+            // A syncer without a transport syncs with every peer.
+            Some(transport) => self
+                .syncers
+                .iter()
+                .find(|(t, _)| t.is_none_or(|t| t == transport)),
+        };
+        syncer
+            .map(|(_, send)| send)
+            .with_context(|| match transport {
+                Some(transport) => format!("{transport:?} sync is not configured"),
+                None => "no syncer is configured".to_string(),
+            })
+    }
+
+    /// Sends `msg` to every [`Syncer`].
+    async fn send_all(&self, msg: Msg) -> Result<()> {
+        for (_, send) in &self.syncers {
+            send.send(msg.clone()).await?;
         }
+        Ok(())
     }
 
-    /// Add peer to [`Syncer`].
+    /// Add peer to the [`Syncer`] for its transport.
     pub(crate) async fn add_peer(
         &mut self,
         addr: Addr,
         graph_id: GraphId,
         cfg: SyncPeerConfig,
     ) -> Result<()> {
-        let peer = Msg::AddPeer {
-            peer: SyncPeer { addr, graph_id },
+        let peer = SyncPeer { addr, graph_id };
+        let send = self.sender(cfg.transport)?;
+        // A peer that moved to another transport is no longer synced
+        // with over the old one.
+        if let Some(old) = self
+            .cfgs
+            .get(&(addr, graph_id))
+            .and_then(|old| self.sender(old.transport).ok())
+            .filter(|old| !old.same_channel(send))
+        {
+            if let Err(e) = old
+                .send(Msg::RemovePeer { peer: peer.clone() })
+                .await
+                .context("unable to remove peer")
+            {
+                error!(?e, "error removing peer from syncer");
+                return Err(e);
+            }
+        }
+        let msg = Msg::AddPeer {
+            peer,
             cfg: cfg.clone(),
         };
-        if let Err(e) = self.send.send(peer).await.context("unable to add peer") {
+        if let Err(e) = send.send(msg).await.context("unable to add peer") {
             error!(?e, "error adding peer to syncer");
             return Err(e);
         }
@@ -94,15 +181,15 @@ impl SyncPeers {
         }
 
         self.cfgs.insert((addr, graph_id), cfg);
+        self.save(graph_id).await?;
 
         Ok(())
     }
 
-    /// Remove peer from [`Syncer`].
+    /// Remove peer from the [`Syncer`]s.
     pub(crate) async fn remove_peer(&mut self, addr: Addr, graph_id: GraphId) -> Result<()> {
         if let Err(e) = self
-            .send
-            .send(Msg::RemovePeer {
+            .send_all(Msg::RemovePeer {
                 peer: SyncPeer { addr, graph_id },
             })
             .await
@@ -112,23 +199,110 @@ impl SyncPeers {
             return Err(e);
         }
 
-        self.cfgs.remove(&(addr, graph_id));
+        if self.cfgs.remove(&(addr, graph_id)).is_some() {
+            self.save(graph_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes every peer of `graph_id` from the [`Syncer`]s, along
+    /// with their saved state.
+    pub(crate) async fn remove_team(&mut self, graph_id: GraphId) -> Result<()> {
+        if let Err(e) = self
+            .send_all(Msg::RemoveTeam { graph_id })
+            .await
+            .context("unable to remove team")
+        {
+            error!(?e, "error removing team from syncer");
+            return Err(e);
+        }
+
+        self.cfgs.retain(|(_, id), _| *id != graph_id);
+        self.save(graph_id).await?;
 
         Ok(())
     }
 
+    /// Writes the peers of `graph_id` that are not ephemeral to disk.
+    async fn save(&self, graph_id: GraphId) -> Result<()> {
+        let peers: Vec<_> = self
+            .list_peers(graph_id)
+            .into_iter()
+            .filter(|(_, cfg)| !cfg.ephemeral)
+            .collect();
+        let team_id = TeamId::from(graph_id.into_id());
+        self.dir
+            .save(&team_id, &peers)
+            .await
+            .context("unable to save sync peers")
+    }
+
+    /// Returns the peers that `graph_id` is synced with, sorted by
+    /// address.
+    ///
+    /// Answered from the configs kept here rather than by the [`Syncer`],
+    /// which does not read messages while it is syncing.
+    pub(crate) fn list_peers(&self, graph_id: GraphId) -> Vec<(Addr, SyncPeerConfig)> {
+        let mut peers: Vec<_> = self
+            .cfgs
+            .iter()
+            .filter(|((_, id), _)| *id == graph_id)
+            .map(|((addr, _), cfg)| (*addr, cfg.clone()))
+            .collect();
+        peers.sort_by_key(|(addr, _)| addr.to_string());
+        peers
+    }
+
+    /// Returns the health of the peers that `graph_id` is synced with,
+    /// sorted by address.
+    pub(crate) fn status(&self, graph_id: GraphId) -> Vec<(Addr, PeerHealth)> {
+        let health = self.health.lock().unwrap_or_else(PoisonError::into_inner);
+        self.list_peers(graph_id)
+            .into_iter()
+            .map(|(addr, _)| {
+                let peer = SyncPeer { addr, graph_id };
+                (addr, health.get(&peer).cloned().unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// Changes the config of a peer that was added with
+    /// [`add_peer`][Self::add_peer].
+    pub(crate) async fn update_peer(
+        &mut self,
+        addr: Addr,
+        graph_id: GraphId,
+        cfg: SyncPeerConfig,
+    ) -> Result<()> {
+        if !self.cfgs.contains_key(&(addr, graph_id)) {
+            anyhow::bail!("{addr} is not a sync peer");
+        }
+        self.add_peer(addr, graph_id, cfg).await
+    }
+
     /// Sync with a peer immediately.
+    ///
+    /// The peer is synced with over the transport in `cfg`, else the one
+    /// it was added with.
     pub(crate) async fn sync_now(
         &self,
         addr: Addr,
         graph_id: GraphId,
-        _cfg: Option<SyncPeerConfig>,
+        cfg: Option<SyncPeerConfig>,
     ) -> Result<()> {
+        let transport = match cfg {
+            Some(cfg) => cfg.transport,
+            None => self
+                .cfgs
+                .get(&(addr, graph_id))
+                .and_then(|cfg| cfg.transport),
+        };
         let peer = Msg::SyncNow {
             peer: SyncPeer { addr, graph_id },
         };
         if let Err(e) = self
-            .send
+            .sender(transport)?
             .send(peer)
             .await
             .context("unable to add sync now peer")
@@ -140,7 +314,14 @@ impl SyncPeers {
     }
 }
 
-type EffectSender = mpsc::Sender<(GraphId, Vec<EF>)>;
+pub(crate) type EffectSender = mpsc::Sender<(GraphId, Vec<EF>)>;
+
+/// The shortest time a peer is asked to push hints for.
+const MIN_SUBSCRIPTION: Duration = Duration::from_secs(60);
+
+/// A copy of each peer's [`PeerHealth`] that [`SyncPeers`] can read
+/// while the [`Syncer`] is busy syncing.
+type SharedHealth = Arc<SyncMutex<HashMap<SyncPeer, PeerHealth>>>;
 
 /// Syncs with each peer after the specified interval.
 /// Uses a [`DelayQueue`] to obtain the next peer to sync with.
@@ -152,6 +333,8 @@ pub struct Syncer<ST> {
     peers: HashMap<SyncPeer, PeerInfo>,
     /// Receives added/removed peers.
     recv: mpsc::Receiver<Msg>,
+    /// Sends to `recv`, for hints pushed by peers.
+    send: mpsc::Sender<Msg>,
     /// Delay queue for getting the next peer to sync with.
     queue: DelayQueue<SyncPeer>,
     /// Used to send effects to the API to be processed.
@@ -160,6 +343,14 @@ pub struct Syncer<ST> {
     invalid: InvalidGraphs,
     /// Additional state used by the syncer
     state: ST,
+    /// Shared with [`SyncPeers`].
+    health: SharedHealth,
+    /// Backoff for peers that don't have their own.
+    default_backoff: SyncBackoff,
+    /// The heads each peer is known to have.
+    caches: HashMap<SyncPeer, PeerCache>,
+    /// Where `caches` are saved, if they are.
+    cache_dir: Option<PeerCacheDir>,
 }
 
 struct PeerInfo {
@@ -167,67 +358,365 @@ struct PeerInfo {
     interval: Duration,
     /// Key used to remove peer from queue.
     key: Key,
+    /// How syncing with the peer has gone.
+    health: PeerHealth,
+    /// How to back off while syncs fail.
+    backoff_cfg: SyncBackoff,
+    /// The delays while syncs are failing, or `None` if the last sync
+    /// succeeded.
+    backoff: Option<ExponentialBackoff>,
+    /// Whether the peer is asked to push hints about new commands.
+    subscribe: bool,
+    /// When the last sync prompted by a hint was scheduled for.
+    last_push: Option<Instant>,
+}
+
+impl PeerInfo {
+    /// Returns when to sync after the peer pushed a hint at `now`, or
+    /// `None` if the hint should be ignored because syncs are failing.
+    ///
+    /// Hints are coalesced so that hints prompt at most one sync per
+    /// interval, on top of the syncs at the interval.
+    fn push_deadline(&self, now: Instant) -> Option<Instant> {
+        if self.health.consecutive_failures > 0 {
+            return None;
+        }
+        let due = self.last_push.map_or(now, |last| last + self.interval);
+        Some(due.max(now))
+    }
+
+    /// Returns how long to wait before retrying after a failed sync, or
+    /// `None` if the last sync succeeded and the interval should be used.
+    fn backoff_delay(&mut self) -> Option<Duration> {
+        if self.health.consecutive_failures == 0 {
+            self.backoff = None;
+            return None;
+        }
+        let cfg = &self.backoff_cfg;
+        let delay = self
+            .backoff
+            .get_or_insert_with(|| {
+                let builder = ExponentialBuilder::new()
+                    .with_min_delay(cfg.min_delay)
+                    .with_max_delay(cfg.max_delay)
+                    .with_factor(cfg.factor)
+                    .without_max_times();
+                if cfg.jitter {
+                    builder.with_jitter().build()
+                } else {
+                    builder.build()
+                }
+            })
+            .next()
+            .unwrap_or(cfg.max_delay);
+        Some(delay.max(self.interval))
+    }
+}
+
+/// How syncing with a peer has gone.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PeerHealth {
+    /// When the last sync started.
+    pub last_attempt: Option<SystemTime>,
+    /// When the last successful sync started.
+    pub last_success: Option<SystemTime>,
+    /// The number of syncs that have failed since the last success.
+    pub consecutive_failures: u32,
+    /// The error from the last failed sync.
+    pub last_error: Option<String>,
+    /// The number of commands received by the last sync.
+    pub last_commands: usize,
+    /// The number of commands received so far by the sync that is
+    /// running, if one is.
+    pub receiving: Option<usize>,
 }
 
 /// Types that contain additional data that are part of a [`Syncer`]
 /// object.
 pub trait SyncState: Sized {
-    /// Syncs with the peer.
+    /// The transport peers are synced with over, or `None` if peers are
+    /// synced with over it whatever their transport is.
+    const TRANSPORT: Option<SyncTransport>;
+
+    /// Syncs with the peer, returning the number of commands received.
     fn sync_impl<S>(
         syncer: &mut Syncer<Self>,
         id: GraphId,
         sink: &mut S,
         peer: &Addr,
-    ) -> impl Future<Output = SyncResult<()>> + Send
+    ) -> impl Future<Output = SyncResult<usize>> + Send
     where
         S: Sink<<crate::EN as Engine>::Effect> + Send;
 }
 
 impl<ST> Syncer<ST> {
-    /// Creates a new `Syncer`.
+    /// Creates a new `Syncer` that syncs with the peers added to `peers`
+    /// over its transport.
+    ///
+    /// The first `Syncer` created for `peers` is the default one.
     pub(crate) fn new(
         client: Client,
         send_effects: EffectSender,
         invalid: InvalidGraphs,
         state: ST,
-    ) -> (Self, SyncPeers) {
+        peers: &mut SyncPeers,
+        default_backoff: SyncBackoff,
+        cache_dir: Option<PeerCacheDir>,
+    ) -> Self
+    where
+        ST: SyncState,
+    {
         let (send, recv) = mpsc::channel::<Msg>(128);
-        let peers = SyncPeers::new(send);
-        (
-            Self {
-                client,
-                peers: HashMap::new(),
-                recv,
-                queue: DelayQueue::new(),
-                send_effects,
-                invalid,
-                state,
-            },
-            peers,
-        )
+        peers.syncers.push((ST::TRANSPORT, send.clone()));
+        Self {
+            client,
+            peers: HashMap::new(),
+            recv,
+            send,
+            queue: DelayQueue::new(),
+            send_effects,
+            invalid,
+            state,
+            health: Arc::clone(&peers.health),
+            default_backoff,
+            caches: HashMap::new(),
+            cache_dir,
+        }
     }
 
     /// Add a peer to the delay queue, overwriting an existing one.
     fn add_peer(&mut self, peer: SyncPeer, cfg: &SyncPeerConfig) {
         let key = self.queue.insert(peer.clone(), cfg.interval);
+        let backoff_cfg = cfg
+            .backoff
+            .clone()
+            .unwrap_or_else(|| self.default_backoff.clone());
         self.peers
             .entry(peer)
             .and_modify(|info| {
                 self.queue.remove(&info.key);
                 info.interval = cfg.interval;
                 info.key = key;
+                info.backoff_cfg = backoff_cfg.clone();
+                info.backoff = None;
+                info.subscribe = cfg.subscribe;
             })
             .or_insert(PeerInfo {
                 interval: cfg.interval,
                 key,
+                health: PeerHealth::default(),
+                backoff_cfg,
+                backoff: None,
+                subscribe: cfg.subscribe,
+                last_push: None,
             });
     }
 
+    /// Moves the next sync with `peer` earlier after it pushed a hint.
+    fn schedule_push(&mut self, peer: &SyncPeer) {
+        let Some(info) = self.peers.get_mut(peer) else {
+            return;
+        };
+        let Some(due) = info.push_deadline(Instant::now()) else {
+            return;
+        };
+        // A sync that is already due sooner covers the hint.
+        if due < self.queue.deadline(&info.key) {
+            self.queue.reset_at(&info.key, due);
+            info.last_push = Some(due);
+        }
+    }
+
+    /// Returns how long to ask `peer` to push hints for, or `None` if it
+    /// should not be asked.
+    ///
+    /// The subscription is renewed by each sync, so it outlasts a few
+    /// missed ones.
+    fn subscription(&self, peer: &SyncPeer) -> Option<Duration> {
+        let info = self.peers.get(peer).filter(|info| info.subscribe)?;
+        Some(
+            info.interval
+                .saturating_mul(3)
+                .max(info.backoff_cfg.max_delay)
+                .max(MIN_SUBSCRIPTION),
+        )
+    }
+
+    /// Adds the peers saved by a previous run that are synced with over
+    /// this syncer's transport to both `self` and `peers`.
+    ///
+    /// This bypasses the message channel, which could fill up before the
+    /// syncer starts reading it. Peers with `sync_now` set are synced with
+    /// as soon as the syncer starts. It must be called after every syncer
+    /// was created for `peers`.
+    pub(crate) fn restore_peers(
+        &mut self,
+        peers: &mut SyncPeers,
+        saved: &[(TeamId, Vec<(Addr, SyncPeerConfig)>)],
+    ) {
+        let is_default = peers
+            .sender(None)
+            .is_ok_and(|send| send.same_channel(&self.send));
+        for (team_id, team_peers) in saved {
+            let graph_id = GraphId::from(team_id.into_id());
+            for (addr, cfg) in team_peers {
+                match peers.sender(cfg.transport) {
+                    Ok(send) if send.same_channel(&self.send) => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        // Only warn once, not for each syncer.
+                        if is_default {
+                            warn!(%team_id, %addr, %e, "skipping saved sync peer");
+                        }
+                        continue;
+                    }
+                }
+                let (addr, cfg) = (*addr, cfg.clone());
+                let peer = SyncPeer { addr, graph_id };
+                self.add_peer(peer.clone(), &cfg);
+                if cfg.sync_now {
+                    if let Some(info) = self.peers.get(&peer) {
+                        self.queue.reset(&info.key, Duration::ZERO);
+                    }
+                }
+                peers.cfgs.insert((addr, graph_id), cfg);
+            }
+        }
+    }
+
     /// Remove a peer from the delay queue.
-    fn remove_peer(&mut self, peer: SyncPeer) {
+    async fn remove_peer(&mut self, peer: SyncPeer) {
         if let Some(info) = self.peers.remove(&peer) {
             self.queue.remove(&info.key);
         }
+        self.shared_health().remove(&peer);
+        self.caches.remove(&peer);
+        if let Err(e) = self.save_caches(peer.graph_id).await {
+            error!(?e, "unable to save peer caches");
+        }
+    }
+
+    /// Removes every peer of `graph_id` and deletes its saved caches.
+    async fn remove_team(&mut self, graph_id: GraphId) {
+        self.peers.retain(|p, info| {
+            let keep = p.graph_id != graph_id;
+            if !keep {
+                self.queue.remove(&info.key);
+            }
+            keep
+        });
+        self.shared_health().retain(|p, _| p.graph_id != graph_id);
+        self.caches.retain(|p, _| p.graph_id != graph_id);
+        if let Some(dir) = &self.cache_dir {
+            let team_id = TeamId::from(graph_id.into_id());
+            if let Err(e) = dir.remove(&team_id).await {
+                error!(?e, "unable to remove peer caches");
+            }
+        }
+    }
+
+    /// Rebuilds the peer caches saved by a previous run.
+    ///
+    /// Heads that are no longer in the graph are skipped, as are teams
+    /// whose graph is gone, e.g., because the team was removed while
+    /// its caches were being saved. The files of such teams are deleted.
+    pub(crate) async fn restore_caches(
+        &mut self,
+        saved: impl IntoIterator<Item = (TeamId, Vec<(Addr, Vec<Address>)>)>,
+    ) -> Result<()> {
+        let mut gone = Vec::new();
+        {
+            let mut client = self.client.lock().await;
+            'teams: for (team_id, heads) in saved {
+                let graph_id = GraphId::from(team_id.into_id());
+                for (addr, addrs) in heads {
+                    let mut cache = PeerCache::new();
+                    match client.update_heads(graph_id, addrs, &mut cache) {
+                        Ok(()) => {}
+                        Err(ClientError::StorageError(StorageError::NoSuchStorage)) => {
+                            warn!(%team_id, "skipping peer caches of missing team");
+                            self.caches.retain(|p, _| p.graph_id != graph_id);
+                            gone.push(team_id);
+                            continue 'teams;
+                        }
+                        Err(e) => {
+                            return Err(e)
+                                .with_context(|| format!("unable to restore peer cache of {addr}"))
+                        }
+                    }
+                    self.caches.insert(SyncPeer { addr, graph_id }, cache);
+                }
+            }
+        }
+        if let Some(dir) = &self.cache_dir {
+            for team_id in gone {
+                dir.remove(&team_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the peer caches of `graph_id` to disk, if they are saved.
+    async fn save_caches(&self, graph_id: GraphId) -> Result<()> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(());
+        };
+        let mut heads: Vec<_> = self
+            .caches
+            .iter()
+            .filter(|(peer, cache)| peer.graph_id == graph_id && !cache.heads().is_empty())
+            .map(|(peer, cache)| (peer.addr, cache.heads().to_vec()))
+            .collect();
+        heads.sort_by_key(|(addr, _)| addr.to_string());
+        let team_id = TeamId::from(graph_id.into_id());
+        dir.save(&team_id, &heads)
+            .await
+            .context("unable to save peer caches")
+    }
+
+    fn shared_health(&self) -> MutexGuard<'_, HashMap<SyncPeer, PeerHealth>> {
+        self.health.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records that the running sync with `peer` has received
+    /// `received` commands so far.
+    ///
+    /// This is cleared once the sync finishes.
+    fn report_progress(&self, peer: &SyncPeer, received: usize) {
+        let Some(info) = self.peers.get(peer) else {
+            return;
+        };
+        self.shared_health()
+            .entry(peer.clone())
+            .or_insert_with(|| info.health.clone())
+            .receiving = Some(received);
+    }
+
+    /// Records the result of a sync with `peer` that started at
+    /// `attempt`.
+    ///
+    /// Only peers added with [`SyncPeers::add_peer`] are tracked, not
+    /// ones that were only synced with using [`SyncPeers::sync_now`].
+    fn update_health(&mut self, peer: &SyncPeer, attempt: SystemTime, result: &SyncResult<usize>) {
+        let Some(info) = self.peers.get_mut(peer) else {
+            return;
+        };
+        let health = &mut info.health;
+        health.last_attempt = Some(attempt);
+        match result {
+            Ok(n) => {
+                health.last_success = Some(attempt);
+                health.consecutive_failures = 0;
+                health.last_commands = *n;
+            }
+            Err(err) => {
+                health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+                health.last_error = Some(err.to_string());
+                health.last_commands = 0;
+            }
+        }
+        let health = health.clone();
+        self.shared_health().insert(peer.clone(), health);
     }
 }
 
@@ -245,7 +734,14 @@ impl<ST: SyncState> Syncer<ST> {
                         self.sync(&peer).await?;
                     },
                     Msg::AddPeer { peer, cfg } => self.add_peer(peer, &cfg),
-                    Msg::RemovePeer { peer } => self.remove_peer(peer),
+                    Msg::RemovePeer { peer } => self.remove_peer(peer).await,
+                    Msg::RemoveTeam { graph_id } => self.remove_team(graph_id).await,
+                    Msg::Pushed { peer } => {
+                        // Ignore peers we no longer want hints from.
+                        if self.subscription(&peer).is_some() {
+                            self.schedule_push(&peer);
+                        }
+                    }
                 }
             }
             // get next peer from delay queue.
@@ -254,7 +750,15 @@ impl<ST: SyncState> Syncer<ST> {
                 let info = self.peers.get_mut(&peer).assume("peer must exist")?;
                 info.key = self.queue.insert(peer.clone(), info.interval);
                 // sync with peer.
-                self.sync(&peer).await?;
+                let result = self.sync(&peer).await;
+                // Wait longer before the next attempt if it failed. The
+                // peer is gone if its graph could not be finalized.
+                if let Some(info) = self.peers.get_mut(&peer) {
+                    if let Some(delay) = info.backoff_delay() {
+                        self.queue.reset(&info.key, delay);
+                    }
+                }
+                result?;
             }
         }
         Ok(())
@@ -266,8 +770,17 @@ impl<ST: SyncState> Syncer<ST> {
         trace!("syncing with peer");
         let effects: Vec<EF> = {
             let mut sink = VecSink::new();
-            if let Err(e) = <ST as SyncState>::sync_impl(self, peer.graph_id, &mut sink, &peer.addr)
-                .await
+            let attempt = SystemTime::now();
+            let result =
+                <ST as SyncState>::sync_impl(self, peer.graph_id, &mut sink, &peer.addr).await;
+            self.update_health(peer, attempt, &result);
+            // Received commands are the only thing that changes the cache.
+            if matches!(result, Ok(n) if n > 0) {
+                if let Err(e) = self.save_caches(peer.graph_id).await {
+                    error!(?e, "unable to save peer caches");
+                }
+            }
+            if let Err(e) = result
                 .context("sync_peer error")
                 .inspect_err(|err| error!("{err:?}"))
             {
@@ -283,6 +796,8 @@ impl<ST: SyncState> Syncer<ST> {
                         }
                         keep
                     });
+                    self.shared_health()
+                        .retain(|p, _| p.graph_id != peer.graph_id);
                     self.invalid.insert(peer.graph_id);
                 }
                 return Err(SyncError::Other(e));
@@ -299,3 +814,80 @@ impl<ST: SyncState> Syncer<ST> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    #[test(tokio::test)]
+    async fn test_backoff_delay() {
+        let interval = Duration::from_millis(100);
+        let mut queue = DelayQueue::new();
+        let mut info = PeerInfo {
+            interval,
+            key: queue.insert((), interval),
+            health: PeerHealth::default(),
+            backoff_cfg: SyncBackoff {
+                min_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(4),
+                factor: 2.0,
+                jitter: false,
+            },
+            backoff: None,
+            subscribe: false,
+            last_push: None,
+        };
+        let secs = Duration::from_secs;
+
+        // The interval is used while syncs succeed.
+        assert_eq!(info.backoff_delay(), None);
+
+        info.health.consecutive_failures = 1;
+        let delays: Vec<_> = (0..4).filter_map(|_| info.backoff_delay()).collect();
+        assert_eq!(delays, [secs(1), secs(2), secs(4), secs(4)]);
+
+        // A success resets the backoff.
+        info.health.consecutive_failures = 0;
+        assert_eq!(info.backoff_delay(), None);
+        info.health.consecutive_failures = 1;
+        assert_eq!(info.backoff_delay(), Some(secs(1)));
+
+        // Failing peers are never synced with more often than usual.
+        info.interval = secs(3);
+        info.backoff = None;
+        let delays: Vec<_> = (0..3).filter_map(|_| info.backoff_delay()).collect();
+        assert_eq!(delays, [secs(3), secs(3), secs(4)]);
+    }
+
+    #[test(tokio::test(start_paused = true))]
+    async fn test_push_deadline() {
+        let interval = Duration::from_secs(10);
+        let mut queue = DelayQueue::new();
+        let mut info = PeerInfo {
+            interval,
+            key: queue.insert((), interval),
+            health: PeerHealth::default(),
+            backoff_cfg: SyncBackoff::default(),
+            backoff: None,
+            subscribe: true,
+            last_push: None,
+        };
+        let now = Instant::now();
+
+        // A peer that was never synced with is synced with right away.
+        assert_eq!(info.push_deadline(now), Some(now));
+
+        // Later hints wait for the interval to pass.
+        info.last_push = Some(now);
+        let later = now + Duration::from_secs(3);
+        assert_eq!(info.push_deadline(later), Some(now + interval));
+        let much_later = now + Duration::from_secs(30);
+        assert_eq!(info.push_deadline(much_later), Some(much_later));
+
+        // Hints are ignored while backing off.
+        info.health.consecutive_failures = 1;
+        assert_eq!(info.push_deadline(much_later), None);
+    }
+}
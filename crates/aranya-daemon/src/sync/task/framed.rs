@@ -0,0 +1,298 @@
+//! Sync sessions over a byte stream, shared by the QUIC and TCP syncers.
+//!
+//! Messages are framed by a big-endian `u32` length. The server answers each sync request with
+//! one frame per sync response message, ending with an empty message, so only one message needs
+//! to be held in memory at a time. A sync that brings in new commands sends another request over
+//! the same stream, until it stops finding new commands.
+
+use anyhow::Context as _;
+use aranya_crypto::Rng;
+use aranya_runtime::{
+    Command as _, Engine, PeerCache, Sink, Storage as _, StorageProvider, SyncRequestMessage,
+    SyncRequester, SyncResponder, MAX_SYNC_MESSAGE_SIZE,
+};
+use buggy::{bug, BugExt as _};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tracing::{debug, info, instrument};
+
+use super::{SyncPeer, SyncResponse, Syncer};
+use crate::{
+    aranya::Client as AranyaClient,
+    sync::{Result as SyncResult, SyncError},
+};
+
+/// The largest frame a peer may send: one sync message along with the
+/// [`SyncResponse`] around it.
+const MAX_FRAME_SIZE: usize = MAX_SYNC_MESSAGE_SIZE + 64;
+
+/// The most sync sessions run over one stream before the rest of a sync
+/// is left to the next one.
+const MAX_SYNC_ROUNDS: usize = 16;
+
+/// Sends `data` as a frame: its length as a big-endian `u32` followed
+/// by the data itself.
+pub(super) async fn send_frame<W>(send: &mut W, data: &[u8]) -> SyncResult<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if data.len() > MAX_FRAME_SIZE {
+        bug!("frame is larger than MAX_FRAME_SIZE");
+    }
+    let len = u32::try_from(data.len()).assume("frame length fits in a u32")?;
+    let mut frame = Vec::with_capacity(data.len().saturating_add(4));
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(data);
+    send.write_all(&frame)
+        .await
+        .context("failed to write frame")?;
+    Ok(())
+}
+
+/// Serializes `msg` with postcard and sends it as a frame.
+pub(super) async fn write_frame<W, T>(send: &mut W, msg: &T) -> SyncResult<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let data = postcard::to_allocvec(msg).context("postcard unable to serialize frame")?;
+    send_frame(send, &data).await
+}
+
+/// Reads the next frame, or `None` if the peer finished sending.
+pub(super) async fn read_frame<R>(recv: &mut R) -> SyncResult<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = match recv.read_u32().await {
+        Ok(len) => usize::try_from(len).assume("u32 fits in usize")?,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context("failed to read frame")
+                .into())
+        }
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(anyhow::anyhow!("frame of {len} bytes is too large").into());
+    }
+    let mut buf = vec![0u8; len];
+    recv.read_exact(&mut buf)
+        .await
+        .context("failed to read frame")?;
+    Ok(Some(buf))
+}
+
+impl<ST> Syncer<ST> {
+    /// Syncs with `peer` over a stream, returning the number of commands
+    /// received.
+    ///
+    /// The sending half is shut down once the sync is done.
+    pub(super) async fn sync_stream<R, W, S>(
+        &mut self,
+        recv: &mut R,
+        send: &mut W,
+        peer: &SyncPeer,
+        sink: &mut S,
+    ) -> SyncResult<usize>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        S: Sink<<crate::EN as Engine>::Effect>,
+    {
+        // TODO: Real server address.
+        let server_addr = ();
+
+        let mut cache = self.caches.remove(peer).unwrap_or_default();
+        let result: SyncResult<usize> = async {
+            let mut received = 0;
+            // Each round only covers as many segments as a sync session
+            // can, so keep going while rounds bring in new commands.
+            for _ in 0..MAX_SYNC_ROUNDS {
+                let mut sync_requester = SyncRequester::new(peer.graph_id, &mut Rng, server_addr);
+
+                // send sync request.
+                self.send_sync_request(send, &mut sync_requester, &mut cache, peer)
+                    .await
+                    .map_err(|e| SyncError::SendSyncRequest(Box::new(e)))?;
+
+                // receive sync response.
+                let (n, advanced) = self
+                    .receive_sync_response(
+                        recv,
+                        &mut sync_requester,
+                        &mut cache,
+                        peer,
+                        received,
+                        sink,
+                    )
+                    .await
+                    .map_err(|e| SyncError::ReceiveSyncResponse(Box::new(e)))?;
+                received += n;
+                if !advanced {
+                    break;
+                }
+            }
+            send.shutdown().await.context("failed to close stream")?;
+            Ok(received)
+        }
+        .await;
+        self.caches.insert(peer.clone(), cache);
+        result
+    }
+
+    #[instrument(skip(self, send, syncer, cache))]
+    async fn send_sync_request<W, A>(
+        &self,
+        send: &mut W,
+        syncer: &mut SyncRequester<'_, A>,
+        cache: &mut PeerCache,
+        peer: &SyncPeer,
+    ) -> SyncResult<()>
+    where
+        W: AsyncWrite + Unpin,
+        A: Serialize + DeserializeOwned + Clone,
+    {
+        info!("client sending sync request to sync server");
+        let mut send_buf = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+
+        let (len, _) = {
+            let mut client = self.client.lock().await;
+            syncer
+                .poll(&mut send_buf, client.provider(), cache)
+                .context("sync poll failed")?
+        };
+        debug!(?len, "sync poll finished");
+        let request = send_buf.get(..len).assume("sync request fits in buffer")?;
+
+        send_frame(send, request).await?;
+        debug!(?peer, "sent sync request");
+
+        Ok(())
+    }
+
+    /// Receives and commits each message of a sync response as it
+    /// arrives.
+    ///
+    /// Returns the number of commands received and whether any of them
+    /// were new. `progress` is the number of commands received by
+    /// earlier rounds of the same sync.
+    #[instrument(skip(self, recv, syncer, cache, sink))]
+    async fn receive_sync_response<R, S, A>(
+        &self,
+        recv: &mut R,
+        syncer: &mut SyncRequester<'_, A>,
+        cache: &mut PeerCache,
+        peer: &SyncPeer,
+        progress: usize,
+        sink: &mut S,
+    ) -> SyncResult<(usize, bool)>
+    where
+        R: AsyncRead + Unpin,
+        S: Sink<<crate::EN as Engine>::Effect>,
+        A: Serialize + DeserializeOwned + Clone,
+    {
+        info!("client receiving sync response from sync server");
+
+        let id = &peer.graph_id;
+        let mut n = 0;
+        let mut advanced = false;
+        loop {
+            let frame = read_frame(recv)
+                .await?
+                .context("stream closed before sync response ended")?;
+            debug!(?peer, n = frame.len(), "received sync response message");
+
+            // process the sync response.
+            let resp = postcard::from_bytes(&frame)
+                .context("postcard unable to deserialize sync response")?;
+            let data = match resp {
+                SyncResponse::Ok(data) => data,
+                SyncResponse::Err(msg) => return Err(anyhow::anyhow!("sync error: {msg}").into()),
+            };
+            // An empty message ends the response.
+            if data.is_empty() {
+                break;
+            }
+            let Some(cmds) = syncer.receive(&data)? else {
+                continue;
+            };
+            debug!(num = cmds.len(), "received commands");
+            if cmds.is_empty() {
+                continue;
+            }
+            n += cmds.len();
+            let mut client = self.client.lock().await;
+            let old_head = client
+                .provider()
+                .get_storage(*id)
+                .and_then(|s| s.get_head());
+            let mut trx = client.transaction(*id);
+            client
+                .add_commands(&mut trx, sink, &cmds)
+                .context("unable to add received commands")?;
+            client.commit(&mut trx, sink).context("commit failed")?;
+            let new_head = client
+                .provider()
+                .get_storage(*id)
+                .and_then(|s| s.get_head());
+            advanced |= old_head.ok() != new_head.ok();
+            // The peer has every command it sent.
+            client
+                .update_heads(*id, cmds.iter().filter_map(|cmd| cmd.address().ok()), cache)
+                .context("unable to update peer heads")?;
+            drop(client);
+            debug!("committed");
+            self.report_progress(peer, progress + n);
+        }
+        if n == 0 {
+            debug!("nothing to sync");
+        }
+        // Only announce commands we didn't already have, so that peers
+        // subscribed to each other don't keep hinting.
+        if advanced {
+            self.client.committed(*id);
+        }
+
+        Ok((n, advanced))
+    }
+}
+
+/// Streams the sync response for a poll request, one message per
+/// frame, followed by an empty message.
+///
+/// Only one message is held in memory at a time.
+pub(super) async fn poll_respond<EN, SP, W>(
+    client: &AranyaClient<EN, SP>,
+    request_msg: SyncRequestMessage,
+    cache: &mut PeerCache,
+    send: &mut W,
+) -> SyncResult<()>
+where
+    SP: StorageProvider,
+    W: AsyncWrite + Unpin,
+{
+    info!("server responding to sync request");
+
+    // TODO: Use real server address
+    let server_address = ();
+    let mut resp = SyncResponder::new(server_address);
+
+    resp.receive(request_msg).context("sync recv failed")?;
+
+    let mut buf = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+    let mut sent = 0usize;
+    loop {
+        let len = resp
+            .poll(&mut buf, client.lock().await.provider(), cache)
+            .context("sync resp poll failed")?;
+        let data = buf.get(..len).assume("sync message fits in buffer")?;
+        write_frame(send, &SyncResponse::Ok(data.into())).await?;
+        if len == 0 {
+            break;
+        }
+        sent += 1;
+    }
+    debug!(messages = sent, "sync poll finished");
+    Ok(())
+}
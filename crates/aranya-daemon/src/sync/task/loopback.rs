@@ -0,0 +1,485 @@
+//! In-process client and server for syncing Aranya graph commands.
+//!
+//! Daemons attached to the same [`Network`] sync with each other without opening any sockets.
+//! Each sync connects to the peer's [`Server`] over an in-memory pipe, and messages on the pipe
+//! are framed the same way as for the QUIC and TCP syncers.
+//!
+//! The network can partition daemons from each other, delay their syncs and lose them. Lost
+//! syncs are picked by a seeded RNG, so a test that runs its syncs in the same order sees the
+//! same faults on every run.
+//!
+//! Connections are not authenticated, so this is only meant for tests.
+
+use core::net::{Ipv4Addr, SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as SyncMutex, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use aranya_daemon_api::SyncTransport;
+use aranya_runtime::{Engine, GraphId, PeerCache, Sink, StorageProvider, SyncType};
+use aranya_util::Addr;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt as _, DuplexStream, ReadBuf},
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+use tracing::{debug, error, info, instrument};
+
+use super::{
+    framed::{poll_respond, read_frame, write_frame},
+    SyncPeer, SyncResponse, SyncState, Syncer,
+};
+use crate::{aranya::Client as AranyaClient, sync::Result as SyncResult};
+
+/// The size of the buffer in each direction of a connection.
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// Errors specific to the loopback syncer
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// No server is attached to the network at the peer's address.
+    #[error("no sync server at the peer's address")]
+    Unreachable,
+    /// The network has partitioned the peer from this daemon.
+    #[error("partitioned from the peer")]
+    Partitioned,
+    /// The network lost the sync.
+    #[error("sync was lost")]
+    Lost,
+}
+
+/// An in-memory network that daemons sync over.
+///
+/// Cloning a `Network` returns another handle to the same network.
+#[derive(Clone)]
+pub struct Network {
+    inner: Arc<SyncMutex<Inner>>,
+    /// Counts the syncs that have finished, whether or not they
+    /// succeeded.
+    finished: Arc<watch::Sender<u64>>,
+    /// Counts the bytes that syncs have sent and received.
+    transferred: Arc<AtomicU64>,
+}
+
+struct Inner {
+    /// Sends incoming connections to the server at each address.
+    servers: HashMap<Addr, mpsc::Sender<DuplexStream>>,
+    /// The port of the next address handed out.
+    next_port: u16,
+    /// Decides which syncs are lost.
+    rng: SplitMix64,
+    /// Links that can't be used.
+    partitions: HashSet<(Addr, Addr)>,
+    /// How long syncs over each link take to connect.
+    delays: HashMap<(Addr, Addr), Duration>,
+    /// The chance of losing each sync.
+    loss: f64,
+}
+
+/// Returns the key for the link between `a` and `b`, which is the same
+/// in both directions.
+fn link(a: Addr, b: Addr) -> (Addr, Addr) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+impl Network {
+    /// Creates an empty network whose random choices are seeded with
+    /// `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(SyncMutex::new(Inner {
+                servers: HashMap::new(),
+                next_port: 1,
+                rng: SplitMix64(seed),
+                partitions: HashSet::new(),
+                delays: HashMap::new(),
+                loss: 0.0,
+            })),
+            finished: Arc::new(watch::Sender::new(0)),
+            transferred: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stops syncs between `a` and `b` in both directions until the
+    /// network is healed.
+    pub fn partition(&self, a: Addr, b: Addr) {
+        self.lock().partitions.insert(link(a, b));
+    }
+
+    /// Removes every partition.
+    pub fn heal(&self) {
+        self.lock().partitions.clear();
+    }
+
+    /// Delays each sync between `a` and `b` by `delay`.
+    pub fn set_delay(&self, a: Addr, b: Addr, delay: Duration) {
+        self.lock().delays.insert(link(a, b), delay);
+    }
+
+    /// Loses each sync with a chance of `loss`, between 0 and 1.
+    pub fn set_loss(&self, loss: f64) {
+        self.lock().loss = loss.clamp(0.0, 1.0);
+    }
+
+    /// Returns a number below `n` from the network's seeded RNG, so
+    /// that a test can make its own choices from the same seed.
+    ///
+    /// Returns 0 if `n` is 0.
+    pub fn random(&self, n: usize) -> usize {
+        let n = u64::try_from(n).unwrap_or(u64::MAX);
+        let r = self.lock().rng.next_u64().checked_rem(n).unwrap_or(0);
+        usize::try_from(r).unwrap_or(0)
+    }
+
+    /// Returns a receiver of the number of syncs that have finished
+    /// over the network, whether or not they succeeded.
+    pub fn finished(&self) -> watch::Receiver<u64> {
+        self.finished.subscribe()
+    }
+
+    /// Returns the number of bytes that syncs have sent and received
+    /// over the network so far.
+    pub fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    /// Attaches a server to the network at a new address.
+    fn bind(&self) -> (Addr, mpsc::Receiver<DuplexStream>) {
+        let mut inner = self.lock();
+        let addr = Addr::from((Ipv4Addr::LOCALHOST, inner.next_port));
+        inner.next_port = inner.next_port.wrapping_add(1);
+        let (send, recv) = mpsc::channel(16);
+        inner.servers.insert(addr, send);
+        (addr, recv)
+    }
+
+    /// Opens a connection from `from` to the server at `to`.
+    async fn connect(&self, from: Addr, to: Addr) -> Result<Counted, Error> {
+        let (server, delay) = {
+            let mut inner = self.lock();
+            if inner.partitions.contains(&link(from, to)) {
+                return Err(Error::Partitioned);
+            }
+            // Draw even when nothing is lost, so that changing the loss
+            // doesn't change the later draws.
+            let roll = inner.rng.next_f64();
+            if roll < inner.loss {
+                return Err(Error::Lost);
+            }
+            let server = inner.servers.get(&to).cloned().ok_or(Error::Unreachable)?;
+            let delay = inner.delays.get(&link(from, to)).copied();
+            (server, delay)
+        };
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        let (client, server_end) = tokio::io::duplex(PIPE_SIZE);
+        server
+            .send(server_end)
+            .await
+            .map_err(|_| Error::Unreachable)?;
+        Ok(Counted {
+            inner: client,
+            bytes: Arc::clone(&self.transferred),
+        })
+    }
+}
+
+/// The client end of a connection, which counts the bytes it sends and
+/// receives.
+struct Counted {
+    inner: DuplexStream,
+    bytes: Arc<AtomicU64>,
+}
+
+impl Counted {
+    fn count<T>(&self, poll: &Poll<io::Result<T>>, n: usize) {
+        if matches!(poll, Poll::Ready(Ok(_))) {
+            let n = u64::try_from(n).unwrap_or(u64::MAX);
+            self.bytes.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+}
+
+impl AsyncRead for Counted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.count(&poll, buf.filled().len().saturating_sub(before));
+        poll
+    }
+}
+
+impl AsyncWrite for Counted {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.count(&poll, n);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A small seeded RNG, so runs can be repeated.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fit in an `f64` exactly.
+        #[allow(clippy::cast_precision_loss)]
+        let r = (self.next_u64() >> 11) as f64;
+        r * (1.0 / 9_007_199_254_740_992.0)
+    }
+}
+
+/// Loopback syncer state used for sending sync requests and processing sync responses
+pub struct State {
+    /// The network to sync over.
+    network: Network,
+    /// The address of this daemon's server.
+    addr: Addr,
+}
+
+impl State {
+    /// Creates a new instance for the daemon whose server is at `addr`.
+    pub fn new(network: Network, addr: Addr) -> Self {
+        Self { network, addr }
+    }
+}
+
+impl SyncState for State {
+    // Tests sync with every peer over the in-memory network.
+    const TRANSPORT: Option<SyncTransport> = None;
+
+    #[allow(clippy::manual_async_fn)]
+    #[instrument(skip(syncer, sink))]
+    /// Syncs with the peer over a new connection.
+    fn sync_impl<S>(
+        syncer: &mut Syncer<Self>,
+        id: GraphId,
+        sink: &mut S,
+        peer: &Addr,
+    ) -> impl Future<Output = SyncResult<usize>> + Send
+    where
+        S: Sink<<crate::EN as Engine>::Effect> + Send,
+    {
+        async move {
+            let result = async {
+                let stream = syncer
+                    .state
+                    .network
+                    .connect(syncer.state.addr, *peer)
+                    .await?;
+                let (mut recv, mut send) = tokio::io::split(stream);
+
+                let sync_peer = SyncPeer {
+                    addr: *peer,
+                    graph_id: id,
+                };
+                syncer
+                    .sync_stream(&mut recv, &mut send, &sync_peer, sink)
+                    .await
+            }
+            .await;
+            syncer.state.network.finished.send_modify(|n| *n += 1);
+            result
+        }
+    }
+}
+
+/// The Aranya loopback sync server.
+/// Used to listen for incoming `SyncRequests` and respond with `SyncResponse` when they are received.
+pub struct Server<EN, SP> {
+    /// Thread-safe Aranya client reference.
+    aranya: AranyaClient<EN, SP>,
+    /// The server's address on the network.
+    addr: Addr,
+    /// Receives connections from peers.
+    conns: mpsc::Receiver<DuplexStream>,
+    /// Tracks running tasks.
+    set: JoinSet<()>,
+}
+
+impl<EN, SP> Server<EN, SP> {
+    /// Returns the address the sync server is attached at.
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, self.addr.port())))
+    }
+}
+
+impl<EN, SP> Server<EN, SP>
+where
+    EN: Engine + Send + 'static,
+    SP: StorageProvider + Send + Sync + 'static,
+{
+    /// Creates a new `Server` attached to `network` at a new address.
+    pub fn new(aranya: AranyaClient<EN, SP>, network: &Network) -> Self {
+        let (addr, conns) = network.bind();
+        Self {
+            aranya,
+            addr,
+            conns,
+            set: JoinSet::new(),
+        }
+    }
+
+    /// Begins accepting incoming requests.
+    #[instrument(skip_all)]
+    pub async fn serve(mut self) {
+        info!(addr = %self.addr, "loopback sync server listening for incoming connections");
+
+        while let Some(stream) = self.conns.recv().await {
+            debug!("received incoming loopback connection");
+            while self.set.try_join_next().is_some() {}
+
+            let client = self.aranya.clone();
+            self.set.spawn(async move {
+                if let Err(e) = Self::sync(client, stream).await {
+                    error!(?e, "server unable to sync with peer");
+                }
+            });
+        }
+    }
+
+    /// Responds to each sync request on a connection until the peer
+    /// finishes sending.
+    async fn sync(client: AranyaClient<EN, SP>, stream: DuplexStream) -> SyncResult<()> {
+        let (mut recv, mut send) = tokio::io::split(stream);
+        // The heads the peer is known to have, for later rounds of the
+        // same sync.
+        let mut cache = PeerCache::default();
+        while let Some(request) = read_frame(&mut recv).await? {
+            let res = async {
+                let request: SyncType<()> =
+                    postcard::from_bytes(&request).map_err(|e| anyhow::anyhow!(e))?;
+                match request {
+                    SyncType::Poll {
+                        request,
+                        address: (),
+                    } => poll_respond(&client, request, &mut cache, &mut send).await,
+                    SyncType::Subscribe { .. } | SyncType::Unsubscribe { .. } => {
+                        Err(anyhow::anyhow!("subscriptions are not supported over loopback").into())
+                    }
+                    SyncType::Push { .. } => {
+                        Err(anyhow::anyhow!("pushes are only sent to subscribers").into())
+                    }
+                }
+            }
+            .await;
+            if let Err(err) = res {
+                error!(?err, "error responding to sync request");
+                write_frame(&mut send, &SyncResponse::Err(format!("{err:?}"))).await?;
+                break;
+            }
+        }
+        // The peer may already be gone.
+        let _ = send.shutdown().await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+
+    use test_log::test;
+
+    use super::*;
+
+    #[test]
+    fn test_random_is_seeded() {
+        let draws = |seed| {
+            let network = Network::new(seed);
+            (0..16).map(|_| network.random(100)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+    }
+
+    #[test(tokio::test)]
+    async fn test_connect_faults() {
+        let network = Network::new(0);
+        let (a, _a_conns) = network.bind();
+        let (b, mut b_conns) = network.bind();
+
+        network.connect(a, b).await.expect("b should be reachable");
+        assert!(b_conns.recv().await.is_some());
+
+        // Partitions apply in both directions.
+        network.partition(b, a);
+        let res = network.connect(a, b).await;
+        assert!(matches!(res, Err(Error::Partitioned)));
+        network.heal();
+
+        network.set_loss(1.0);
+        let res = network.connect(a, b).await;
+        assert!(matches!(res, Err(Error::Lost)));
+        network.set_loss(0.0);
+
+        let c = Addr::from((Ipv4Addr::LOCALHOST, 999));
+        let res = network.connect(a, c).await;
+        assert!(matches!(res, Err(Error::Unreachable)));
+    }
+
+    #[test(tokio::test)]
+    async fn test_transferred() {
+        use tokio::io::AsyncReadExt as _;
+
+        let network = Network::new(0);
+        let (a, _a_conns) = network.bind();
+        let (b, mut b_conns) = network.bind();
+
+        let mut client = network.connect(a, b).await.expect("b should be reachable");
+        let mut server = b_conns.recv().await.expect("should accept");
+        client.write_all(b"hello").await.expect("should write");
+        server.write_all(b"hey").await.expect("should write");
+        let mut buf = [0u8; 3];
+        client.read_exact(&mut buf).await.expect("should read");
+
+        // Only the client's end is counted, in both directions.
+        assert_eq!(network.transferred(), 8);
+    }
+}
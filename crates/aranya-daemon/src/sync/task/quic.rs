@@ -3,64 +3,103 @@
 //! The QUIC connections are secured with a rustls PSK.
 //! A different PSK will be used for each Aranya team.
 //!
-//! If a QUIC connection does not exist with a certain peer, a new QUIC connection will be created.
-//! Each sync request/response will use a single QUIC stream which is closed after the sync completes.
+//! If a QUIC connection does not exist with a certain peer and team, a new QUIC connection will be
+//! created. Each sync uses a single QUIC stream which is closed after the sync completes. Messages
+//! on the stream are framed the same way as for the TCP syncer.
+//!
+//! The server binds each connection to the team of the PSK that was negotiated on it, and rejects
+//! requests for any other team.
+//!
+//! Once the grace period of a rotated seed ends, the server closes connections made with its PSKs.
+//! Until the team's seed is rotated again, those PSKs can still be used to fetch the rotation over a
+//! connection with a separate ALPN protocol, so that peers that missed it can catch up.
+//! Peers that missed more than one rotation have to be given the current seed again.
+//!
+//! A peer can also subscribe to a team over its connection. After new commands are committed to
+//! the team, the server opens a unidirectional stream to each subscriber and pushes a hint that
+//! its heads changed, which the subscriber answers by syncing.
 
 use core::net::SocketAddr;
 use std::{
-    collections::{btree_map::Entry, BTreeMap},
+    collections::{btree_map::Entry, BTreeMap, HashMap},
     future::Future,
     net::Ipv4Addr,
-    sync::{Arc, Mutex as SyncMutex},
+    sync::{Arc, Mutex as SyncMutex, PoisonError},
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use aranya_crypto::Rng;
-use aranya_daemon_api::TeamId;
+use aranya_crypto::{DeviceId, EncryptionPublicKey};
+use aranya_daemon_api::{SyncTransport, TeamId, WrappedSeed};
 use aranya_runtime::{
-    Engine, GraphId, PeerCache, Sink, StorageProvider, SyncRequestMessage, SyncRequester,
-    SyncResponder, SyncType, MAX_SYNC_MESSAGE_SIZE,
+    CommandId, Engine, GraphId, PeerCache, Sink, Storage, StorageProvider, SyncRequestMessage,
+    SyncResponseMessage, SyncType,
 };
 use aranya_util::{
-    rustls::{NoCertResolver, SkipServerVerification},
+    rustls::{NoCertResolver, NoCertVerifier},
     Addr,
 };
 use buggy::{bug, BugExt as _};
 use bytes::Bytes;
 #[allow(deprecated)]
 use s2n_quic::provider::tls::rustls::rustls::{
-    server::PresharedKeySelection, ClientConfig, ServerConfig,
+    client::PskKexMode, server::PresharedKeySelection, ClientConfig, ServerConfig,
 };
 use s2n_quic::{
     client::Connect,
-    connection::Error as ConnErr,
-    provider::{
-        congestion_controller::Bbr,
-        tls::rustls::{self as rustls_provider, rustls::server::SelectsPresharedKeys},
-    },
-    stream::{BidirectionalStream, ReceiveStream, SendStream},
+    connection::{Error as ConnErr, Handle, StreamAcceptor},
+    provider::{congestion_controller::Bbr, tls::rustls as rustls_provider},
+    stream::{BidirectionalStream, SendStream},
     Client as QuicClient, Connection, Server as QuicServer,
 };
-use serde::{de::DeserializeOwned, Serialize};
-use tokio::{io::AsyncReadExt, sync::mpsc, task::JoinSet};
-use tracing::{debug, error, info, instrument};
+use tokio::{
+    io::AsyncReadExt,
+    sync::{broadcast, mpsc},
+    task::JoinSet,
+};
+use tracing::{debug, error, info, instrument, warn};
 
-use super::SyncResponse;
+use super::{
+    framed::{poll_respond, read_frame, send_frame, write_frame},
+    SyncResponse,
+};
 use crate::{
+    actions::Actions as _,
     aranya::Client as AranyaClient,
+    policy::{Effect, Role, SyncSeedRotated},
     sync::{
-        task::{SyncState, Syncer},
+        task::{Msg, SyncPeer, SyncState, Syncer},
         Result as SyncResult, SyncError,
     },
+    CS,
 };
 
 mod psk;
 
-pub(crate) use psk::PskSeed;
 pub use psk::PskStore;
+pub(crate) use psk::{aqc_ctrl_epoch, take_chosen_team, PskSeed, DEFAULT_SEED_GRACE};
+use psk::{ConnTeam, TeamBinder};
 
 /// ALPN protocol identifier for Aranya QUIC sync.
-const ALPN_QUIC_SYNC: &[u8] = b"quic-sync-unstable";
+const ALPN_QUIC_SYNC: &[u8] = b"quic-sync-unstable-2";
+
+/// ALPN protocol identifier for fetching the rotation of a team's
+/// seed with the PSKs of the seed it retired.
+const ALPN_SEED_RECOVERY: &[u8] = b"quic-sync-seed-recovery-unstable-1";
+
+/// The longest a peer can subscribe for at once.
+const MAX_SUBSCRIPTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Application error code for connections closed because their PSK
+/// was retired.
+const RETIRED_PSK_ERROR_CODE: u32 = 1;
+
+/// Application error code for seed recovery connections whose PSK
+/// did not retire a seed.
+const NO_SEED_ROTATION_ERROR_CODE: u32 = 2;
+
+/// How long fetching a seed rotation can take.
+const SEED_RECOVERY_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Errors specific to the QUIC syncer
 #[derive(Debug, thiserror::Error)]
@@ -89,14 +128,21 @@ pub enum Error {
 pub struct State {
     /// QUIC client to make sync requests to another peer's sync server and handle sync responses.
     client: QuicClient,
-    /// Address -> Connection map to lookup existing connections before creating a new connection.
-    conns: BTreeMap<Addr, Connection>,
+    /// QUIC client to fetch seed rotations that this device missed.
+    recovery: QuicClient,
+    /// (Address, Graph) -> Connection map to lookup existing connections before creating a new
+    /// connection. Each connection is bound to the team of the PSK it was made with.
+    conns: BTreeMap<(Addr, GraphId), Handle>,
     /// PSK store shared between the daemon API server and QUIC syncer client and server.
     /// This store is modified by [`crate::api::DaemonApiServer`].
     store: Arc<PskStore>,
+    /// Receives the hints pushed over each connection.
+    tasks: JoinSet<()>,
 }
 
 impl SyncState for State {
+    const TRANSPORT: Option<SyncTransport> = Some(SyncTransport::Quic);
+
     #[allow(clippy::manual_async_fn)]
     #[instrument(skip(syncer, sink))]
     /// Syncs with the peer.
@@ -106,7 +152,7 @@ impl SyncState for State {
         id: GraphId,
         sink: &mut S,
         peer: &Addr,
-    ) -> impl Future<Output = SyncResult<()>> + Send
+    ) -> impl Future<Output = SyncResult<usize>> + Send
     where
         S: Sink<<crate::EN as Engine>::Effect> + Send,
     {
@@ -115,29 +161,41 @@ impl SyncState for State {
             syncer.state.store.set_team(id.into_id().into());
 
             let stream = syncer
-                .connect(peer)
+                .connect(peer, id)
                 .await
                 .inspect_err(|e| error!("Could not create connection: {e}"))?;
             // TODO: spawn a task for send/recv?
             let (mut recv, mut send) = stream.split();
 
-            // TODO: Real server address.
-            let server_addr = ();
-            let mut sync_requester = SyncRequester::new(id, &mut Rng, server_addr);
-
-            // send sync request.
-            syncer
-                .send_sync_request(&mut send, &mut sync_requester, peer)
+            let sync_peer = SyncPeer {
+                addr: *peer,
+                graph_id: id,
+            };
+            let n = match syncer
+                .sync_stream(&mut recv, &mut send, &sync_peer, sink)
                 .await
-                .map_err(|e| SyncError::SendSyncRequest(Box::new(e)))?;
+            {
+                Ok(n) => n,
+                Err(e) => {
+                    if syncer.psk_retired(peer, id) {
+                        info!(?peer, "PSK was retired, fetching the seed rotation");
+                        if let Err(e) = syncer.recover_seed(peer, id).await {
+                            warn!(?peer, %e, "unable to fetch the seed rotation");
+                        }
+                    }
+                    return Err(e);
+                }
+            };
 
-            // receive sync response.
-            syncer
-                .receive_sync_response(&mut recv, &mut sync_requester, &id, sink, peer)
-                .await
-                .map_err(|e| SyncError::ReceiveSyncResponse(Box::new(e)))?;
+            if let Some(remain_open) = syncer.subscription(&sync_peer) {
+                // Polling still works if the peer can't push, so this
+                // doesn't fail the sync.
+                if let Err(e) = syncer.subscribe(peer, id, remain_open).await {
+                    warn!(?peer, %e, "unable to subscribe to peer");
+                }
+            }
 
-            Ok(())
+            Ok(n)
         }
     }
 }
@@ -146,14 +204,29 @@ impl State {
     /// Creates a new instance
     pub fn new(psk_store: Arc<PskStore>) -> SyncResult<Self>
 where {
-        // Create client config (INSECURE: skips server cert verification)
+        Ok(Self {
+            client: Self::start_client(&psk_store, ALPN_QUIC_SYNC)?,
+            recovery: Self::start_client(&psk_store, ALPN_SEED_RECOVERY)?,
+            conns: BTreeMap::new(),
+            store: psk_store,
+            tasks: JoinSet::new(),
+        })
+    }
+
+    /// Starts a QUIC client that uses the PSKs in `psk_store` and
+    /// the `alpn` protocol.
+    fn start_client(psk_store: &Arc<PskStore>, alpn: &[u8]) -> SyncResult<QuicClient> {
+        // Create client config (PSKs only: every server certificate is rejected)
         let mut client_config = ClientConfig::builder()
             .dangerous()
-            .with_custom_certificate_verifier(SkipServerVerification::new())
+            .with_custom_certificate_verifier(NoCertVerifier::new())
             .with_no_client_auth();
-        client_config.alpn_protocols = vec![ALPN_QUIC_SYNC.to_vec()]; // Set field directly
+        client_config.alpn_protocols = vec![alpn.to_vec()]; // Set field directly
         client_config.preshared_keys = psk_store.clone(); // Pass the Arc<ClientPresharedKeys>
 
+        // Use (EC)DHE with the PSK for forward secrecy.
+        client_config.psk_kex_modes = vec![PskKexMode::PskWithDhe];
+
         // Client builder doesn't support adding preshared keys
         #[allow(deprecated)]
         let provider = rustls_provider::Client::new(client_config);
@@ -169,24 +242,21 @@ where {
             .context("can't start quic client")
             .map_err(Error::ClientConfig)?;
 
-        Ok(Self {
-            client,
-            conns: BTreeMap::new(),
-            store: psk_store,
-        })
+        Ok(client)
     }
 }
 
 impl Syncer<State> {
     #[instrument(skip(self))]
-    async fn connect(&mut self, peer: &Addr) -> SyncResult<BidirectionalStream> {
+    async fn connect(&mut self, peer: &Addr, id: GraphId) -> SyncResult<BidirectionalStream> {
         info!(?peer, "client connecting to QUIC sync server");
         // Check if there is an existing connection with the peer.
         // If not, create a new connection.
         let conns = &mut self.state.conns;
         let client = &self.state.client;
+        let tasks = &mut self.state.tasks;
 
-        let conn = match conns.entry(*peer) {
+        let conn = match conns.entry((*peer, id)) {
             Entry::Occupied(entry) => {
                 info!("Client is able to re-use existing QUIC connection");
                 entry.into_mut()
@@ -209,14 +279,15 @@ impl Syncer<State> {
 
                 conn.keep_alive(true).map_err(Error::from)?;
                 debug!(?peer, "created new quic connection");
-                entry.insert(conn)
+                let (handle, acceptor) = conn.split();
+                tasks.spawn(receive_pushes(acceptor, *peer, self.send.clone()));
+                entry.insert(handle)
             }
         };
 
         info!("client connected to QUIC sync server");
 
         let open_stream_res = conn
-            .handle()
             .open_bidirectional_stream()
             .await
             .inspect_err(|e| error!(?peer, "unable to open bidi stream: {}", e));
@@ -230,7 +301,7 @@ impl Syncer<State> {
             }
             // Other errors means the stream has closed
             Err(e) => {
-                conns.remove(peer);
+                conns.remove(&(*peer, id));
                 return Err(SyncError::QuicSync(e.into()));
             }
         };
@@ -239,94 +310,192 @@ impl Syncer<State> {
         Ok(stream)
     }
 
-    #[instrument(skip(self, syncer))]
-    async fn send_sync_request<A>(
-        &self,
-        send: &mut SendStream,
-        syncer: &mut SyncRequester<'_, A>,
-        peer: &Addr,
-    ) -> SyncResult<()>
-    where
-        A: Serialize + DeserializeOwned + Clone,
-    {
-        info!("client sending sync request to QUIC sync server");
-        let mut send_buf = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
-
-        let (len, _) = {
-            let mut client = self.client.lock().await;
-            // TODO: save PeerCache somewhere.
-            syncer
-                .poll(&mut send_buf, client.provider(), &mut PeerCache::new())
-                .context("sync poll failed")?
+    /// Reports whether `peer` closed the connection for `id` because
+    /// its PSK was retired, forgetting the connection if so.
+    fn psk_retired(&mut self, peer: &Addr, id: GraphId) -> bool {
+        let key = (*peer, id);
+        let Some(handle) = self.state.conns.get_mut(&key) else {
+            return false;
         };
-        debug!(?len, "sync poll finished");
-        send_buf.truncate(len);
+        match handle.ping() {
+            Err(ConnErr::Application { error, .. }) if error == RETIRED_PSK_ERROR_CODE.into() => {
+                self.state.conns.remove(&key);
+                true
+            }
+            _ => false,
+        }
+    }
 
-        send.send(Bytes::from(send_buf))
+    /// Fetches the rotation of `id`'s seed from `peer` with the
+    /// PSKs of the seed it retired, and hands it to the daemon to
+    /// install.
+    #[instrument(skip(self))]
+    async fn recover_seed(&mut self, peer: &Addr, id: GraphId) -> SyncResult<()> {
+        let addr = tokio::net::lookup_host(peer.to_socket_addrs())
             .await
-            .map_err(Error::from)?;
-        send.close().await.map_err(Error::from)?;
-        debug!(?peer, "sent sync request");
+            .context("DNS lookup on for peer address")?
+            .next()
+            .assume("invalid peer address")?;
+        let client = &self.state.recovery;
+        let data = tokio::time::timeout(SEED_RECOVERY_TIMEOUT, async {
+            let mut conn = client
+                .connect(Connect::new(addr).with_server_name("127.0.0.1"))
+                .await
+                .map_err(Error::from)?;
+            let stream = conn
+                .open_bidirectional_stream()
+                .await
+                .map_err(Error::from)?;
+            let (mut recv, mut send) = stream.split();
+            send.close().await.map_err(Error::from)?;
+            read_frame(&mut recv).await
+        })
+        .await
+        .context("timed out fetching seed rotation")??
+        .context("stream closed before seed rotation")?;
+        let rotation: SyncSeedRotated =
+            postcard::from_bytes(&data).context("postcard unable to deserialize seed rotation")?;
+        self.check_rotation(id, &rotation).await?;
+        debug!(?peer, "fetched seed rotation");
+
+        self.send_effects
+            .send((id, vec![Effect::SyncSeedRotated(rotation)]))
+            .await
+            .context("unable to send seed rotation")?;
+        Ok(())
+    }
+
+    /// Checks that `rotation` was made by an owner or admin of `id`,
+    /// who wrapped every seed in it.
+    ///
+    /// Any device that had the retired seed can send a rotation, so
+    /// unlike the ones in the graph, it has to be checked.
+    async fn check_rotation(&self, id: GraphId, rotation: &SyncSeedRotated) -> anyhow::Result<()> {
+        let author = DeviceId::from(rotation.author_id);
+
+        let (_ctrl, effects) = self
+            .client
+            .actions(&id)
+            .query_device_role_off_graph(author)
+            .await
+            .context("unable to query seed rotation author's role")?;
+        let role = effects.into_iter().find_map(|e| match e {
+            Effect::QueryDeviceRoleResult(e) => Some(e.role),
+            _ => None,
+        });
+        if !matches!(role, Some(Role::Owner | Role::Admin)) {
+            anyhow::bail!("seed rotation author is not an owner or admin");
+        }
 
+        let (_ctrl, effects) = self
+            .client
+            .actions(&id)
+            .query_device_keybundle_off_graph(author)
+            .await
+            .context("unable to query seed rotation author's keybundle")?;
+        let keys = effects
+            .into_iter()
+            .find_map(|e| match e {
+                Effect::QueryDeviceKeyBundleResult(e) => Some(e.device_keys),
+                _ => None,
+            })
+            .context("unable to query seed rotation author's keybundle")?;
+        let author_pk: EncryptionPublicKey<CS> =
+            postcard::from_bytes(&keys.enc_key).context("bad author enc key")?;
+        let author_pk_id = author_pk.id()?;
+
+        let wrapped_seeds: Vec<(aranya_daemon_api::DeviceId, WrappedSeed)> =
+            postcard::from_bytes(&rotation.wrapped_seeds).context("invalid wrapped seeds")?;
+        for (_, wrapped) in &wrapped_seeds {
+            if wrapped.sender_pk.id()? != author_pk_id {
+                anyhow::bail!("seed was not wrapped by the seed rotation author");
+            }
+        }
         Ok(())
     }
 
-    #[instrument(skip(self, syncer, sink))]
-    async fn receive_sync_response<S, A>(
-        &self,
-        recv: &mut ReceiveStream,
-        syncer: &mut SyncRequester<'_, A>,
-        id: &GraphId,
-        sink: &mut S,
+    /// Asks `peer` to push hints about new commands in `id` for
+    /// `remain_open`.
+    #[instrument(skip(self))]
+    async fn subscribe(
+        &mut self,
         peer: &Addr,
-    ) -> SyncResult<()>
-    where
-        S: Sink<<crate::EN as Engine>::Effect>,
-        A: Serialize + DeserializeOwned + Clone,
-    {
-        info!("client receiving sync response from QUIC sync server");
+        id: GraphId,
+        remain_open: Duration,
+    ) -> SyncResult<()> {
+        let stream = self.connect(peer, id).await?;
+        let (mut recv, mut send) = stream.split();
 
-        let mut recv_buf = Vec::new();
-        recv.read_to_end(&mut recv_buf)
-            .await
-            .context("failed to read sync response")?;
-        debug!(?peer, n = recv_buf.len(), "received sync response");
-
-        // process the sync response.
-        let resp = postcard::from_bytes(&recv_buf)
-            .context("postcard unable to deserialize sync response")?;
-        let data = match resp {
-            SyncResponse::Ok(data) => data,
-            SyncResponse::Err(msg) => return Err(anyhow::anyhow!("sync error: {msg}").into()),
+        let request: SyncType<()> = SyncType::Subscribe {
+            remain_open: remain_open.as_secs(),
+            max_bytes: 0,
+            commands: Default::default(),
+            storage_id: id,
+            address: (),
         };
-        if data.is_empty() {
-            debug!("nothing to sync");
-            return Ok(());
-        }
-        if let Some(cmds) = syncer.receive(&data)? {
-            debug!(num = cmds.len(), "received commands");
-            if !cmds.is_empty() {
-                let mut client = self.client.lock().await;
-                let mut trx = client.transaction(*id);
-                // TODO: save PeerCache somewhere.
-                client
-                    .add_commands(&mut trx, sink, &cmds)
-                    .context("unable to add received commands")?;
-                client.commit(&mut trx, sink).context("commit failed")?;
-                // TODO: Update heads
-                // client.update_heads(
-                //     id,
-                //     cmds.iter().filter_map(|cmd| cmd.address().ok()),
-                //     heads,
-                // )?;
-                debug!("committed");
+        write_frame(&mut send, &request).await?;
+        send.close().await.map_err(Error::from)?;
+
+        let data = read_frame(&mut recv)
+            .await?
+            .context("stream closed before subscribe response")?;
+        match postcard::from_bytes(&data)
+            .context("postcard unable to deserialize subscribe response")?
+        {
+            SyncResponse::Ok(_) => {
+                debug!(?peer, ?remain_open, "subscribed");
+                Ok(())
             }
+            SyncResponse::Err(msg) => Err(anyhow::anyhow!("subscribe error: {msg}").into()),
         }
+    }
+}
 
-        Ok(())
+/// Reads the hints that `peer` pushes over its connection until the
+/// connection closes.
+async fn receive_pushes(mut acceptor: StreamAcceptor, peer: Addr, send: mpsc::Sender<Msg>) {
+    while let Ok(Some(mut stream)) = acceptor.accept_receive_stream().await {
+        let mut buf = Vec::new();
+        if let Err(e) = stream.read_to_end(&mut buf).await {
+            warn!(?peer, %e, "unable to read push");
+            continue;
+        }
+        match postcard::from_bytes::<SyncType<()>>(&buf) {
+            Ok(SyncType::Push { storage_id, .. }) => {
+                debug!(?peer, ?storage_id, "received push");
+                let peer = SyncPeer {
+                    addr: peer,
+                    graph_id: storage_id,
+                };
+                if send.send(Msg::Pushed { peer }).await.is_err() {
+                    return;
+                }
+            }
+            Ok(_) => warn!(?peer, "peer pushed an unexpected message"),
+            Err(e) => warn!(?peer, %e, "unable to deserialize push"),
+        }
     }
+    debug!(?peer, "stopped receiving pushes");
 }
 
+/// Peers that subscribed to a graph, by graph and peer address.
+type Subscribers = Arc<SyncMutex<BTreeMap<(GraphId, SocketAddr), Subscriber>>>;
+
+/// A peer that subscribed to a graph.
+struct Subscriber {
+    /// The connection the peer subscribed over.
+    handle: Handle,
+    /// When the subscription ends.
+    until: Instant,
+}
+
+/// The heads each connected peer is known to have, by graph and peer
+/// address.
+///
+/// Peers connect from a new address after restarting, so these are
+/// dropped with the connection rather than saved.
+type PeerCaches = Arc<SyncMutex<HashMap<(GraphId, SocketAddr), PeerCache>>>;
+
 /// The Aranya QUIC sync server.
 /// Used to listen for incoming `SyncRequests` and respond with `SyncResponse` when they are received.
 pub struct Server<EN, SP> {
@@ -336,9 +505,12 @@ pub struct Server<EN, SP> {
     server: QuicServer,
     /// Tracks running tasks.
     set: JoinSet<()>,
-    /// Indicates the "active team".
-    /// Used to ensure that the chosen PSK corresponds to an incoming sync request.
-    active_team: Arc<SyncMutex<Option<TeamId>>>,
+    /// Peers to push hints to.
+    subscribers: Subscribers,
+    /// The heads each connected peer is known to have.
+    caches: PeerCaches,
+    /// Checks that a connection's PSK has not been retired.
+    server_keys: Arc<PskStore>,
 }
 
 impl<EN, SP> Server<EN, SP> {
@@ -359,15 +531,14 @@ where
     pub async fn new(
         aranya: AranyaClient<EN, SP>,
         addr: &Addr,
-        server_keys: Arc<dyn SelectsPresharedKeys>,
-        mut active_team_rx: mpsc::Receiver<TeamId>,
+        server_keys: Arc<PskStore>,
     ) -> SyncResult<Self> {
         // Create Server Config
         let mut server_config = ServerConfig::builder()
             .with_no_client_auth()
             .with_cert_resolver(Arc::new(NoCertResolver::default()));
-        server_config.alpn_protocols = vec![ALPN_QUIC_SYNC.to_vec()]; // Set field directly
-        server_config.preshared_keys = PresharedKeySelection::Required(server_keys);
+        server_config.alpn_protocols = vec![ALPN_QUIC_SYNC.to_vec(), ALPN_SEED_RECOVERY.to_vec()]; // Set field directly
+        server_config.preshared_keys = PresharedKeySelection::Required(server_keys.clone());
 
         let tls_server_provider = rustls_provider::Server::new(server_config);
 
@@ -387,33 +558,121 @@ where
             .with_congestion_controller(Bbr::default())
             .context("can't set congestion controller config")
             .map_err(Error::ServerConfig)?
+            .with_event(TeamBinder)
+            .assume("can set sync server event subscriber")
+            .map_err(Error::Bug)?
             .start()
             .context("can't start QUIC server")?;
 
-        let active_team = Arc::new(SyncMutex::new(None));
         let mut set = JoinSet::new();
-        {
-            let active_team = Arc::clone(&active_team);
-            set.spawn(async move {
-                while let Some(team_id) = active_team_rx.recv().await {
-                    match active_team.lock() {
-                        Ok(ref mut guard) => {
-                            guard.replace(team_id);
-                        }
-                        Err(e) => error!(%e),
-                    }
-                }
-            });
-        }
+        let subscribers = Subscribers::default();
+        set.spawn(Self::push_hints(
+            aranya.clone(),
+            aranya.subscribe_commits(),
+            Arc::clone(&subscribers),
+        ));
 
         Ok(Self {
             aranya,
             server,
             set,
-            active_team,
+            subscribers,
+            caches: PeerCaches::default(),
+            server_keys,
         })
     }
 
+    /// Pushes a hint to the subscribers of each graph that new commands
+    /// are committed to.
+    async fn push_hints(
+        client: AranyaClient<EN, SP>,
+        mut commits: broadcast::Receiver<GraphId>,
+        subscribers: Subscribers,
+    ) {
+        let mut pushes = JoinSet::new();
+        loop {
+            let graph_ids = match commits.recv().await {
+                Ok(graph_id) => vec![graph_id],
+                // Hint every subscriber rather than miss one.
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let subs = subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+                    let mut graph_ids: Vec<_> = subs.keys().map(|(id, _)| *id).collect();
+                    graph_ids.dedup();
+                    graph_ids
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            while pushes.try_join_next().is_some() {}
+
+            for graph_id in graph_ids {
+                let targets: Vec<_> = {
+                    let now = Instant::now();
+                    let mut subs = subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+                    subs.retain(|_, sub| sub.until > now);
+                    subs.iter()
+                        .filter(|((id, _), _)| *id == graph_id)
+                        .map(|((_, peer), sub)| (*peer, sub.handle.clone()))
+                        .collect()
+                };
+                if targets.is_empty() {
+                    continue;
+                }
+                let data = match Self::hint(&client, graph_id).await {
+                    Ok(data) => data,
+                    Err(e) => {
+                        error!(%e, ?graph_id, "unable to create push hint");
+                        continue;
+                    }
+                };
+                for (peer, mut handle) in targets {
+                    let data = data.clone();
+                    let subscribers = Arc::clone(&subscribers);
+                    pushes.spawn(async move {
+                        let res: Result<(), Error> = async {
+                            let mut send = handle.open_send_stream().await?;
+                            send.send(data).await?;
+                            send.close().await?;
+                            Ok(())
+                        }
+                        .await;
+                        match res {
+                            Ok(()) => debug!(?peer, ?graph_id, "pushed hint"),
+                            Err(e) => {
+                                // The peer has to subscribe again once it
+                                // reconnects.
+                                warn!(?peer, %e, "unable to push hint");
+                                subscribers
+                                    .lock()
+                                    .unwrap_or_else(PoisonError::into_inner)
+                                    .remove(&(graph_id, peer));
+                            }
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// Creates a hint that `graph_id` has a new head.
+    async fn hint(client: &AranyaClient<EN, SP>, graph_id: GraphId) -> anyhow::Result<Bytes> {
+        let head: CommandId = {
+            let mut client = client.lock().await;
+            let storage = client.provider().get_storage(graph_id)?;
+            storage.get_command_id(storage.get_head()?)?
+        };
+        let push: SyncType<()> = SyncType::Push {
+            // Hints aren't part of a sync session.
+            message: SyncResponseMessage::Offer {
+                session_id: 0,
+                head,
+            },
+            storage_id: graph_id,
+            address: (),
+        };
+        let data = postcard::to_allocvec(&push).context("postcard unable to serialize push")?;
+        Ok(Bytes::from(data))
+    }
+
     /// Begins accepting incoming requests.
     #[instrument(skip_all)]
     pub async fn serve(mut self) {
@@ -430,21 +689,56 @@ where
                 continue;
             };
             let client = self.aranya.clone();
-            let active_team = {
-                let Ok(guard) = self.active_team.lock().inspect_err(|e| error!(%e)) else {
+            let handle = conn.handle();
+            let subscribers = Arc::clone(&self.subscribers);
+            let caches = Arc::clone(&self.caches);
+            let server_keys = Arc::clone(&self.server_keys);
+            let (active_team, identity) = match conn.query_event_context(|ctx: &ConnTeam| ctx.psk())
+            {
+                Ok(Some(psk)) => psk,
+                Ok(None) => {
+                    // Dropping the connection closes it.
+                    error!(?peer, "connection is not bound to a team");
                     continue;
-                };
-                let Some(active_team) = *guard else { continue };
-                active_team
+                }
+                Err(e) => {
+                    error!(?peer, %e, "unable to get team of connection");
+                    continue;
+                }
             };
+            debug!(?peer, ?active_team, "connection bound to team");
+            if conn
+                .application_protocol()
+                .is_ok_and(|alpn| alpn == ALPN_SEED_RECOVERY)
+            {
+                let rotation = server_keys.rotation(&identity);
+                self.set.spawn(Self::send_rotation(conn, peer, rotation));
+                continue;
+            }
             self.set.spawn(async move {
                 loop {
                     // Accept incoming streams.
                     match conn.accept_bidirectional_stream().await {
                         Ok(Some(stream)) => {
                             debug!(?peer, "received incoming QUIC stream");
-                            if let Err(e) =
-                                Self::sync(client.clone(), peer, stream, &active_team).await
+                            // The PSK's seed may have been rotated, and
+                            // its grace period ended, since the
+                            // handshake.
+                            if !server_keys.can_sync(&identity) {
+                                info!(?peer, "closing connection with a retired PSK");
+                                handle.close(RETIRED_PSK_ERROR_CODE.into());
+                                break;
+                            }
+                            if let Err(e) = Self::sync(
+                                client.clone(),
+                                peer,
+                                stream,
+                                &active_team,
+                                &handle,
+                                &subscribers,
+                                &caches,
+                            )
+                            .await
                             {
                                 error!(?e, ?peer, "server unable to sync with peer");
                                 break;
@@ -452,97 +746,153 @@ where
                         }
                         Ok(None) => {
                             debug!(?peer, "QUIC connection was closed");
-                            return;
+                            break;
                         }
                         Err(e) => {
                             error!(?peer, "error receiving QUIC stream: {}", e);
-                            return;
+                            break;
                         }
                     }
                 }
+                let graph_id = GraphId::from(active_team.into_id());
+                caches
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(&(graph_id, peer));
             });
         }
 
         error!("server terminated: {:?}", self.local_addr());
     }
 
-    /// Responds to a sync.
+    /// Sends `rotation` to a peer that connected with the PSK of the
+    /// seed it retired.
+    #[instrument(skip_all, fields(peer = %peer))]
+    async fn send_rotation(mut conn: Connection, peer: SocketAddr, rotation: Option<Bytes>) {
+        let Some(rotation) = rotation else {
+            info!(?peer, "no seed rotation to send for PSK");
+            conn.close(NO_SEED_ROTATION_ERROR_CODE.into());
+            return;
+        };
+        let res: SyncResult<()> = async {
+            let Some(stream) = conn
+                .accept_bidirectional_stream()
+                .await
+                .map_err(Error::from)?
+            else {
+                return Ok(());
+            };
+            let (_, mut send) = stream.split();
+            send_frame(&mut send, &rotation).await?;
+            send.close().await.map_err(Error::from)?;
+            Ok(())
+        }
+        .await;
+        match res {
+            Ok(()) => debug!(?peer, "sent seed rotation"),
+            Err(e) => warn!(?peer, %e, "unable to send seed rotation"),
+        }
+    }
+
+    /// Responds to each sync request on `stream` until the peer finishes
+    /// sending.
     #[instrument(skip_all, fields(peer = %peer))]
-    pub async fn sync(
+    async fn sync(
         client: AranyaClient<EN, SP>,
         peer: SocketAddr,
         stream: BidirectionalStream,
         active_team: &TeamId,
+        handle: &Handle,
+        subscribers: &Subscribers,
+        caches: &PeerCaches,
     ) -> SyncResult<()> {
-        info!(?peer, "server received a sync request");
-
-        let mut recv_buf = Vec::new();
         let (mut recv, mut send) = stream.split();
-        recv.read_to_end(&mut recv_buf)
-            .await
-            .context("failed to read sync request")?;
-        debug!(?peer, n = recv_buf.len(), "received sync request");
-
-        // Generate a sync response for a sync request.
-        let sync_response_res = Self::sync_respond(client, &recv_buf, active_team)
-            .await
-            .inspect_err(|e| error!(?e, "error responding to sync request"));
-
-        let resp = match sync_response_res {
-            Ok(data) => SyncResponse::Ok(data),
-            Err(err) => SyncResponse::Err(format!("{err:?}")),
-        };
-        // Serialize the sync response.
-        let data =
-            postcard::to_allocvec(&resp).context("postcard unable to serialize sync response")?;
-
-        let data_len = data.len();
-        send.send(Bytes::from(data))
-            .await
-            .context("Could not send sync response")?;
+        while let Some(request) = read_frame(&mut recv).await? {
+            info!(?peer, n = request.len(), "server received a sync request");
+
+            // Stream the sync response for the sync request.
+            let res = Self::sync_respond(
+                client.clone(),
+                &request,
+                &mut send,
+                active_team,
+                peer,
+                handle,
+                subscribers,
+                caches,
+            )
+            .await;
+            if let Err(err) = res {
+                error!(?err, "error responding to sync request");
+                write_frame(&mut send, &SyncResponse::Err(format!("{err:?}"))).await?;
+                break;
+            }
+        }
         send.close().await.map_err(Error::from)?;
-        debug!(?peer, n = data_len, "server sent sync response");
+        debug!(?peer, "server finished sync");
 
         Ok(())
     }
 
-    /// Generates a sync response for a sync request.
+    /// Sends the response to a sync request.
     #[instrument(skip_all)]
+    #[allow(clippy::too_many_arguments)]
     async fn sync_respond(
         client: AranyaClient<EN, SP>,
         request_data: &[u8],
+        send: &mut SendStream,
         active_team: &TeamId,
-    ) -> SyncResult<Box<[u8]>> {
-        info!("server responding to sync request");
-
-        // TODO: Use real server address
-        let server_address = ();
-        let mut resp = SyncResponder::new(server_address);
-
-        let SyncType::Poll {
-            request: request_msg,
-            address: (),
-        } = postcard::from_bytes(request_data).map_err(|e| anyhow::anyhow!(e))?
-        else {
-            bug!("Other sync types are not implemented");
-        };
-
-        check_request(active_team, &request_msg)?;
-
-        resp.receive(request_msg).context("sync recv failed")?;
-
-        let mut buf = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
-        // TODO: save PeerCache somewhere.
-        let len = resp
-            .poll(
-                &mut buf,
-                client.lock().await.provider(),
-                &mut PeerCache::new(),
-            )
-            .context("sync resp poll failed")?;
-        debug!(len = len, "sync poll finished");
-        buf.truncate(len);
-        Ok(buf.into())
+        peer: SocketAddr,
+        handle: &Handle,
+        subscribers: &Subscribers,
+        caches: &PeerCaches,
+    ) -> SyncResult<()> {
+        let request: SyncType<()> =
+            postcard::from_bytes(request_data).map_err(|e| anyhow::anyhow!(e))?;
+        let subs = || subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+        match request {
+            SyncType::Poll {
+                request,
+                address: (),
+            } => {
+                check_request(active_team, &request)?;
+                let key = (GraphId::from(active_team.into_id()), peer);
+                let mut cache = caches
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(&key)
+                    .unwrap_or_default();
+                let result = poll_respond(&client, request, &mut cache, send).await;
+                caches
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(key, cache);
+                return result;
+            }
+            SyncType::Subscribe {
+                remain_open,
+                storage_id,
+                ..
+            } => {
+                check_team(active_team, &storage_id)?;
+                let remain_open = Duration::from_secs(remain_open).min(MAX_SUBSCRIPTION);
+                let until = Instant::now()
+                    .checked_add(remain_open)
+                    .assume("subscription end is representable")?;
+                let handle = handle.clone();
+                subs().insert((storage_id, peer), Subscriber { handle, until });
+                info!(?peer, ?remain_open, "peer subscribed");
+            }
+            SyncType::Unsubscribe { address: () } => {
+                let graph_id = GraphId::from(active_team.into_id());
+                subs().remove(&(graph_id, peer));
+                info!(?peer, "peer unsubscribed");
+            }
+            SyncType::Push { .. } => {
+                return Err(anyhow::anyhow!("pushes are only sent to subscribers").into());
+            }
+        }
+        write_frame(send, &SyncResponse::Ok(Box::default())).await
     }
 }
 
@@ -550,6 +900,12 @@ fn check_request(team_id: &TeamId, request: &SyncRequestMessage) -> SyncResult<(
     let SyncRequestMessage::SyncRequest { storage_id, .. } = request else {
         bug!("Should be a SyncRequest")
     };
+    check_team(team_id, storage_id)
+}
+
+/// Checks that a request for `storage_id` was made with the PSK of
+/// `team_id`.
+fn check_team(team_id: &TeamId, storage_id: &GraphId) -> SyncResult<()> {
     if team_id.as_bytes() != storage_id.as_bytes() {
         return Err(SyncError::QuicSync(Error::InvalidPSK));
     }
@@ -1,24 +1,28 @@
 //! PSK setup for rustls for use with QUIC connections
 
 use std::{
+    cell::Cell,
     collections::HashMap,
     sync::{Arc, Mutex as SyncMutex},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{bail, Context as _, Result};
 use aranya_crypto::{
-    id::IdError, policy::GroupId, tls::PskSeedId, Csprng, Identified as _, KeyStoreExt as _,
-    PolicyId,
+    dangerous::spideroak_crypto::kdf::Kdf as _, id::IdError, policy::GroupId, tls::PskSeedId,
+    CipherSuite, Csprng, Identified as _, KeyStoreExt as _, PolicyId,
 };
-use aranya_daemon_api::{CipherSuiteId, TeamId, SEED_IKM_SIZE};
-use buggy::BugExt as _;
-use s2n_quic::provider::tls::rustls::rustls::{
-    client,
-    crypto::{hash::HashAlgorithm, PresharedKey},
-    pki_types::ServerName,
-    server,
+use aranya_daemon_api::{AqcCtrlPsk, CipherSuiteId, TeamId, SEED_IKM_SIZE};
+use bytes::Bytes;
+use s2n_quic::provider::{
+    event::{events, ConnectionInfo, ConnectionMeta, Subscriber},
+    tls::rustls::rustls::{
+        client,
+        crypto::{hash::HashAlgorithm, PresharedKey},
+        pki_types::ServerName,
+        server,
+    },
 };
-use tokio::sync::mpsc;
 use tracing::{error, warn};
 
 use crate::{keystore::LocalStore, CE, CS, KS};
@@ -26,6 +30,23 @@ use crate::{keystore::LocalStore, CE, CS, KS};
 pub(crate) type TeamIdPSKPair = (TeamId, Arc<PresharedKey>);
 
 const QUIC_SYNC_PSK_CONTEXT: &[u8] = b"AranyaQuicSync-v1";
+const AQC_CTRL_PSK_CONTEXT: &[u8] = b"AranyaAqcCtrl-v1";
+
+/// How long each AQC control PSK is used before it's rotated.
+const AQC_CTRL_PSK_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long the PSKs of a team's previous seed are still offered
+/// and accepted after the seed is rotated, unless configured.
+pub(crate) const DEFAULT_SEED_GRACE: Duration = Duration::from_secs(15 * 60);
+
+/// Returns the AQC control PSK rotation epoch that `now` falls
+/// in.
+pub(crate) fn aqc_ctrl_epoch(now: SystemTime) -> u64 {
+    let since = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    since.as_secs() / AQC_CTRL_PSK_PERIOD.as_secs()
+}
 
 #[derive(Clone)]
 pub(crate) struct PskSeed(pub(crate) aranya_crypto::tls::PskSeed<CS>);
@@ -75,6 +96,47 @@ impl PskSeed {
                 psk_to_rustls(psk)
             })
     }
+
+    /// Derives the team's AQC control PSKs for `epoch`, one per
+    /// cipher suite.
+    ///
+    /// The identity is the seed's PSK identity followed by the
+    /// epoch, and the secret is expanded from the seed's PSK
+    /// with that identity, so every epoch has different PSKs.
+    pub(crate) fn generate_aqc_ctrl_psks(
+        &self,
+        team: TeamId,
+        epoch: u64,
+    ) -> impl Iterator<Item = Result<AqcCtrlPsk>> {
+        type Kdf = <CS as CipherSuite>::Kdf;
+
+        let group = GroupId::from(team.into_id());
+        let policy = PolicyId::default();
+        self.0
+            .clone()
+            .generate_psks(
+                AQC_CTRL_PSK_CONTEXT,
+                group,
+                policy,
+                CipherSuiteId::all().iter().copied(),
+            )
+            .map(move |r| {
+                let psk = r?;
+                let mut identity = psk.identity().as_bytes().to_vec();
+                identity.extend_from_slice(&epoch.to_be_bytes());
+                let prk = Kdf::extract(psk.raw_secret_bytes(), AQC_CTRL_PSK_CONTEXT);
+                let mut secret = vec![0; psk.raw_secret_bytes().len()];
+                Kdf::expand(&mut secret, &prk, &identity)
+                    .context("unable to expand AQC ctrl PSK")?;
+                Ok(AqcCtrlPsk {
+                    team,
+                    identity: identity.into(),
+                    suite: psk.identity().cipher_suite(),
+                    secret: secret.into(),
+                    current: false,
+                })
+            })
+    }
 }
 
 fn psk_to_rustls(psk: aranya_crypto::tls::Psk<CS>) -> Result<PresharedKey> {
@@ -100,12 +162,11 @@ fn psk_to_rustls(psk: aranya_crypto::tls::Psk<CS>) -> Result<PresharedKey> {
 #[derive(Debug)]
 pub struct PskStore {
     inner: SyncMutex<PskStoreInner>,
-    // sender to report the selected team
-    active_team_tx: mpsc::Sender<TeamId>,
 }
 
 impl PskStore {
-    pub(crate) fn new<I>(initial_keys: I) -> (Self, mpsc::Receiver<TeamId>)
+    /// Creates a store of `initial_keys`.
+    pub(crate) fn new<I>(initial_keys: I) -> Self
     where
         I: IntoIterator<Item = TeamIdPSKPair>,
     {
@@ -120,18 +181,15 @@ impl PskStore {
             identity_psk.insert(PskIdAsKey(psk), team_id);
         }
 
-        let (active_team_tx, active_team_rx) = mpsc::channel::<TeamId>(10);
-        (
-            Self {
-                inner: SyncMutex::new(PskStoreInner {
-                    active_team: None,
-                    team_identities,
-                    identity_team: identity_psk,
-                }),
-                active_team_tx,
-            },
-            active_team_rx,
-        )
+        Self {
+            inner: SyncMutex::new(PskStoreInner {
+                active_team: None,
+                team_identities,
+                identity_team: identity_psk,
+                retired: Vec::new(),
+                recovery: HashMap::new(),
+            }),
+        }
     }
 
     pub(crate) fn insert(&self, team_id: TeamId, psk: Arc<PresharedKey>) -> Result<()> {
@@ -149,10 +207,95 @@ impl PskStore {
         }
     }
 
+    /// Replaces the team's PSKs with `psks`, keeping the previous
+    /// ones until their grace period ends at `until`, so that
+    /// peers that have not synced the rotation yet can still sync.
+    ///
+    /// After that, peers can still use the previous PSKs to fetch
+    /// `rotation`, the postcard-encoded rotation, until the team's
+    /// seed is rotated again.
+    pub(crate) fn rotate(
+        &self,
+        team_id: TeamId,
+        psks: Vec<Arc<PresharedKey>>,
+        until: SystemTime,
+        rotation: Bytes,
+    ) -> Result<()> {
+        match self.inner.lock() {
+            Ok(ref mut inner) => {
+                inner.prune();
+                for psk in &psks {
+                    inner
+                        .identity_team
+                        .insert(PskIdAsKey(Arc::clone(psk)), team_id);
+                }
+                let previous = inner
+                    .team_identities
+                    .insert(team_id, psks)
+                    .unwrap_or_default();
+                inner.retired.push(Retired {
+                    team_id,
+                    until,
+                    psks: previous.clone(),
+                });
+                inner.set_recovery(team_id, previous, rotation);
+                Ok(())
+            }
+            Err(e) => bail!(e.to_string()),
+        }
+    }
+
+    /// Lets peers use the PSKs of the team's newest retired seed
+    /// to fetch `rotation`, the postcard-encoded rotation that
+    /// retired it.
+    pub(crate) fn recover(
+        &self,
+        team_id: TeamId,
+        psks: Vec<Arc<PresharedKey>>,
+        rotation: Bytes,
+    ) -> Result<()> {
+        match self.inner.lock() {
+            Ok(ref mut inner) => {
+                inner.set_recovery(team_id, psks, rotation);
+                Ok(())
+            }
+            Err(e) => bail!(e.to_string()),
+        }
+    }
+
+    /// Adds the PSKs of one of the team's rotated seeds, which
+    /// are used until their grace period ends at `until`.
+    pub(crate) fn retire(
+        &self,
+        team_id: TeamId,
+        psks: Vec<Arc<PresharedKey>>,
+        until: SystemTime,
+    ) -> Result<()> {
+        match self.inner.lock() {
+            Ok(ref mut inner) => {
+                for psk in &psks {
+                    inner
+                        .identity_team
+                        .insert(PskIdAsKey(Arc::clone(psk)), team_id);
+                }
+                inner.retired.push(Retired {
+                    team_id,
+                    until,
+                    psks,
+                });
+                inner.prune();
+                Ok(())
+            }
+            Err(e) => bail!(e.to_string()),
+        }
+    }
+
     pub(crate) fn remove(&self, team_id: TeamId) -> Result<()> {
         match self.inner.lock() {
             Ok(ref mut inner) => {
                 inner.team_identities.remove(&team_id);
+                inner.retired.retain(|r| r.team_id != team_id);
+                inner.recovery.remove(&team_id);
 
                 inner.identity_team.retain(|_, other| *other != team_id);
 
@@ -167,31 +310,63 @@ impl PskStore {
         let mut inner = self.inner.lock().expect("poisoned mutex");
         let _ = inner.active_team.replace(team_id);
     }
+
+    /// Reports whether the PSK with `identity` can be used to sync,
+    /// which is false once its grace period has ended.
+    #[allow(clippy::expect_used)]
+    pub(crate) fn can_sync(&self, identity: &[u8]) -> bool {
+        let mut inner = self.inner.lock().expect("poisoned mutex");
+        inner.prune();
+
+        let has = |psks: &[Arc<PresharedKey>]| psks.iter().any(|psk| psk.identity() == identity);
+        inner.team_identities.values().any(|psks| has(psks))
+            || inner.retired.iter().any(|r| has(&r.psks))
+    }
+
+    /// Returns the postcard-encoded rotation that retired the PSK
+    /// with `identity`, if the PSK can be used to fetch it.
+    #[allow(clippy::expect_used)]
+    pub(crate) fn rotation(&self, identity: &[u8]) -> Option<Bytes> {
+        let inner = self.inner.lock().expect("poisoned mutex");
+        inner
+            .recovery
+            .values()
+            .find(|r| r.psks.iter().any(|psk| psk.identity() == identity))
+            .map(|r| r.rotation.clone())
+    }
 }
 
 impl client::PresharedKeyStore for PskStore {
     #[allow(clippy::expect_used)]
     fn psks(&self, _server_name: &ServerName<'_>) -> Vec<Arc<PresharedKey>> {
-        let inner = self.inner.lock().expect("poisoned mutex");
+        let mut inner = self.inner.lock().expect("poisoned mutex");
+        inner.prune();
 
-        let Some(active_team) = inner.active_team.as_ref() else {
+        let Some(active_team) = inner.active_team else {
             return Vec::new();
         };
-        let Some(active_identities) = inner.team_identities.get(active_team) else {
+        let Some(active_identities) = inner.team_identities.get(&active_team) else {
             return Vec::new();
         };
-        active_identities.clone()
+        // Offer the current PSKs first, then the retired ones for
+        // peers that have not rotated yet.
+        let mut psks = active_identities.clone();
+        for retired in inner.retired.iter().filter(|r| r.team_id == active_team) {
+            psks.extend(retired.psks.iter().cloned());
+        }
+        psks
     }
 }
 
 impl server::SelectsPresharedKeys for PskStore {
     #[allow(clippy::expect_used)]
     fn load_psk(&self, identity: &[u8]) -> Option<Arc<PresharedKey>> {
-        let inner = self
+        let mut inner = self
             .inner
             .lock()
             .inspect_err(|e| error!("mutex poisoned: {e}"))
             .ok()?;
+        inner.prune();
 
         let (k, _) = inner.identity_team.get_key_value(identity)?;
         Some(k.0.clone())
@@ -200,14 +375,70 @@ impl server::SelectsPresharedKeys for PskStore {
     #[allow(clippy::expect_used)]
     fn chosen(&self, identity: &[u8]) {
         let inner = self.inner.lock().expect("poisoned mutex");
-        let Some(team_id) = inner.identity_team.get(identity) else {
+        let team_id = inner.identity_team.get(identity).copied();
+        if team_id.is_none() {
             warn!("identity removed?");
-            return;
-        };
-        self.active_team_tx
-            .try_send(*team_id)
-            .assume("Failed to send identity")
-            .ok();
+        }
+        CHOSEN_PSK.set(team_id.map(|team_id| (team_id, identity.into())));
+    }
+}
+
+thread_local! {
+    /// The team and identity of the PSK that the server chose for the
+    /// handshake that is being processed on this thread.
+    static CHOSEN_PSK: Cell<Option<(TeamId, Box<[u8]>)>> = const { Cell::new(None) };
+}
+
+/// Takes the team of the PSK that the server chose for the handshake
+/// that was just processed on this thread.
+pub(crate) fn take_chosen_team() -> Option<TeamId> {
+    CHOSEN_PSK.take().map(|(team_id, _)| team_id)
+}
+
+/// Binds each incoming connection to the team of the PSK that was
+/// negotiated on it.
+///
+/// rustls reports the chosen PSK through [`PskStore`], which is shared by
+/// every connection. The server derives the connection's handshake keys
+/// right after choosing the PSK, while processing the same `ClientHello`
+/// on the same thread, so the team is moved into the connection's context
+/// when those keys are installed.
+#[derive(Debug, Default)]
+pub(crate) struct TeamBinder;
+
+/// The team a connection is bound to by [`TeamBinder`], and the
+/// identity of the PSK it was bound with.
+#[derive(Debug, Default)]
+pub(crate) struct ConnTeam(Option<(TeamId, Box<[u8]>)>);
+
+impl ConnTeam {
+    /// Returns the team and PSK identity, or `None` if the handshake
+    /// did not use one of our PSKs.
+    pub(crate) fn psk(&self) -> Option<(TeamId, Box<[u8]>)> {
+        self.0.clone()
+    }
+}
+
+impl Subscriber for TeamBinder {
+    type ConnectionContext = ConnTeam;
+
+    fn create_connection_context(
+        &mut self,
+        _meta: &ConnectionMeta,
+        _info: &ConnectionInfo,
+    ) -> Self::ConnectionContext {
+        ConnTeam::default()
+    }
+
+    fn on_key_update(
+        &mut self,
+        context: &mut Self::ConnectionContext,
+        _meta: &ConnectionMeta,
+        event: &events::KeyUpdate,
+    ) {
+        if matches!(event.key_type, events::KeyType::Handshake { .. }) {
+            context.0 = CHOSEN_PSK.take();
+        }
     }
 }
 
@@ -218,6 +449,69 @@ struct PskStoreInner {
     /// Indicates the "active team".
     /// Used by [`PskStore`] to restrict the PSKs that are offered by the client.
     active_team: Option<TeamId>,
+    /// PSKs of rotated seeds that are still in their grace period.
+    retired: Vec<Retired>,
+    /// The PSKs of each team's newest retired seed, which can
+    /// still be used to fetch the rotation after their grace
+    /// period ends.
+    recovery: HashMap<TeamId, Recovery>,
+}
+
+impl PskStoreInner {
+    /// Drops the retired PSKs whose grace period has ended, unless
+    /// they can still be used to fetch a rotation.
+    fn prune(&mut self) {
+        let now = SystemTime::now();
+        let (expired, retired): (Vec<_>, Vec<_>) =
+            self.retired.drain(..).partition(|r| r.until <= now);
+        self.retired = retired;
+        for r in expired {
+            self.forget(r.psks);
+        }
+    }
+
+    /// Replaces the team's [`Recovery`].
+    fn set_recovery(&mut self, team_id: TeamId, psks: Vec<Arc<PresharedKey>>, rotation: Bytes) {
+        for psk in &psks {
+            self.identity_team
+                .insert(PskIdAsKey(Arc::clone(psk)), team_id);
+        }
+        if let Some(old) = self.recovery.insert(team_id, Recovery { psks, rotation }) {
+            self.forget(old.psks);
+        }
+    }
+
+    /// Stops accepting each of `psks` that is no longer used.
+    fn forget(&mut self, psks: Vec<Arc<PresharedKey>>) {
+        for psk in psks {
+            let identity = psk.identity();
+            let has =
+                |psks: &[Arc<PresharedKey>]| psks.iter().any(|psk| psk.identity() == identity);
+            let used = self.team_identities.values().any(|psks| has(psks))
+                || self.retired.iter().any(|r| has(&r.psks))
+                || self.recovery.values().any(|r| has(&r.psks));
+            if !used {
+                self.identity_team.remove(identity);
+            }
+        }
+    }
+}
+
+/// The PSKs of a rotated seed.
+#[derive(Debug)]
+struct Retired {
+    team_id: TeamId,
+    until: SystemTime,
+    psks: Vec<Arc<PresharedKey>>,
+}
+
+/// The PSKs of a team's newest retired seed, and the rotation
+/// that retired it.
+#[derive(Debug)]
+struct Recovery {
+    psks: Vec<Arc<PresharedKey>>,
+    /// The rotation, encoded with postcard.
+    rotation: Bytes,
 }
 
 #[derive(Debug)]
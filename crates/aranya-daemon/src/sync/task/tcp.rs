@@ -0,0 +1,327 @@
+//! Aranya TCP client and server for syncing Aranya graph commands.
+//!
+//! For networks that don't allow UDP. Each sync opens a new TCP connection secured with TLS 1.3,
+//! using the same team PSKs as the QUIC syncer, and the server binds the connection to the team of
+//! the PSK that was negotiated on it. Messages on the connection are framed the same way as for
+//! the QUIC syncer.
+//!
+//! Peers can't push hints over TCP, so subscriptions are only supported by the QUIC syncer.
+
+use core::net::SocketAddr;
+use std::{future::Future, sync::Arc};
+
+use anyhow::Context as _;
+use aranya_daemon_api::{SyncTransport, TeamId};
+use aranya_runtime::{
+    Engine, GraphId, PeerCache, Sink, StorageProvider, SyncRequestMessage, SyncType,
+};
+use aranya_util::{
+    rustls::{NoCertResolver, NoCertVerifier},
+    Addr,
+};
+use buggy::{bug, BugExt as _};
+#[allow(deprecated)]
+use s2n_quic::provider::tls::rustls::rustls::{
+    client::PskKexMode,
+    pki_types::ServerName,
+    server::{PresharedKeySelection, SelectsPresharedKeys},
+    version::TLS13,
+    ClientConfig, ClientConnection, ServerConfig, ServerConnection,
+};
+use tokio::{
+    io::AsyncWriteExt as _,
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+use tracing::{debug, error, info, instrument};
+
+use super::{
+    framed::{poll_respond, read_frame, write_frame},
+    quic::PskStore,
+    SyncPeer, SyncResponse, SyncState, Syncer,
+};
+use crate::{
+    aranya::Client as AranyaClient,
+    sync::{Result as SyncResult, SyncError},
+};
+
+mod tls;
+
+use tls::TlsStream;
+
+/// ALPN protocol identifier for Aranya TCP sync.
+const ALPN_TCP_SYNC: &[u8] = b"tcp-sync-unstable";
+
+/// Errors specific to the TCP syncer
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// TCP connection error
+    #[error("TCP connection error: {0}")]
+    TcpConnectionError(#[from] std::io::Error),
+    /// TLS client config error
+    #[error("TLS client config error: {0}")]
+    ClientConfig(anyhow::Error),
+    /// Invalid PSK used for syncing
+    #[error("Invalid PSK used when attempting to sync")]
+    InvalidPSK,
+    /// TLS server config error
+    #[error("TLS server config error: {0}")]
+    ServerConfig(anyhow::Error),
+}
+
+/// TCP syncer state used for sending sync requests and processing sync responses
+pub struct State {
+    /// TLS config used to connect to each peer's sync server.
+    config: Arc<ClientConfig>,
+    /// PSK store shared between the daemon API server and the syncers and sync servers.
+    /// This store is modified by [`crate::api::DaemonApiServer`].
+    store: Arc<PskStore>,
+}
+
+impl SyncState for State {
+    const TRANSPORT: Option<SyncTransport> = Some(SyncTransport::Tcp);
+
+    #[allow(clippy::manual_async_fn)]
+    #[instrument(skip(syncer, sink))]
+    /// Syncs with the peer over a new connection.
+    fn sync_impl<S>(
+        syncer: &mut Syncer<Self>,
+        id: GraphId,
+        sink: &mut S,
+        peer: &Addr,
+    ) -> impl Future<Output = SyncResult<usize>> + Send
+    where
+        S: Sink<<crate::EN as Engine>::Effect> + Send,
+    {
+        async move {
+            let stream = syncer
+                .state
+                .connect(peer, id)
+                .await
+                .inspect_err(|e| error!("Could not create connection: {e}"))?;
+            let (mut recv, mut send) = tokio::io::split(stream);
+
+            let sync_peer = SyncPeer {
+                addr: *peer,
+                graph_id: id,
+            };
+            syncer
+                .sync_stream(&mut recv, &mut send, &sync_peer, sink)
+                .await
+        }
+    }
+}
+
+impl State {
+    /// Creates a new instance
+    pub fn new(psk_store: Arc<PskStore>) -> Self {
+        // Create client config (PSKs only: every server certificate is rejected)
+        let mut client_config = ClientConfig::builder_with_protocol_versions(&[&TLS13])
+            .dangerous()
+            .with_custom_certificate_verifier(NoCertVerifier::new())
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![ALPN_TCP_SYNC.to_vec()];
+        client_config.preshared_keys = psk_store.clone();
+        client_config.psk_kex_modes = vec![PskKexMode::PskWithDhe];
+
+        Self {
+            config: Arc::new(client_config),
+            store: psk_store,
+        }
+    }
+
+    /// Connects to `peer` with the PSKs of `id`.
+    #[instrument(skip(self))]
+    async fn connect(&self, peer: &Addr, id: GraphId) -> SyncResult<TlsStream> {
+        info!(?peer, "client connecting to TCP sync server");
+        let addr = tokio::net::lookup_host(peer.to_socket_addrs())
+            .await
+            .context("DNS lookup on for peer address")?
+            .next()
+            .assume("invalid peer address")?;
+        let tcp = TcpStream::connect(addr).await.map_err(Error::from)?;
+        tcp.set_nodelay(true).map_err(Error::from)?;
+
+        // The client offers the PSKs of the active team, which it reads
+        // while creating the `ClientHello`.
+        self.store.set_team(id.into_id().into());
+        // Note: cert is not used but server name must be set to connect.
+        let server_name = ServerName::try_from("127.0.0.1").assume("server name is valid")?;
+        let conn = ClientConnection::new(Arc::clone(&self.config), server_name)
+            .context("unable to create TLS connection")
+            .map_err(Error::ClientConfig)?;
+        let stream = TlsStream::handshake(tcp, conn).await.map_err(Error::from)?;
+        debug!(?peer, "created new TCP connection");
+        Ok(stream)
+    }
+}
+
+/// The Aranya TCP sync server.
+/// Used to listen for incoming `SyncRequests` and respond with `SyncResponse` when they are received.
+pub struct Server<EN, SP> {
+    /// Thread-safe Aranya client reference.
+    aranya: AranyaClient<EN, SP>,
+    /// Accepts connections from peers.
+    listener: TcpListener,
+    /// TLS config for each connection.
+    config: Arc<ServerConfig>,
+    /// Tracks running tasks.
+    set: JoinSet<()>,
+}
+
+impl<EN, SP> Server<EN, SP> {
+    /// Returns the local address the sync server bound to.
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+}
+
+impl<EN, SP> Server<EN, SP>
+where
+    EN: Engine + Send + 'static,
+    SP: StorageProvider + Send + Sync + 'static,
+{
+    /// Creates a new `Server`.
+    #[inline]
+    #[allow(deprecated)]
+    pub async fn new(
+        aranya: AranyaClient<EN, SP>,
+        addr: &Addr,
+        server_keys: Arc<dyn SelectsPresharedKeys>,
+    ) -> SyncResult<Self> {
+        // Create Server Config
+        let mut server_config = ServerConfig::builder_with_protocol_versions(&[&TLS13])
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(NoCertResolver::default()));
+        server_config.alpn_protocols = vec![ALPN_TCP_SYNC.to_vec()];
+        server_config.preshared_keys = PresharedKeySelection::Required(server_keys);
+
+        let addr = tokio::net::lookup_host(addr.to_socket_addrs())
+            .await
+            .context("DNS lookup on for peer address")?
+            .next()
+            .assume("invalid server address")?;
+        let listener = TcpListener::bind(addr)
+            .await
+            .context("can't bind TCP sync server")
+            .map_err(Error::ServerConfig)?;
+
+        Ok(Self {
+            aranya,
+            listener,
+            config: Arc::new(server_config),
+            set: JoinSet::new(),
+        })
+    }
+
+    /// Begins accepting incoming requests.
+    #[instrument(skip_all)]
+    pub async fn serve(mut self) {
+        info!(
+            "TCP sync server listening for incoming connections: {:?}",
+            self.local_addr()
+        );
+
+        loop {
+            let (tcp, peer) = match self.listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!(%e, "unable to accept TCP connection");
+                    continue;
+                }
+            };
+            debug!(?peer, "received incoming TCP connection");
+            if let Err(e) = tcp.set_nodelay(true) {
+                error!(%e, "unable to set TCP_NODELAY");
+                continue;
+            }
+            while self.set.try_join_next().is_some() {}
+
+            let client = self.aranya.clone();
+            let config = Arc::clone(&self.config);
+            self.set.spawn(async move {
+                if let Err(e) = Self::sync(client, peer, tcp, config).await {
+                    error!(?e, ?peer, "server unable to sync with peer");
+                }
+            });
+        }
+    }
+
+    /// Responds to each sync request on a connection until the peer
+    /// finishes sending.
+    #[instrument(skip_all, fields(peer = %peer))]
+    async fn sync(
+        client: AranyaClient<EN, SP>,
+        peer: SocketAddr,
+        tcp: TcpStream,
+        config: Arc<ServerConfig>,
+    ) -> SyncResult<()> {
+        let conn = ServerConnection::new(config)
+            .context("unable to create TLS connection")
+            .map_err(Error::ServerConfig)?;
+        let stream = TlsStream::handshake(tcp, conn).await.map_err(Error::from)?;
+        let Some(active_team) = stream.team_id() else {
+            return Err(Error::InvalidPSK.into());
+        };
+        debug!(?peer, ?active_team, "connection bound to team");
+
+        let (mut recv, mut send) = tokio::io::split(stream);
+        // The heads the peer is known to have, for later rounds of the
+        // same sync.
+        let mut cache = PeerCache::default();
+        while let Some(request) = read_frame(&mut recv).await? {
+            info!(?peer, n = request.len(), "server received a sync request");
+
+            let res =
+                Self::sync_respond(&client, &request, &mut send, &active_team, &mut cache).await;
+            if let Err(err) = res {
+                error!(?err, "error responding to sync request");
+                write_frame(&mut send, &SyncResponse::Err(format!("{err:?}"))).await?;
+                break;
+            }
+        }
+        send.shutdown().await.map_err(Error::from)?;
+        debug!(?peer, "server finished sync");
+
+        Ok(())
+    }
+
+    /// Sends the response to a sync request.
+    async fn sync_respond(
+        client: &AranyaClient<EN, SP>,
+        request_data: &[u8],
+        send: &mut (impl tokio::io::AsyncWrite + Unpin),
+        active_team: &TeamId,
+        cache: &mut PeerCache,
+    ) -> SyncResult<()> {
+        let request: SyncType<()> =
+            postcard::from_bytes(request_data).map_err(|e| anyhow::anyhow!(e))?;
+        match request {
+            SyncType::Poll {
+                request,
+                address: (),
+            } => {
+                check_request(active_team, &request)?;
+                poll_respond(client, request, cache, send).await
+            }
+            SyncType::Subscribe { .. } | SyncType::Unsubscribe { .. } => {
+                Err(anyhow::anyhow!("subscriptions are not supported over TCP").into())
+            }
+            SyncType::Push { .. } => {
+                Err(anyhow::anyhow!("pushes are only sent to subscribers").into())
+            }
+        }
+    }
+}
+
+/// Checks that a request was made with the PSK of `team_id`.
+fn check_request(team_id: &TeamId, request: &SyncRequestMessage) -> SyncResult<()> {
+    let SyncRequestMessage::SyncRequest { storage_id, .. } = request else {
+        bug!("Should be a SyncRequest")
+    };
+    if team_id.as_bytes() != storage_id.as_bytes() {
+        return Err(SyncError::TcpSync(Error::InvalidPSK));
+    }
+
+    Ok(())
+}
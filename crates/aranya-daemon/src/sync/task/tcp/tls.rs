@@ -0,0 +1,210 @@
+//! TLS over TCP, driven by rustls.
+
+use std::{
+    future::poll_fn,
+    io::{self, Read, Write},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use aranya_daemon_api::TeamId;
+use s2n_quic::provider::tls::rustls::rustls::Connection;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+use crate::sync::task::quic::take_chosen_team;
+
+/// A TLS connection over a TCP stream.
+pub(super) struct TlsStream {
+    io: TcpStream,
+    conn: Connection,
+    /// The team of the PSK that was negotiated, for server
+    /// connections.
+    team: Option<TeamId>,
+}
+
+impl TlsStream {
+    /// Completes the handshake for `conn` over `io`.
+    pub(super) async fn handshake(io: TcpStream, conn: impl Into<Connection>) -> io::Result<Self> {
+        let mut stream = Self {
+            io,
+            conn: conn.into(),
+            team: None,
+        };
+        poll_fn(|cx| stream.poll_handshake(cx)).await?;
+        Ok(stream)
+    }
+
+    /// Returns the team of the PSK that the server chose, or `None` if
+    /// this is a client connection.
+    pub(super) fn team_id(&self) -> Option<TeamId> {
+        self.team
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            while self.conn.wants_write() {
+                ready!(self.poll_write_tls(cx))?;
+            }
+            if !self.conn.is_handshaking() {
+                return Poll::Ready(Ok(()));
+            }
+            if ready!(self.poll_read_tls(cx))? == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during handshake",
+                )));
+            }
+        }
+    }
+
+    /// Reads TLS records from the socket and processes them.
+    fn poll_read_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut io = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        let n = match self.conn.read_tls(&mut io) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Poll::Pending,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let res = self.conn.process_new_packets();
+        // The server picks the PSK while processing the `ClientHello`,
+        // which happens on this thread in `process_new_packets`.
+        if let (Connection::Server(_), Some(team)) = (&self.conn, take_chosen_team()) {
+            self.team = Some(team);
+        }
+        if let Err(e) = res {
+            // Try to tell the peer why.
+            let _ = self.conn.write_tls(&mut SyncIo {
+                io: &mut self.io,
+                cx,
+            });
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    /// Writes as many pending TLS records as the socket takes without
+    /// blocking.
+    fn write_pending_tls(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        while self.conn.wants_write() {
+            match self.poll_write_tls(cx) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(e)) => return Err(e),
+                Poll::Pending => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes pending TLS records to the socket.
+    fn poll_write_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut io = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        match self.conn.write_tls(&mut io) {
+            Ok(0) => Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+            Ok(n) => Poll::Ready(Ok(n)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // Writes aren't flushed, so make sure the peer has everything
+        // it might be waiting on before waiting on it.
+        this.write_pending_tls(cx)?;
+        loop {
+            match this.conn.reader().read(buf.initialize_unfilled()) {
+                Ok(n) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            // The reader reports the end of the stream once the socket
+            // has been read to the end.
+            ready!(this.poll_read_tls(cx))?;
+        }
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Don't let records pile up faster than the socket takes them.
+        while this.conn.wants_write() {
+            ready!(this.poll_write_tls(cx))?;
+        }
+        let n = this.conn.writer().write(buf)?;
+        this.write_pending_tls(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.conn.writer().flush()?;
+        while this.conn.wants_write() {
+            ready!(this.poll_write_tls(cx))?;
+        }
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Does nothing after the first call.
+        self.conn.send_close_notify();
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// Adapts the socket to the blocking I/O traits that rustls uses,
+/// reporting [`io::ErrorKind::WouldBlock`] when it isn't ready.
+struct SyncIo<'a, 'b> {
+    io: &'a mut TcpStream,
+    cx: &'a mut Context<'b>,
+}
+
+impl Read for SyncIo<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut *self.io).poll_read(self.cx, &mut buf) {
+            Poll::Ready(Ok(())) => Ok(buf.filled().len()),
+            Poll::Ready(Err(e)) => Err(e),
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Write for SyncIo<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write(self.cx, buf) {
+            Poll::Ready(res) => res,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.io).poll_flush(self.cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
@@ -1,10 +1,18 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    marker::PhantomData,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{Context, Result};
 use aranya_crypto::{tls::PskSeedId, Id};
-use aranya_daemon_api::TeamId;
-use aranya_util::create_dir_all;
+use aranya_daemon_api::{SyncPeerConfig, TeamId};
+use aranya_runtime::Address;
+use aranya_util::{create_dir_all, write_file, Addr};
+use ciborium as cbor;
 use s2n_quic::provider::tls::rustls::rustls::crypto::PresharedKey;
+use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
     fs::{read, read_dir, remove_file, File},
     io::AsyncWriteExt,
@@ -12,6 +20,7 @@ use tokio::{
 
 use crate::{
     keystore::LocalStore,
+    policy::SyncSeedRotated,
     sync::task::quic::{self as qs},
     CE, KS,
 };
@@ -19,6 +28,33 @@ use crate::{
 #[derive(Debug)]
 pub(crate) struct SeedDir(PathBuf);
 
+/// A team's seeds, as stored in a [`SeedDir`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct TeamSeeds {
+    /// The current seed.
+    pub(crate) seed_id: PskSeedId,
+    /// When the current seed was rotated, in seconds since the
+    /// Unix epoch, or zero if it never was.
+    pub(crate) rotated_at: u64,
+    /// Seeds that were rotated out, and when their grace period
+    /// ends.
+    pub(crate) retired: Vec<(PskSeedId, SystemTime)>,
+}
+
+impl TeamSeeds {
+    /// Returns the retired seeds whose grace period has not ended
+    /// by `now`.
+    pub(crate) fn retired_at(
+        &self,
+        now: SystemTime,
+    ) -> impl Iterator<Item = (PskSeedId, SystemTime)> + '_ {
+        self.retired
+            .iter()
+            .copied()
+            .filter(move |(_, until)| *until > now)
+    }
+}
+
 impl SeedDir {
     pub(crate) async fn new(p: PathBuf) -> Result<Self> {
         create_dir_all(&p).await?;
@@ -26,7 +62,12 @@ impl SeedDir {
     }
 
     pub(crate) async fn get(&self, team_id: &TeamId) -> Result<PskSeedId> {
-        Self::read_id(self.0.join(team_id.to_string())).await
+        Ok(self.get_seeds(team_id).await?.seed_id)
+    }
+
+    /// Returns the team's current and retired seeds.
+    pub(crate) async fn get_seeds(&self, team_id: &TeamId) -> Result<TeamSeeds> {
+        Self::read(self.0.join(team_id.to_string())).await
     }
 
     pub(crate) async fn append(&self, team_id: &TeamId, seed_id: &PskSeedId) -> Result<()> {
@@ -41,6 +82,28 @@ impl SeedDir {
         Ok(())
     }
 
+    /// Replaces the team's seeds with `seeds`.
+    pub(crate) async fn replace(&self, team_id: &TeamId, seeds: &TeamSeeds) -> Result<()> {
+        let file_name = self.0.join(team_id.to_string());
+
+        let mut buf = seeds.seed_id.as_bytes().to_vec();
+        buf.extend_from_slice(&seeds.rotated_at.to_be_bytes());
+        for (seed_id, until) in &seeds.retired {
+            let until = until
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let until = u64::try_from(until).context("grace period end is out of range")?;
+            buf.extend_from_slice(seed_id.as_bytes());
+            buf.extend_from_slice(&until.to_be_bytes());
+        }
+        write_file(file_name, &buf)
+            .await
+            .context("could not write seed id file")?;
+
+        Ok(())
+    }
+
     pub(crate) async fn remove(&self, team_id: &TeamId) -> Result<()> {
         let file_name = self.0.join(team_id.to_string());
         remove_file(file_name)
@@ -51,28 +114,136 @@ impl SeedDir {
     }
 
     pub(crate) async fn list(&self) -> Result<Vec<(TeamId, PskSeedId)>> {
+        Ok(self
+            .list_seeds()
+            .await?
+            .into_iter()
+            .map(|(team_id, seeds)| (team_id, seeds.seed_id))
+            .collect())
+    }
+
+    /// Returns every team's current and retired seeds.
+    pub(crate) async fn list_seeds(&self) -> Result<Vec<(TeamId, TeamSeeds)>> {
         let mut entries = read_dir(&self.0).await?;
         let mut out = Vec::new();
 
         while let Some(entry) = entries.next_entry().await? {
             let team_id = TeamId::decode(entry.file_name().as_encoded_bytes())?;
-            let seed_id = Self::read_id(entry.path()).await?;
-            out.push((team_id, seed_id));
+            let seeds = Self::read(entry.path()).await?;
+            out.push((team_id, seeds));
         }
 
         Ok(out)
     }
 
-    async fn read_id(path: PathBuf) -> Result<PskSeedId> {
+    /// Reads a seed ID, which is followed by when it was rotated
+    /// if it replaced another seed, and then by each retired seed
+    /// ID and when its grace period ends, in milliseconds since
+    /// the Unix epoch.
+    async fn read(path: PathBuf) -> Result<TeamSeeds> {
         const ID_SIZE: usize = size_of::<Id>();
+        const TIME_SIZE: usize = size_of::<u64>();
         let bytes = read(path).await?;
-        let arr: [u8; ID_SIZE] = bytes.try_into().map_err(|input| {
-            anyhow::anyhow!(
+        let Some((id, rest)) = bytes.split_first_chunk::<ID_SIZE>() else {
+            anyhow::bail!(
                 "could not convert {:?} to an array of {ID_SIZE} bytes",
-                input
-            )
-        })?;
-        Ok(arr.into())
+                bytes
+            );
+        };
+        let (rotated_at, mut rest) = match rest.split_first_chunk::<TIME_SIZE>() {
+            Some((rotated_at, rest)) => (u64::from_be_bytes(*rotated_at), rest),
+            None if rest.is_empty() => (0, rest),
+            None => anyhow::bail!("invalid seed rotation time {:?}", rest),
+        };
+        let mut retired = Vec::new();
+        while let Some((id, tail)) = rest.split_first_chunk::<ID_SIZE>() {
+            let Some((until, tail)) = tail.split_first_chunk::<TIME_SIZE>() else {
+                anyhow::bail!("invalid retired seed grace period {:?}", tail);
+            };
+            let until = Duration::from_millis(u64::from_be_bytes(*until));
+            retired.push(((*id).into(), SystemTime::UNIX_EPOCH + until));
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            anyhow::bail!("invalid retired seed {:?}", rest);
+        }
+        Ok(TeamSeeds {
+            seed_id: (*id).into(),
+            rotated_at,
+            retired,
+        })
+    }
+}
+
+/// Persistent sync peers.
+pub(crate) type SyncPeerDir = TeamFileDir<(Addr, SyncPeerConfig)>;
+
+/// The heads that sync peers are known to have.
+pub(crate) type PeerCacheDir = TeamFileDir<(Addr, Vec<Address>)>;
+
+/// The rotation that replaced each team's previous seed.
+pub(crate) type SeedRotationDir = TeamFileDir<SyncSeedRotated>;
+
+/// A list of `T` per team, stored as one CBOR file per team.
+#[derive(Debug)]
+pub(crate) struct TeamFileDir<T> {
+    path: PathBuf,
+    /// What the files hold, for error messages.
+    what: &'static str,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TeamFileDir<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub(crate) async fn new(p: PathBuf, what: &'static str) -> Result<Self> {
+        create_dir_all(&p).await?;
+        Ok(Self {
+            path: p,
+            what,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Replaces the team's items, removing its file if `items` is empty.
+    pub(crate) async fn save(&self, team_id: &TeamId, items: &[T]) -> Result<()> {
+        if items.is_empty() {
+            return self.remove(team_id).await;
+        }
+
+        let mut buf = Vec::new();
+        cbor::into_writer(items, &mut buf)?;
+        write_file(self.path.join(team_id.to_string()), &buf)
+            .await
+            .with_context(|| format!("could not write {} file", self.what))?;
+
+        Ok(())
+    }
+
+    /// Removes the team's file, if it has one.
+    pub(crate) async fn remove(&self, team_id: &TeamId) -> Result<()> {
+        match remove_file(self.path.join(team_id.to_string())).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("could not remove {} file", self.what))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) async fn list(&self) -> Result<Vec<(TeamId, Vec<T>)>> {
+        let mut entries = read_dir(&self.path).await?;
+        let mut out = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            let team_id = TeamId::decode(entry.file_name().as_encoded_bytes())?;
+            let bytes = read(entry.path()).await?;
+            let items = cbor::from_reader(&bytes[..])
+                .with_context(|| format!("invalid {} file for team {team_id}", self.what))?;
+            out.push((team_id, items));
+        }
+
+        Ok(out)
     }
 }
 
@@ -98,6 +269,68 @@ pub(crate) async fn load_team_psk_pairs(
     Ok(out)
 }
 
+/// Loads the PSKs of each team's newest retired seed, along with
+/// the rotation that retired it, even if its grace period has
+/// ended.
+pub(crate) async fn load_recovery_team_psks(
+    eng: &mut CE,
+    store: &mut LocalStore<KS>,
+    dir: &SeedDir,
+    rotations: &SeedRotationDir,
+) -> Result<Vec<(TeamId, Vec<Arc<PresharedKey>>, SyncSeedRotated)>> {
+    let mut rotations = rotations.list().await?;
+    let mut out = Vec::new();
+
+    for (team_id, seeds) in dir.list_seeds().await? {
+        let Some((seed_id, _)) = seeds.retired.last() else {
+            continue;
+        };
+        let Some(rotation) = rotations
+            .iter_mut()
+            .find(|(id, _)| *id == team_id)
+            .and_then(|(_, rotations)| rotations.pop())
+        else {
+            continue;
+        };
+        let Some(seed) = qs::PskSeed::load(eng, store, seed_id)? else {
+            continue;
+        };
+        let psks = seed
+            .generate_psks(team_id)
+            .map(|psk| psk.map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        out.push((team_id, psks, rotation));
+    }
+
+    Ok(out)
+}
+
+/// Loads the PSKs of the retired seeds whose grace period has not
+/// ended yet, along with when it ends.
+pub(crate) async fn load_retired_team_psks(
+    eng: &mut CE,
+    store: &mut LocalStore<KS>,
+    dir: &SeedDir,
+) -> Result<Vec<(TeamId, Vec<Arc<PresharedKey>>, SystemTime)>> {
+    let now = SystemTime::now();
+    let mut out = Vec::new();
+
+    for (team_id, seeds) in dir.list_seeds().await? {
+        for (seed_id, until) in seeds.retired_at(now) {
+            let Some(seed) = qs::PskSeed::load(eng, store, &seed_id)? else {
+                continue;
+            };
+            let psks = seed
+                .generate_psks(team_id)
+                .map(|psk| psk.map(Arc::new))
+                .collect::<Result<Vec<_>>>()?;
+            out.push((team_id, psks, until));
+        }
+    }
+
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -148,6 +381,137 @@ mod tests {
         Ok(())
     }
 
+    #[test(tokio::test(flavor = "multi_thread"))]
+    async fn test_replace_and_get_seeds() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let seed_dir = SeedDir::new(tmp_dir.path().join("seeds"))
+            .await
+            .context("could not create seed dir")?;
+
+        let team_id = Id::random(&mut Rng).into();
+        let first = Id::random(&mut Rng).into();
+        seed_dir.append(&team_id, &first).await?;
+        assert_eq!(
+            seed_dir.get_seeds(&team_id).await?,
+            TeamSeeds {
+                seed_id: first,
+                rotated_at: 0,
+                retired: Vec::new(),
+            }
+        );
+
+        // Grace periods are stored with millisecond precision.
+        let now = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let second = (Id::random(&mut Rng).into(), now + Duration::from_secs(1));
+        let seeds = TeamSeeds {
+            seed_id: Id::random(&mut Rng).into(),
+            rotated_at: 1_700_000_000,
+            retired: vec![(first, now - Duration::from_secs(1)), second],
+        };
+        seed_dir.replace(&team_id, &seeds).await?;
+        assert_eq!(seed_dir.get_seeds(&team_id).await?, seeds);
+        assert_eq!(seed_dir.get(&team_id).await?, seeds.seed_id);
+        assert_eq!(seeds.retired_at(now).collect::<Vec<_>>(), vec![second]);
+
+        Ok(())
+    }
+
+    #[test(tokio::test(flavor = "multi_thread"))]
+    async fn test_sync_peers_save_and_list() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let path = tmp_dir.path().join("sync_peers");
+
+        let peer_dir = SyncPeerDir::new(path, "sync peers")
+            .await
+            .context("could not create sync peer dir")?;
+
+        let team_a = Id::random(&mut Rng).into();
+        let team_b = Id::random(&mut Rng).into();
+        let cfg = |secs| SyncPeerConfig {
+            interval: Duration::from_secs(secs),
+            sync_now: true,
+            ephemeral: false,
+            backoff: None,
+            subscribe: false,
+            transport: None,
+        };
+        let addr_a: Addr = "127.0.0.1:5050".parse()?;
+        let addr_b: Addr = "example.com:5050".parse()?;
+
+        peer_dir
+            .save(&team_a, &[(addr_a, cfg(1)), (addr_b, cfg(2))])
+            .await?;
+        peer_dir.save(&team_b, &[(addr_a, cfg(3))]).await?;
+        // Saving a team again replaces its peers.
+        peer_dir.save(&team_b, &[(addr_b, cfg(4))]).await?;
+
+        // Config doesn't implement `PartialEq`, so compare the intervals.
+        let summarize = |list: Vec<(TeamId, Vec<(Addr, SyncPeerConfig)>)>| {
+            let mut out: Vec<_> = list
+                .into_iter()
+                .map(|(team_id, peers)| {
+                    let peers: Vec<_> = peers
+                        .into_iter()
+                        .map(|(addr, cfg)| (addr, cfg.interval.as_secs()))
+                        .collect();
+                    (team_id, peers)
+                })
+                .collect();
+            out.sort_unstable();
+            out
+        };
+
+        let mut expected = vec![
+            (team_a, vec![(addr_a, 1), (addr_b, 2)]),
+            (team_b, vec![(addr_b, 4)]),
+        ];
+        expected.sort_unstable();
+        assert_eq!(summarize(peer_dir.list().await?), expected);
+
+        // Saving no peers removes the team, and removing it twice is fine.
+        peer_dir.save(&team_a, &[]).await?;
+        peer_dir.save(&team_a, &[]).await?;
+        assert_eq!(
+            summarize(peer_dir.list().await?),
+            [(team_b, vec![(addr_b, 4)])]
+        );
+
+        Ok(())
+    }
+
+    #[test(tokio::test(flavor = "multi_thread"))]
+    async fn test_peer_caches_save_and_list() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let path = tmp_dir.path().join("peer_caches");
+
+        let cache_dir = PeerCacheDir::new(path, "peer cache")
+            .await
+            .context("could not create peer cache dir")?;
+
+        let team_id = Id::random(&mut Rng).into();
+        let addr: Addr = "127.0.0.1:5050".parse()?;
+        let head = |max_cut| Address {
+            id: Id::random(&mut Rng).into(),
+            max_cut,
+        };
+        let heads = vec![(addr, vec![head(3), head(4)])];
+
+        cache_dir.save(&team_id, &heads).await?;
+        assert_eq!(cache_dir.list().await?, [(team_id, heads.clone())]);
+
+        // Saving no heads removes the team.
+        cache_dir.save(&team_id, &[]).await?;
+        assert_eq!(cache_dir.list().await?, []);
+
+        // So does removing it, even if it has no file.
+        cache_dir.save(&team_id, &heads).await?;
+        cache_dir.remove(&team_id).await?;
+        cache_dir.remove(&team_id).await?;
+        assert_eq!(cache_dir.list().await?, []);
+
+        Ok(())
+    }
+
     #[test(tokio::test(flavor = "multi_thread"))]
     async fn test_append_and_remove() -> Result<()> {
         let tmp_dir = tempdir()?;
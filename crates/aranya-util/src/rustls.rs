@@ -1,29 +1,28 @@
-//! This module provides TLS configuration components that **deliberately bypass security checks**.
-//! Useful for server configurations that rely on PSKs to secure connections.
-
-// --- Start SkipServerVerification ---
-// INSECURE: Allows connecting to any server certificate.
-// Requires the `dangerous_configuration` feature on the `rustls` crate.
-// Use full paths for traits and types
-// TODO: remove this once we have a way to exclusively use PSKs.
-// Currently, we use this to allow the server to be set up to use PSKs
-// without having to rely on the server certificate.
+//! TLS configuration components for connections that are authenticated only by PSKs.
+//!
+//! Neither side of such a connection has a certificate. The server's [`NoCertResolver`] has
+//! nothing to sign with and the client's [`NoCertVerifier`] rejects every certificate, so a
+//! handshake that does not use a PSK fails.
 
 use std::sync::Arc;
 
 use s2n_quic::provider::tls::rustls::rustls::pki_types::ServerName;
 #[allow(deprecated)]
-use s2n_quic::provider::tls::rustls::rustls::{self, crypto::CryptoProvider};
+use s2n_quic::provider::tls::rustls::rustls::{self, crypto::CryptoProvider, CertificateError};
 
 #[derive(Debug)]
-/// A server certificate verifier that accepts any certificate without validation.
+/// A server certificate verifier that rejects every certificate.
 ///
-/// This struct implements [`rustls::client::danger::ServerCertVerifier`] but performs no actual
-/// certificate verification, effectively accepting any server certificate presented during
-/// the TLS handshake.
-pub struct SkipServerVerification(Arc<CryptoProvider>);
+/// This struct implements [`rustls::client::danger::ServerCertVerifier`] for clients that
+/// only use PSKs. A PSK handshake never asks it to verify anything, so a server can only
+/// reach it by falling back to certificate authentication, which then fails.
+pub struct NoCertVerifier(Arc<CryptoProvider>);
+
+/// The previous name of [`NoCertVerifier`].
+#[deprecated(since = "0.7.0", note = "renamed to `NoCertVerifier`")]
+pub type SkipServerVerification = NoCertVerifier;
 
-impl SkipServerVerification {
+impl NoCertVerifier {
     #![allow(clippy::expect_used)]
     /// Creates a new instance using the default [`CryptoProvider`]
     pub fn new() -> Arc<Self> {
@@ -32,7 +31,7 @@ impl SkipServerVerification {
     }
 }
 
-impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+impl rustls::client::danger::ServerCertVerifier for NoCertVerifier {
     fn verify_server_cert(
         &self,
         _end_entity: &rustls::pki_types::CertificateDer<'_>,
@@ -41,44 +40,39 @@ impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
         _ocsp_response: &[u8],
         _now: rustls::pki_types::UnixTime,
     ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::danger::ServerCertVerified::assertion())
+        Err(rustls::Error::InvalidCertificate(
+            CertificateError::ApplicationVerificationFailure,
+        ))
     }
 
     fn verify_tls12_signature(
         &self,
-        message: &[u8],
-        cert: &rustls::pki_types::CertificateDer<'_>,
-        dss: &rustls::DigitallySignedStruct,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
     ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        // Use the selected provider's verification algorithms
-        rustls::crypto::verify_tls12_signature(
-            message,
-            cert,
-            dss,
-            &self.0.signature_verification_algorithms,
-        )
+        Err(rustls::Error::InvalidCertificate(
+            CertificateError::ApplicationVerificationFailure,
+        ))
     }
 
     fn verify_tls13_signature(
         &self,
-        message: &[u8],
-        cert: &rustls::pki_types::CertificateDer<'_>,
-        dss: &rustls::DigitallySignedStruct,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
     ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        // Use the selected provider's verification algorithms
-        rustls::crypto::verify_tls13_signature(
-            message,
-            cert,
-            dss,
-            &self.0.signature_verification_algorithms,
-        )
+        Err(rustls::Error::InvalidCertificate(
+            CertificateError::ApplicationVerificationFailure,
+        ))
     }
 
     fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        // Still advertised in the ClientHello, even though no
+        // signature is ever accepted.
         self.0.signature_verification_algorithms.supported_schemes()
     }
 }
-// --- End SkipServerVerification ---
 
 /// A certificate resolver that provides no certificates.
 ///